@@ -0,0 +1,107 @@
+//! Measures the instruction dispatch hot path: how many instructions per
+//! second `Cpu::execute_instruction` sustains on a tight loop of common
+//! XOR/ADD/PUSH/POP/CMP/Jcc instructions. None of them print, so the numbers
+//! are dispatch and not stdout. The primary opcodes dispatch through a
+//! static handler table; the match this replaced is gone, so there is no
+//! second path left to compare against.
+//!
+//! The `memory` group runs the same loop with the memory behind a
+//! `Box<dyn Memory>` (`Cpu`) and as a concrete `SystemMemory`
+//! (`CpuGeneric<SystemMemory>`), to show what static dispatch saves.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use dos_emu::memory::Memory;
+use dos_emu::{
+    init_bios_data_area, init_bios_interrupts, Cpu, CpuGeneric, DiskImage, Serial, SystemMemory,
+};
+use std::path::PathBuf;
+
+const INSTRUCTIONS: u64 = 10_000;
+
+// Runs forever: counts AX up to 100, then starts over. The MOV handlers
+// all trace to stdout, so the register copy goes through the stack instead;
+// every instruction here runs without printing.
+const LOOP: &[u8] = &[
+    0x31, 0xC0, // 0100: xor ax, ax
+    0x05, 0x01, 0x00, // 0102: add ax, 1
+    0x50, // 0105: push ax
+    0x5B, // 0106: pop bx
+    0x83, 0xF8, 0x64, // 0107: cmp ax, 100
+    0x75, 0xF6, // 010A: jne 0102
+    0xEB, 0xF2, // 010C: jmp 0100
+];
+
+fn setup_cpu() -> Cpu {
+    let disk = DiskImage::new(&PathBuf::from("drive_c")).expect("Failed to create disk image");
+    let mut cpu = Cpu::new(
+        Box::new(SystemMemory::new(1024 * 1024)),
+        Serial::new(),
+        disk,
+    );
+    init_bios_interrupts(&mut cpu);
+    init_bios_data_area(&mut cpu);
+    for (i, &byte) in LOOP.iter().enumerate() {
+        cpu.poke_byte(0, 0x100 + i as u16, byte);
+    }
+    cpu.regs.cs = 0;
+    cpu.regs.ip = 0x100;
+    cpu
+}
+
+// JMP FAR 0000:0100, so the loop runs straight from the reset vector with
+// no disk or BIOS behind it
+const RESET: &[u8] = &[0xEA, 0x00, 0x01, 0x00, 0x00];
+
+fn setup_bare_cpu<M: Memory>(memory: M) -> CpuGeneric<M> {
+    let mut cpu = CpuGeneric::with_memory(memory, Serial::new());
+    cpu.inject_bytes_at_reset(RESET)
+        .expect("reset code doesn't fit");
+    cpu.load_at(0, 0x100, LOOP);
+    cpu
+}
+
+fn run_loop<M: Memory>(cpu: &mut CpuGeneric<M>) {
+    for _ in 0..INSTRUCTIONS {
+        cpu.execute_instruction().expect("benchmark loop failed");
+    }
+}
+
+fn bench_memory_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memory");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("dynamic", |b| {
+        b.iter_batched_ref(
+            || setup_bare_cpu::<Box<dyn Memory>>(Box::new(SystemMemory::new(1024 * 1024))),
+            run_loop,
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("static", |b| {
+        b.iter_batched_ref(
+            || setup_bare_cpu(SystemMemory::new(1024 * 1024)),
+            run_loop,
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("table", |b| {
+        b.iter_batched_ref(
+            setup_cpu,
+            |cpu| {
+                for _ in 0..INSTRUCTIONS {
+                    cpu.execute_instruction().expect("benchmark loop failed");
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch, bench_memory_dispatch);
+criterion_main!(benches);
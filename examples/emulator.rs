@@ -4,44 +4,213 @@
  *
  * This example demonstrates how to use the DOS emulator library to create
  * a complete emulation environment.
+ *
+ * Usage: emulator [--drive PATH] [--rom PATH] [--max-cycles N]
+ *                 [--telnet PORT] [--trace] [--dump PATH]
  */
 
 use dos_emu::{
-    disk::PARTITION_TABLE_OFFSET, init_bios_data_area, init_bios_interrupts, Cpu, DiskImage,
-    Serial, SystemMemory,
+    bios::boot_from_disk, cpu::dump::CONVENTIONAL_DUMP_RANGE, init_bios_data_area,
+    init_bios_interrupts, Cpu, DiskImage, Serial, StopReason, SystemMemory,
 };
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Create disk image
-    let drive_c_path = PathBuf::from("drive_c");
-    let disk = DiskImage::new(&drive_c_path)?;
+const USAGE: &str = "Usage: emulator [--drive PATH] [--rom PATH] [--max-cycles N] \
+[--telnet PORT] [--trace] [--dump PATH]
+
+  --drive PATH      Directory holding the C: drive (default: drive_c)
+  --rom PATH        BIOS ROM image to map below 1MB instead of the built-in one
+  --max-cycles N    Stop after N instructions
+  --telnet PORT     Bridge COM1 to the first client on 127.0.0.1:PORT
+  --trace           Print every instruction before it runs
+  --dump PATH       Write the first 1MB of guest memory to PATH if the CPU crashes
+  --help            Show this message";
+
+// Instructions run between polls of the telnet connection
+const TELNET_SLICE: u64 = 10_000;
 
-    // Verify MBR has boot code - fail if not present
-    let mbr_sector = match disk.read_sector(0) {
-        Some(data) => data,
-        None => {
-            eprintln!("Failed to read MBR");
-            std::process::exit(1);
+#[derive(Debug, PartialEq)]
+struct Options {
+    drive: PathBuf,
+    rom: Option<PathBuf>,
+    max_cycles: Option<u64>,
+    telnet: Option<u16>,
+    trace: bool,
+    dump: Option<PathBuf>,
+    help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            drive: PathBuf::from("drive_c"),
+            rom: None,
+            max_cycles: None,
+            telnet: None,
+            trace: false,
+            dump: None,
+            help: false,
+        }
+    }
+}
+
+/// Parses the arguments after the program name
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut seen = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if seen.contains(&arg) {
+            return Err(format!("{} given more than once", arg));
+        }
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--drive" => options.drive = PathBuf::from(value()?),
+            "--rom" => options.rom = Some(PathBuf::from(value()?)),
+            "--max-cycles" => {
+                let value = value()?;
+                let cycles = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("Invalid cycle count: {}", value))?;
+                options.max_cycles = Some(cycles);
+            }
+            "--telnet" => {
+                let value = value()?;
+                let port = value
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("Invalid port: {}", value))?;
+                options.telnet = Some(port);
+            }
+            "--trace" => options.trace = true,
+            "--dump" => options.dump = Some(PathBuf::from(value()?)),
+            "--help" | "-h" => options.help = true,
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+        seen.push(arg);
+    }
+
+    if options.help && seen.len() > 1 {
+        return Err("--help takes no other arguments".to_string());
+    }
+    Ok(options)
+}
+
+// Waits for one client and makes the socket non-blocking so the CPU can keep
+// running between reads. Bytes go through untouched: a telnet client on a
+// port other than 23 doesn't start option negotiation on its own.
+fn accept_telnet(port: u16) -> std::io::Result<TcpStream> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for a telnet connection on port {}", port);
+    let (stream, peer) = listener.accept()?;
+    println!("COM1 connected to {}", peer);
+    stream.set_nonblocking(true)?;
+    Ok(stream)
+}
+
+// Moves one slice's worth of COM1 traffic across the connection. Returns
+// false once the client has gone away.
+fn pump_telnet(cpu: &mut Cpu, stream: &mut TcpStream) -> bool {
+    let mut buf = [0u8; 256];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return false,
+            Ok(n) => buf[..n].iter().for_each(|&b| cpu.serial.add_input(b)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(_) => return false,
+        }
+    }
+    let output: Vec<u8> = std::iter::from_fn(|| cpu.serial.get_output()).collect();
+    output.is_empty() || stream.write_all(&output).is_ok()
+}
+
+fn trace(cpu: &Cpu) {
+    let (text, bytes) = cpu.disassemble(cpu.regs.cs, cpu.regs.ip);
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    eprintln!(
+        "{:04X}:{:04X} {:<14} {}",
+        cpu.regs.cs, cpu.regs.ip, hex, text
+    );
+}
+
+// Runs to the cycle cap or until something stops the CPU, tracing and
+// servicing the telnet connection along the way if asked to
+fn run(cpu: &mut Cpu, options: &Options, mut telnet: Option<TcpStream>) -> StopReason {
+    let mut remaining = options.max_cycles.unwrap_or(u64::MAX);
+    if !options.trace && telnet.is_none() {
+        return cpu.run_for(remaining);
+    }
+
+    let slice = if options.trace { 1 } else { TELNET_SLICE };
+    loop {
+        if options.trace {
+            trace(cpu);
+        }
+        let steps = slice.min(remaining);
+        let reason = cpu.run_for(steps);
+        remaining -= steps;
+
+        if let Some(stream) = telnet.as_mut() {
+            if !pump_telnet(cpu, stream) {
+                println!("Telnet client disconnected");
+                telnet = None;
+            }
+        }
+        if reason != StopReason::InstructionLimit || remaining == 0 {
+            return reason;
         }
-    };
-    if mbr_sector.len() != 512 {
-        eprintln!("Invalid MBR sector size");
-        std::process::exit(1);
     }
+}
 
-    // Check for valid boot code (at least some non-zero bytes in boot code area)
-    let has_boot_code = mbr_sector[0..PARTITION_TABLE_OFFSET]
-        .iter()
-        .any(|&byte| byte != 0);
+// Instructions per second over the whole run; wall time includes waiting
+// on a telnet client, so this is only a benchmark without one
+fn report_speed(instructions: u64, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let ips = if seconds > 0.0 {
+        instructions as f64 / seconds
+    } else {
+        0.0
+    };
+    eprintln!(
+        "Executed {} instructions in {:.3}s ({:.0} IPS)",
+        instructions, seconds, ips
+    );
+}
 
-    if !has_boot_code {
-        eprintln!("MBR boot code not present");
-        std::process::exit(1);
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(64);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    // Create disk image
+    let disk = DiskImage::new(&options.drive)?;
+    if disk.fs_root().is_none() {
+        eprintln!(
+            "Note: {} has no fs/ directory; DOS file services will find no files",
+            options.drive.display()
+        );
     }
 
     // Create memory with ROM and RAM
-    let memory = SystemMemory::new(1024 * 1024); // 1MB RAM
+    let mut memory = SystemMemory::new(1024 * 1024); // 1MB RAM
+    if let Some(rom) = &options.rom {
+        memory.load_bios_rom_file(rom)?;
+    }
 
     // Initialize CPU with memory and serial port
     let mut cpu = Cpu::new(Box::new(memory), Serial::new(), disk);
@@ -55,26 +224,129 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Err("No valid ROM loaded".into());
     }
 
-    // Set initial state to start at BIOS reset vector
-    cpu.regs.cs = 0xF000;
-    cpu.regs.ip = 0xFFF0;
-    cpu.regs.ds = 0x0000;
-    cpu.regs.es = 0x0000;
-    cpu.regs.ss = 0x0000;
-    cpu.regs.sp = 0x7C00;
+    // Load the MBR, or report that there is nothing to boot
+    boot_from_disk(&mut cpu)?;
 
-    // Run CPU
-    loop {
-        if cpu.is_halted() {
+    let telnet = options.telnet.map(accept_telnet).transpose()?;
+
+    // Run CPU until something stops it
+    let started = Instant::now();
+    let reason = run(&mut cpu, &options, telnet);
+    let elapsed = started.elapsed();
+    cpu.serial.flush_console();
+    report_speed(cpu.instruction_count(), elapsed);
+    // Anything but a clean stop is worth a post-mortem
+    let crashed = matches!(
+        reason,
+        StopReason::Runaway
+            | StopReason::InvalidOpcode(_)
+            | StopReason::DivideError
+            | StopReason::Error(_)
+            | StopReason::InternalFault { .. }
+    );
+    let exit_code = match reason {
+        StopReason::Halted => {
             println!("CPU halted normally");
-            break;
+            0
         }
-
-        if let Err(e) = cpu.execute_instruction() {
-            println!("CPU error: {}", e);
-            break;
+        StopReason::Runaway => {
+            eprintln!(
+                "CPU stopped: runaway detected at {:04X}:{:04X}",
+                cpu.regs.cs, cpu.regs.ip
+            );
+            2
+        }
+        StopReason::InvalidOpcode(opcode) => {
+            let opcode = if opcode > 0xFF {
+                format!("0F {:02X}", opcode & 0xFF)
+            } else {
+                format!("{:02X}", opcode)
+            };
+            eprintln!(
+                "CPU stopped: invalid opcode {} at {:04X}:{:04X}",
+                opcode, cpu.regs.cs, cpu.regs.ip
+            );
+            3
+        }
+        StopReason::DivideError => {
+            eprintln!(
+                "CPU stopped: divide error at {:04X}:{:04X}",
+                cpu.regs.cs, cpu.regs.ip
+            );
+            4
         }
+        StopReason::InstructionLimit => {
+            eprintln!("CPU stopped: instruction limit reached");
+            1
+        }
+        StopReason::NoBootDevice => {
+            eprintln!("CPU stopped: no bootable device");
+            5
+        }
+        StopReason::GuestExit(code) => {
+            println!("Guest exited with code {}", code);
+            code as i32
+        }
+        // Only run_until_output reports this
+        StopReason::OutputMatched => 0,
+        StopReason::Error(e) => {
+            eprintln!("CPU error: {}", e);
+            1
+        }
+        StopReason::InternalFault { message, .. } => {
+            eprintln!("Emulator fault: {}", message);
+            6
+        }
+    };
+
+    if let (true, Some(path)) = (crashed, &options.dump) {
+        cpu.dump_memory(path, CONVENTIONAL_DUMP_RANGE)?;
     }
 
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(
+            "--drive /tmp/c --rom bios.bin --max-cycles 5000 --telnet 2323 --trace --dump crash.bin",
+        ))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                drive: PathBuf::from("/tmp/c"),
+                rom: Some(PathBuf::from("bios.bin")),
+                max_cycles: Some(5000),
+                telnet: Some(2323),
+                trace: true,
+                dump: Some(PathBuf::from("crash.bin")),
+                help: false,
+            }
+        );
+        assert_eq!(parse_args(args("")).unwrap(), Options::default());
+
+        for bad in [
+            "--drive",
+            "--max-cycles 0",
+            "--max-cycles lots",
+            "--telnet 70000",
+            "--trace --trace",
+            "--help --trace",
+            "--floppy a.img",
+        ] {
+            assert!(parse_args(args(bad)).is_err(), "{}", bad);
+        }
+    }
+}
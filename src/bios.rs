@@ -2,133 +2,389 @@
 // DO NOT implement video/graphics functionality. All output goes through serial port.
 
 use crate::cpu::Cpu;
+use crate::disk::{DiskImage, Mbr, MBR_SIGNATURE};
+use crate::dos::handle_dos_interrupt;
+use crate::ems::handle_ems_interrupt;
 use chrono::{Datelike, Timelike};
-use std::io::Write;
 
 #[allow(dead_code)]
 const SERIAL_PORT: u16 = 0x3F8; // COM1 port
 
-pub fn init_bios_interrupts(cpu: &mut Cpu) {
-    // Initialize interrupt vector table at 0x0000
+// Service selection for init_bios_interrupts_selective
+pub const BIOS_VIDEO: u8 = 0x01; // INT 10h
+pub const BIOS_DISK: u8 = 0x02; // INT 13h
+pub const BIOS_SERIAL: u8 = 0x04; // INT 14h
+pub const BIOS_KEYBOARD: u8 = 0x08; // INT 16h
+pub const BIOS_BOOT_FAILURE: u8 = 0x10; // INT 18h
+pub const BIOS_ALL: u8 = BIOS_VIDEO | BIOS_DISK | BIOS_SERIAL | BIOS_KEYBOARD | BIOS_BOOT_FAILURE;
 
-    // INT 10h - Video Services
-    set_interrupt_vector(cpu, 0x10, bios_seg(), video_services_offset());
+// What INT 18h prints where an IBM PC would have started ROM BASIC
+const NO_BOOT_DEVICE_MESSAGE: &[u8] = b"No bootable device\r\n";
 
-    // INT 14h - Serial Services (Primary TTY output)
-    set_interrupt_vector(cpu, 0x14, bios_seg(), serial_services_offset());
+pub fn init_bios_interrupts(cpu: &mut Cpu) {
+    init_bios_interrupts_selective(cpu, BIOS_ALL);
+}
 
-    // INT 13h - Disk Services
-    set_interrupt_vector(cpu, 0x13, bios_seg(), disk_services_offset());
+/// Points the vectors for the services in `mask` at the native BIOS. A vector
+/// that is already non-zero was installed by the user and is left alone, so
+/// a custom handler can replace any BIOS service.
+pub fn init_bios_interrupts_selective(cpu: &mut Cpu, mask: u8) {
+    // Initialize interrupt vector table at 0x0000
+    let services = [
+        (BIOS_VIDEO, 0x10, video_services_offset()),
+        // Fast console output, which DOS's CON driver uses
+        (BIOS_VIDEO, 0x29, fast_console_offset()),
+        // Serial Services (Primary TTY output)
+        (BIOS_SERIAL, 0x14, serial_services_offset()),
+        (BIOS_DISK, 0x13, disk_services_offset()),
+        (BIOS_KEYBOARD, 0x16, keyboard_services_offset()),
+        // No ROM BASIC; hook INT 18h before init to provide one
+        (BIOS_BOOT_FAILURE, 0x18, boot_failure_offset()),
+    ];
 
-    // INT 16h - Keyboard Services
-    set_interrupt_vector(cpu, 0x16, bios_seg(), keyboard_services_offset());
+    for (bit, int_num, offset) in services {
+        if mask & bit == 0 {
+            continue;
+        }
+        if cpu.get_ivt(int_num) != (0, 0) {
+            println!("BIOS: Keeping user vector for INT {:02X}h", int_num);
+            continue;
+        }
+        set_interrupt_vector(cpu, int_num, bios_seg(), offset);
+    }
 }
 
-fn set_interrupt_vector(cpu: &mut Cpu, int_num: u8, segment: u16, offset: u16) {
-    let addr = (int_num as u32) * 4;
-    cpu.memory.write_word(addr, offset);
-    cpu.memory.write_word(addr + 2, segment);
+pub(crate) fn set_interrupt_vector(cpu: &mut Cpu, int_num: u8, segment: u16, offset: u16) {
+    cpu.set_ivt(int_num, segment, offset);
 }
 
-fn bios_seg() -> u16 {
+pub(crate) fn bios_seg() -> u16 {
     0xF000
 }
 
 fn video_services_offset() -> u16 {
-    0xE300 // Point to our video interrupt handler in ROM
+    iret_offset() // Teletype output is sent natively
+}
+
+fn fast_console_offset() -> u16 {
+    iret_offset() // The character is sent natively
+}
+
+fn boot_failure_offset() -> u16 {
+    0xE330 // HLT loop, in case anything wakes the CPU
 }
 
 fn serial_services_offset() -> u16 {
-    0xE000 // Point to our serial interrupt handler in ROM
+    iret_offset() // Every port is serviced natively
 }
 
 fn keyboard_services_offset() -> u16 {
-    0x0100 // We'll implement this later
+    iret_offset()
 }
 
 fn disk_services_offset() -> u16 {
-    0x0200 // We'll implement this later
+    iret_offset()
+}
+
+pub(crate) fn iret_offset() -> u16 {
+    0xE320 // Just an IRET, for services handled entirely natively
+}
+
+/// Copies CF and ZF as a native service left them into the FLAGS its INT
+/// pushed at SS:SP+4, so they survive the IRET back to the caller
+pub(crate) fn return_flags(cpu: &mut Cpu) {
+    const RESULT_FLAGS: u16 = 0x0041; // CF, ZF
+    let addr = cpu.get_physical_address(cpu.regs.ss, cpu.regs.sp.wrapping_add(4));
+    let stacked = cpu.memory.read_word(addr);
+    let result = cpu.regs.flags.as_word() & RESULT_FLAGS;
+    cpu.memory
+        .write_word(addr, (stacked & !RESULT_FLAGS) | result);
+}
+
+/// A natively implemented interrupt service, run when the guest takes a
+/// vector that points into the BIOS segment
+pub type InterruptHandler = Box<dyn FnMut(&mut Cpu) -> Result<(), String>>;
+
+/// The native handler for each of the 256 vectors
+pub struct InterruptTable {
+    handlers: Vec<Option<InterruptHandler>>,
+}
+
+impl Default for InterruptTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptTable {
+    /// A table with nothing registered
+    pub fn empty() -> Self {
+        InterruptTable {
+            handlers: (0..256).map(|_| None).collect(),
+        }
+    }
+
+    /// The built-in BIOS, DOS and EMS services. Which of them the guest can
+    /// reach still depends on the vectors `init_bios_interrupts`,
+    /// `install_dos_services` and `install_ems` point at the BIOS segment.
+    pub fn new() -> Self {
+        let mut table = Self::empty();
+        table.register(0x10, Box::new(handle_video_interrupt));
+        table.register(0x11, Box::new(|cpu: &mut Cpu| cpu.int11_equipment_list()));
+        table.register(0x12, Box::new(|cpu: &mut Cpu| cpu.int12_memory_size()));
+        table.register(0x13, Box::new(handle_disk_interrupt));
+        table.register(0x14, Box::new(handle_serial_interrupt));
+        table.register(0x15, Box::new(|cpu: &mut Cpu| cpu.int15_system_services()));
+        table.register(0x16, Box::new(handle_keyboard_interrupt));
+        // ROM BASIC / No Boot Device
+        table.register(
+            0x18,
+            Box::new(|cpu: &mut Cpu| {
+                handle_no_boot_device(cpu);
+                Ok(())
+            }),
+        );
+        table.register(0x1A, Box::new(|cpu: &mut Cpu| cpu.int1a_time_services()));
+        table.register(0x21, Box::new(handle_dos_interrupt));
+        // Fast Console Output
+        table.register(
+            0x29,
+            Box::new(|cpu: &mut Cpu| {
+                tty_output(cpu.regs.get_al(), cpu);
+                Ok(())
+            }),
+        );
+        table.register(0x67, Box::new(handle_ems_interrupt));
+        table
+    }
+
+    /// Sets the handler for `int_num`, replacing any handler already there
+    pub fn register(&mut self, int_num: u8, handler: InterruptHandler) {
+        self.handlers[int_num as usize] = Some(handler);
+    }
+
+    pub fn unregister(&mut self, int_num: u8) -> Option<InterruptHandler> {
+        self.handlers[int_num as usize].take()
+    }
+
+    pub fn is_registered(&self, int_num: u8) -> bool {
+        self.handlers[int_num as usize].is_some()
+    }
+}
+
+impl Cpu {
+    /// Services `int_num` natively with `handler`, overriding any built-in
+    /// service for it. The vector is pointed at a bare IRET in the BIOS
+    /// segment so that a guest `INT` reaches the handler and returns.
+    pub fn set_interrupt_handler(&mut self, int_num: u8, handler: InterruptHandler) {
+        self.interrupt_handlers.register(int_num, handler);
+        set_interrupt_vector(self, int_num, bios_seg(), iret_offset());
+    }
+}
+
+impl Cpu {
+    /// Puts `disk` in BIOS drive `bios_num`, or empties the drive with
+    /// None, e.g. to swap floppies while the guest runs. There is a single
+    /// drive, so this replaces whatever was in it: 00h takes a floppy image
+    /// and 80h a hard disk. The boot checks and the BIOS data area are
+    /// brought up to date, and changing the floppy raises the disk change
+    /// line that INT 13h AH=16h reports.
+    pub fn set_drive(&mut self, bios_num: u8, disk: Option<DiskImage>) -> Result<(), String> {
+        let floppy = match bios_num {
+            0x00 => true,
+            0x80 => false,
+            _ => {
+                return Err(format!(
+                    "No drive {:02X}h; only 00h and 80h exist",
+                    bios_num
+                ))
+            }
+        };
+        if let Some(disk) = &disk {
+            if disk.geometry.is_floppy() != floppy {
+                return Err(format!(
+                    "Drive {:02X}h takes a {} image",
+                    bios_num,
+                    if floppy { "floppy" } else { "hard disk" }
+                ));
+            }
+        }
+
+        self.disk = disk.unwrap_or_else(DiskImage::empty);
+        self.check_boot_code();
+        if floppy {
+            self.disk_changed = true;
+        }
+        write_drive_counts(self);
+        Ok(())
+    }
 }
 
 pub fn handle_bios_interrupt(cpu: &mut Cpu, int_num: u8) -> Result<(), String> {
-    match int_num {
-        0x10 => handle_video_interrupt(cpu),
-        0x14 => handle_serial_interrupt(cpu),
-        0x13 => handle_disk_interrupt(cpu),
-        0x16 => handle_keyboard_interrupt(cpu),
-        0x11 => {
-            cpu.int11_equipment_list()?;
-            Ok(())
-        } // Get Equipment List
-        0x12 => {
-            cpu.int12_memory_size()?;
-            Ok(())
-        } // Get Memory Size
-        0x15 => {
-            cpu.int15_system_services()?;
-            Ok(())
-        } // System Services
-        0x1A => {
-            cpu.int1a_time_services()?;
-            Ok(())
-        } // Time Services
-        _ => Err(format!("Unhandled BIOS interrupt: {:02X}", int_num)),
+    // The handler is taken out for the call so it can borrow the CPU
+    let mut handler = cpu
+        .interrupt_handlers
+        .unregister(int_num)
+        .ok_or_else(|| format!("Unhandled BIOS interrupt: {:02X}", int_num))?;
+    // A panic still puts it back before unwinding on to `execute_instruction`
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler(cpu)));
+    // Unless it registered a replacement for itself
+    if !cpu.interrupt_handlers.is_registered(int_num) {
+        cpu.interrupt_handlers.register(int_num, handler);
     }
+    result.unwrap_or_else(|payload| std::panic::resume_unwind(payload))?;
+    // The vector's IRET pops the FLAGS pushed on entry
+    return_flags(cpu);
+    Ok(())
+}
+
+// Console characters go to the serial sink, echoed on the console
+pub(crate) fn tty_output(char: u8, cpu: &mut Cpu) {
+    cpu.serial.transmit(char);
+    cpu.serial.echo(char);
+}
+
+/// The BIOS bootstrap (what INT 19h does after POST) for the first hard
+/// disk. With an active partition in the MBR, the MBR is loaded at
+/// 0000:7C00 and run with DL=80h. Otherwise INT 18h is taken, which stops
+/// with `StopReason::NoBootDevice` unless a handler was hooked there.
+pub fn boot_from_disk(cpu: &mut Cpu) -> Result<(), String> {
+    cpu.regs.ss = 0x0000;
+    cpu.regs.sp = 0x7C00;
+    cpu.regs.ds = 0x0000;
+    cpu.regs.es = 0x0000;
+
+    let mbr = cpu.disk.read_sector(0).unwrap_or_default();
+    let bootable = Mbr::from_bytes(&mbr)
+        .map(|mbr| mbr.signature == MBR_SIGNATURE && mbr.partitions.iter().any(|p| p.bootable))
+        .unwrap_or(false);
+    if !bootable {
+        println!("BIOS: No active partition, taking INT 18h");
+        return cpu.int(0x18);
+    }
+
+    for (i, &byte) in mbr.iter().enumerate() {
+        cpu.memory.write_byte(0x7C00 + i as u32, byte);
+    }
+    cpu.regs.cs = 0x0000;
+    cpu.regs.ip = 0x7C00;
+    cpu.regs.set_dl(0x80);
+    Ok(())
+}
+
+/// Which drive the bootstrap loads from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// The MBR of the first hard disk, run with DL=80h
+    HardDisk,
+    /// Sector 0 of the first floppy, run with DL=00h
+    Floppy,
+}
+
+/// `boot_from_disk` for either kind of drive. A floppy has no partition
+/// table to check: its boot sector is loaded at 0000:7C00 if it ends in
+/// 55 AA, and INT 18h is taken otherwise.
+pub fn boot(cpu: &mut Cpu, mode: BootMode) -> Result<(), String> {
+    if mode == BootMode::HardDisk {
+        return boot_from_disk(cpu);
+    }
+    cpu.regs.ss = 0x0000;
+    cpu.regs.sp = 0x7C00;
+    cpu.regs.ds = 0x0000;
+    cpu.regs.es = 0x0000;
+
+    let sector = cpu.disk.read_sector(0).unwrap_or_default();
+    if sector.len() != 512 || sector[510..] != MBR_SIGNATURE {
+        println!("BIOS: No boot signature on the floppy, taking INT 18h");
+        return cpu.int(0x18);
+    }
+
+    for (i, &byte) in sector.iter().enumerate() {
+        cpu.memory.write_byte(0x7C00 + i as u32, byte);
+    }
+    cpu.regs.cs = 0x0000;
+    cpu.regs.ip = 0x7C00;
+    cpu.regs.set_dl(0x00);
+    Ok(())
+}
+
+fn handle_no_boot_device(cpu: &mut Cpu) {
+    for &byte in NO_BOOT_DEVICE_MESSAGE {
+        tty_output(byte, cpu);
+    }
+    cpu.no_boot_device = true;
+    cpu.halted = true;
 }
 
 fn handle_video_interrupt(cpu: &mut Cpu) -> Result<(), String> {
     match cpu.regs.get_ah() {
         0x0E => {
             // Redirect TTY output to serial port
-            let char = cpu.regs.get_al();
-            print!("{}", char as char);
-            std::io::stdout().flush().unwrap();
+            tty_output(cpu.regs.get_al(), cpu);
             Ok(())
         }
         _ => Ok(()),
     }
 }
 
+// INT 14h status bit for a port that didn't respond
+const SERIAL_TIMEOUT: u8 = 0x80;
+
+// DX selects the port: 0 for COM1 through 3 for COM4. COM1 is the line the
+// host watches; the others are whatever UARTs were attached. A port that
+// isn't there answers every call with a timeout.
 fn handle_serial_interrupt(cpu: &mut Cpu) -> Result<(), String> {
+    let index = cpu.regs.get_dx() as usize;
+    let Some(status) = cpu.serial.line_status(index) else {
+        cpu.regs.set_ah(SERIAL_TIMEOUT);
+        return Ok(());
+    };
+    // Bit 7 of the LSR is a FIFO error on a 16550; here it means timeout
+    let status = status & !SERIAL_TIMEOUT;
     match cpu.regs.get_ah() {
         0x00 => {
-            // Initialize port
-            let port = cpu.regs.get_dx() as u32; // Convert to u32
-            let divisor = 0x0C; // 9600 baud
-
-            // Set DLAB
-            cpu.memory.write_byte(port + 3, 0x80);
-            // Set divisor
-            cpu.memory.write_byte(port, divisor as u8);
-            cpu.memory.write_byte(port + 1, (divisor >> 8) as u8);
-            // 8N1, clear DLAB
-            cpu.memory.write_byte(port + 3, 0x03);
-            // Enable FIFO, clear them, with 14-byte threshold
-            cpu.memory.write_byte(port + 2, 0xC7);
-            // IRQs enabled, RTS/DSR set
-            cpu.memory.write_byte(port + 4, 0x0B);
-
+            // Initialize port; AL holds the baud rate and line settings
+            let al = cpu.regs.get_al();
+            let modem = cpu.serial.get_port(index).map_or(0, |port| {
+                port.initialize(al);
+                port.msr
+            });
             cpu.regs.set_ah(0); // Success
+            cpu.regs.set_al(modem);
             Ok(())
         }
         0x01 => {
             // Send character
             let char = cpu.regs.get_al(); // Only use low byte
-            cpu.serial.write_byte(0x3F8, char);
+            if index == 0 {
+                cpu.serial.transmit(char);
+            } else if let Some(port) = cpu.serial.get_port(index) {
+                port.write_byte(char);
+            }
             cpu.regs.set_ah(0); // Success
             Ok(())
         }
         0x02 => {
             // Read character
-            let char = cpu.serial.read_byte(0x3F8);
-            cpu.regs.set_al(char);
-            cpu.regs.set_ah(0); // Success
+            let char = if index == 0 {
+                cpu.serial.take_input()
+            } else {
+                cpu.serial.get_port(index).and_then(|port| port.read_byte())
+            };
+            match char {
+                Some(char) => {
+                    cpu.regs.set_al(char);
+                    cpu.regs.set_ah(0); // Success
+                }
+                None => cpu.regs.set_ah(SERIAL_TIMEOUT),
+            }
             Ok(())
         }
         0x03 => {
-            // Get port status
-            let status = if cpu.serial.has_data() { 0x01 } else { 0x00 };
+            // Get port status: line status in AH, modem status in AL
+            let modem = cpu.serial.get_port(index).map_or(0, |port| port.msr);
             cpu.regs.set_ah(status);
+            cpu.regs.set_al(modem);
             Ok(())
         }
         _ => Err(format!(
@@ -162,18 +418,26 @@ pub fn handle_disk_interrupt(cpu: &mut Cpu) -> Result<(), String> {
         0x02 => {
             // Read Sectors
             let sector = cpu.regs.get_cl() & 0x3F;
-            let cylinder = ((cpu.regs.get_cl() & 0xC0) << 2) | cpu.regs.get_ch();
+            // Cylinder bits 8-9 live in the top of CL
+            let cylinder = (((cpu.regs.get_cl() & 0xC0) as u16) << 2) | cpu.regs.get_ch() as u16;
             let head = cpu.regs.get_dh();
             let count = cpu.regs.get_al();
             let buffer_segment = cpu.regs.get_es();
             let buffer_offset = cpu.regs.get_bx();
 
-            // Calculate LBA = (cylinder * heads_per_cylinder + head) * sectors_per_track + (sector - 1)
-            let heads_per_cylinder = 16;
-            let sectors_per_track = 63;
-            let lba = (cylinder as u32 * heads_per_cylinder as u32 + head as u32)
-                * sectors_per_track as u32
-                + (sector - 1) as u32;
+            let lba = match cpu.disk.geometry.chs_to_lba(cylinder, head, sector) {
+                Some(lba) => lba,
+                None => {
+                    println!(
+                        "INT 13h: C/H/S {}/{}/{} outside drive geometry",
+                        cylinder, head, sector
+                    );
+                    cpu.regs.set_ah(0x04); // Sector not found
+                    cpu.regs.set_al(0);
+                    cpu.regs.flags.set_carry(true);
+                    return Ok(());
+                }
+            };
 
             let mut success = true;
             for i in 0..count {
@@ -201,6 +465,55 @@ pub fn handle_disk_interrupt(cpu: &mut Cpu) -> Result<(), String> {
             }
             Ok(())
         }
+        0x15 => {
+            // Get disk type. CF stays clear even when there is no drive.
+            let floppy = cpu.disk.geometry.is_floppy();
+            if (drive == 0x00) != floppy || !matches!(drive, 0x00 | 0x80) {
+                cpu.regs.set_ah(DISK_TYPE_NONE);
+            } else if floppy {
+                cpu.regs.set_ah(DISK_TYPE_FLOPPY);
+            } else {
+                let sectors = cpu.disk.sector_count();
+                cpu.regs.set_ah(DISK_TYPE_FIXED);
+                cpu.regs.set_cx((sectors >> 16) as u16);
+                cpu.regs.set_dx(sectors as u16);
+            }
+            cpu.regs.flags.set_carry(false);
+            Ok(())
+        }
+        0x16 => {
+            // Detect disk change. Only the floppy drive has a change line;
+            // reading it clears it.
+            if drive != 0x00 {
+                cpu.regs.set_ah(ERR_INVALID_COMMAND);
+                cpu.regs.flags.set_carry(true);
+            } else if core::mem::take(&mut cpu.disk_changed) {
+                cpu.regs.set_ah(ERR_DISK_CHANGED);
+                cpu.regs.flags.set_carry(true);
+            } else if !cpu.disk.has_medium() {
+                cpu.regs.set_ah(ERR_INVALID_DRIVE);
+                cpu.regs.flags.set_carry(true);
+            } else {
+                cpu.regs.set_ah(ERR_SUCCESS);
+                cpu.regs.flags.set_carry(false);
+            }
+            Ok(())
+        }
+        0x41 => {
+            // Extensions installation check: BX=55AAh in, AA55h out
+            if cpu.regs.get_bx() != 0x55AA {
+                cpu.regs.set_ah(0x01);
+                cpu.regs.flags.set_carry(true);
+                return Ok(());
+            }
+            cpu.regs.set_bx(0xAA55);
+            cpu.regs.set_ah(EDD_VERSION);
+            cpu.regs.set_cx(EDD_FIXED_DISK_ACCESS);
+            cpu.regs.flags.set_carry(false);
+            Ok(())
+        }
+        0x42 => disk_transfer_extended(cpu, false),
+        0x43 => disk_transfer_extended(cpu, true),
         0xC0 => {
             // Get Drive Parameters
             if drive == 0x80 {
@@ -233,9 +546,88 @@ pub fn handle_disk_interrupt(cpu: &mut Cpu) -> Result<(), String> {
     }
 }
 
+// INT 13h AH=15h drive types
+const DISK_TYPE_NONE: u8 = 0x00;
+// A floppy drive with a change line, which AH=16h reports
+const DISK_TYPE_FLOPPY: u8 = 0x02;
+const DISK_TYPE_FIXED: u8 = 0x03;
+
+// INT 13h extensions version 1.x, with only the fixed disk access
+// subset (AH=42h-44h, 47h, 48h) in the support bitmap
+const EDD_VERSION: u8 = 0x01;
+const EDD_FIXED_DISK_ACCESS: u16 = 0x0001;
+
+// AH=42h/43h: the disk address packet at DS:SI holds its own size, the
+// sector count, the transfer buffer as offset:segment and a 64-bit
+// starting LBA. The count field is rewritten with the number of sectors
+// actually transferred, as the spec requires on both success and failure.
+fn disk_transfer_extended(cpu: &mut Cpu, write: bool) -> Result<(), String> {
+    let (ds, si) = (cpu.regs.ds, cpu.regs.si);
+    let dap = |offset: u16| si.wrapping_add(offset);
+    let size = cpu.peek_byte(ds, si);
+    let count = cpu.peek_word(ds, dap(2));
+    let buffer_offset = cpu.peek_word(ds, dap(4));
+    let buffer_segment = cpu.peek_word(ds, dap(6));
+    let lba = (0..4).fold(0u64, |lba, i| {
+        lba | (cpu.peek_word(ds, dap(8 + i * 2)) as u64) << (i * 16)
+    });
+
+    if size < 0x10 {
+        cpu.regs.set_ah(0x01); // Invalid function/parameter
+        cpu.regs.flags.set_carry(true);
+        return Ok(());
+    }
+
+    let mut transferred = 0u16;
+    let mut status = 0x00;
+    while transferred < count {
+        let Ok(sector_lba) = u32::try_from(lba + transferred as u64) else {
+            status = 0x04; // Sector not found
+            break;
+        };
+        let buffer = buffer_offset.wrapping_add(transferred.wrapping_mul(512));
+        if write {
+            let data: Vec<u8> = (0..512u16)
+                .map(|j| cpu.peek_byte(buffer_segment, buffer.wrapping_add(j)))
+                .collect();
+            if let Err(e) = cpu.disk.write_sector(sector_lba, &data) {
+                println!(
+                    "INT 13h: extended write of LBA {} failed: {}",
+                    sector_lba, e
+                );
+                status = if e.kind() == std::io::ErrorKind::PermissionDenied {
+                    0x03 // Write protected
+                } else {
+                    0x04
+                };
+                break;
+            }
+        } else {
+            match cpu.disk.read_sector(sector_lba) {
+                Some(data) => {
+                    for (j, &byte) in data.iter().enumerate() {
+                        cpu.poke_byte(buffer_segment, buffer.wrapping_add(j as u16), byte);
+                    }
+                }
+                None => {
+                    status = 0x04;
+                    break;
+                }
+            }
+        }
+        transferred += 1;
+    }
+
+    cpu.poke_word(ds, dap(2), transferred);
+    cpu.regs.set_ah(status);
+    cpu.regs.flags.set_carry(status != 0);
+    Ok(())
+}
+
 impl Cpu {
+    // Returns the word `init_bios_data_area` stored, as a real BIOS does
     fn int11_equipment_list(&mut self) -> Result<(), String> {
-        self.regs.ax = BIOS_EQUIPMENT_LIST;
+        self.regs.ax = self.memory.read_word(BDA_EQUIPMENT_LIST);
         Ok(())
     }
 
@@ -270,11 +662,11 @@ impl Cpu {
         Ok(())
     }
 
-    fn int1a_time_services(&mut self) -> Result<(), String> {
+    pub(crate) fn int1a_time_services(&mut self) -> Result<(), String> {
         match self.regs.get_ah() {
             0x00 => {
                 // Read system clock counter
-                let ticks = (self.cycles / 54945) as u32; // ~18.2 Hz tick rate
+                let ticks = self.timer_ticks();
                 self.regs.set_al(0); // Midnight flag
                 self.regs.set_cx((ticks >> 16) as u16);
                 self.regs.set_dx(ticks as u16);
@@ -282,13 +674,24 @@ impl Cpu {
                 Ok(())
             }
             0x02 => {
-                let now = chrono::Local::now();
-                // Set hours and minutes
-                self.regs.set_ch(now.hour() as u8);
-                self.regs.set_cl(now.minute() as u8);
-                // Set month and day
-                self.regs.set_dh(now.month() as u8);
-                self.regs.set_dl(now.day() as u8);
+                // Read RTC time: CH=hours, CL=minutes, DH=seconds in BCD,
+                // DL=0 for no daylight saving
+                let now = self.clock.now();
+                self.regs.set_ch(to_bcd(now.hour()));
+                self.regs.set_cl(to_bcd(now.minute()));
+                self.regs.set_dh(to_bcd(now.second()));
+                self.regs.set_dl(0);
+                self.regs.flags.set_carry(false);
+                Ok(())
+            }
+            0x04 => {
+                // Read RTC date: CH=century, CL=year, DH=month, DL=day in BCD
+                let now = self.clock.now();
+                let year = now.year() as u32;
+                self.regs.set_ch(to_bcd(year / 100));
+                self.regs.set_cl(to_bcd(year % 100));
+                self.regs.set_dh(to_bcd(now.month()));
+                self.regs.set_dl(to_bcd(now.day()));
                 self.regs.flags.set_carry(false);
                 Ok(())
             }
@@ -322,23 +725,50 @@ impl Cpu {
 }
 
 // Add these constants for BIOS services
-const BIOS_EQUIPMENT_LIST: u16 = 0b0000_0010_0000_0011; // Base memory, serial port, no display
 const BIOS_MEMORY_SIZE: u16 = 640; // 640K conventional memory
+const BDA_EQUIPMENT_LIST: u32 = 0x0410;
+const BDA_HARD_DISK_COUNT: u32 = 0x0475;
+
+// Equipment word bits: 0 is "floppy drives present" with the count less
+// one in bits 6-7, and bits 9-11 count serial ports. The video bits (4-5)
+// stay 00, which is the closest thing to "no display" this headless build
+// can report.
+const EQUIPMENT_FLOPPY: u16 = 0x0001;
+const EQUIPMENT_FLOPPY_COUNT_SHIFT: u16 = 6;
+const EQUIPMENT_SERIAL_COUNT_SHIFT: u16 = 9;
+
+/// The INT 11h equipment word for the drive and serial ports attached to
+/// `cpu`. A hard disk doesn't appear here; it is counted at 0040:0075.
+pub fn equipment_word(cpu: &Cpu) -> u16 {
+    let floppies = (cpu.disk.has_medium() && cpu.disk.geometry.is_floppy()) as u16;
+    let mut word = (cpu.serial.port_count() as u16 & 0x07) << EQUIPMENT_SERIAL_COUNT_SHIFT;
+    if floppies > 0 {
+        word |= EQUIPMENT_FLOPPY | ((floppies - 1) << EQUIPMENT_FLOPPY_COUNT_SHIFT);
+    }
+    word
+}
+
+// The equipment word and hard disk count, which change with the drive
+fn write_drive_counts(cpu: &mut Cpu) {
+    let equipment = equipment_word(cpu);
+    cpu.memory.write_word(BDA_EQUIPMENT_LIST, equipment);
+    let hard_disks = (cpu.disk.has_medium() && !cpu.disk.geometry.is_floppy()) as u8;
+    cpu.memory.write_byte(BDA_HARD_DISK_COUNT, hard_disks);
+}
 
 pub fn init_bios_data_area(cpu: &mut Cpu) {
     // BIOS data area starts at 0x0400
 
-    // Equipment list (serial ports only, no display)
-    cpu.memory.write_word(0x0410, 0x0001);
+    write_drive_counts(cpu);
 
     // Base memory size (640KB)
     cpu.memory.write_word(0x0413, 640);
 
-    // COM port addresses
-    cpu.memory.write_word(0x0400, 0x3F8); // COM1
-    cpu.memory.write_word(0x0402, 0x2F8); // COM2
-    cpu.memory.write_word(0x0404, 0x3E8); // COM3
-    cpu.memory.write_word(0x0406, 0x2E8); // COM4
+    // COM port addresses, zero where nothing is attached
+    for index in 0..4 {
+        let base = cpu.serial.port_base(index).unwrap_or(0);
+        cpu.memory.write_word(0x0400 + 2 * index as u32, base);
+    }
 }
 
 #[allow(dead_code)]
@@ -347,9 +777,7 @@ pub fn print_debug(_cpu: &mut Cpu, _msg: &str) {
 }
 
 // Add error code constants
-#[allow(dead_code)]
 const ERR_SUCCESS: u8 = 0x00;
-#[allow(dead_code)]
 const ERR_INVALID_COMMAND: u8 = 0x01;
 #[allow(dead_code)]
 const ERR_ADDRESS_MARK: u8 = 0x02;
@@ -359,7 +787,6 @@ const ERR_WRITE_PROTECT: u8 = 0x03;
 const ERR_SECTOR_NOT_FOUND: u8 = 0x04;
 #[allow(dead_code)]
 const ERR_RESET_FAILED: u8 = 0x05;
-#[allow(dead_code)]
 const ERR_DISK_CHANGED: u8 = 0x06;
 #[allow(dead_code)]
 const ERR_DRIVE_PARAMETER: u8 = 0x07;
@@ -375,7 +802,6 @@ const ERR_BAD_TRACK: u8 = 0x0B;
 const ERR_MEDIA_TYPE: u8 = 0x0C;
 #[allow(dead_code)]
 const ERR_INVALID_SECTORS: u8 = 0x0D;
-#[allow(dead_code)]
 const ERR_INVALID_DRIVE: u8 = 0x80;
 
 #[allow(dead_code)]
@@ -417,12 +843,17 @@ fn perform_dma_transfer(
     true
 }
 
+// The RTC keeps its fields as two BCD digits
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10 % 10) << 4) | (value % 10)) as u8
+}
+
 #[allow(dead_code)]
 fn handle_time_interrupt(cpu: &mut Cpu) -> Result<(), String> {
     match cpu.regs.get_ah() {
         0x00 => {
             // Get system time
-            let now = chrono::Local::now();
+            let now = cpu.clock.now();
             cpu.regs.set_al(0); // Midnight flag
             cpu.regs.set_cx(((now.hour() << 8) | now.minute()) as u16);
             cpu.regs.set_dx((now.second() << 8) as u16);
@@ -430,14 +861,14 @@ fn handle_time_interrupt(cpu: &mut Cpu) -> Result<(), String> {
         }
         0x02 => {
             // Get real-time clock time
-            let now = chrono::Local::now();
+            let now = cpu.clock.now();
             cpu.regs.set_cx(((now.hour() << 8) | now.minute()) as u16);
             cpu.regs.set_dx((now.second() << 8) as u16);
             Ok(())
         }
         0x04 => {
             // Get real-time clock date
-            let now = chrono::Local::now();
+            let now = cpu.clock.now();
             cpu.regs.set_cx(now.year() as u16);
             cpu.regs
                 .set_dx(((now.month() as u16) << 8) | now.day() as u16);
@@ -453,11 +884,12 @@ fn handle_time_interrupt(cpu: &mut Cpu) -> Result<(), String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cpu::test_utils::setup_test_cpu;
+    use crate::cpu::test_utils::{setup_executable_cpu, setup_test_cpu};
+    use crate::cpu::StopReason;
 
     #[test]
     fn test_bios_interrupt_vectors() {
-        let mut cpu = setup_test_cpu();
+        let cpu = setup_test_cpu();
 
         // Test INT 10h vector (Video Services)
         assert_eq!(cpu.memory.read_word(0x40), video_services_offset());
@@ -468,21 +900,53 @@ mod tests {
         assert_eq!(cpu.memory.read_word(0x52), bios_seg());
     }
 
+    #[test]
+    fn test_init_preserves_user_vectors() {
+        let mut cpu = setup_test_cpu();
+        // Wipe what setup installed, then hook INT 13h ourselves
+        for int_num in [0x10u32, 0x13, 0x14, 0x16] {
+            cpu.memory.write_word(int_num * 4, 0);
+            cpu.memory.write_word(int_num * 4 + 2, 0);
+        }
+        set_interrupt_vector(&mut cpu, 0x13, 0x1234, 0x0100);
+
+        init_bios_interrupts(&mut cpu);
+        assert_eq!(cpu.memory.read_word(0x4C), 0x0100);
+        assert_eq!(cpu.memory.read_word(0x4E), 0x1234);
+        assert_eq!(cpu.memory.read_word(0x40), video_services_offset());
+        assert_eq!(cpu.memory.read_word(0x42), bios_seg());
+    }
+
+    #[test]
+    fn test_init_selective_installs_only_masked_services() {
+        let mut cpu = setup_test_cpu();
+        for int_num in [0x10u32, 0x13, 0x14, 0x16] {
+            cpu.memory.write_word(int_num * 4, 0);
+            cpu.memory.write_word(int_num * 4 + 2, 0);
+        }
+
+        init_bios_interrupts_selective(&mut cpu, BIOS_DISK | BIOS_SERIAL);
+        assert_eq!(cpu.memory.read_word(0x4C), disk_services_offset());
+        assert_eq!(cpu.memory.read_word(0x50), serial_services_offset());
+        assert_eq!(cpu.memory.read_word(0x40), 0);
+        assert_eq!(cpu.memory.read_word(0x58), 0);
+    }
+
     #[test]
     fn test_bios_data_area() {
         let cpu = setup_test_cpu();
 
-        // Test equipment list
-        assert_eq!(cpu.memory.read_word(0x410), 0x0001);
+        // Test equipment list: one serial port, no floppies
+        assert_eq!(cpu.memory.read_word(0x410), 0x0200);
 
         // Test base memory size
         assert_eq!(cpu.memory.read_word(0x413), 640);
 
-        // Test COM port addresses
+        // Test COM port addresses; only COM1 is attached
         assert_eq!(cpu.memory.read_word(0x400), 0x3F8); // COM1
-        assert_eq!(cpu.memory.read_word(0x402), 0x2F8); // COM2
-        assert_eq!(cpu.memory.read_word(0x404), 0x3E8); // COM3
-        assert_eq!(cpu.memory.read_word(0x406), 0x2E8); // COM4
+        assert_eq!(cpu.memory.read_word(0x402), 0); // COM2
+        assert_eq!(cpu.memory.read_word(0x404), 0); // COM3
+        assert_eq!(cpu.memory.read_word(0x406), 0); // COM4
     }
 
     #[test]
@@ -500,6 +964,7 @@ mod tests {
         cpu.regs.set_al(b'A'); // Character to send
         assert!(handle_serial_interrupt(&mut cpu).is_ok());
         assert_eq!(cpu.regs.get_ah(), 0); // Success
+        assert_eq!(cpu.serial.get_output(), Some(b'A'));
     }
 
     #[test]
@@ -539,11 +1004,87 @@ mod tests {
         assert!(!cpu.regs.flags.get_carry());
     }
 
+    #[test]
+    fn test_int14_selects_port_from_dx() {
+        let mut cpu = setup_test_cpu();
+        cpu.serial.attach_port(1).unwrap();
+
+        // Initialize COM2 and send through it
+        cpu.regs.set_dx(1);
+        cpu.regs.set_ah(0x00);
+        cpu.regs.set_al(0xE3); // 9600 8N1
+        handle_serial_interrupt(&mut cpu).unwrap();
+        assert_eq!(cpu.regs.get_ah(), 0);
+        assert!(cpu.serial.get_port(1).unwrap().initialized);
+
+        cpu.regs.set_ah(0x01);
+        cpu.regs.set_al(b'A');
+        handle_serial_interrupt(&mut cpu).unwrap();
+        assert_eq!(
+            cpu.serial.get_port(1).unwrap().tx_fifo.pop_front(),
+            Some(b'A')
+        );
+        // Nothing went out on COM1
+        assert!(cpu.serial.output().is_empty());
+
+        // COM2 has nothing to read; COM3 isn't there at all
+        cpu.regs.set_ah(0x02);
+        handle_serial_interrupt(&mut cpu).unwrap();
+        assert_eq!(cpu.regs.get_ah(), 0x80);
+        cpu.regs.set_dx(2);
+        cpu.regs.set_ah(0x03);
+        handle_serial_interrupt(&mut cpu).unwrap();
+        assert_eq!(cpu.regs.get_ah(), 0x80);
+
+        // COM1 is still the host's line
+        cpu.regs.set_dx(0);
+        cpu.regs.set_ah(0x01);
+        cpu.regs.set_al(b'B');
+        handle_serial_interrupt(&mut cpu).unwrap();
+        assert_eq!(cpu.serial.get_output(), Some(b'B'));
+        cpu.serial.add_input(b'c');
+        cpu.regs.set_ah(0x02);
+        handle_serial_interrupt(&mut cpu).unwrap();
+        assert_eq!((cpu.regs.get_ah(), cpu.regs.get_al()), (0, b'c'));
+    }
+
     #[test]
     fn test_equipment_list() {
+        // The generated C: image is a hard disk; only COM1 is attached
         let mut cpu = setup_test_cpu();
         assert!(cpu.int11_equipment_list().is_ok());
-        assert_eq!(cpu.regs.ax, BIOS_EQUIPMENT_LIST);
+        assert_eq!(cpu.regs.ax, 1 << 9);
+        assert_eq!(cpu.memory.read_byte(BDA_HARD_DISK_COUNT), 1);
+        assert_eq!(cpu.memory.read_word(0x0402), 0);
+    }
+
+    #[test]
+    fn test_equipment_word_counts_serial_ports_and_floppies() {
+        use crate::disk::disk_image::{DiskGeometry, DiskImage};
+
+        let mut cpu = setup_test_cpu();
+        cpu.serial.attach_port(1).unwrap();
+        init_bios_data_area(&mut cpu);
+        assert!(cpu.int11_equipment_list().is_ok());
+        assert_eq!((cpu.regs.ax >> 9) & 0x07, 2);
+        assert_eq!(cpu.regs.ax & 0x0001, 0);
+        assert_eq!(cpu.memory.read_word(0x0400), 0x3F8);
+        assert_eq!(cpu.memory.read_word(0x0402), 0x2F8);
+        assert_eq!(cpu.memory.read_word(0x0404), 0);
+        assert!(cpu.serial.attach_port(4).is_err());
+
+        // A 1.44M floppy in the drive sets bit 0 with a count of one
+        cpu.disk = DiskImage::empty();
+        cpu.disk.raw = Some(vec![0; 1_474_560]);
+        cpu.disk.geometry = DiskGeometry::from_image_size(1_474_560);
+        init_bios_data_area(&mut cpu);
+        assert!(cpu.int11_equipment_list().is_ok());
+        assert_eq!(cpu.regs.ax & 0x00C1, 0x0001);
+        assert_eq!(cpu.memory.read_byte(BDA_HARD_DISK_COUNT), 0);
+
+        // And an empty drive is nothing at all
+        cpu.disk = DiskImage::empty();
+        assert_eq!(equipment_word(&cpu) & 0x0001, 0);
     }
 
     #[test]
@@ -552,4 +1093,395 @@ mod tests {
         assert!(cpu.int12_memory_size().is_ok());
         assert_eq!(cpu.regs.ax, BIOS_MEMORY_SIZE);
     }
+
+    #[test]
+    fn test_int13_read_uses_drive_geometry() {
+        use crate::disk::disk_image::{DiskGeometry, DiskImage};
+
+        // A 1.44M floppy (80/2/18) with each sector tagged with its LBA
+        let mut data = vec![0u8; 1_474_560];
+        for (lba, sector) in data.chunks_mut(512).enumerate() {
+            sector[..2].copy_from_slice(&(lba as u16).to_le_bytes());
+        }
+        let mut cpu = setup_test_cpu();
+        cpu.disk = DiskImage::empty();
+        cpu.disk.raw = Some(data);
+        cpu.disk.geometry = DiskGeometry::from_image_size(1_474_560);
+
+        // C=1, H=1, S=1 -> (1 * 2 + 1) * 18 + 0 = LBA 54
+        cpu.regs.ax = 0x0201;
+        cpu.regs.cx = 0x0101;
+        cpu.regs.dx = 0x0100;
+        cpu.regs.es = 0x2000;
+        cpu.regs.bx = 0x0000;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.memory.read_word(0x20000), 54);
+
+        // Sector 19 doesn't exist on an 18-sector track
+        cpu.regs.ax = 0x0201;
+        cpu.regs.cx = 0x0013;
+        cpu.regs.dx = 0x0000;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.get_ah(), 0x04);
+
+        // Nor does head 2
+        cpu.regs.ax = 0x0201;
+        cpu.regs.cx = 0x0001;
+        cpu.regs.dx = 0x0200;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert_eq!(cpu.regs.get_ah(), 0x04);
+    }
+
+    #[test]
+    fn test_int13_extended_read_write() {
+        use crate::disk::disk_image::{DiskGeometry, DiskImage};
+
+        let mut data = vec![0u8; 1024 * 512];
+        for (lba, sector) in data.chunks_mut(512).enumerate() {
+            sector.fill(lba as u8);
+            sector[..2].copy_from_slice(&(lba as u16).to_le_bytes());
+        }
+        let mut cpu = setup_test_cpu();
+        cpu.disk = DiskImage::empty();
+        cpu.disk.raw = Some(data);
+        cpu.disk.geometry = DiskGeometry::hard_disk(1024);
+
+        // Installation check
+        cpu.regs.ax = 0x4100;
+        cpu.regs.bx = 0x55AA;
+        cpu.regs.dx = 0x0080;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.bx, 0xAA55);
+        assert_eq!(cpu.regs.cx & 0x0001, 0x0001);
+
+        // DAP at 1000:0010 reading 2 sectors from LBA 100 into 2000:0100
+        let dap: [u8; 16] = [
+            0x10, 0x00, 0x02, 0x00, 0x00, 0x01, 0x00, 0x20, 100, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        for (i, &byte) in dap.iter().enumerate() {
+            cpu.poke_byte(0x1000, 0x0010 + i as u16, byte);
+        }
+        cpu.regs.ds = 0x1000;
+        cpu.regs.si = 0x0010;
+        cpu.regs.ax = 0x4200;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.get_ah(), 0x00);
+        assert_eq!(cpu.peek_word(0x1000, 0x0012), 2);
+        for (i, lba) in [100u32, 101].into_iter().enumerate() {
+            let expected = cpu.disk.read_sector(lba).unwrap();
+            let base = 0x20100 + i as u32 * 512;
+            let actual: Vec<u8> = (0..512).map(|j| cpu.memory.read_byte(base + j)).collect();
+            assert_eq!(actual, expected, "LBA {}", lba);
+        }
+
+        // Write the first sector read back out to LBA 7 with AH=43h
+        cpu.poke_word(0x1000, 0x0012, 1);
+        cpu.poke_byte(0x1000, 0x0018, 7);
+        cpu.regs.ax = 0x4300;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.disk.read_sector(7), cpu.disk.read_sector(100));
+
+        // A packet shorter than 16 bytes is rejected
+        cpu.poke_byte(0x1000, 0x0010, 0x08);
+        cpu.regs.ax = 0x4200;
+        assert!(handle_disk_interrupt(&mut cpu).is_ok());
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.get_ah(), 0x01);
+    }
+
+    #[test]
+    fn test_int29_fast_console_output() {
+        // MOV AL,'X'; INT 29h; HLT
+        let mut cpu = setup_executable_cpu(&[0xB0, b'X', 0xCD, 0x29, 0xF4]);
+        assert_eq!(cpu.memory.read_word(0x29 * 4), fast_console_offset());
+        assert_eq!(cpu.run_for(10), StopReason::Halted);
+        assert_eq!(cpu.serial.get_output(), Some(b'X'));
+        assert_eq!(cpu.serial.get_output(), None);
+        // Returned to the HLT after the INT
+        assert_eq!(cpu.regs.cs, 0);
+        assert_eq!(cpu.regs.ip, 0x105);
+    }
+
+    // setup_executable_cpu, but with nothing in the drive
+    fn setup_diskless_cpu() -> Cpu {
+        let mut cpu = setup_executable_cpu(&[]);
+        cpu.disk = crate::disk::DiskImage::empty();
+        cpu.has_valid_mbr = false;
+        cpu.has_valid_boot_sector = false;
+        cpu
+    }
+
+    #[test]
+    fn test_boot_without_active_partition_reports_no_boot_device() {
+        let mut cpu = setup_diskless_cpu();
+        assert_eq!(cpu.memory.read_word(0x18 * 4), boot_failure_offset());
+
+        assert!(boot_from_disk(&mut cpu).is_ok());
+        assert_eq!(cpu.run_for(100), StopReason::NoBootDevice);
+        let output: Vec<u8> = cpu.serial.output().iter().copied().collect();
+        assert_eq!(output, NO_BOOT_DEVICE_MESSAGE);
+        assert_eq!(
+            (cpu.regs.cs, cpu.regs.ip),
+            (bios_seg(), boot_failure_offset())
+        );
+    }
+
+    #[test]
+    fn test_boot_loads_mbr_with_active_partition() {
+        let mut cpu = setup_executable_cpu(&[]);
+        assert!(boot_from_disk(&mut cpu).is_ok());
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x7C00));
+        assert_eq!(cpu.regs.get_dl(), 0x80);
+        let mbr = cpu.disk.read_sector(0).unwrap();
+        assert_eq!(cpu.memory.read_byte(0x7C00), mbr[0]);
+        assert_eq!(cpu.memory.read_word(0x7DFE), 0xAA55);
+        assert!(cpu.serial.output().is_empty());
+    }
+
+    // A blank 1.44M floppy with a boot signature and a JMP at offset 0
+    fn bootable_floppy(name: &str) -> DiskImage {
+        let mut image = vec![0u8; 1_474_560];
+        image[0] = 0xEB;
+        image[510] = 0x55;
+        image[511] = 0xAA;
+        let path = std::env::temp_dir().join(format!(
+            "dos_emu_floppy_{}_{}.img",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, &image).unwrap();
+        let disk = DiskImage::from_floppy_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        disk
+    }
+
+    #[test]
+    fn test_floppy_boot_loads_sector_0() {
+        let mut cpu = setup_diskless_cpu();
+        cpu.disk = bootable_floppy("boot");
+        assert!(boot(&mut cpu, BootMode::Floppy).is_ok());
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x7C00));
+        assert_eq!(cpu.regs.get_dl(), 0x00);
+        assert_eq!(cpu.memory.read_byte(0x7C00), 0xEB);
+        assert_eq!(cpu.memory.read_word(0x7DFE), 0xAA55);
+
+        // Without the signature there is nothing to boot
+        let mut cpu = setup_diskless_cpu();
+        assert!(boot(&mut cpu, BootMode::Floppy).is_ok());
+        assert_eq!(cpu.run_for(100), StopReason::NoBootDevice);
+    }
+
+    #[test]
+    fn test_get_disk_type() {
+        let get_type = |cpu: &mut Cpu, drive: u8| {
+            cpu.regs.set_ah(0x15);
+            cpu.regs.set_dl(drive);
+            cpu.regs.flags.set_carry(true);
+            handle_disk_interrupt(cpu).unwrap();
+            assert!(!cpu.regs.flags.get_carry());
+            (cpu.regs.get_ah(), cpu.regs.get_cx(), cpu.regs.get_dx())
+        };
+
+        // A 10 MB hard disk: 20480 sectors
+        let mut cpu = setup_diskless_cpu();
+        let image = vec![0u8; 10 * 1024 * 1024];
+        let path = std::env::temp_dir().join(format!("dos_emu_type_{}.img", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+        cpu.disk = DiskImage::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(get_type(&mut cpu, 0x80), (0x03, 0x0000, 0x5000));
+        assert_eq!(get_type(&mut cpu, 0x00).0, 0x00);
+
+        // The generated C: drive counts its whole 2 GB
+        let mut cpu = setup_test_cpu();
+        assert_eq!(get_type(&mut cpu, 0x80), (0x03, 0x0040, 0x0000));
+
+        let mut cpu = setup_diskless_cpu();
+        cpu.disk = bootable_floppy("type");
+        assert_eq!(get_type(&mut cpu, 0x00).0, 0x02); // Change line, so AH=16h is worth polling
+        assert_eq!(get_type(&mut cpu, 0x80).0, 0x00);
+        assert_eq!(get_type(&mut cpu, 0x81).0, 0x00);
+    }
+
+    #[test]
+    fn test_swapping_the_floppy_raises_disk_change_once() {
+        let mut cpu = setup_diskless_cpu();
+        cpu.disk = bootable_floppy("swap");
+        init_bios_data_area(&mut cpu);
+        let detect_change = |cpu: &mut Cpu| {
+            cpu.regs.set_ah(0x16);
+            cpu.regs.set_dl(0x00);
+            handle_disk_interrupt(cpu).unwrap();
+            (cpu.regs.get_ah(), cpu.regs.flags.get_carry())
+        };
+        assert_eq!(detect_change(&mut cpu), (0x00, false));
+
+        cpu.set_drive(0x00, None).unwrap();
+        assert!(!cpu.has_valid_boot_sector());
+        assert_eq!(
+            cpu.memory.read_word(BDA_EQUIPMENT_LIST) & EQUIPMENT_FLOPPY,
+            0
+        );
+
+        cpu.set_drive(0x00, Some(bootable_floppy("swap"))).unwrap();
+        assert!(cpu.has_valid_boot_sector());
+        assert_ne!(
+            cpu.memory.read_word(BDA_EQUIPMENT_LIST) & EQUIPMENT_FLOPPY,
+            0
+        );
+        assert_eq!(detect_change(&mut cpu), (ERR_DISK_CHANGED, true));
+        assert_eq!(detect_change(&mut cpu), (0x00, false));
+
+        // The hard disk has no change line, and a floppy won't go there
+        cpu.regs.set_dl(0x80);
+        cpu.regs.set_ah(0x16);
+        handle_disk_interrupt(&mut cpu).unwrap();
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.set_drive(0x80, Some(bootable_floppy("swap"))).is_err());
+        assert!(cpu.set_drive(0x01, None).is_err());
+    }
+
+    #[test]
+    fn test_boot_failure_uses_hooked_int18() {
+        // A "ROM BASIC" hooked at 2000:0000 before the BIOS is initialized
+        let mut cpu = setup_diskless_cpu();
+        set_interrupt_vector(&mut cpu, 0x18, 0x2000, 0x0000);
+        init_bios_interrupts(&mut cpu);
+
+        assert!(boot_from_disk(&mut cpu).is_ok());
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x2000, 0x0000));
+        assert!(!cpu.is_halted());
+        assert!(cpu.serial.output().is_empty());
+    }
+
+    #[test]
+    fn test_custom_interrupt_handler() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut cpu = setup_executable_cpu(&[0xCD, 0x80, 0x90]); // INT 80h; NOP
+        let calls = Rc::new(Cell::new(0));
+        let seen = Rc::clone(&calls);
+        cpu.set_interrupt_handler(
+            0x80,
+            Box::new(move |cpu: &mut Cpu| {
+                seen.set(seen.get() + 1);
+                cpu.regs.ax = 0x1234;
+                Ok(())
+            }),
+        );
+
+        // From host code
+        cpu.int_n(0x80).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(cpu.regs.ax, 0x1234);
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (bios_seg(), iret_offset()));
+        cpu.execute_instruction().unwrap(); // IRET
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0100));
+
+        // And from the guest, returning to the instruction after INT 80h
+        cpu.regs.ax = 0;
+        cpu.execute_instruction().unwrap();
+        cpu.execute_instruction().unwrap();
+        assert_eq!(calls.get(), 2);
+        assert_eq!(cpu.regs.ax, 0x1234);
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0102));
+    }
+
+    #[test]
+    fn test_override_builtin_interrupt_handler() {
+        let mut cpu = setup_executable_cpu(&[]);
+        cpu.set_interrupt_handler(
+            0x1A,
+            Box::new(|cpu: &mut Cpu| {
+                cpu.regs.dx = 0xBEEF;
+                Ok(())
+            }),
+        );
+        cpu.regs.set_ah(0x00);
+        handle_bios_interrupt(&mut cpu, 0x1A).unwrap();
+        assert_eq!(cpu.regs.dx, 0xBEEF);
+
+        assert!(cpu.interrupt_handlers.unregister(0x1A).is_some());
+        assert!(handle_bios_interrupt(&mut cpu, 0x1A).is_err());
+    }
+
+    #[test]
+    fn test_native_services_return_flags_through_iret() {
+        // MOV AX,0E41h; INT 10h; INT 80h; INT 80h
+        let mut cpu = setup_executable_cpu(&[0xB8, 0x41, 0x0E, 0xCD, 0x10, 0xCD, 0x80, 0xCD, 0x80]);
+        cpu.set_interrupt_handler(
+            0x80,
+            Box::new(|cpu: &mut Cpu| {
+                let carry = cpu.regs.flags.get_carry();
+                cpu.regs.flags.set_carry(!carry);
+                Ok(())
+            }),
+        );
+
+        for _ in 0..3 {
+            cpu.execute_instruction().unwrap(); // MOV; INT 10h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0105));
+        assert_eq!(cpu.serial.get_output(), Some(b'A'));
+        assert_eq!(cpu.serial.get_output(), None);
+
+        // What the handler leaves in CF is what the caller sees after IRET
+        cpu.execute_instruction().unwrap();
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0107));
+        assert!(cpu.regs.flags.get_carry());
+        cpu.execute_instruction().unwrap();
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0109));
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn test_guest_int13_returns_status_and_carry() {
+        let mut cpu = setup_executable_cpu(&[
+            0xF9, // STC
+            0xB8, 0x00, 0x15, // MOV AX,1500h
+            0xB2, 0x80, // MOV DL,80h
+            0xCD, 0x13, // INT 13h
+            0xB4, 0x16, // MOV AH,16h
+            0xB2, 0x80, // MOV DL,80h
+            0xCD, 0x13, // INT 13h
+        ]);
+
+        // Get disk type clears the CF set before the call
+        for _ in 0..5 {
+            cpu.execute_instruction().unwrap(); // STC; MOV; MOV; INT 13h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0108));
+        assert_eq!(cpu.regs.get_ah(), DISK_TYPE_FIXED);
+        assert!(!cpu.regs.flags.get_carry());
+
+        // A hard disk has no change line to read
+        for _ in 0..4 {
+            cpu.execute_instruction().unwrap(); // MOV; MOV; INT 13h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x010E));
+        assert_eq!(cpu.regs.get_ah(), ERR_INVALID_COMMAND);
+        assert!(cpu.regs.flags.get_carry());
+    }
+
+    #[test]
+    fn test_guest_int14_sends_once_and_keeps_al() {
+        // MOV AX,0158h; INT 14h; NOP
+        let mut cpu = setup_executable_cpu(&[0xB8, 0x58, 0x01, 0xCD, 0x14, 0x90]);
+        cpu.regs.dx = 0; // COM1
+        for _ in 0..3 {
+            cpu.execute_instruction().unwrap(); // MOV; INT 14h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0105));
+        assert_eq!(cpu.serial.output(), b"X");
+        assert_eq!((cpu.regs.get_ah(), cpu.regs.get_al()), (0x00, b'X'));
+    }
 }
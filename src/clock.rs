@@ -0,0 +1,93 @@
+// Where the BIOS and DOS date/time services get the current time from. The
+// host's local time by default; tests and embedders can put a fixed time
+// behind the CPU with `Cpu::set_clock` so guest-visible dates are repeatable.
+
+use chrono::{Duration, NaiveDateTime};
+use std::cell::Cell;
+use std::rc::Rc;
+
+pub trait Clock {
+    /// The current local date and time as the guest should see it
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// The host's local time
+pub struct HostClock;
+
+impl Clock for HostClock {
+    fn now(&self) -> NaiveDateTime {
+        chrono::Local::now().naive_local()
+    }
+}
+
+/// Always reports the same instant
+pub struct FixedClock(pub NaiveDateTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> NaiveDateTime {
+        self.0
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so
+/// one can go behind the CPU while the caller keeps the other to `set` or
+/// `advance` it, e.g. to step a guest across midnight.
+#[derive(Clone)]
+pub struct ManualClock(Rc<Cell<NaiveDateTime>>);
+
+impl ManualClock {
+    pub fn new(instant: NaiveDateTime) -> Self {
+        ManualClock(Rc::new(Cell::new(instant)))
+    }
+
+    pub fn set(&self, instant: NaiveDateTime) {
+        self.0.set(instant);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.0.set(self.0.get() + by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_fixed_clock() {
+        let instant = NaiveDate::from_ymd_opt(1999, 12, 31)
+            .unwrap()
+            .and_hms_opt(23, 59, 59)
+            .unwrap();
+        let clock = FixedClock(instant);
+        assert_eq!(clock.now(), instant);
+        assert_eq!(clock.now(), instant);
+    }
+
+    #[test]
+    fn test_manual_clock_clones_share_the_time() {
+        let start = NaiveDate::from_ymd_opt(1999, 12, 31)
+            .unwrap()
+            .and_hms_opt(23, 59, 0)
+            .unwrap();
+        let clock = ManualClock::new(start);
+        let handle = clock.clone();
+        handle.advance(Duration::seconds(60));
+        assert_eq!(
+            clock.now(),
+            NaiveDate::from_ymd_opt(2000, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+        handle.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
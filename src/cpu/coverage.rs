@@ -0,0 +1,47 @@
+use crate::prelude::*;
+use alloc::collections::BTreeSet;
+
+/// Per-opcode execution counts, used to see which instructions a run
+/// exercised and which unimplemented ones it tried to execute.
+pub struct OpcodeCoverage {
+    pub primary: [u64; 256],
+    pub extended: [u64; 256], // 0x0F two-byte opcodes, indexed by the second byte
+    // Unimplemented opcodes that were attempted; 0x0Fxx for two-byte opcodes
+    unimplemented: BTreeSet<u16>,
+}
+
+impl Default for OpcodeCoverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpcodeCoverage {
+    pub fn new() -> Self {
+        OpcodeCoverage {
+            primary: [0; 256],
+            extended: [0; 256],
+            unimplemented: BTreeSet::new(),
+        }
+    }
+
+    pub fn record_primary(&mut self, opcode: u8) {
+        self.primary[opcode as usize] += 1;
+    }
+
+    pub fn record_extended(&mut self, opcode: u8) {
+        self.extended[opcode as usize] += 1;
+    }
+
+    pub fn record_unimplemented(&mut self, opcode: u16) {
+        self.unimplemented.insert(opcode);
+    }
+
+    pub fn unimplemented(&self) -> Vec<u16> {
+        self.unimplemented.iter().copied().collect()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
@@ -0,0 +1,307 @@
+// Instruction decoding shared by execution and the disassembler: which
+// prefixes an instruction has, whether a ModR/M byte follows the opcode,
+// and how big its displacement and immediates are. Keeping that in one
+// table stops the two from drifting apart on instruction lengths.
+
+use super::{CpuGeneric, RepPrefix, SegmentRegister};
+use crate::memory::Memory;
+
+/// More prefixes than this can't be a real instruction; the next prefix
+/// byte is taken as the opcode so a run of them decodes in bounded steps
+pub const MAX_PREFIXES: u8 = 14;
+
+/// The longest instruction `decode_with` can produce: a full run of
+/// prefixes, both opcode bytes, ModR/M, a word displacement and two word
+/// immediates
+pub const MAX_LENGTH: usize = MAX_PREFIXES as usize + 9;
+
+/// A displacement or immediate as it was encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Byte(u8),
+    Word(u16),
+}
+
+impl Value {
+    /// The value zero-extended to 16 bits
+    pub fn unsigned(self) -> u16 {
+        match self {
+            Value::Byte(byte) => byte as u16,
+            Value::Word(word) => word,
+        }
+    }
+
+    /// The value sign-extended to 16 bits, as disp8 and rel8 are
+    pub fn signed(self) -> u16 {
+        match self {
+            Value::Byte(byte) => byte as i8 as i16 as u16,
+            Value::Word(word) => word,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Prefixes {
+    pub segment: Option<SegmentRegister>,
+    pub rep: Option<RepPrefix>,
+    pub lock: bool,
+    /// Prefix bytes ahead of the opcode, repeats included
+    pub count: u8,
+}
+
+/// The three fields of a ModR/M byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModRm {
+    pub mode: u8,
+    pub reg: u8,
+    pub rm: u8,
+}
+
+impl ModRm {
+    pub fn from_byte(byte: u8) -> Self {
+        ModRm {
+            mode: byte >> 6,
+            reg: (byte >> 3) & 0x07,
+            rm: byte & 0x07,
+        }
+    }
+
+    pub fn is_memory(&self) -> bool {
+        self.mode != 3
+    }
+
+    // mod=00 rm=110 is a bare disp16 address rather than [BP]
+    fn displacement_size(&self) -> Option<Size> {
+        match (self.mode, self.rm) {
+            (0, 6) | (2, _) => Some(Size::Word),
+            (1, _) => Some(Size::Byte),
+            _ => None,
+        }
+    }
+}
+
+/// One decoded instruction. `displacement` is the ModR/M displacement, or
+/// the address of a MOV AL/AX moffs form. `immediate` holds the first
+/// immediate, or the offset of a far pointer; `immediate2` the segment of a
+/// far pointer or ENTER's nesting level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub prefixes: Prefixes,
+    pub opcode: u8,
+    /// The byte after a 0x0F escape
+    pub secondary_opcode: Option<u8>,
+    pub modrm: Option<ModRm>,
+    pub displacement: Option<Value>,
+    pub immediate: Option<Value>,
+    pub immediate2: Option<Value>,
+    /// Prefixes, opcode and operands in bytes
+    pub length: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Byte,
+    Word,
+}
+
+/// What a prefix byte does, or None if `byte` isn't one
+pub fn prefix(byte: u8) -> Option<fn(&mut Prefixes)> {
+    let apply: fn(&mut Prefixes) = match byte {
+        0x26 => |p| p.segment = Some(SegmentRegister::ES),
+        0x2E => |p| p.segment = Some(SegmentRegister::CS),
+        0x36 => |p| p.segment = Some(SegmentRegister::SS),
+        0x3E => |p| p.segment = Some(SegmentRegister::DS),
+        0xF0 => |p| p.lock = true,
+        0xF2 => |p| p.rep = Some(RepPrefix::Repne),
+        0xF3 => |p| p.rep = Some(RepPrefix::Rep),
+        _ => return None,
+    };
+    Some(apply)
+}
+
+// Whether an opcode takes a ModR/M byte, and the sizes of the immediates
+// after it. `reg` is only known once the ModR/M byte has been read, which
+// matters for F6/F7 where only TEST has an immediate.
+fn operands(opcode: u8, secondary: Option<u8>, reg: Option<u8>) -> (bool, [Option<Size>; 2]) {
+    use Size::{Byte, Word};
+    let none = [None, None];
+    match opcode {
+        0x00..=0x3F if opcode & 0x07 < 4 => (true, none),
+        0x00..=0x3F if opcode & 0x07 == 4 => (false, [Some(Byte), None]),
+        0x00..=0x3F if opcode & 0x07 == 5 => (false, [Some(Word), None]),
+        0x0F => (matches!(secondary, Some(0x40..=0x4F | 0xAF)), none),
+        0x62 | 0x84..=0x8F | 0xC4 | 0xC5 | 0xD0..=0xD3 | 0xD8..=0xDF | 0xFE | 0xFF => (true, none),
+        0x68 | 0xA9 | 0xB8..=0xBF | 0xC2 | 0xCA | 0xE8 | 0xE9 => (false, [Some(Word), None]),
+        0x69 | 0x81 | 0xC7 => (true, [Some(Word), None]),
+        0x6A | 0x70..=0x7F | 0xA8 | 0xB0..=0xB7 | 0xCD | 0xD4 | 0xD5 | 0xE0..=0xE7 | 0xEB => {
+            (false, [Some(Byte), None])
+        }
+        0x6B | 0x80 | 0x82 | 0x83 | 0xC0 | 0xC1 | 0xC6 => (true, [Some(Byte), None]),
+        0x9A | 0xEA => (false, [Some(Word), Some(Word)]),
+        0xC8 => (false, [Some(Word), Some(Byte)]),
+        0xF6 if reg.is_some_and(|reg| reg < 2) => (true, [Some(Byte), None]),
+        0xF7 if reg.is_some_and(|reg| reg < 2) => (true, [Some(Word), None]),
+        0xF6 | 0xF7 => (true, none),
+        _ => (false, none),
+    }
+}
+
+/// Decodes an instruction from `read`, which returns the byte at a given
+/// offset in the code segment
+pub fn decode_with(mut read: impl FnMut(u16) -> u8, ip: u16) -> Instruction {
+    let mut offset = ip;
+    let mut next = |size: Size| -> Value {
+        let low = read(offset);
+        offset = offset.wrapping_add(1);
+        match size {
+            Size::Byte => Value::Byte(low),
+            Size::Word => {
+                let high = read(offset);
+                offset = offset.wrapping_add(1);
+                Value::Word(u16::from_le_bytes([low, high]))
+            }
+        }
+    };
+
+    let mut prefixes = Prefixes::default();
+    let opcode = loop {
+        let byte = next(Size::Byte).unsigned() as u8;
+        match prefix(byte) {
+            Some(apply) if prefixes.count < MAX_PREFIXES => {
+                apply(&mut prefixes);
+                prefixes.count += 1;
+            }
+            _ => break byte,
+        }
+    };
+
+    let secondary_opcode = (opcode == 0x0F).then(|| next(Size::Byte).unsigned() as u8);
+    let (has_modrm, _) = operands(opcode, secondary_opcode, None);
+    let modrm = has_modrm.then(|| ModRm::from_byte(next(Size::Byte).unsigned() as u8));
+    let displacement = match (modrm, opcode) {
+        (Some(modrm), _) => modrm.displacement_size().map(&mut next),
+        (None, 0xA0..=0xA3) => Some(next(Size::Word)),
+        _ => None,
+    };
+    let (_, sizes) = operands(opcode, secondary_opcode, modrm.map(|m| m.reg));
+    let immediate = sizes[0].map(&mut next);
+    let immediate2 = sizes[1].map(&mut next);
+
+    Instruction {
+        prefixes,
+        opcode,
+        secondary_opcode,
+        modrm,
+        displacement,
+        immediate,
+        immediate2,
+        length: offset.wrapping_sub(ip),
+    }
+}
+
+/// Decodes the instruction at `cs:ip` straight from `memory`, with
+/// addresses wrapping at 1MB as on an 8086
+pub fn decode(memory: &dyn Memory, cs: u16, ip: u16) -> Instruction {
+    decode_with(
+        |offset| memory.read_byte((((cs as u32) << 4) + offset as u32) & 0xF_FFFF),
+        ip,
+    )
+}
+
+impl<M: Memory> CpuGeneric<M> {
+    /// Decodes the instruction at `cs:ip` as the CPU would see it, A20 and
+    /// any prefetched bytes included, without executing it
+    pub fn decode(&self, cs: u16, ip: u16) -> Instruction {
+        decode_with(|offset| self.peek_code_byte(cs, offset), ip)
+    }
+}
+
+// The bytes of the instruction being executed, as they were fetched to
+// decode it. The handler's operand fetches are served from here, so it runs
+// on exactly what was decoded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FetchedBytes {
+    cs: u16,
+    ip: u16,
+    bytes: [u8; MAX_LENGTH],
+    len: usize,
+}
+
+impl FetchedBytes {
+    pub(crate) fn start(&mut self, cs: u16, ip: u16) {
+        self.cs = cs;
+        self.ip = ip;
+        self.len = 0;
+    }
+
+    pub(crate) fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.bytes.get_mut(self.len) {
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// The fetched copy of the byte at `cs:ip`, if it is part of the
+    /// instruction
+    pub(crate) fn get(&self, cs: u16, ip: u16) -> Option<u8> {
+        let index = ip.wrapping_sub(self.ip) as usize;
+        (cs == self.cs && index < self.len).then(|| self.bytes[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+
+    #[test]
+    fn test_decode_mov_bx_si_disp8_ax() {
+        // MOV [BX+SI+4],AX
+        let cpu = setup_executable_cpu(&[0x89, 0x40, 0x04]);
+        let insn = decode(cpu.memory.as_ref(), 0, 0x100);
+        assert_eq!(insn.opcode, 0x89);
+        assert_eq!(insn.prefixes, Prefixes::default());
+        assert_eq!(
+            insn.modrm,
+            Some(ModRm {
+                mode: 1,
+                reg: 0, // AX
+                rm: 0,  // BX+SI
+            })
+        );
+        assert_eq!(insn.displacement, Some(Value::Byte(4)));
+        assert_eq!(insn.immediate, None);
+        assert_eq!(insn.length, 3);
+        assert_eq!(cpu.decode(0, 0x100), insn);
+    }
+
+    #[test]
+    fn test_decode_prefixes_and_immediates() {
+        // ES: ADD WORD PTR [BX+1234],5678 / ENTER 0010,02 / TEST BYTE PTR [SI],7F
+        let code = [
+            0x26, 0x81, 0x87, 0x34, 0x12, 0x78, 0x56, 0xC8, 0x10, 0x00, 0x02, 0xF6, 0x04, 0x7F,
+        ];
+        let cpu = setup_executable_cpu(&code);
+        let insn = cpu.decode(0, 0x100);
+        assert_eq!(insn.prefixes.segment, Some(SegmentRegister::ES));
+        assert_eq!(insn.prefixes.count, 1);
+        assert_eq!(insn.displacement, Some(Value::Word(0x1234)));
+        assert_eq!(insn.immediate, Some(Value::Word(0x5678)));
+        assert_eq!(insn.length, 7);
+
+        let insn = cpu.decode(0, 0x107);
+        assert_eq!(insn.immediate, Some(Value::Word(0x0010)));
+        assert_eq!(insn.immediate2, Some(Value::Byte(0x02)));
+        assert_eq!(insn.length, 4);
+
+        // Only TEST in group 3 has an immediate
+        let insn = cpu.decode(0, 0x10B);
+        assert_eq!(insn.immediate, Some(Value::Byte(0x7F)));
+        assert_eq!(insn.length, 3);
+        assert_eq!(Value::Byte(0xFE).signed(), 0xFFFE);
+    }
+}
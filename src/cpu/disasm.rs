@@ -0,0 +1,519 @@
+// A side-effect free disassembler for the 8086 opcode map plus the 80186
+// additions, for monitors, tracing and external tooling. Output follows
+// DEBUG's conventions: upper case, bare hex, no spaces after commas, and
+// jump targets printed as absolute offsets.
+
+use super::decode::{self, Instruction, Value};
+use super::{CpuGeneric, RepPrefix, SegmentRegister};
+use crate::memory::Memory;
+use crate::prelude::*;
+
+const REG8: [&str; 8] = ["AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH"];
+const REG16: [&str; 8] = ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI"];
+const SREG: [&str; 4] = ["ES", "CS", "SS", "DS"];
+const RM_BASE: [&str; 8] = ["BX+SI", "BX+DI", "BP+SI", "BP+DI", "SI", "DI", "BP", "BX"];
+const ALU: [&str; 8] = ["ADD", "OR", "ADC", "SBB", "AND", "SUB", "XOR", "CMP"];
+const SHIFT: [&str; 8] = ["ROL", "ROR", "RCL", "RCR", "SHL", "SHR", "SAL", "SAR"];
+const GROUP3: [&str; 8] = ["TEST", "TEST", "NOT", "NEG", "MUL", "IMUL", "DIV", "IDIV"];
+const JCC: [&str; 16] = [
+    "JO", "JNO", "JB", "JNB", "JZ", "JNZ", "JBE", "JA", "JS", "JNS", "JPE", "JPO", "JL", "JGE",
+    "JLE", "JG",
+];
+
+struct Decoder<'a> {
+    insn: &'a Instruction,
+    // The offset just past the instruction, which relative targets count from
+    next_ip: u16,
+    segment: Option<&'static str>,
+    segment_used: bool,
+    // How many of the instruction's immediates have been formatted
+    immediates_used: usize,
+}
+
+// A decoded ModR/M byte: the reg field and the r/m operand as text
+struct ModRm {
+    reg: u8,
+    rm: String,
+    is_memory: bool,
+}
+
+impl Decoder<'_> {
+    // The instruction's immediates in encoding order; the table in decode
+    // guarantees one is there whenever an opcode's format asks for it
+    fn immediate(&mut self) -> Value {
+        let value = [self.insn.immediate, self.insn.immediate2][self.immediates_used];
+        self.immediates_used += 1;
+        value.unwrap_or(Value::Byte(0))
+    }
+
+    fn imm8(&mut self) -> String {
+        format!("{:02X}", self.immediate().unsigned())
+    }
+
+    fn imm16(&mut self) -> String {
+        format!("{:04X}", self.immediate().unsigned())
+    }
+
+    // A rel8/rel16 target relative to the end of the instruction
+    fn rel(&mut self) -> String {
+        let rel = self.immediate().signed();
+        format!("{:04X}", self.next_ip.wrapping_add(rel))
+    }
+
+    fn far_pointer(&mut self) -> String {
+        let offset = self.immediate().unsigned();
+        let segment = self.immediate().unsigned();
+        format!("{:04X}:{:04X}", segment, offset)
+    }
+
+    fn displacement(&self) -> Value {
+        self.insn.displacement.unwrap_or(Value::Word(0))
+    }
+
+    fn segment_prefix(&mut self) -> &'static str {
+        match self.segment {
+            Some(segment) => {
+                self.segment_used = true;
+                segment
+            }
+            None => "",
+        }
+    }
+
+    fn memory(&mut self, address: String) -> String {
+        let segment = self.segment_prefix();
+        if segment.is_empty() {
+            format!("[{}]", address)
+        } else {
+            format!("{}:[{}]", segment, address)
+        }
+    }
+
+    fn modrm(&mut self, wide: bool) -> ModRm {
+        let modrm = self.insn.modrm.unwrap_or(decode::ModRm::from_byte(0));
+        let (mode, reg, rm) = (modrm.mode, modrm.reg, modrm.rm);
+        let operand = match (mode, rm) {
+            (3, _) if wide => REG16[rm as usize].to_string(),
+            (3, _) => REG8[rm as usize].to_string(),
+            (0, 6) => {
+                let address = format!("{:04X}", self.displacement().unsigned());
+                self.memory(address)
+            }
+            (0, _) => self.memory(RM_BASE[rm as usize].to_string()),
+            (1, _) => {
+                let disp = self.displacement().signed() as i16;
+                let address = if disp < 0 {
+                    format!("{}-{:02X}", RM_BASE[rm as usize], -disp)
+                } else {
+                    format!("{}+{:02X}", RM_BASE[rm as usize], disp)
+                };
+                self.memory(address)
+            }
+            _ => {
+                let disp = self.displacement().unsigned();
+                self.memory(format!("{}+{:04X}", RM_BASE[rm as usize], disp))
+            }
+        };
+        ModRm {
+            reg,
+            rm: operand,
+            is_memory: modrm.is_memory(),
+        }
+    }
+
+    // The r/m operand of an instruction with no register operand to imply
+    // its size
+    fn sized(modrm: &ModRm, wide: bool) -> String {
+        match (modrm.is_memory, wide) {
+            (false, _) => modrm.rm.clone(),
+            (true, true) => format!("WORD PTR {}", modrm.rm),
+            (true, false) => format!("BYTE PTR {}", modrm.rm),
+        }
+    }
+
+    fn reg(wide: bool, reg: u8) -> &'static str {
+        if wide {
+            REG16[reg as usize]
+        } else {
+            REG8[reg as usize]
+        }
+    }
+
+    fn instruction(&mut self, opcode: u8) -> String {
+        let wide = opcode & 0x01 != 0;
+        match opcode {
+            // ADD/OR/ADC/SBB/AND/SUB/XOR/CMP in their six encodings
+            0x00..=0x3F if opcode & 0x07 < 6 => {
+                let op = ALU[(opcode >> 3) as usize];
+                match opcode & 0x07 {
+                    0 | 1 => {
+                        let m = self.modrm(wide);
+                        format!("{} {},{}", op, m.rm, Self::reg(wide, m.reg))
+                    }
+                    2 | 3 => {
+                        let m = self.modrm(wide);
+                        format!("{} {},{}", op, Self::reg(wide, m.reg), m.rm)
+                    }
+                    4 => format!("{} AL,{}", op, self.imm8()),
+                    _ => format!("{} AX,{}", op, self.imm16()),
+                }
+            }
+            0x06 | 0x0E | 0x16 | 0x1E => format!("PUSH {}", SREG[(opcode >> 3) as usize]),
+            0x07 | 0x17 | 0x1F => format!("POP {}", SREG[(opcode >> 3) as usize]),
+            0x0F => match self.insn.secondary_opcode.unwrap_or(0) {
+                0x0B => "UD2".to_string(),
+                second @ 0x40..=0x4F => {
+                    let m = self.modrm(true);
+                    let condition = &JCC[(second & 0x0F) as usize][1..];
+                    format!("CMOV{} {},{}", condition, REG16[m.reg as usize], m.rm)
+                }
+                0xAF => {
+                    let m = self.modrm(true);
+                    format!("IMUL {},{}", REG16[m.reg as usize], m.rm)
+                }
+                second => format!("DB 0F,{:02X}", second),
+            },
+            0x27 => "DAA".to_string(),
+            0x2F => "DAS".to_string(),
+            0x37 => "AAA".to_string(),
+            0x3F => "AAS".to_string(),
+            0x40..=0x47 => format!("INC {}", REG16[(opcode & 0x07) as usize]),
+            0x48..=0x4F => format!("DEC {}", REG16[(opcode & 0x07) as usize]),
+            0x50..=0x57 => format!("PUSH {}", REG16[(opcode & 0x07) as usize]),
+            0x58..=0x5F => format!("POP {}", REG16[(opcode & 0x07) as usize]),
+            0x60 => "PUSHA".to_string(),
+            0x61 => "POPA".to_string(),
+            0x62 => {
+                let m = self.modrm(true);
+                format!("BOUND {},{}", REG16[m.reg as usize], m.rm)
+            }
+            0x68 => format!("PUSH {}", self.imm16()),
+            0x69 | 0x6B => {
+                let m = self.modrm(true);
+                let imm = if opcode == 0x69 {
+                    self.imm16()
+                } else {
+                    self.imm8()
+                };
+                format!("IMUL {},{},{}", REG16[m.reg as usize], m.rm, imm)
+            }
+            0x6A => format!("PUSH {}", self.imm8()),
+            0x6C => "INSB".to_string(),
+            0x6D => "INSW".to_string(),
+            0x6E => "OUTSB".to_string(),
+            0x6F => "OUTSW".to_string(),
+            0x70..=0x7F => format!("{} {}", JCC[(opcode & 0x0F) as usize], self.rel()),
+            0x80..=0x83 => {
+                let m = self.modrm(opcode != 0x80 && opcode != 0x82);
+                let imm = if opcode == 0x81 {
+                    self.imm16()
+                } else {
+                    self.imm8()
+                };
+                let target = Self::sized(&m, opcode != 0x80 && opcode != 0x82);
+                format!("{} {},{}", ALU[m.reg as usize], target, imm)
+            }
+            0x84 | 0x85 => {
+                let m = self.modrm(wide);
+                format!("TEST {},{}", m.rm, Self::reg(wide, m.reg))
+            }
+            0x86 | 0x87 => {
+                let m = self.modrm(wide);
+                format!("XCHG {},{}", m.rm, Self::reg(wide, m.reg))
+            }
+            0x88 | 0x89 => {
+                let m = self.modrm(wide);
+                format!("MOV {},{}", m.rm, Self::reg(wide, m.reg))
+            }
+            0x8A | 0x8B => {
+                let m = self.modrm(wide);
+                format!("MOV {},{}", Self::reg(wide, m.reg), m.rm)
+            }
+            0x8C => {
+                let m = self.modrm(true);
+                format!("MOV {},{}", m.rm, SREG[(m.reg & 0x03) as usize])
+            }
+            0x8D => {
+                let m = self.modrm(true);
+                format!("LEA {},{}", REG16[m.reg as usize], m.rm)
+            }
+            0x8E => {
+                let m = self.modrm(true);
+                format!("MOV {},{}", SREG[(m.reg & 0x03) as usize], m.rm)
+            }
+            0x8F => {
+                let m = self.modrm(true);
+                format!("POP {}", Self::sized(&m, true))
+            }
+            0x90 => "NOP".to_string(),
+            0x91..=0x97 => format!("XCHG AX,{}", REG16[(opcode & 0x07) as usize]),
+            0x98 => "CBW".to_string(),
+            0x99 => "CWD".to_string(),
+            0x9A => {
+                format!("CALL {}", self.far_pointer())
+            }
+            0x9B => "WAIT".to_string(),
+            0x9C => "PUSHF".to_string(),
+            0x9D => "POPF".to_string(),
+            0x9E => "SAHF".to_string(),
+            0x9F => "LAHF".to_string(),
+            0xA0..=0xA3 => {
+                let address = format!("{:04X}", self.displacement().unsigned());
+                let memory = self.memory(address);
+                let acc = if wide { "AX" } else { "AL" };
+                if opcode < 0xA2 {
+                    format!("MOV {},{}", acc, memory)
+                } else {
+                    format!("MOV {},{}", memory, acc)
+                }
+            }
+            0xA4 => self.string_op("MOVSB"),
+            0xA5 => self.string_op("MOVSW"),
+            0xA6 => self.string_op("CMPSB"),
+            0xA7 => self.string_op("CMPSW"),
+            0xA8 => format!("TEST AL,{}", self.imm8()),
+            0xA9 => format!("TEST AX,{}", self.imm16()),
+            0xAA => "STOSB".to_string(),
+            0xAB => "STOSW".to_string(),
+            0xAC => self.string_op("LODSB"),
+            0xAD => self.string_op("LODSW"),
+            0xAE => "SCASB".to_string(),
+            0xAF => "SCASW".to_string(),
+            0xB0..=0xB7 => format!("MOV {},{}", REG8[(opcode & 0x07) as usize], self.imm8()),
+            0xB8..=0xBF => format!("MOV {},{}", REG16[(opcode & 0x07) as usize], self.imm16()),
+            0xC0 | 0xC1 | 0xD0..=0xD3 => {
+                let wide = opcode & 0x01 != 0;
+                let m = self.modrm(wide);
+                let count = match opcode {
+                    0xC0 | 0xC1 => self.imm8(),
+                    0xD0 | 0xD1 => "1".to_string(),
+                    _ => "CL".to_string(),
+                };
+                format!(
+                    "{} {},{}",
+                    SHIFT[m.reg as usize],
+                    Self::sized(&m, wide),
+                    count
+                )
+            }
+            0xC2 => format!("RET {}", self.imm16()),
+            0xC3 => "RET".to_string(),
+            0xC4 | 0xC5 => {
+                let m = self.modrm(true);
+                let op = if opcode == 0xC4 { "LES" } else { "LDS" };
+                format!("{} {},{}", op, REG16[m.reg as usize], m.rm)
+            }
+            0xC6 | 0xC7 => {
+                let m = self.modrm(wide);
+                let imm = if wide { self.imm16() } else { self.imm8() };
+                format!("MOV {},{}", Self::sized(&m, wide), imm)
+            }
+            0xC8 => {
+                let size = self.imm16();
+                format!("ENTER {},{}", size, self.imm8())
+            }
+            0xC9 => "LEAVE".to_string(),
+            0xCA => format!("RETF {}", self.imm16()),
+            0xCB => "RETF".to_string(),
+            0xCC => "INT 3".to_string(),
+            0xCD => format!("INT {}", self.imm8()),
+            0xCE => "INTO".to_string(),
+            0xCF => "IRET".to_string(),
+            0xD4 => format!("AAM {}", self.imm8()),
+            0xD5 => format!("AAD {}", self.imm8()),
+            0xD6 => "SALC".to_string(),
+            0xD7 => self.string_op("XLAT"),
+            0xD8..=0xDF => {
+                let m = self.modrm(true);
+                format!("ESC {:02X},{}", ((opcode & 0x07) << 3) | m.reg, m.rm)
+            }
+            0xE0 => format!("LOOPNZ {}", self.rel()),
+            0xE1 => format!("LOOPZ {}", self.rel()),
+            0xE2 => format!("LOOP {}", self.rel()),
+            0xE3 => format!("JCXZ {}", self.rel()),
+            0xE4 => format!("IN AL,{}", self.imm8()),
+            0xE5 => format!("IN AX,{}", self.imm8()),
+            0xE6 => format!("OUT {},AL", self.imm8()),
+            0xE7 => format!("OUT {},AX", self.imm8()),
+            0xE8 => format!("CALL {}", self.rel()),
+            0xE9 => format!("JMP {}", self.rel()),
+            0xEA => {
+                format!("JMP {}", self.far_pointer())
+            }
+            0xEB => format!("JMP SHORT {}", self.rel()),
+            0xEC => "IN AL,DX".to_string(),
+            0xED => "IN AX,DX".to_string(),
+            0xEE => "OUT DX,AL".to_string(),
+            0xEF => "OUT DX,AX".to_string(),
+            0xF4 => "HLT".to_string(),
+            0xF5 => "CMC".to_string(),
+            0xF6 | 0xF7 => {
+                let m = self.modrm(wide);
+                let target = Self::sized(&m, wide);
+                if m.reg < 2 {
+                    let imm = if wide { self.imm16() } else { self.imm8() };
+                    format!("TEST {},{}", target, imm)
+                } else {
+                    format!("{} {}", GROUP3[m.reg as usize], target)
+                }
+            }
+            0xF8 => "CLC".to_string(),
+            0xF9 => "STC".to_string(),
+            0xFA => "CLI".to_string(),
+            0xFB => "STI".to_string(),
+            0xFC => "CLD".to_string(),
+            0xFD => "STD".to_string(),
+            0xFE => {
+                let m = self.modrm(false);
+                match m.reg {
+                    0 => format!("INC {}", Self::sized(&m, false)),
+                    1 => format!("DEC {}", Self::sized(&m, false)),
+                    _ => "DB FE".to_string(),
+                }
+            }
+            0xFF => {
+                let m = self.modrm(true);
+                match m.reg {
+                    0 => format!("INC {}", Self::sized(&m, true)),
+                    1 => format!("DEC {}", Self::sized(&m, true)),
+                    2 => format!("CALL {}", m.rm),
+                    3 => format!("CALL FAR {}", m.rm),
+                    4 => format!("JMP {}", m.rm),
+                    5 => format!("JMP FAR {}", m.rm),
+                    6 => format!("PUSH {}", Self::sized(&m, true)),
+                    _ => "DB FF".to_string(),
+                }
+            }
+            // 63-67, F1 and anything else the 8086/80186 leaves undefined
+            _ => format!("DB {:02X}", opcode),
+        }
+    }
+
+    // String instructions whose source segment can be overridden show the
+    // override with the mnemonic
+    fn string_op(&mut self, mnemonic: &str) -> String {
+        match self.segment_prefix() {
+            "" => mnemonic.to_string(),
+            segment => format!("{} {}:", mnemonic, segment),
+        }
+    }
+}
+
+impl<M: Memory> CpuGeneric<M> {
+    /// Disassembles the instruction at `cs:ip` without executing it or
+    /// touching any state. Returns the text and the instruction's bytes,
+    /// prefixes included, so the next instruction starts `bytes.len()`
+    /// further on.
+    pub fn disassemble(&self, cs: u16, ip: u16) -> (String, Vec<u8>) {
+        let insn = self.decode(cs, ip);
+        let prefixes = insn.prefixes;
+        let mut decoder = Decoder {
+            insn: &insn,
+            next_ip: ip.wrapping_add(insn.length),
+            segment: prefixes.segment.map(|segment| match segment {
+                SegmentRegister::ES => "ES",
+                SegmentRegister::CS => "CS",
+                SegmentRegister::SS => "SS",
+                SegmentRegister::DS => "DS",
+            }),
+            segment_used: false,
+            immediates_used: 0,
+        };
+        let text = decoder.instruction(insn.opcode);
+
+        let mut parts = Vec::new();
+        if prefixes.lock {
+            parts.push("LOCK".to_string());
+        }
+        match prefixes.rep {
+            Some(RepPrefix::Repne) => parts.push("REPNZ".to_string()),
+            Some(RepPrefix::Rep) => parts.push("REP".to_string()),
+            None => {}
+        }
+        if let (Some(segment), false) = (decoder.segment, decoder.segment_used) {
+            // An override with no memory operand to apply to
+            parts.push(format!("{}:", segment));
+        }
+        parts.push(text);
+
+        let bytes = (0..insn.length)
+            .map(|i| self.peek_code_byte(cs, ip.wrapping_add(i)))
+            .collect();
+        (parts.join(" "), bytes)
+    }
+
+    /// Disassembles `count` consecutive instructions from `cs:start_ip` as
+    /// `(offset, text, bytes)`, e.g. for a monitor's unassemble command
+    pub fn disassemble_range(
+        &self,
+        cs: u16,
+        start_ip: u16,
+        count: usize,
+    ) -> Vec<(u16, String, Vec<u8>)> {
+        let mut ip = start_ip;
+        let mut listing = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (text, bytes) = self.disassemble(cs, ip);
+            let next = ip.wrapping_add(bytes.len() as u16);
+            listing.push((ip, text, bytes));
+            ip = next;
+        }
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::test_utils::setup_executable_cpu;
+
+    fn disassemble(code: &[u8]) -> (String, Vec<u8>) {
+        let cpu = setup_executable_cpu(code);
+        cpu.disassemble(0, 0x100)
+    }
+
+    #[test]
+    fn test_disassemble_single_instructions() {
+        let cases: [(&[u8], &str); 12] = [
+            (&[0xB8, 0x34, 0x12], "MOV AX,1234"),
+            (&[0x01, 0xD8], "ADD AX,BX"),
+            (&[0x8B, 0x46, 0xFE], "MOV AX,[BP-02]"),
+            (&[0x26, 0x88, 0x87, 0x00, 0x10], "MOV ES:[BX+1000],AL"),
+            (&[0x80, 0x3E, 0x00, 0x02, 0x05], "CMP BYTE PTR [0200],05"),
+            (&[0x83, 0xC4, 0xFE], "ADD SP,FE"),
+            (&[0xEB, 0xFE], "JMP SHORT 0100"),
+            (&[0x74, 0x02], "JZ 0104"),
+            (&[0xE8, 0xFD, 0xFF], "CALL 0100"),
+            (&[0xF3, 0xA4], "REP MOVSB"),
+            (&[0xD1, 0xE0], "SHL AX,1"),
+            (&[0xFF, 0x1E, 0x34, 0x12], "CALL FAR [1234]"),
+        ];
+        for (code, text) in cases {
+            assert_eq!(disassemble(code), (text.to_string(), code.to_vec()));
+        }
+        assert_eq!(
+            disassemble(&[0x0F, 0x44, 0xC3]),
+            ("CMOVZ AX,BX".to_string(), vec![0x0F, 0x44, 0xC3])
+        );
+        // Undefined opcodes come out as data, one byte at a time
+        assert_eq!(disassemble(&[0x63]).0, "DB 63");
+    }
+
+    #[test]
+    fn test_disassemble_range_advances_by_length() {
+        // MOV CX,0005 / MOV AL,[SI+04] / REP STOSB / INT 21
+        let code = [0xB9, 0x05, 0x00, 0x8A, 0x44, 0x04, 0xF3, 0xAA, 0xCD, 0x21];
+        let cpu = setup_executable_cpu(&code);
+        let listing = cpu.disassemble_range(0, 0x100, 4);
+
+        let addresses: Vec<u16> = listing.iter().map(|(ip, _, _)| *ip).collect();
+        assert_eq!(addresses, vec![0x100, 0x103, 0x106, 0x108]);
+        let texts: Vec<&str> = listing.iter().map(|(_, text, _)| text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["MOV CX,0005", "MOV AL,[SI+04]", "REP STOSB", "INT 21"]
+        );
+        assert_eq!(listing[3].2, vec![0xCD, 0x21]);
+        // Nothing was executed
+        assert_eq!(cpu.regs.ip, 0x100);
+    }
+}
@@ -1,183 +1,512 @@
-use super::Cpu;
-use crate::cpu::SegmentRegister;
+use super::decode::{self, Instruction};
+use super::{prefetch, Cpu, CpuGeneric, CpuModel};
+use crate::memory::Memory;
+use crate::prelude::*;
+
+// `start_ip` is where the instruction's first prefix byte sits
+type OpcodeHandler<M> = fn(&mut CpuGeneric<M>, &Instruction, u16) -> Result<(), String>;
 
 // All instruction implementations should go in their respective modules under instructions/
 // This file should only contain the instruction dispatch logic (execute_instruction)
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
+    /// Runs one instruction. A panic inside the emulator (a bug, not
+    /// anything the guest can legitimately cause) is caught here and
+    /// returned as an error naming CS:IP, so the host can shut down cleanly
+    /// or take a dump; `run_for` reports it as `StopReason::InternalFault`.
+    /// The CPU may be left part way through the instruction.
+    #[cfg(feature = "std")]
+    pub fn execute_instruction(&mut self) -> Result<(), String> {
+        let (cs, ip) = (self.regs.cs, self.regs.ip);
+        self.last_internal_fault = None;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.execute_instruction_unguarded()
+        }));
+        result.unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            self.last_internal_fault = Some((cs, ip));
+            Err(format!(
+                "Internal fault at {:04X}:{:04X}: {}",
+                cs, ip, message
+            ))
+        })
+    }
+
+    /// Without std there is no unwinding to catch
+    #[cfg(not(feature = "std"))]
     pub fn execute_instruction(&mut self) -> Result<(), String> {
+        self.execute_instruction_unguarded()
+    }
+
+    fn execute_instruction_unguarded(&mut self) -> Result<(), String> {
         // First check ROM validity
         if !self.memory.has_valid_rom() {
             return Err("Cannot execute: BIOS ROM is corrupted or invalid".to_string());
         }
 
-        // Check if we have valid MBR boot code
-        if !self.has_valid_mbr {
+        // Check if we have valid MBR boot code, unless the caller supplied
+        // the code at the reset vector itself
+        if !self.has_valid_mbr && !self.injected_reset_code {
             return Err("Cannot execute: No valid MBR boot code".to_string());
         }
 
         // Check if we have valid boot sector
-        if !self.has_valid_boot_sector {
+        if !self.has_valid_boot_sector && !self.injected_reset_code {
             return Err("Cannot execute: No valid boot sector at LBA 63".to_string());
         }
 
-        let _cs_ip = ((self.regs.cs as u32) << 4) + (self.regs.ip as u32);
-        let opcode = self.fetch_byte()?;
+        // Hardware interrupts are taken between instructions
+        if self.deliver_pending_irq()? {
+            return Ok(());
+        }
 
-        match opcode {
-            // Data Transfer Instructions
-            0x06 => Ok(self.push_es()?),
-            0x07 => Ok(self.pop_es()?),
-            0x88 => Ok(self.mov_rm8_r8()?),
-            0x89 => Ok(self.mov_rm16_r16()?),
-            0x8A => Ok(self.mov_r8_rm8()?),
-            0x8B => Ok(self.mov_r16_rm16()?),
-            0x8C => Ok(self.mov_rm16_sreg()?),
-            0x8E => Ok(self.mov_sreg_rm16()?),
-            0xB0 => Ok(self.mov_al_imm8()?),
-            0xB1 => Ok(self.mov_cl_imm8()?),
-            0xB4 => Ok(self.mov_ah_imm8()?),
-            0xB5 => Ok(self.mov_ch_imm8()?),
-            0xB6 => Ok(self.mov_dh_imm8()?),
-            0xB2 => Ok(self.mov_dl_imm8()?),
-            0xB8 => Ok(self.mov_ax_imm16()?),
-            0xB9 => Ok(self.mov_cx_imm16()?),
-            0xBB => Ok(self.mov_bx_imm16()?),
-            0xBC => Ok(self.mov_sp_imm16()?),
-            0xBE => Ok(self.mov_si_imm16()?),
-            0xC4 => Ok(self.les_r16_m16()?),
-            0x44 => Ok(self.inc_sp()?),
-            0x45 => Ok(self.inc_bp()?),
-            0x46 => Ok(self.inc_si()?),
-            0x41 => Ok(self.inc_cx()?),
-            0x4E => Ok(self.dec_si()?),
-            0x4F => Ok(self.dec_di()?),
-
-            // Interrupt Instructions
-            0xCD => {
-                let interrupt_number = self.fetch_byte()?;
-                Ok(self.int(interrupt_number)?)
+        let start_ip = self.regs.ip;
+        self.instruction_start_ip = start_ip;
+        self.rep_last_prefix_ip = None;
+        self.last_invalid_opcode = None;
+        self.last_divide_error = false;
+        // A shadow from the previous instruction covers only this one
+        let shadowed = core::mem::take(&mut self.interrupt_shadow);
+        let insn = self.fetch_instruction()?;
+        self.coverage.record_primary(insn.opcode);
+        // The 8086 through the 80286 run LOCK on anything; the 80386 faults
+        let result =
+            if self.lock_prefix && self.model >= CpuModel::I80386 && !Self::is_lockable(&insn) {
+                self.raise_invalid_opcode()
+            } else {
+                self.execute_opcode(&insn, start_ip)
+            };
+        self.instruction_count += 1;
+        self.clear_prefixes();
+        self.fetched.clear();
+        if let Some(queue) = self.prefetch.as_mut() {
+            if prefetch::always_transfers(&insn) {
+                queue.flush();
             }
+        }
+        if shadowed {
+            // Back-to-back SS loads don't extend the shadow
+            self.interrupt_shadow = false;
+        }
+        result
+    }
+
+    // Fetches the whole instruction at CS:IP, through the prefetch queue when
+    // there is one, and decodes it with the decoder the disassembler uses.
+    // The fetched bytes are kept for the handler's operand fetches, so the
+    // LOCK check, the queue and the handler all see the same instruction
+    // even if it has been overwritten in memory since. Leaves the prefixes
+    // in effect and IP just past the opcode.
+    fn fetch_instruction(&mut self) -> Result<Instruction, String> {
+        self.clear_prefixes();
+        let (cs, ip) = (self.regs.cs, self.regs.ip);
+        self.fetched.start(cs, ip);
+        let mut error = None;
+        let insn = decode::decode_with(
+            |_| {
+                let byte = self.fetch_byte().unwrap_or_else(|e| {
+                    error.get_or_insert(e);
+                    0
+                });
+                self.fetched.push(byte);
+                byte
+            },
+            ip,
+        );
+        if let Some(e) = error {
+            return Err(e);
+        }
+        self.regs.ip = ip.wrapping_add(insn.prefixes.count as u16 + 1);
+
+        self.segment_override = insn.prefixes.segment;
+        self.rep_prefix = insn.prefixes.rep;
+        self.lock_prefix = insn.prefixes.lock;
+        // REP only repeats string instructions; on anything else the CPU
+        // ignores it and the instruction runs once
+        if self.rep_prefix.is_some() && !Self::is_string_opcode(insn.opcode) {
+            println!("Ignoring REP prefix on opcode {:02X}", insn.opcode);
+            self.rep_prefix = None;
+        }
+        Ok(insn)
+    }
 
-            // Arithmetic Instructions
-            0x00 => Ok(self.add_rm8_r8()?),
-            0x01 => Ok(self.add_rm16_r16()?),
-            0x02 => Ok(self.add_r8_rm8()?),
-            0x03 => Ok(self.add_ax_rm16()?),
-            0x04 => Ok(self.add_al_imm8()?),
-            0x05 => Ok(self.add_ax_imm16()?),
-            0x10 => Ok(self.adc_r8_rm8()?),
-            0x12 => Ok(self.adc_al_rm8()?),
-            0x08 => Ok(self.or_rm8_r8()?),
-            0x38 => Ok(self.cmp_rm8_r8()?),
-            0x40 => Ok(self.inc_ax()?),
-            0x3C => Ok(self.cmp_al_imm8()?),
-            0x3B => Ok(self.cmp_r16_rm16()?),
-            0x36 => {
-                self.set_segment_override(SegmentRegister::SS);
-                self.execute_instruction()?;
-                self.clear_segment_override();
-                Ok(())
+    const fn is_string_opcode(opcode: u8) -> bool {
+        matches!(opcode, 0xA4..=0xA7 | 0xAA..=0xAF)
+    }
+
+    // LOCK is only legal before a read-modify-write of memory: ADD, OR,
+    // ADC, SBB, AND, SUB, XOR, NOT, NEG, INC, DEC or XCHG with a memory
+    // destination. Anything else takes INT 6 on a processor that checks.
+    fn is_lockable(insn: &Instruction) -> bool {
+        let Some(modrm) = insn.modrm.filter(|modrm| modrm.mode != 3) else {
+            return false;
+        };
+        match insn.opcode {
+            0x00 | 0x01 | 0x08 | 0x09 | 0x10 | 0x11 | 0x18 | 0x19 => true,
+            0x20 | 0x21 | 0x28 | 0x29 | 0x30 | 0x31 | 0x86 | 0x87 => true,
+            // Group 1 /7 is CMP, which writes nothing
+            0x80..=0x83 => modrm.reg != 7,
+            0xF6 | 0xF7 => matches!(modrm.reg, 2 | 3),
+            0xFE | 0xFF => matches!(modrm.reg, 0 | 1),
+            _ => false,
+        }
+    }
+
+    /// Handlers for the primary opcode map, indexed by opcode. Dispatch and
+    /// `is_opcode_implemented` both read this, so they can't disagree.
+    const PRIMARY_OPCODES: [Option<OpcodeHandler<M>>; 256] = {
+        let mut table: [Option<OpcodeHandler<M>>; 256] = [None; 256];
+        // Data Transfer Instructions
+        table[0x06] = Some(|cpu, _, _| cpu.push_es());
+        table[0x07] = Some(|cpu, _, _| cpu.pop_es());
+        table[0x17] = Some(|cpu, _, _| cpu.pop_ss());
+        table[0x88] = Some(|cpu, _, _| cpu.mov_rm8_r8());
+        table[0x89] = Some(|cpu, _, _| cpu.mov_rm16_r16());
+        table[0x8A] = Some(|cpu, _, _| cpu.mov_r8_rm8());
+        table[0x8B] = Some(|cpu, _, _| cpu.mov_r16_rm16());
+        table[0x8C] = Some(|cpu, _, _| cpu.mov_rm16_sreg());
+        table[0x8E] = Some(|cpu, _, _| cpu.mov_sreg_rm16());
+        table[0xB0] = Some(|cpu, _, _| cpu.mov_al_imm8());
+        table[0xB1] = Some(|cpu, _, _| cpu.mov_cl_imm8());
+        table[0xB4] = Some(|cpu, _, _| cpu.mov_ah_imm8());
+        table[0xB5] = Some(|cpu, _, _| cpu.mov_ch_imm8());
+        table[0xB6] = Some(|cpu, _, _| cpu.mov_dh_imm8());
+        table[0xB2] = Some(|cpu, _, _| cpu.mov_dl_imm8());
+        table[0xB3] = Some(|cpu, _, _| cpu.mov_bl_imm8());
+        table[0xB7] = Some(|cpu, _, _| cpu.mov_bh_imm8());
+        table[0xB8] = Some(|cpu, _, _| cpu.mov_ax_imm16());
+        table[0xB9] = Some(|cpu, _, _| cpu.mov_cx_imm16());
+        table[0xBB] = Some(|cpu, _, _| cpu.mov_bx_imm16());
+        table[0xBC] = Some(|cpu, _, _| cpu.mov_sp_imm16());
+        table[0xBE] = Some(|cpu, _, _| cpu.mov_si_imm16());
+        table[0xC4] = Some(|cpu, _, _| cpu.les_r16_m16());
+        table[0xC6] = Some(|cpu, _, _| cpu.mov_rm8_imm8());
+        table[0xC7] = Some(|cpu, _, _| cpu.mov_rm16_imm16());
+        table[0x44] = Some(|cpu, _, _| cpu.inc_sp());
+        table[0x45] = Some(|cpu, _, _| cpu.inc_bp());
+        table[0x46] = Some(|cpu, _, _| cpu.inc_si());
+        table[0x41] = Some(|cpu, _, _| cpu.inc_cx());
+        table[0x4E] = Some(|cpu, _, _| cpu.dec_si());
+        table[0x4F] = Some(|cpu, _, _| cpu.dec_di());
+
+        // Interrupt Instructions
+        table[0xCC] = Some(|cpu, _, _| cpu.int3());
+        table[0xCD] = Some(|cpu, _, _| {
+            // The return address pushed is past the vector byte
+            let interrupt_number = cpu.fetch_byte()?;
+            cpu.int(interrupt_number)
+        });
+        table[0xCE] = Some(|cpu, _, _| cpu.int_overflow());
+
+        // Arithmetic Instructions
+        table[0x00] = Some(|cpu, _, _| cpu.add_rm8_r8());
+        table[0x01] = Some(|cpu, _, _| cpu.add_rm16_r16());
+        table[0x02] = Some(|cpu, _, _| cpu.add_r8_rm8());
+        table[0x03] = Some(|cpu, _, _| cpu.add_ax_rm16());
+        table[0x04] = Some(|cpu, _, _| cpu.add_al_imm8());
+        table[0x05] = Some(|cpu, _, _| cpu.add_ax_imm16());
+        table[0x10] = Some(|cpu, _, _| cpu.adc_r8_rm8());
+        table[0x11] = Some(|cpu, _, _| cpu.adc_rm16_r16());
+        table[0x12] = Some(|cpu, _, _| cpu.adc_al_rm8());
+        table[0x13] = Some(|cpu, _, _| cpu.adc_r16_rm16());
+        table[0x08] = Some(|cpu, _, _| cpu.or_rm8_r8());
+        table[0x38] = Some(|cpu, _, _| cpu.cmp_rm8_r8());
+        table[0x40] = Some(|cpu, _, _| cpu.inc_ax());
+        table[0x39] = Some(|cpu, _, _| cpu.cmp_rm16_r16());
+        table[0x3A] = Some(|cpu, _, _| cpu.cmp_r8_rm8());
+        table[0x3C] = Some(|cpu, _, _| cpu.cmp_al_imm8());
+        table[0x3B] = Some(|cpu, _, _| cpu.cmp_r16_rm16());
+        table[0x3D] = Some(|cpu, _, _| cpu.cmp_ax_imm16());
+        table[0x19] = Some(|cpu, _, _| cpu.sbb_rm16_r16());
+        table[0x1B] = Some(|cpu, _, _| cpu.sbb_r16_rm16());
+        table[0x1C] = Some(|cpu, _, _| cpu.sbb_al_imm8());
+        table[0x69] = Some(|cpu, _, _| cpu.imul_r16_rm16_imm16());
+        table[0x6B] = Some(|cpu, _, _| cpu.imul_r16_rm16_imm8());
+
+        // Logic Instructions
+        table[0x20] = Some(|cpu, _, _| cpu.and_rm8_r8());
+        table[0x30] = Some(|cpu, _, _| cpu.xor_rm8_r8());
+        table[0x31] = Some(|cpu, _, _| cpu.xor_rm16_r16());
+        table[0x32] = Some(|cpu, _, _| cpu.xor_r8_rm8());
+        table[0x33] = Some(|cpu, _, _| cpu.xor_r16_rm16());
+
+        // Control Flow Instructions
+        table[0xE8] = Some(|cpu, _, _| cpu.call_near());
+        table[0xE9] = Some(|cpu, _, _| cpu.jmp_near());
+        table[0xEA] = Some(|cpu, _, _| cpu.jmp_far());
+        table[0xEB] = Some(|cpu, _, _| cpu.jmp_short());
+        table[0xE3] = Some(|cpu, _, _| cpu.jcxz());
+        table[0xE2] = Some(|cpu, _, _| cpu.loop_cx());
+        table[0xE0] = Some(|cpu, _, _| cpu.loopnz());
+        table[0xE1] = Some(|cpu, _, _| cpu.loopz());
+        table[0x70] = Some(|cpu, _, _| cpu.jo_rel8());
+        table[0x71] = Some(|cpu, _, _| cpu.jno_rel8());
+        table[0x72] = Some(|cpu, _, _| cpu.jb_rel8());
+        table[0x73] = Some(|cpu, _, _| cpu.jnb_rel8());
+        table[0x74] = Some(|cpu, _, _| cpu.jz_rel8());
+        table[0x75] = Some(|cpu, _, _| cpu.jnz_rel8());
+        table[0x76] = Some(|cpu, _, _| cpu.jbe_rel8());
+        table[0x77] = Some(|cpu, _, _| cpu.jnbe_rel8());
+        table[0x78] = Some(|cpu, _, _| cpu.js_rel8());
+        table[0x79] = Some(|cpu, _, _| cpu.jns_rel8());
+        table[0x7A] = Some(|cpu, _, _| cpu.jp_rel8());
+        table[0x7B] = Some(|cpu, _, _| cpu.jnp_rel8());
+        table[0x7C] = Some(|cpu, _, _| cpu.jl_rel8());
+        table[0x7D] = Some(|cpu, _, _| cpu.jnl_rel8());
+        table[0x7E] = Some(|cpu, _, _| cpu.jle_rel8());
+        table[0x7F] = Some(|cpu, _, _| cpu.jnle_rel8());
+
+        // String Instructions
+        let mut opcode = 0xA4;
+        while opcode <= 0xAF {
+            if Self::is_string_opcode(opcode) {
+                table[opcode as usize] =
+                    Some(|cpu, insn, start_ip| cpu.execute_string(insn.opcode, start_ip));
             }
-            0x1C => Ok(self.sbb_al_imm8()?),
-
-            // Logic Instructions
-            0x20 => Ok(self.and_rm8_r8()?),
-            0x30 => Ok(self.xor_rm8_r8()?),
-            0x31 => Ok(self.xor_rm16_r16()?),
-            0x32 => Ok(self.xor_r8_rm8()?),
-            0x33 => Ok(self.xor_r16_rm16()?),
-
-            // Control Flow Instructions
-            0xE8 => Ok(self.call_near()?),
-            0xE9 => Ok(self.jmp_near()?),
-            0xEA => Ok(self.jmp_far()?),
-            0xEB => Ok(self.jmp_short()?),
-            0xE3 => Ok(self.jcxz()?),
-            0xE2 => Ok(self.loop_cx()?),
-            0x70 => Ok(self.jo_rel8()?),
-            0x71 => Ok(self.jno_rel8()?),
-            0x72 => Ok(self.jb_rel8()?),
-            0x73 => Ok(self.jnb_rel8()?),
-            0x74 => Ok(self.jz_rel8()?),
-            0x75 => Ok(self.jnz_rel8()?),
-            0x76 => Ok(self.jbe_rel8()?),
-            0x77 => Ok(self.jnbe_rel8()?),
-
-            // String Instructions
-            0xA4 => Ok(self.movsb()?),
-            0xA5 => Ok(self.movsw()?),
-            0xAC => Ok(self.lodsb()?),
-            0xAD => Ok(self.lodsw()?),
-            0xAA => Ok(self.stosb()?),
-            0xAB => Ok(self.stosw()?),
-
-            // Flag Instructions
-            0xF8 => Ok(self.clc()?),
-            0xF9 => Ok(self.stc()?),
-            0xFA => Ok(self.cli()?),
-            0xFB => Ok(self.sti()?),
-            0xFC => Ok(self.cld()?),
-            0xFD => Ok(self.std()?),
-
-            // I/O Instructions
-            0xE4 => Ok(self.in_al_imm8()?),
-            0xE5 => Ok(self.in_ax_imm8()?),
-            0xE6 => Ok(self.out_imm8_al()?),
-            0xE7 => Ok(self.out_imm8_ax()?),
-            0xEC => Ok(self.in_al_dx()?),
-            0xED => Ok(self.in_ax_dx()?),
-            0xEE => Ok(self.out_dx_al()?),
-            0xEF => Ok(self.out_dx_ax()?),
-
-            // Group Instructions
-            0x80 => Ok(self.execute_group1_rm8_imm8(0)?),
-            0x81 => Ok(self.handle_81_group()?),
-            0x82 => Ok(self.handle_82_group()?),
-            0x83 => Ok(self.handle_83_group()?),
-            0xF6 => Ok(self.handle_f6_group()?),
-            0xF7 => Ok(self.handle_f7_group()?),
-            0xFE => Ok(self.handle_fe_group()?),
-            0xFF => Ok(self.handle_ff_group()?),
-
-            // Other Instructions
-            0x90..=0x97 => Ok(self.xchg_ax_r16(opcode - 0x90)?),
-            0xF4 => {
+            opcode += 1;
+        }
+
+        // Flag Instructions
+        table[0xF8] = Some(|cpu, _, _| cpu.clc());
+        table[0xF9] = Some(|cpu, _, _| cpu.stc());
+        table[0x9C] = Some(|cpu, _, _| cpu.pushf());
+        table[0x9D] = Some(|cpu, _, _| cpu.popf());
+        table[0x9E] = Some(|cpu, _, _| cpu.sahf());
+        table[0x9F] = Some(|cpu, _, _| cpu.lahf());
+        table[0xFA] = Some(|cpu, _, _| cpu.cli());
+        table[0xFB] = Some(|cpu, _, _| cpu.sti());
+        table[0xFC] = Some(|cpu, _, _| cpu.cld());
+        table[0xFD] = Some(|cpu, _, _| cpu.std());
+
+        // I/O Instructions
+        table[0xE4] = Some(|cpu, _, _| cpu.in_al_imm8());
+        table[0xE5] = Some(|cpu, _, _| cpu.in_ax_imm8());
+        table[0xE6] = Some(|cpu, _, _| cpu.out_imm8_al());
+        table[0xE7] = Some(|cpu, _, _| cpu.out_imm8_ax());
+        table[0xEC] = Some(|cpu, _, _| cpu.in_al_dx());
+        table[0xED] = Some(|cpu, _, _| cpu.in_ax_dx());
+        table[0xEE] = Some(|cpu, _, _| cpu.out_dx_al());
+        table[0xEF] = Some(|cpu, _, _| cpu.out_dx_ax());
+
+        // Group Instructions
+        table[0x80] = Some(|cpu, _, _| cpu.execute_group1_rm8_imm8());
+        table[0x81] = Some(|cpu, _, _| cpu.handle_81_group());
+        table[0x82] = Some(|cpu, _, _| cpu.undocumented(Self::handle_82_group));
+        table[0x83] = Some(|cpu, _, _| cpu.handle_83_group());
+        table[0xC0] = Some(|cpu, _, _| cpu.handle_c0_group());
+        table[0xC1] = Some(|cpu, _, _| cpu.handle_c1_group());
+        table[0xD0] = Some(|cpu, _, _| cpu.handle_d0_group());
+        table[0xD1] = Some(|cpu, _, _| cpu.handle_d1_group());
+        table[0xD2] = Some(|cpu, _, _| cpu.handle_d2_group());
+        table[0xD3] = Some(|cpu, _, _| cpu.handle_d3_group());
+        table[0xF6] = Some(|cpu, _, _| cpu.handle_f6_group());
+        table[0xF7] = Some(|cpu, _, _| cpu.handle_f7_group());
+        table[0xFE] = Some(|cpu, _, _| cpu.handle_fe_group());
+        table[0xFF] = Some(|cpu, _, _| cpu.handle_ff_group());
+
+        // Other Instructions
+        table[0x90] = Some(|cpu, _, _| cpu.nop());
+        let mut opcode = 0x91;
+        while opcode <= 0x97 {
+            table[opcode] = Some(|cpu, insn, _| cpu.xchg_ax_r16(insn.opcode - 0x90));
+            opcode += 1;
+        }
+        table[0xF4] = Some(|cpu, _, _| {
+            cpu.halted = true;
+            Ok(())
+        });
+        // 0xF1 => Ok(self.int1()?),
+
+        // Stack Instructions
+        table[0x50] = Some(|cpu, _, _| cpu.push_ax());
+        table[0x51] = Some(|cpu, _, _| cpu.push_cx());
+        table[0x52] = Some(|cpu, _, _| cpu.push_dx());
+        table[0x53] = Some(|cpu, _, _| cpu.push_bx());
+        table[0x59] = Some(|cpu, _, _| cpu.pop_cx());
+        table[0x5A] = Some(|cpu, _, _| cpu.pop_dx());
+        table[0x5B] = Some(|cpu, _, _| cpu.pop_bx());
+        table[0x58] = Some(|cpu, _, _| cpu.pop_ax());
+        table[0xCF] = Some(|cpu, _, _| cpu.iret());
+
+        // New instructions
+        table[0xD6] = Some(|cpu, _, _| cpu.undocumented(Self::salc));
+        table[0xD4] = Some(|cpu, _, _| cpu.aam());
+
+        // Stack Instructions
+        table[0xC8] = Some(|cpu, _, _| {
+            let nesting_level = cpu.fetch_byte()? & 0x1F;
+            cpu.enter(nesting_level)
+        });
+        table[0xC9] = Some(|cpu, _, _| cpu.leave());
+        table[0xC2] = Some(|cpu, _, _| cpu.ret_near_imm16());
+        table[0xC3] = Some(|cpu, _, _| cpu.ret_near());
+        table[0xCA] = Some(|cpu, _, _| cpu.ret_far_imm16());
+        table[0xCB] = Some(|cpu, _, _| cpu.ret_far());
+
+        // Two-byte opcodes
+        table[0x0F] = Some(|cpu, _, _| cpu.handle_0f_opcode());
+        table
+    };
+
+    // `start_ip` is the offset of the first prefix byte, which REP string
+    // instructions return to while iterations remain
+    fn execute_opcode(&mut self, insn: &Instruction, start_ip: u16) -> Result<(), String> {
+        match Self::PRIMARY_OPCODES[insn.opcode as usize] {
+            Some(handler) => handler(self, insn, start_ip),
+            None => {
+                self.coverage.record_unimplemented(insn.opcode as u16);
+                self.last_invalid_opcode = Some(insn.opcode as u16);
                 self.halted = true;
-                Ok(())
-            }
-            // 0xF1 => Ok(self.int1()?),
-
-            // Stack Instructions
-            0x50 => Ok(self.push_ax()?),
-            0x51 => Ok(self.push_cx()?),
-            0x52 => Ok(self.push_dx()?),
-            0x53 => Ok(self.push_bx()?),
-            0x59 => Ok(self.pop_cx()?),
-            0x5A => Ok(self.pop_dx()?),
-            0x5B => Ok(self.pop_bx()?),
-            0x58 => Ok(self.pop_ax()?),
-            0xCF => Ok(self.iret()?),
-
-            // New instructions
-            0xD6 => Ok(self.salc()?),
-            0xD4 => Ok(self.aam()?),
-
-            // Stack Instructions
-            0xC8 => {
-                let nesting_level = self.fetch_byte()? & 0x1F;
-                Ok(self.enter(nesting_level)?)
+                Err(format!("Illegal opcode {:#04X}", insn.opcode))
             }
-            0xC9 => Ok(self.leave()?),
-            0xCA => Ok(self.ret_far_imm16()?),
+        }
+    }
 
-            // Prefix Instructions
-            0xF0 => self.execute_instruction(),
+    // Dispatch for the 0x0F two-byte opcode map
+    fn handle_0f_opcode(&mut self) -> Result<(), String> {
+        let opcode = self.fetch_byte()?;
+        self.coverage.record_extended(opcode);
+
+        match opcode {
+            0x0B => Ok(self.ud2()?),
+            0x40..=0x4F => Ok(self.cmovcc(opcode)?),
+            0xAF => Ok(self.imul_r16_rm16()?),
 
             _ => {
+                self.coverage.record_unimplemented(0x0F00 | opcode as u16);
+                self.last_invalid_opcode = Some(0x0F00 | opcode as u16);
                 self.halted = true;
-                Err(format!("Illegal opcode {:#04X}", opcode))
+                Err(format!("Illegal opcode 0x0F {:#04X}", opcode))
             }
         }
     }
 }
+
+impl Cpu {
+    /// Whether the primary opcode `opcode` has a handler. Prefixes count.
+    /// An opcode the configured model lacks, or one that raises INT 6 for
+    /// some encodings, still has a handler.
+    pub fn is_opcode_implemented(opcode: u8) -> bool {
+        decode::prefix(opcode).is_some() || Self::PRIMARY_OPCODES[opcode as usize].is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
+    use crate::cpu::CpuModel;
+
+    // Every primary opcode with a handler today. Anything here that stops
+    // being implemented is a regression; add to it as handlers land.
+    #[rustfmt::skip]
+    const IMPLEMENTED: &[u8] = &[
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x0F,
+        0x10, 0x11, 0x12, 0x13, 0x17, 0x19, 0x1B, 0x1C,
+        0x20, 0x26, 0x2E,
+        0x30, 0x31, 0x32, 0x33, 0x36, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+        0x40, 0x41, 0x44, 0x45, 0x46, 0x4E, 0x4F,
+        0x50, 0x51, 0x52, 0x53, 0x58, 0x59, 0x5A, 0x5B,
+        0x69, 0x6B,
+        0x70, 0x71, 0x72, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x7B, 0x7C, 0x7D, 0x7E, 0x7F,
+        0x80, 0x81, 0x82, 0x83, 0x88, 0x89, 0x8A, 0x8B, 0x8C, 0x8E,
+        0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x9C, 0x9D, 0x9E, 0x9F,
+        0xA4, 0xA5, 0xA6, 0xA7, 0xAA, 0xAB, 0xAC, 0xAD, 0xAE, 0xAF,
+        0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBB, 0xBC, 0xBE,
+        0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xCB, 0xCC, 0xCD, 0xCE, 0xCF,
+        0xD0, 0xD1, 0xD2, 0xD3, 0xD4, 0xD6,
+        0xE0, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xEB, 0xEC, 0xED, 0xEE, 0xEF,
+        0xF0, 0xF2, 0xF3, 0xF4, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
+    ];
+
+    #[test]
+    fn test_no_implemented_opcode_regresses() {
+        let missing: Vec<String> = IMPLEMENTED
+            .iter()
+            .filter(|&&opcode| !Cpu::is_opcode_implemented(opcode))
+            .map(|opcode| format!("{:02X}", opcode))
+            .collect();
+        assert!(missing.is_empty(), "no longer implemented: {:?}", missing);
+
+        // The probe itself: nothing implements F1
+        assert!(!Cpu::is_opcode_implemented(0xF1));
+    }
+
+    #[test]
+    fn test_opcode_coverage() {
+        let mut cpu = setup_executable_cpu(&[
+            0xB8, 0x03, 0x00, // MOV AX, 3
+            0x05, 0x02, 0x00, // ADD AX, 2
+            0x90, // NOP
+            0x90, // NOP
+            0x0F, 0xAF, 0xC0, // IMUL AX, AX
+            0x0F, 0xFF, // Unimplemented two-byte opcode
+        ]);
+        cpu.set_model(CpuModel::I80386);
+
+        for _ in 0..5 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert!(cpu.execute_instruction().is_err());
+        assert_eq!(cpu.regs.ax, 25);
+
+        let coverage = cpu.opcode_coverage();
+        assert_eq!(coverage[0xB8], 1);
+        assert_eq!(coverage[0x05], 1);
+        assert_eq!(coverage[0x90], 2);
+        assert_eq!(coverage[0x0F], 2);
+        assert_eq!(coverage[0x01], 0);
+        assert_eq!(cpu.opcode_0f_coverage()[0xAF], 1);
+        assert_eq!(cpu.unimplemented_opcodes(), vec![0x0FFF]);
+
+        cpu.reset_opcode_coverage();
+        assert_eq!(cpu.opcode_coverage()[0x90], 0);
+        assert!(cpu.unimplemented_opcodes().is_empty());
+    }
+
+    #[test]
+    fn test_rep_prefix_ignored_on_non_string_opcode() {
+        // REP MOV AX,BX; REPNE INC AX; HLT
+        let mut cpu = setup_executable_cpu(&[0xF3, 0x89, 0xD8, 0xF2, 0x40, 0xF4]);
+        cpu.regs.bx = 0x1234;
+        cpu.regs.cx = 5;
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0x1234);
+        assert_eq!(cpu.regs.cx, 5);
+        assert_eq!(cpu.regs.ip, 0x103);
+        assert_eq!(cpu.rep_prefix, None);
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0x1235);
+        assert_eq!(cpu.regs.cx, 5);
+        assert_eq!(cpu.regs.ip, 0x105);
+    }
+
+    #[test]
+    fn test_ud2_raises_int6_unlike_unimplemented_opcode() {
+        let mut cpu = setup_executable_cpu(&[
+            0x0F, 0x0B, // UD2
+            0x0F, 0xFF, // Unimplemented two-byte opcode
+        ]);
+        // INT 6 handler at 0000:0600
+        cpu.memory.write_word(0x06 * 4, 0x0600);
+        cpu.memory.write_word(0x06 * 4 + 2, 0x0000);
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0600));
+        // The return address points back at the UD2 itself
+        assert_eq!(cpu.memory.read_word(cpu.regs.sp as u32), 0x100);
+        assert!(!cpu.is_halted());
+        assert!(cpu.unimplemented_opcodes().is_empty());
+
+        cpu.regs.ip = 0x102;
+        assert!(cpu.execute_instruction().is_err());
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.unimplemented_opcodes(), vec![0x0FFF]);
+    }
+}
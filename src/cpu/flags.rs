@@ -1,10 +1,10 @@
-use std::ops::{BitAnd, BitOr, Not};
+use core::ops::{BitAnd, BitOr, Not};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Flags {
     carry: bool,     // CF (bit 0)
     parity: bool,    // PF (bit 2)
-    adjust: bool,    // AF (bit 4)
+    auxiliary: bool, // AF (bit 4)
     zero: bool,      // ZF (bit 6)
     sign: bool,      // SF (bit 7)
     trap: bool,      // TF (bit 8)
@@ -24,7 +24,7 @@ impl Flags {
         Flags {
             carry: false,
             parity: false,
-            adjust: false,
+            auxiliary: false,
             zero: false,
             sign: false,
             trap: false,
@@ -39,7 +39,7 @@ impl Flags {
         Flags {
             carry: (word & 0x0001) != 0,
             parity: (word & 0x0004) != 0,
-            adjust: (word & 0x0010) != 0,
+            auxiliary: (word & 0x0010) != 0,
             zero: (word & 0x0040) != 0,
             sign: (word & 0x0080) != 0,
             trap: (word & 0x0100) != 0,
@@ -57,8 +57,8 @@ impl Flags {
         self.parity
     }
     #[allow(dead_code)]
-    pub fn get_adjust(&self) -> bool {
-        self.adjust
+    pub fn get_auxiliary(&self) -> bool {
+        self.auxiliary
     }
     pub fn get_zero(&self) -> bool {
         self.zero
@@ -88,8 +88,8 @@ impl Flags {
     pub fn set_parity(&mut self, value: bool) {
         self.parity = value;
     }
-    pub fn set_adjust(&mut self, value: bool) {
-        self.adjust = value;
+    pub fn set_auxiliary(&mut self, value: bool) {
+        self.auxiliary = value;
     }
     pub fn set_zero(&mut self, value: bool) {
         self.zero = value;
@@ -119,7 +119,7 @@ impl Flags {
         if self.parity {
             result |= 0x04;
         }
-        if self.adjust {
+        if self.auxiliary {
             result |= 0x10;
         }
         if self.zero {
@@ -134,7 +134,7 @@ impl Flags {
     pub fn set_from_byte(&mut self, value: u8) {
         self.carry = (value & 0x01) != 0;
         self.parity = (value & 0x04) != 0;
-        self.adjust = (value & 0x10) != 0;
+        self.auxiliary = (value & 0x10) != 0;
         self.zero = (value & 0x40) != 0;
         self.sign = (value & 0x80) != 0;
     }
@@ -151,7 +151,7 @@ impl Flags {
         }
         // Reserved bit 3 is always 1
         result |= 0x0008;
-        if self.adjust {
+        if self.auxiliary {
             result |= 0x0010;
         }
         if self.zero {
@@ -180,7 +180,7 @@ impl Flags {
         // Skip reserved bit 1
         self.parity = (value & 0x0004) != 0;
         // Skip reserved bit 3
-        self.adjust = (value & 0x0010) != 0;
+        self.auxiliary = (value & 0x0010) != 0;
         self.zero = (value & 0x0040) != 0;
         self.sign = (value & 0x0080) != 0;
         self.trap = (value & 0x0100) != 0;
@@ -189,21 +189,6 @@ impl Flags {
         self.overflow = (value & 0x0800) != 0;
     }
 
-    #[allow(dead_code)]
-    pub fn update_flags_dec16(&mut self, result: u16) {
-        // Zero flag
-        self.set_zero(result == 0);
-
-        // Sign flag
-        self.set_sign((result & 0x8000) != 0);
-
-        // Overflow flag (set if decrementing 0x8000)
-        self.set_overflow(result == 0x7FFF);
-
-        // Auxiliary flag (set if decrementing a value with low nibble of 0)
-        self.set_adjust((result & 0x0F) == 0x0F);
-    }
-
     #[allow(dead_code)]
     pub fn update_logical_flags(&mut self, result: u16) {
         self.set_zero(result == 0);
@@ -229,7 +214,7 @@ impl Flags {
         if self.parity {
             word |= 0x0004;
         }
-        if self.adjust {
+        if self.auxiliary {
             word |= 0x0010;
         }
         if self.zero {
@@ -256,7 +241,7 @@ impl Flags {
     pub fn set_from_word(&mut self, word: u16) {
         self.carry = (word & 0x0001) != 0;
         self.parity = (word & 0x0004) != 0;
-        self.adjust = (word & 0x0010) != 0;
+        self.auxiliary = (word & 0x0010) != 0;
         self.zero = (word & 0x0040) != 0;
         self.sign = (word & 0x0080) != 0;
         self.trap = (word & 0x0100) != 0;
@@ -300,22 +285,6 @@ impl Flags {
         self.set_zero(value == 0);
         self.set_sign((value & 0x8000) != 0);
     }
-
-    pub fn update_flags_add16(&mut self, a: u16, b: u16, result: u16) {
-        self.set_carry(result < a);
-        self.set_zero(result == 0);
-        self.set_sign((result & 0x8000) != 0);
-        self.set_overflow(((a ^ result) & (b ^ result) & 0x8000) != 0);
-        self.set_parity(result.count_ones() % 2 == 0);
-    }
-
-    pub fn update_flags_sub16(&mut self, a: u16, b: u16, result: u16) {
-        self.set_carry(a < b);
-        self.set_zero(result == 0);
-        self.set_sign((result & 0x8000) != 0);
-        self.set_overflow(((a ^ b) & 0x8000) != 0 && ((a ^ result) & 0x8000) != 0);
-        self.set_parity(result.count_ones() % 2 == 0);
-    }
 }
 
 impl BitOr<u16> for Flags {
@@ -354,7 +323,7 @@ mod tests {
         let flags = Flags::new();
         assert!(!flags.get_carry());
         assert!(!flags.get_parity());
-        assert!(!flags.get_adjust());
+        assert!(!flags.get_auxiliary());
         assert!(!flags.get_zero());
         assert!(!flags.get_sign());
         assert!(!flags.get_trap());
@@ -383,6 +352,17 @@ mod tests {
         assert!(flags.get_direction());
     }
 
+    #[test]
+    fn test_auxiliary_is_bit_4() {
+        let mut flags = Flags::new();
+        flags.set_auxiliary(true);
+        assert_eq!(flags.as_word(), 0x0010);
+        assert!(flags.get_auxiliary());
+        assert!(Flags::from_word(0x0010).get_auxiliary());
+        flags.set_auxiliary(false);
+        assert_eq!(flags.as_word(), 0x0000);
+    }
+
     #[test]
     fn test_flags_as_word() {
         let mut flags = Flags::new();
@@ -409,30 +389,11 @@ mod tests {
 
         assert!(flags.get_carry()); // bit 0 set
         assert!(flags.get_parity()); // bit 2 set (corrected)
-        assert!(flags.get_adjust()); // bit 4 set
+        assert!(flags.get_auxiliary()); // bit 4 set
         assert!(!flags.get_zero()); // bit 6 clear
         assert!(flags.get_sign()); // bit 7 set
     }
 
-    #[test]
-    fn test_update_flags_arithmetic() {
-        let mut flags = Flags::new();
-
-        // Test addition with overflow
-        flags.update_flags_add16(0x7FFF, 0x0001, 0x8000);
-        assert!(!flags.get_carry());
-        assert!(!flags.get_zero());
-        assert!(flags.get_sign());
-        assert!(flags.get_overflow());
-
-        // Test addition with carry
-        flags.update_flags_add16(0xFFFF, 0x0001, 0x0000);
-        assert!(flags.get_carry());
-        assert!(flags.get_zero());
-        assert!(!flags.get_sign());
-        assert!(!flags.get_overflow());
-    }
-
     #[test]
     fn test_update_flags_logical() {
         let mut flags = Flags::new();
@@ -1,6 +1,8 @@
-use crate::cpu::Cpu;
+use crate::cpu::{CpuGeneric, CpuModel};
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub fn add_rm8_r8(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         println!("ModR/M byte: 0x{:02X}", modrm);
@@ -81,6 +83,74 @@ impl Cpu {
         Ok(())
     }
 
+    pub fn adc_rm16_r16(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let dest = self.get_rm16(modrm)?;
+        let src = self.regs.get_reg16((modrm >> 3) & 0x07);
+        let result = self.adc16(dest, src);
+        self.write_rm16(modrm, result)?;
+        Ok(())
+    }
+
+    pub fn adc_r16_rm16(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let src = self.get_rm16(modrm)?;
+        let reg = (modrm >> 3) & 0x07;
+        let dest = self.regs.get_reg16(reg);
+        let result = self.adc16(dest, src);
+        self.regs.set_reg16(reg, result)?;
+        Ok(())
+    }
+
+    // dest + src + CF. The carry out has to be tracked across both additions,
+    // since either one can wrap (0xFFFF + 0 + 1 carries from the second)
+    fn adc16(&mut self, dest: u16, src: u16) -> u16 {
+        let carry_in = self.regs.flags.get_carry() as u16;
+        let (partial, carry1) = dest.overflowing_add(src);
+        let (result, carry2) = partial.overflowing_add(carry_in);
+        println!(
+            "ADC16: dest={:04X}, src={:04X}, carry={} -> {:04X}",
+            dest, src, carry_in, result
+        );
+        self.regs.flags.set_carry(carry1 || carry2);
+        self.regs
+            .flags
+            .set_overflow(((dest ^ result) & (src ^ result) & 0x8000) != 0);
+        self.regs
+            .flags
+            .set_auxiliary((dest & 0x0F) + (src & 0x0F) + carry_in > 0x0F);
+        self.set_result_flags16(result);
+        result
+    }
+
+    // SBB borrows if src + CF exceeds dest, which also covers src = 0xFFFF with CF set
+    fn sbb16(&mut self, dest: u16, src: u16) -> u16 {
+        let borrow_in = self.regs.flags.get_carry() as u16;
+        let (partial, borrow1) = dest.overflowing_sub(src);
+        let (result, borrow2) = partial.overflowing_sub(borrow_in);
+        println!(
+            "SBB16: dest={:04X}, src={:04X}, borrow={} -> {:04X}",
+            dest, src, borrow_in, result
+        );
+        self.regs.flags.set_carry(borrow1 || borrow2);
+        self.regs
+            .flags
+            .set_overflow(((dest ^ src) & (dest ^ result) & 0x8000) != 0);
+        self.regs
+            .flags
+            .set_auxiliary((dest & 0x0F) < (src & 0x0F) + borrow_in);
+        self.set_result_flags16(result);
+        result
+    }
+
+    fn set_result_flags16(&mut self, result: u16) {
+        self.regs.flags.set_zero(result == 0);
+        self.regs.flags.set_sign((result & 0x8000) != 0);
+        self.regs
+            .flags
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
+    }
+
     pub fn add_ax_rm16(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         let rm_val = self.get_rm16(modrm)?;
@@ -105,19 +175,29 @@ impl Cpu {
 
     pub fn cmp_al_imm8(&mut self) -> Result<(), String> {
         let imm8 = self.fetch_byte()?;
-        let al = self.regs.get_reg8(0); // AL is register 0
-        let (result, carry) = al.overflowing_sub(imm8);
-        self.update_flags_sub(al, imm8, result, carry);
+        self.cmp8(self.regs.get_al(), imm8);
+        Ok(())
+    }
+
+    pub fn cmp_ax_imm16(&mut self) -> Result<(), String> {
+        let imm16 = self.fetch_word()?;
+        self.cmp16(self.regs.ax, imm16);
         Ok(())
     }
 
     pub fn cmp_r16_rm16(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         let rm_val = self.get_rm16(modrm)?;
-        let reg = (modrm >> 3) & 0x07;
-        let reg_val = self.regs.get_reg16(reg);
-        let (result, carry) = reg_val.overflowing_sub(rm_val);
-        self.update_flags_sub16(reg_val, rm_val, result, carry);
+        let reg_val = self.regs.get_reg16((modrm >> 3) & 0x07);
+        self.cmp16(reg_val, rm_val);
+        Ok(())
+    }
+
+    pub fn cmp_rm16_r16(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let rm_val = self.get_rm16(modrm)?;
+        let reg_val = self.regs.get_reg16((modrm >> 3) & 0x07);
+        self.cmp16(rm_val, reg_val);
         Ok(())
     }
 
@@ -125,9 +205,16 @@ impl Cpu {
         let modrm = self.fetch_byte()?;
         let rm_val = self.get_rm8(modrm)?;
         let reg_val = self.regs.get_reg8((modrm >> 3) & 0x07);
-        let (result, carry) = rm_val.overflowing_sub(reg_val);
         // CMP is like SUB but doesn't store the result
-        self.update_flags_sub(rm_val, reg_val, result, carry);
+        self.cmp8(rm_val, reg_val);
+        Ok(())
+    }
+
+    pub fn cmp_r8_rm8(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let rm_val = self.get_rm8(modrm)?;
+        let reg_val = self.regs.get_reg8((modrm >> 3) & 0x07);
+        self.cmp8(reg_val, rm_val);
         Ok(())
     }
 
@@ -139,19 +226,17 @@ impl Cpu {
         self.regs
             .flags
             .set_overflow(((a ^ result) & (b ^ result) & 0x80) != 0);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_auxiliary(((a ^ b ^ result) & 0x10) != 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
     }
 
-    pub fn update_flags_add16(&mut self, _a: u16, _b: u16, result: u16, carry: bool) {
+    pub fn update_flags_add16(&mut self, a: u16, b: u16, result: u16, carry: bool) {
+        self.update_flags_arithmetic_16(a, b, result, false);
         self.regs.flags.set_carry(carry);
-        self.regs.flags.set_zero(result == 0);
-        self.regs.flags.set_sign((result & 0x8000) != 0);
-        let result_i16 = result as i16;
-        let overflow = !(-0x8000..=0x7FFF).contains(&result_i16);
-        self.regs.flags.set_overflow(overflow);
-        self.regs
-            .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
     }
 
     // INC/DEC instructions
@@ -177,22 +262,52 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// IMUL r16, r/m16, imm16 (0x69, 80186+)
     pub fn imul_r16_rm16_imm16(&mut self) -> Result<(), String> {
+        self.require_model(CpuModel::I80186, "IMUL r16, r/m16, imm16")?;
         let modrm = self.fetch_byte()?;
         let rm_val = self.get_rm16(modrm)?;
         let imm = self.fetch_word()?;
         println!("IMUL: rm_val={:04X}, imm={:04X}", rm_val, imm);
-        let result = (rm_val as i16 as i32) * (imm as i16 as i32);
+        self.imul16_to_reg(modrm, rm_val, imm as i16)
+    }
+
+    /// IMUL r16, r/m16, imm8 (0x6B, 80186+); the immediate is sign-extended
+    pub fn imul_r16_rm16_imm8(&mut self) -> Result<(), String> {
+        self.require_model(CpuModel::I80186, "IMUL r16, r/m16, imm8")?;
+        let modrm = self.fetch_byte()?;
+        let rm_val = self.get_rm16(modrm)?;
+        let imm = self.fetch_byte()? as i8;
+        println!("IMUL: rm_val={:04X}, imm={:02X}", rm_val, imm);
+        self.imul16_to_reg(modrm, rm_val, imm as i16)
+    }
+
+    /// IMUL r16, r/m16 (0x0F 0xAF, 80386+)
+    pub fn imul_r16_rm16(&mut self) -> Result<(), String> {
+        self.require_model(CpuModel::I80386, "IMUL r16, r/m16")?;
+        let modrm = self.fetch_byte()?;
+        let rm_val = self.get_rm16(modrm)?;
+        let reg_val = self.regs.get_reg16((modrm >> 3) & 0x07);
+        self.imul16_to_reg(modrm, reg_val, rm_val as i16)
+    }
+
+    // Signed 16x16 multiply into the ModR/M reg field. CF and OF are set when
+    // the full product does not fit in a signed 16-bit result.
+    fn imul16_to_reg(
+        &mut self,
+        modrm: u8,
+        multiplicand: u16,
+        multiplier: i16,
+    ) -> Result<(), String> {
+        let result = (multiplicand as i16 as i32) * (multiplier as i32);
         println!("IMUL: result={:08X}", result);
         let reg = (modrm >> 3) & 0x07;
         self.regs.set_reg16(reg, result as u16)?;
-        // Set flags based on overflow
-        let overflow = !(-0x8000..=0x7FFF).contains(&(result as i16));
+        let overflow = result != (result as i16 as i32);
         self.regs.flags.set_carry(overflow);
         self.regs.flags.set_overflow(overflow);
         self.regs.flags.set_sign((result as i16) < 0);
-        self.regs.flags.set_zero(result == 0);
+        self.regs.flags.set_zero(result as i16 == 0);
         Ok(())
     }
 
@@ -254,11 +369,30 @@ impl Cpu {
         Ok(())
     }
 
+    pub fn sbb_rm16_r16(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let dest = self.get_rm16(modrm)?;
+        let src = self.regs.get_reg16((modrm >> 3) & 0x07);
+        let result = self.sbb16(dest, src);
+        self.write_rm16(modrm, result)?;
+        Ok(())
+    }
+
+    pub fn sbb_r16_rm16(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let src = self.get_rm16(modrm)?;
+        let reg = (modrm >> 3) & 0x07;
+        let dest = self.regs.get_reg16(reg);
+        let result = self.sbb16(dest, src);
+        self.regs.set_reg16(reg, result)?;
+        Ok(())
+    }
+
     pub fn aam(&mut self) -> Result<(), String> {
         let al = self.regs.get_al();
         let divisor = self.fetch_byte()?;
         if divisor == 0 {
-            return Err("Division by zero in AAM".to_string());
+            return Err(self.divide_error("Division by zero in AAM"));
         }
         let ah = al / divisor;
         let al_new = al % divisor;
@@ -268,7 +402,9 @@ impl Cpu {
         // Update flags based on the result in AL
         self.regs.flags.set_sign((al_new & 0x80) != 0);
         self.regs.flags.set_zero(al_new == 0);
-        self.regs.flags.set_parity(al_new.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(al_new.count_ones().is_multiple_of(2));
         // Carry and overflow are undefined by the AAM instruction
 
         Ok(())
@@ -283,7 +419,7 @@ impl Cpu {
         let reg_val = self.regs.get_reg16(reg);
         let result = rm_val.wrapping_sub(reg_val);
         self.regs.set_reg16(rm, result)?;
-        self.regs.flags.update_flags_sub16(rm_val, reg_val, result);
+        self.update_flags_sub16(rm_val, reg_val, result, rm_val < reg_val);
         Ok(())
     }
 
@@ -330,7 +466,7 @@ impl Cpu {
 
         // Check for division by zero
         if rm_val == 0 {
-            return Err("Division by zero".to_string());
+            return Err(self.divide_error("Division by zero"));
         }
 
         // Get 32-bit dividend from DX:AX
@@ -342,7 +478,7 @@ impl Cpu {
         let remainder = dividend % divisor;
 
         if quotient > 0xFFFF {
-            return Err("Division overflow".to_string());
+            return Err(self.divide_error("Division overflow"));
         }
 
         // Store results
@@ -358,6 +494,9 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_support::run_one;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -370,30 +509,119 @@ mod tests {
         Cpu::new(memory, serial, disk)
     }
 
+    // CMP flags for a - b, shared by every encoding: (a, b, CF, ZF, SF, OF)
+    const CMP_CASES: [(u16, u16, bool, bool, bool, bool); 6] = [
+        (0x05, 0x05, false, true, false, false), // equal
+        (0x01, 0x02, true, false, true, false),  // below
+        (0x80, 0x01, false, false, false, true), // -128 - 1 overflows
+        (0x7F, 0xFF, true, false, true, true),   // 127 - -1 overflows
+        (0x00, 0x80, true, false, true, true),   // 0 - -128 overflows
+        (0xFF, 0x01, false, false, true, false), // above, negative result
+    ];
+
+    fn cmp_flags(cpu: &Cpu) -> (bool, bool, bool, bool) {
+        let flags = &cpu.regs.flags;
+        (
+            flags.get_carry(),
+            flags.get_zero(),
+            flags.get_sign(),
+            flags.get_overflow(),
+        )
+    }
+
+    #[test]
+    fn test_cmp_flags_agree_across_encodings() {
+        for (a, b, cf, zf, sf, of) in CMP_CASES {
+            let expected = (cf, zf, sf, of);
+            // The byte cases moved into the high byte give the same flags
+            let (wa, wb) = (a << 8, b << 8);
+            let (a, b) = (a as u8, b as u8);
+
+            let byte_forms: [(&[u8], &str); 5] = [
+                (&[0x3C, b], "CMP AL,imm8"),
+                (&[0x38, 0xD8], "CMP AL,BL (38)"),
+                (&[0x3A, 0xC3], "CMP AL,BL (3A)"),
+                (&[0x80, 0xF8, b], "CMP AL,imm8 (80)"),
+                (&[0xAE], "SCASB"),
+            ];
+            for (code, name) in byte_forms {
+                let cpu = run_one(
+                    |cpu| {
+                        cpu.regs.set_al(a);
+                        cpu.regs.set_bl(b);
+                        cpu.regs.di = 0x0300;
+                        cpu.poke_byte(cpu.regs.es, 0x0300, b);
+                    },
+                    code,
+                );
+                assert_eq!(cmp_flags(&cpu), expected, "{} {:02X},{:02X}", name, a, b);
+                assert_eq!(cpu.regs.get_al(), a, "{} changed AL", name);
+            }
+
+            let [wb_low, wb_high] = wb.to_le_bytes();
+            let word_forms: [(&[u8], &str); 5] = [
+                (&[0x3D, wb_low, wb_high], "CMP AX,imm16"),
+                (&[0x39, 0xD8], "CMP AX,BX (39)"),
+                (&[0x3B, 0xC3], "CMP AX,BX (3B)"),
+                (&[0x81, 0xF8, wb_low, wb_high], "CMP AX,imm16 (81)"),
+                (&[0xAF], "SCASW"),
+            ];
+            for (code, name) in word_forms {
+                let cpu = run_one(
+                    |cpu| {
+                        cpu.regs.ax = wa;
+                        cpu.regs.bx = wb;
+                        cpu.regs.di = 0x0300;
+                        cpu.poke_word(cpu.regs.es, 0x0300, wb);
+                    },
+                    code,
+                );
+                assert_eq!(cmp_flags(&cpu), expected, "{} {:04X},{:04X}", name, wa, wb);
+                assert_eq!(cpu.regs.ax, wa, "{} changed AX", name);
+            }
+
+            // CMPSB compares DS:SI against ES:DI, in that order
+            let cpu = run_one(
+                |cpu| {
+                    cpu.regs.si = 0x0200;
+                    cpu.regs.di = 0x0300;
+                    cpu.poke_byte(cpu.regs.ds, 0x0200, a);
+                    cpu.poke_byte(cpu.regs.es, 0x0300, b);
+                },
+                &[0xA6],
+            );
+            assert_eq!(cmp_flags(&cpu), expected, "CMPSB {:02X},{:02X}", a, b);
+        }
+    }
+
     #[test]
     fn test_add_rm8_r8() {
-        let mut cpu = setup_cpu();
-        // Set AL to 5 and AH to 5
+        let mut cpu = setup_executable_cpu(&[]);
         cpu.regs.set_al(5);
         cpu.regs.set_ah(5);
-        // Set CS to 0 so physical address matches IP
-        cpu.regs.cs = 0;
-        // Set IP to a known location
-        cpu.regs.ip = 0x100;
-        // ModR/M byte: 0xE0 = 11 100 000
-        // 11: register-to-register mode
-        // 100: source register is AH (reg 4)
-        // 000: destination register is AL (reg 0)
-        cpu.memory.write_byte(0x100, 0xE0);
-        assert!(cpu.add_rm8_r8().is_ok());
-        // Result should be AL = AL + AH = 5 + 5 = 10
+        // ADD AL,AH: ModR/M 11 100 000, register mode with AH as the source
+        cpu.step_bytes(&[0x00, 0xE0]).unwrap();
         assert_eq!(cpu.regs.get_al(), 10);
+        assert_eq!(cpu.regs.ip, 0x102);
         // Check flags
         assert!(!cpu.regs.flags.get_carry()); // No carry expected
         assert!(!cpu.regs.flags.get_zero()); // Result is not zero
         assert!(!cpu.regs.flags.get_sign()); // Result is positive
     }
 
+    #[test]
+    fn test_opcode_00_dispatches_to_add_rm8_r8() {
+        // ADD AH,AL: 0x00 adds the reg field into r/m, the reverse of 0x02
+        let mut cpu = setup_executable_cpu(&[]);
+        cpu.regs.set_al(1);
+        cpu.regs.set_ah(2);
+        cpu.step_bytes(&[0x00, 0xC4]).unwrap();
+        assert_eq!(cpu.regs.get_ah(), 3);
+        assert_eq!(cpu.regs.get_al(), 1);
+        assert_eq!(cpu.opcode_coverage()[0x00], 1);
+        assert_eq!(cpu.regs.ip, 0x102);
+    }
+
     #[test]
     fn test_add_rm16_r16() {
         let mut cpu = setup_cpu();
@@ -466,19 +694,19 @@ mod tests {
 
     #[test]
     fn test_cmp_al_imm8() {
-        let mut cpu = setup_cpu();
+        let mut cpu = setup_executable_cpu(&[]);
         cpu.regs.ax = 0x0005; // AL = 5
-        cpu.memory.write_byte(0, 0x03); // Compare with 3
-        assert!(cpu.cmp_al_imm8().is_ok());
-        assert!(cpu.regs.flags.get_carry() == false); // 5 > 3, no borrow needed
-        assert!(cpu.regs.flags.get_zero() == false); // Result is not zero
+        cpu.step_bytes(&[0x3C, 0x03]).unwrap(); // Compare with 3
+        assert_eq!(cpu.regs.ax, 0x0005);
+        assert!(!cpu.regs.flags.get_carry()); // 5 > 3, no borrow needed
+        assert!(!cpu.regs.flags.get_zero()); // Result is not zero
     }
 
     #[test]
     fn test_inc_ax() {
-        let mut cpu = setup_cpu();
+        let mut cpu = setup_executable_cpu(&[]);
         cpu.regs.ax = 0x1234;
-        assert!(cpu.inc_ax().is_ok());
+        cpu.step_bytes(&[0x40]).unwrap();
         assert_eq!(cpu.regs.ax, 0x1235);
     }
 
@@ -493,6 +721,7 @@ mod tests {
     #[test]
     fn test_imul_r16_rm16_imm16() {
         let mut cpu = setup_cpu();
+        cpu.set_model(CpuModel::I80186);
         // Set AX to 2
         cpu.regs.set_ax(2);
         // Set CS to 0 so physical address matches IP
@@ -528,6 +757,68 @@ mod tests {
         assert!(!cpu.regs.flags.get_zero()); // Result is not zero
     }
 
+    #[test]
+    fn test_imul_r16_rm16_imm8() {
+        let mut cpu = setup_cpu();
+        cpu.set_model(CpuModel::I80186);
+        cpu.regs.cs = 0;
+        cpu.regs.ip = 0x100;
+        // IMUL CX, BX, -3 with BX = 100
+        cpu.regs.bx = 100;
+        cpu.memory.write_byte(0x100, 0xCB); // 11 001 011: dest=CX, src=BX
+        cpu.memory.write_byte(0x101, 0xFD); // -3
+        assert!(cpu.imul_r16_rm16_imm8().is_ok());
+        assert_eq!(cpu.regs.cx, (-300i16) as u16);
+        assert!(!cpu.regs.flags.get_carry());
+        assert!(!cpu.regs.flags.get_overflow());
+        assert!(cpu.regs.flags.get_sign());
+
+        // 0x4000 * 4 = 0x10000 doesn't fit in 16 bits
+        cpu.regs.ip = 0x100;
+        cpu.regs.bx = 0x4000;
+        cpu.memory.write_byte(0x101, 0x04);
+        assert!(cpu.imul_r16_rm16_imm8().is_ok());
+        assert_eq!(cpu.regs.cx, 0x0000);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_imul_imm16_overflow() {
+        let mut cpu = setup_cpu();
+        cpu.set_model(CpuModel::I80186);
+        cpu.regs.cs = 0;
+        cpu.regs.ip = 0x100;
+        // IMUL AX, AX, -1000 with AX = 1000
+        cpu.regs.ax = 1000;
+        cpu.memory.write_byte(0x100, 0xC0);
+        cpu.memory.write_word(0x101, (-1000i16) as u16);
+        assert!(cpu.imul_r16_rm16_imm16().is_ok());
+        assert_eq!(cpu.regs.ax, (-1_000_000i32) as u16);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_imul_model_gate() {
+        let mut cpu = setup_cpu();
+        cpu.regs.cs = 0;
+        cpu.regs.ip = 0x100;
+        cpu.memory.write_byte(0x100, 0xC0);
+        cpu.memory.write_byte(0x101, 0x02);
+        let err = cpu.imul_r16_rm16_imm8().unwrap_err();
+        assert!(err.contains("8086"));
+
+        // The two-operand form needs a 386
+        cpu.set_model(CpuModel::I80186);
+        cpu.regs.ip = 0x100;
+        assert!(cpu.imul_r16_rm16().is_err());
+        cpu.set_model(CpuModel::I80386);
+        cpu.regs.ax = 7;
+        assert!(cpu.imul_r16_rm16().is_ok());
+        assert_eq!(cpu.regs.ax, 49);
+    }
+
     #[test]
     fn test_salc() {
         let mut cpu = setup_cpu();
@@ -536,6 +827,39 @@ mod tests {
         assert_eq!(cpu.regs.ax & 0xFF, 0xFF); // AL should be set to 0xFF when carry is set
     }
 
+    #[test]
+    fn test_salc_follows_undocumented_policy() {
+        // STC; SALC
+        let mut cpu = setup_executable_cpu(&[0xF9, 0xD6]);
+        assert!(cpu.allow_undocumented());
+        cpu.regs.ax = 0x1200;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ax, 0x12FF);
+
+        let mut cpu = setup_executable_cpu(&[0xF9, 0xD6]);
+        cpu.memory.write_word(6 * 4, 0x0500);
+        cpu.memory.write_word(6 * 4 + 2, 0x0000);
+        cpu.set_allow_undocumented(false);
+        cpu.regs.ax = 0x1200;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ax, 0x1200);
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0500));
+        // The return address is the SALC itself
+        assert_eq!(cpu.peek_word(0, cpu.regs.sp), 0x0101);
+
+        // Likewise the 0x82 alias and shift /6
+        for code in [[0x82, 0xC0, 0x01], [0xD0, 0xF0, 0x90]] {
+            let mut cpu = setup_executable_cpu(&code);
+            cpu.memory.write_word(6 * 4, 0x0500);
+            cpu.memory.write_word(6 * 4 + 2, 0x0000);
+            cpu.set_allow_undocumented(false);
+            cpu.step().unwrap();
+            assert_eq!(cpu.regs.ip, 0x0500, "{:02X?}", code);
+        }
+    }
+
     #[test]
     fn test_aam() {
         let mut cpu = setup_cpu();
@@ -572,4 +896,133 @@ mod tests {
         cpu.memory.write_byte(0x300, 0);
         assert!(cpu.aam().is_err());
     }
+
+    #[test]
+    fn test_adc_chains_32bit_add_across_dx_ax() {
+        // DX:AX = 0x0001_FFFF, CX:BX = 0x0000_0001
+        // ADD AX,BX (01 D8) then ADC DX,CX (11 CA)
+        let mut cpu = setup_executable_cpu(&[0x01, 0xD8, 0x11, 0xCA]);
+        cpu.regs.ax = 0xFFFF;
+        cpu.regs.dx = 0x0001;
+        cpu.regs.bx = 0x0001;
+        cpu.regs.cx = 0x0000;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0x0000);
+        assert!(cpu.regs.flags.get_carry());
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.dx, 0x0002);
+        assert!(!cpu.regs.flags.get_carry());
+        assert!(!cpu.regs.flags.get_zero());
+
+        // The second stage alone can carry: 0xFFFF + 0 + CF
+        cpu.regs.ip = 0x100;
+        cpu.regs.dx = 0xFFFF;
+        cpu.regs.cx = 0x0000;
+        cpu.regs.flags.set_carry(true);
+        cpu.memory.write_byte(0x100, 0x13); // ADC DX,CX (r16, r/m16 form)
+        cpu.memory.write_byte(0x101, 0xD1);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.dx, 0x0000);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_zero());
+        assert!(cpu.regs.flags.get_auxiliary());
+        assert!(!cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_sbb_chains_32bit_subtract() {
+        // 0x0002_0000 - 0x0000_0001 = 0x0001_FFFF, borrowing across DX:AX
+        // SBB AX,BX (19 D8) then SBB DX,CX (1B D1)
+        let mut cpu = setup_executable_cpu(&[0x19, 0xD8, 0x1B, 0xD1]);
+        cpu.regs.ax = 0x0000;
+        cpu.regs.dx = 0x0002;
+        cpu.regs.bx = 0x0001;
+        cpu.regs.cx = 0x0000;
+        cpu.regs.flags.set_carry(false);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0xFFFF);
+        assert!(cpu.regs.flags.get_carry());
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.dx, 0x0001);
+        assert!(!cpu.regs.flags.get_carry());
+
+        // Borrow in with src = 0xFFFF must still borrow out
+        cpu.regs.ip = 0x100;
+        cpu.regs.ax = 0xFFFF;
+        cpu.regs.bx = 0xFFFF;
+        cpu.regs.flags.set_carry(true);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0xFFFF);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_sign());
+        assert!(!cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_add_r16_signed_overflow() {
+        // ADD AX,BX in both encodings: 01 D8 (r/m16,r16) and 03 C3 (r16,r/m16)
+        for code in [[0x01, 0xD8], [0x03, 0xC3]] {
+            let cpu = run_one(
+                |cpu| {
+                    cpu.regs.ax = 0x7FFF;
+                    cpu.regs.bx = 0x0001;
+                },
+                &code,
+            );
+            assert_eq!(cpu.regs.ax, 0x8000);
+            assert!(cpu.regs.flags.get_overflow());
+            assert!(!cpu.regs.flags.get_carry());
+
+            let cpu = run_one(
+                |cpu| {
+                    cpu.regs.ax = 0xFFFF;
+                    cpu.regs.bx = 0x0001;
+                },
+                &code,
+            );
+            assert_eq!(cpu.regs.ax, 0x0000);
+            assert!(cpu.regs.flags.get_carry());
+            assert!(!cpu.regs.flags.get_overflow());
+            assert!(cpu.regs.flags.get_zero());
+        }
+    }
+
+    #[test]
+    fn test_sub_and_dec_set_auxiliary_on_nibble_borrow() {
+        // SUB AL,1 through group 1, DEC AL, and DEC r/m16 / DEC SI for words
+        for code in [
+            &[0x80, 0xE8, 0x01][..],
+            &[0xFE, 0xC8],
+            &[0xFF, 0xC8],
+            &[0x4E],
+        ] {
+            let cpu = run_one(
+                |cpu| {
+                    cpu.regs.ax = 0x0010;
+                    cpu.regs.si = 0x0010;
+                    cpu.regs.flags.set_carry(true);
+                },
+                code,
+            );
+            assert!(cpu.regs.flags.get_auxiliary(), "{:02X?}", code);
+            if code[0] != 0x80 {
+                // INC and DEC leave CF as it was
+                assert!(cpu.regs.flags.get_carry(), "{:02X?}", code);
+            }
+
+            let cpu = run_one(
+                |cpu| {
+                    cpu.regs.ax = 0x0011;
+                    cpu.regs.si = 0x0011;
+                },
+                code,
+            );
+            assert!(!cpu.regs.flags.get_auxiliary(), "{:02X?}", code);
+        }
+
+        // DEC of 0x80 is a signed overflow
+        let cpu = run_one(|cpu| cpu.regs.set_al(0x80), &[0xFE, 0xC8]);
+        assert_eq!(cpu.regs.get_al(), 0x7F);
+        assert!(cpu.regs.flags.get_overflow());
+    }
 }
@@ -1,6 +1,8 @@
-use crate::cpu::Cpu;
+use crate::cpu::{timing, CpuGeneric};
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub(crate) fn jmp_near(&mut self) -> Result<(), String> {
         let old_ip = self.regs.ip;
         let offset = self.fetch_word()? as i16;
@@ -29,10 +31,7 @@ impl Cpu {
     }
 
     pub fn jmp_short(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        let _old_ip = self.regs.ip;
-        self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        Ok(())
+        self.jump_rel8(true)
     }
 
     pub(crate) fn call_near(&mut self) -> Result<(), String> {
@@ -80,14 +79,12 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn ret_near(&mut self) -> Result<(), String> {
         let ip = self.pop_word()?;
         self.regs.ip = ip;
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn ret_far(&mut self) -> Result<(), String> {
         let old_cs = self.regs.cs;
         let old_ip = self.regs.ip;
@@ -108,7 +105,8 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
+    // RET imm16 and RETF imm16 release the caller's arguments: the return
+    // address comes off the stack first and only then is imm16 added to SP
     pub fn ret_near_imm16(&mut self) -> Result<(), String> {
         let ip = self.pop_word()?;
         let imm16 = self.fetch_word()?;
@@ -129,9 +127,10 @@ impl Cpu {
             old_ip, self.regs.cx, offset as u8
         );
 
+        self.charge_branch(timing::JCXZ, self.regs.cx == 0);
         if self.regs.cx == 0 {
             // Jump relative to next instruction
-            self.regs.ip = next_ip.wrapping_add(offset as u16);
+            self.regs.ip = next_ip.wrapping_add(offset as i16 as u16);
             println!("JCXZ: Jumping to 0x{:04X}", self.regs.ip);
         } else {
             println!("JCXZ: Not jumping, CX != 0");
@@ -153,9 +152,10 @@ impl Cpu {
             old_ip, old_cx, self.regs.cx, offset as u8
         );
 
+        self.charge_branch(timing::LOOP, self.regs.cx != 0);
         if self.regs.cx != 0 {
             // Jump relative to next instruction
-            self.regs.ip = next_ip.wrapping_add(offset as u16);
+            self.regs.ip = next_ip.wrapping_add(offset as i16 as u16);
             println!("LOOP: Jumping to 0x{:04X}", self.regs.ip);
         } else {
             println!("LOOP: Not jumping, CX = 0");
@@ -164,82 +164,127 @@ impl Cpu {
         Ok(())
     }
 
-    pub(crate) fn jz_rel8(&mut self) -> Result<(), String> {
+    // LOOPZ/LOOPNZ: decrement CX without touching the flags, and jump while
+    // CX is non-zero and ZF is `zero`
+    fn loop_while_zero(&mut self, zero: bool, cost: timing::BranchCost) -> Result<(), String> {
+        self.regs.cx = self.regs.cx.wrapping_sub(1);
+        let taken = self.regs.cx != 0 && self.regs.flags.get_zero() == zero;
+        self.charge_branch(cost, taken);
+        self.jump_rel8(taken)
+    }
+
+    pub(crate) fn loopz(&mut self) -> Result<(), String> {
+        self.loop_while_zero(true, timing::LOOPZ)
+    }
+
+    pub(crate) fn loopnz(&mut self) -> Result<(), String> {
+        self.loop_while_zero(false, timing::LOOPNZ)
+    }
+
+    // Every short jump: the displacement byte is sign-extended and added to
+    // the IP of the next instruction, so 0xFE lands back on the jump itself
+    fn jump_rel8(&mut self, taken: bool) -> Result<(), String> {
         let offset = self.fetch_byte()? as i8;
-        if self.regs.flags.get_zero() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
+        if taken {
+            self.regs.ip = self.regs.ip.wrapping_add(offset as i16 as u16);
         }
         Ok(())
     }
 
+    // The conditional forms, charged by whether the jump happened
+    fn jcc_rel8(&mut self, taken: bool) -> Result<(), String> {
+        self.charge_branch(timing::JCC, taken);
+        self.jump_rel8(taken)
+    }
+
+    pub(crate) fn jz_rel8(&mut self) -> Result<(), String> {
+        let taken = self.regs.flags.get_zero();
+        self.jcc_rel8(taken)
+    }
+
     pub(crate) fn jnz_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if !self.regs.flags.get_zero() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = !self.regs.flags.get_zero();
+        self.jcc_rel8(taken)
     }
 
     pub(crate) fn jo_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if self.regs.flags.get_overflow() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = self.regs.flags.get_overflow();
+        self.jcc_rel8(taken)
     }
 
     pub(crate) fn jno_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if !self.regs.flags.get_overflow() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = !self.regs.flags.get_overflow();
+        self.jcc_rel8(taken)
     }
 
     pub(crate) fn jb_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if self.regs.flags.get_carry() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = self.regs.flags.get_carry();
+        self.jcc_rel8(taken)
     }
 
     pub(crate) fn jnb_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if !self.regs.flags.get_carry() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = !self.regs.flags.get_carry();
+        self.jcc_rel8(taken)
     }
 
     pub(crate) fn jbe_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if self.regs.flags.get_carry() || self.regs.flags.get_zero() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = self.regs.flags.get_carry() || self.regs.flags.get_zero();
+        self.jcc_rel8(taken)
     }
 
     pub(crate) fn jnbe_rel8(&mut self) -> Result<(), String> {
-        let offset = self.fetch_byte()? as i8;
-        if !self.regs.flags.get_carry() && !self.regs.flags.get_zero() {
-            let _old_ip = self.regs.ip;
-            self.regs.ip = self.regs.ip.wrapping_add(offset as u16);
-        }
-        Ok(())
+        let taken = !self.regs.flags.get_carry() && !self.regs.flags.get_zero();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn js_rel8(&mut self) -> Result<(), String> {
+        let taken = self.regs.flags.get_sign();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jns_rel8(&mut self) -> Result<(), String> {
+        let taken = !self.regs.flags.get_sign();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jp_rel8(&mut self) -> Result<(), String> {
+        let taken = self.regs.flags.get_parity();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jnp_rel8(&mut self) -> Result<(), String> {
+        let taken = !self.regs.flags.get_parity();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jl_rel8(&mut self) -> Result<(), String> {
+        let taken = self.regs.flags.get_sign() != self.regs.flags.get_overflow();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jnl_rel8(&mut self) -> Result<(), String> {
+        let taken = self.regs.flags.get_sign() == self.regs.flags.get_overflow();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jle_rel8(&mut self) -> Result<(), String> {
+        let taken = self.regs.flags.get_zero()
+            || self.regs.flags.get_sign() != self.regs.flags.get_overflow();
+        self.jcc_rel8(taken)
+    }
+
+    pub(crate) fn jnle_rel8(&mut self) -> Result<(), String> {
+        let taken = !self.regs.flags.get_zero()
+            && self.regs.flags.get_sign() == self.regs.flags.get_overflow();
+        self.jcc_rel8(taken)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -304,7 +349,7 @@ mod tests {
         assert!(cpu.call_near().is_ok());
 
         // Check that old IP was pushed to SS:SP
-        let stack_addr = ((cpu.regs.ss as u32) << 4) | (cpu.regs.sp as u32);
+        let stack_addr = cpu.get_physical_address(cpu.regs.ss, cpu.regs.sp);
         assert_eq!(
             cpu.memory.read_word(stack_addr),
             0x0002, // Old IP is after the offset word
@@ -504,4 +549,174 @@ mod tests {
         assert_eq!(cpu.regs.cx, 0xFFFF, "CX should underflow to 0xFFFF");
         assert_eq!(cpu.regs.ip, 0x1011, "Should jump when CX becomes 0xFFFF"); // IP + 1 + offset
     }
+
+    #[test]
+    fn test_jmp_short_self_loop() {
+        // JMP $ (EB FE) jumps back onto itself
+        let mut cpu = setup_executable_cpu(&[0xEB, 0xFE]);
+        for _ in 0..3 {
+            cpu.execute_instruction().unwrap();
+            assert_eq!(cpu.regs.ip, 0x100);
+        }
+    }
+
+    #[test]
+    fn test_short_jumps_sign_extend_backwards() {
+        // JZ -128 from the end of a 2-byte jump at 0x1FE lands on 0x180
+        let mut cpu = setup_executable_cpu(&[]);
+        cpu.memory.write_byte(0x1FE, 0x74);
+        cpu.memory.write_byte(0x1FF, 0x80);
+        cpu.regs.ip = 0x1FE;
+        cpu.regs.flags.set_zero(true);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ip, 0x180);
+
+        // JCXZ (E3) with CX=0 and LOOP (E2) both go backwards too
+        let mut cpu = setup_executable_cpu(&[0x90, 0xE3, 0xFD]);
+        cpu.regs.cx = 0;
+        cpu.execute_instruction().unwrap();
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ip, 0x100);
+
+        let mut cpu = setup_executable_cpu(&[0xE2, 0xFE]);
+        cpu.regs.cx = 2;
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.ip, cpu.regs.cx), (0x100, 1));
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.ip, cpu.regs.cx), (0x102, 0));
+
+        // A backward jump from near the bottom of the segment wraps
+        let mut cpu = setup_executable_cpu(&[]);
+        cpu.memory.write_byte(0x10, 0xEB);
+        cpu.memory.write_byte(0x11, 0xF0);
+        cpu.regs.ip = 0x10;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ip, 0x0002);
+        cpu.regs.ip = 0x0000;
+        cpu.memory.write_byte(0x0, 0xEB);
+        cpu.memory.write_byte(0x1, 0x80);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ip, 0xFF82);
+    }
+
+    #[test]
+    fn test_signed_conditional_jumps() {
+        // CMP AL,imm8 then JL/JG/JLE/JGE/JS/JP over a 1-byte gap
+        let cases: [(u8, u8, u8, bool); 8] = [
+            (0xFF, 0x01, 0x7C, true),  // -1 < 1: JL taken
+            (0x01, 0xFF, 0x7C, false), // 1 < -1 is false
+            (0x01, 0xFF, 0x7F, true),  // 1 > -1: JG taken
+            (0x05, 0x05, 0x7E, true),  // JLE on equal
+            (0x05, 0x05, 0x7D, true),  // JGE on equal
+            (0x80, 0x01, 0x7C, true),  // -128 - 1 overflows, still less
+            (0x00, 0x01, 0x78, true),  // 0 - 1 is negative: JS
+            (0x04, 0x01, 0x7A, true),  // 3 has even parity: JP
+        ];
+        for (al, imm, jcc, taken) in cases {
+            let mut cpu = setup_executable_cpu(&[0x3C, imm, jcc, 0x01, 0x90, 0x90]);
+            cpu.regs.set_al(al);
+            cpu.execute_instruction().unwrap();
+            cpu.execute_instruction().unwrap();
+            let expected = if taken { 0x105 } else { 0x104 };
+            assert_eq!(
+                cpu.regs.ip, expected,
+                "AL={:02X} imm={:02X} Jcc={:02X}",
+                al, imm, jcc
+            );
+        }
+    }
+
+    // Lays a return frame out at 0000:1000: the words, then a marker word
+    // where SP should end up
+    fn setup_return_frame(code: &[u8], frame: &[u16]) -> Cpu {
+        let mut cpu = setup_executable_cpu(code);
+        cpu.regs.sp = 0x1000;
+        for (i, &word) in frame.iter().enumerate() {
+            cpu.memory.write_word(0x1000 + 2 * i as u32, word);
+        }
+        cpu
+    }
+
+    #[test]
+    fn test_ret_imm16_pops_then_releases_arguments() {
+        // RET 4 with two argument words above the return address
+        let mut cpu = setup_return_frame(&[0xC2, 0x04, 0x00], &[0x0200, 0x1111, 0x2222]);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ip, 0x0200);
+        assert_eq!(cpu.regs.sp, 0x1000 + 2 + 4);
+
+        // RETF 6 pops IP then CS, then skips three argument words
+        let mut cpu = setup_return_frame(&[0xCA, 0x06, 0x00], &[0x0300, 0x1234, 1, 2, 3]);
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x1234, 0x0300));
+        assert_eq!(cpu.regs.sp, 0x1000 + 4 + 6);
+
+        // Plain RET and RETF leave nothing behind
+        let mut cpu = setup_return_frame(&[0xC3], &[0x0400]);
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.ip, cpu.regs.sp), (0x0400, 0x1002));
+        let mut cpu = setup_return_frame(&[0xCB], &[0x0500, 0x2000]);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(
+            (cpu.regs.cs, cpu.regs.ip, cpu.regs.sp),
+            (0x2000, 0x0500, 0x1004)
+        );
+    }
+
+    #[test]
+    fn test_branch_clocks_depend_on_whether_taken() {
+        // JZ +0 taken and not taken, then LOOP and JCXZ falling through
+        for (zero, clocks) in [(true, timing::JCC.taken), (false, timing::JCC.not_taken)] {
+            let mut cpu = setup_executable_cpu(&[0x74, 0x00]);
+            cpu.regs.flags.set_zero(zero);
+            let before = cpu.clocks;
+            cpu.step().unwrap();
+            assert_eq!(cpu.clocks - before, clocks, "ZF={}", zero);
+            assert_eq!(cpu.cycles, 1);
+        }
+        assert_eq!(timing::JCC.taken, 16);
+        assert_eq!(timing::JCC.not_taken, 4);
+
+        let mut cpu = setup_executable_cpu(&[0xE2, 0xFE, 0xE3, 0x00, 0x90]);
+        cpu.regs.cx = 2;
+        cpu.step().unwrap();
+        assert_eq!(cpu.clocks, timing::LOOP.taken);
+        cpu.step().unwrap();
+        assert_eq!(cpu.clocks, timing::LOOP.taken + timing::LOOP.not_taken);
+        cpu.step().unwrap(); // JCXZ with CX=0 is taken
+        cpu.step().unwrap(); // NOP has no cost in `timing`
+        assert_eq!(
+            cpu.clocks,
+            timing::LOOP.taken + timing::LOOP.not_taken + timing::JCXZ.taken
+        );
+        // The timer still sees one cycle per instruction
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn test_loopz_and_loopnz() {
+        // LOOPZ -2 (back onto itself) runs while CX != 0 and ZF is set
+        let mut cpu = setup_executable_cpu(&[0xE1, 0xFE]);
+        cpu.regs.cx = 3;
+        cpu.regs.flags.set_zero(true);
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.ip), (2, 0x0100));
+        assert_eq!(cpu.clocks, timing::LOOPZ.taken);
+        cpu.regs.flags.set_zero(false);
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.ip), (1, 0x0102));
+        assert_eq!(cpu.clocks, timing::LOOPZ.taken + timing::LOOPZ.not_taken);
+
+        // LOOPNZ wants ZF clear, and stops when CX runs out either way
+        let mut cpu = setup_executable_cpu(&[0xE0, 0xFE]);
+        cpu.regs.cx = 2;
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.ip), (1, 0x0100));
+        assert_eq!(cpu.clocks, timing::LOOPNZ.taken);
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.ip), (0, 0x0102));
+        assert_eq!(cpu.clocks, timing::LOOPNZ.taken + timing::LOOPNZ.not_taken);
+        // Without touching the flags
+        assert!(!cpu.regs.flags.get_zero());
+    }
 }
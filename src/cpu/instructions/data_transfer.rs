@@ -1,6 +1,8 @@
-use crate::cpu::Cpu;
+use crate::cpu::CpuGeneric;
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub(crate) fn mov_rm8_r8(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         println!("MOV: ModR/M byte = 0x{:02X}", modrm);
@@ -51,12 +53,44 @@ impl Cpu {
 
     pub(crate) fn mov_sreg_rm16(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
-        let rm_val = self.get_rm16(modrm)?;
         let sreg = (modrm >> 3) & 0x03;
+        if sreg == 1 {
+            // MOV CS is not a valid encoding
+            return self.raise_invalid_opcode();
+        }
+        let rm_val = self.get_rm16(modrm)?;
         self.regs.set_sreg(sreg, rm_val);
+        if sreg == 2 {
+            self.interrupt_shadow = true;
+        }
         Ok(())
     }
 
+    // C6 /0: the displacement comes before the immediate, so the operand
+    // is decoded first. /1-/7 are not defined on the 8086.
+    pub(crate) fn mov_rm8_imm8(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        if (modrm >> 3) & 0x07 != 0 {
+            return self.raise_invalid_opcode();
+        }
+        let operand = self.decode_rm(modrm)?;
+        let value = self.fetch_byte()?;
+        println!("MOV: rm8 <- imm 0x{:02X}", value);
+        self.write_operand8(operand, value)
+    }
+
+    // C7 /0
+    pub(crate) fn mov_rm16_imm16(&mut self) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        if (modrm >> 3) & 0x07 != 0 {
+            return self.raise_invalid_opcode();
+        }
+        let operand = self.decode_rm(modrm)?;
+        let value = self.fetch_word()?;
+        println!("MOV: rm16 <- imm 0x{:04X}", value);
+        self.write_operand16(operand, value)
+    }
+
     #[allow(dead_code)]
     pub(crate) fn mov_al_moffs8(&mut self) -> Result<(), String> {
         let offset = self.fetch_word()?;
@@ -131,6 +165,23 @@ impl Cpu {
         Ok(())
     }
 
+    // 0x90 encodes XCHG AX,AX, which does nothing; it gets its own handler
+    // so it reads as a NOP in traces and never goes through the swap
+    pub(crate) fn nop(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    // CMOVcc (0x0F 0x40-0x4F) arrived with the Pentium Pro. Once a model
+    // that new exists this becomes a `require_model` check followed by the
+    // move; for now every model rejects it before the ModR/M byte is read.
+    pub(crate) fn cmovcc(&mut self, opcode: u8) -> Result<(), String> {
+        const CONDITIONS: [&str; 16] = [
+            "O", "NO", "B", "NB", "Z", "NZ", "BE", "A", "S", "NS", "P", "NP", "L", "NL", "LE", "G",
+        ];
+        let mnemonic = format!("CMOV{}", CONDITIONS[(opcode & 0x0F) as usize]);
+        self.require_unemulated_model("Pentium Pro", &mnemonic)
+    }
+
     pub(crate) fn xchg_ax_r16(&mut self, reg: u8) -> Result<(), String> {
         let ax = self.regs.ax;
         let reg_val = self.regs.get_reg16(reg);
@@ -141,8 +192,7 @@ impl Cpu {
 
     // Pop instructions
     pub(crate) fn pop_word(&mut self) -> Result<u16, String> {
-        let addr = self.get_physical_address(self.regs.ss, self.regs.sp);
-        let value = self.memory.read_word(addr);
+        let value = self.read_word_at(self.regs.ss, self.regs.sp);
         self.regs.sp = self.regs.sp.wrapping_add(2);
         Ok(value)
     }
@@ -150,10 +200,11 @@ impl Cpu {
     pub(crate) fn les_r16_m16(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         println!("LES: ModR/M byte = 0x{:02X}", modrm);
-        let rm_addr = self.get_rm_addr(modrm)?;
+        let rm_addr = self.get_rm_addr(modrm)? as u16;
         println!("LES: rm_addr = 0x{:04X}", rm_addr);
-        let offset = self.read_word(rm_addr)?;
-        let segment = self.read_word(rm_addr.wrapping_add(2))?;
+        let rm_segment = self.rm_segment(modrm);
+        let offset = self.read_word_at(rm_segment, rm_addr);
+        let segment = self.read_word_at(rm_segment, rm_addr.wrapping_add(2));
         println!(
             "LES: offset = 0x{:04X}, segment = 0x{:04X}",
             offset, segment
@@ -182,12 +233,26 @@ impl Cpu {
         Ok(())
     }
 
+    pub(crate) fn mov_bl_imm8(&mut self) -> Result<(), String> {
+        let imm8 = self.fetch_byte()?;
+        self.regs.set_bl(imm8);
+        Ok(())
+    }
+
+    pub(crate) fn mov_bh_imm8(&mut self) -> Result<(), String> {
+        let imm8 = self.fetch_byte()?;
+        self.regs.set_bh(imm8);
+        Ok(())
+    }
+
     // More data transfer instructions can be added here...
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -368,6 +433,41 @@ mod tests {
         assert_eq!(cpu.regs.bx, 0x1234);
     }
 
+    #[test]
+    fn test_nop_changes_nothing_but_ip() {
+        let mut cpu = crate::cpu::test_utils::setup_executable_cpu(&[0x90]);
+        cpu.regs.ax = 0x1234;
+        cpu.regs.flags.set_carry(true);
+        cpu.regs.flags.set_zero(true);
+        let mut expected = cpu.regs.clone();
+        expected.ip = 0x101;
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs, expected);
+        assert_eq!(cpu.cycles, 1);
+    }
+
+    #[test]
+    fn test_cmovcc_rejected_for_configured_model() {
+        use crate::cpu::CpuModel;
+
+        // CMOVNZ AX,BX
+        let mut cpu = crate::cpu::test_utils::setup_executable_cpu(&[0x0F, 0x45, 0xC3]);
+        let err = cpu.execute_instruction().unwrap_err();
+        assert_eq!(
+            err,
+            "CMOVNZ requires a Pentium Pro or later (configured model: 8086)"
+        );
+
+        // CMOVG AX,BX is refused even on the newest model emulated
+        let mut cpu = crate::cpu::test_utils::setup_executable_cpu(&[0x0F, 0x4F, 0xC3]);
+        cpu.set_model(CpuModel::I80386);
+        let err = cpu.execute_instruction().unwrap_err();
+        assert!(err.starts_with("CMOVG "), "{}", err);
+        assert!(err.contains("configured model: 80386"), "{}", err);
+        assert_eq!(cpu.regs.ax, 0);
+    }
+
     #[test]
     fn test_les_r16_m16() {
         let mut cpu = setup_cpu();
@@ -393,4 +493,142 @@ mod tests {
         // ES should contain the segment
         assert_eq!(cpu.regs.es, 0x2000);
     }
+
+    #[test]
+    fn test_mov_cs_raises_invalid_opcode() {
+        // MOV CS, AX
+        let mut cpu = setup_executable_cpu(&[0x8E, 0xC8]);
+        cpu.memory.write_word(6 * 4, 0x0500);
+        cpu.memory.write_word(6 * 4 + 2, 0x0000);
+        cpu.regs.ax = 0x1234;
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0500));
+        // The pushed return address is the faulting instruction itself
+        assert_eq!(cpu.memory.read_word(cpu.regs.sp as u32), 0x0100);
+    }
+
+    #[test]
+    fn test_mov_r8_imm8_leaves_the_other_half_alone() {
+        // MOV AH,FFh with AX = 0034h
+        let mut cpu = setup_executable_cpu(&[0xB4, 0xFF]);
+        cpu.regs.ax = 0x0034;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0xFF34);
+
+        // B0-B7 in encoding order: AL, CL, DL, BL, AH, CH, DH, BH
+        let mut code = Vec::new();
+        for reg in 0..8u8 {
+            code.extend_from_slice(&[0xB0 + reg, 0x80 | reg]);
+        }
+        let mut cpu = setup_executable_cpu(&code);
+        cpu.regs.ax = 0x1111;
+        cpu.regs.cx = 0x2222;
+        cpu.regs.dx = 0x3333;
+        cpu.regs.bx = 0x4444;
+        for _ in 0..4 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert_eq!(
+            (cpu.regs.ax, cpu.regs.cx, cpu.regs.dx, cpu.regs.bx),
+            (0x1180, 0x2281, 0x3382, 0x4483)
+        );
+        for _ in 0..4 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert_eq!(
+            (cpu.regs.ax, cpu.regs.cx, cpu.regs.dx, cpu.regs.bx),
+            (0x8480, 0x8581, 0x8682, 0x8783)
+        );
+
+        // set_reg8 by encoding, which the ModR/M forms use
+        cpu.regs.set_reg8(7, 0x00).unwrap();
+        assert_eq!(cpu.regs.bx, 0x0083);
+        cpu.regs.set_reg8(3, 0xFF).unwrap();
+        assert_eq!(cpu.regs.bx, 0x00FF);
+    }
+
+    #[test]
+    fn test_mov_rm_imm() {
+        // MOV BYTE PTR [BX],42h; MOV WORD PTR [BX+2],1234h; MOV CX,5678h
+        let code = [
+            0xC6, 0x07, 0x42, 0xC7, 0x47, 0x02, 0x34, 0x12, 0xC7, 0xC1, 0x78, 0x56,
+        ];
+        let mut cpu = setup_executable_cpu(&code);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x0300;
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_byte(0x0300), 0x42);
+        assert_eq!(cpu.regs.ip, 0x0103);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x0302), 0x1234);
+        assert_eq!(cpu.regs.ip, 0x0108);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.cx, 0x5678);
+    }
+
+    #[test]
+    fn test_mov_rm_imm_reg_field_must_be_zero() {
+        // C6 /1 [BX], 42h
+        let mut cpu = setup_executable_cpu(&[0xC6, 0x0F, 0x42]);
+        cpu.memory.write_word(6 * 4, 0x0500);
+        cpu.memory.write_word(6 * 4 + 2, 0x0000);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x0300;
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0500));
+        assert_eq!(cpu.memory.read_word(cpu.regs.sp as u32), 0x0100);
+        assert_eq!(cpu.memory.read_byte(0x0300), 0x00);
+    }
+
+    #[test]
+    fn test_mov_ds_and_ss_shadow() {
+        // MOV DS, AX; MOV SS, AX; MOV SP, 0x1000 (BC 00 10); NOP
+        let mut cpu = setup_executable_cpu(&[0x8E, 0xD8, 0x8E, 0xD0, 0xBC, 0x00, 0x10, 0x90]);
+        cpu.regs.ax = 0x2000;
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ds, 0x2000);
+        assert!(!cpu.in_interrupt_shadow());
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ss, 0x2000);
+        assert!(cpu.in_interrupt_shadow());
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.sp, 0x1000);
+        assert!(!cpu.in_interrupt_shadow());
+    }
+
+    #[test]
+    fn test_word_at_offset_ffff_wraps_within_segment() {
+        // MOV [FFFFh], AX; MOV BX, [FFFFh]
+        let mut cpu = setup_executable_cpu(&[0x89, 0x06, 0xFF, 0xFF, 0x8B, 0x1E, 0xFF, 0xFF]);
+        cpu.regs.ds = 0x2000;
+        cpu.regs.ax = 0xBEEF;
+        cpu.execute_instruction().unwrap();
+
+        // Low byte at DS:FFFF, high byte back at DS:0000, not 3000:0000
+        assert_eq!(cpu.memory.read_byte(0x2FFFF), 0xEF);
+        assert_eq!(cpu.memory.read_byte(0x20000), 0xBE);
+        assert_eq!(cpu.memory.read_byte(0x30000), 0x00);
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.bx, 0xBEEF);
+    }
+
+    #[test]
+    fn test_push_at_sp_one_wraps_within_stack_segment() {
+        // PUSH AX with SP = 1 writes SS:FFFF and SS:0000
+        let mut cpu = setup_executable_cpu(&[0x50]);
+        cpu.regs.ss = 0x3000;
+        cpu.regs.sp = 0x0001;
+        cpu.regs.ax = 0x1234;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.sp, 0xFFFF);
+        assert_eq!(cpu.memory.read_byte(0x3FFFF), 0x34);
+        assert_eq!(cpu.memory.read_byte(0x30000), 0x12);
+    }
 }
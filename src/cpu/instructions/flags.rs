@@ -1,6 +1,13 @@
-use crate::cpu::Cpu;
+use crate::cpu::CpuGeneric;
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
+// SF, ZF, AF, PF and CF: the only flags SAHF/LAHF move
+const AH_FLAGS_MASK: u8 = 0xD5;
+// Bit 1 of FLAGS always reads as 1; bits 3 and 5 always read as 0
+const FLAGS_FIXED_ONE: u8 = 0x02;
+
+impl<M: Memory> CpuGeneric<M> {
     pub(crate) fn cli(&mut self) -> Result<(), String> {
         self.regs.flags.set_interrupt(false);
         Ok(())
@@ -37,27 +44,24 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn sahf(&mut self) -> Result<(), String> {
+        // OF, DF, IF and TF are left alone; bits 1/3/5 of AH are don't-cares
         let ah = (self.regs.ax >> 8) as u8;
-        self.regs.flags.set_from_byte(ah);
+        self.regs.flags.set_from_byte(ah & AH_FLAGS_MASK);
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn lahf(&mut self) -> Result<(), String> {
-        let flags = self.regs.flags.as_byte();
+        let flags = (self.regs.flags.as_byte() & AH_FLAGS_MASK) | FLAGS_FIXED_ONE;
         self.regs.ax = (self.regs.ax & 0x00FF) | ((flags as u16) << 8);
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn pushf(&mut self) -> Result<(), String> {
         let flags = self.regs.flags.as_u16();
         self.push_word(flags)
     }
 
-    #[allow(dead_code)]
     pub(crate) fn popf(&mut self) -> Result<(), String> {
         let flags = self.pop_word()?;
         println!("POPF: Popped flags value: 0x{:04X}", flags);
@@ -74,6 +78,8 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -162,8 +168,40 @@ mod tests {
         cpu.regs.flags.set_zero(true);
         cpu.regs.flags.set_sign(true);
         assert!(cpu.lahf().is_ok());
-        // Check that AH contains the flags
-        assert_eq!(cpu.regs.ax & 0xFF00, (cpu.regs.flags.as_byte() as u16) << 8);
+        // Check that AH contains the flags, plus the always-set bit 1
+        assert_eq!(
+            cpu.regs.ax & 0xFF00,
+            ((cpu.regs.flags.as_byte() | 0x02) as u16) << 8
+        );
+    }
+
+    #[test]
+    fn test_lahf_fixed_bits() {
+        // STC; LAHF with ZF also set
+        let mut cpu = setup_executable_cpu(&[0xF9, 0x9F]);
+        cpu.regs.flags.set_zero(true);
+        cpu.regs.ax = 0x00AA;
+        cpu.execute_instruction().unwrap();
+        cpu.execute_instruction().unwrap();
+        // ZF (0x40) | CF (0x01) | bit 1; bits 3 and 5 clear
+        assert_eq!(cpu.regs.get_ah(), 0x43);
+        assert_eq!(cpu.regs.get_al(), 0xAA);
+    }
+
+    #[test]
+    fn test_sahf_only_touches_low_flags() {
+        let mut cpu = setup_executable_cpu(&[0x9E]);
+        cpu.regs.flags.set_overflow(true);
+        cpu.regs.flags.set_direction(true);
+        cpu.regs.ax = 0xFF00;
+        cpu.execute_instruction().unwrap();
+        assert!(cpu.regs.flags.get_sign());
+        assert!(cpu.regs.flags.get_zero());
+        assert!(cpu.regs.flags.get_auxiliary());
+        assert!(cpu.regs.flags.get_parity());
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_overflow());
+        assert!(cpu.regs.flags.get_direction());
     }
 
     #[test]
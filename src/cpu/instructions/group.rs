@@ -1,125 +1,340 @@
-use crate::cpu::Cpu;
+use crate::cpu::{CpuGeneric, CpuModel, RmOperand};
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
-    pub(crate) fn execute_group1_rm8_imm8(&mut self, _group: u8) -> Result<(), String> {
+/// Where a group 2 (shift/rotate) instruction takes its count from
+#[derive(Debug, Clone, Copy)]
+enum ShiftCount {
+    One,
+    Cl,
+    Imm8,
+}
+
+impl<M: Memory> CpuGeneric<M> {
+    // 0x80 (and its alias 0x82): ADD/OR/ADC/SBB/AND/SUB/XOR/CMP r/m8, imm8,
+    // with the operation taken from the ModR/M reg field. The immediate
+    // follows any displacement, so the operand address is decoded once up
+    // front rather than again for the write-back.
+    pub(crate) fn execute_group1_rm8_imm8(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
-        println!("Group1: ModR/M byte = {:#04x}", modrm);
-        let rm_val = self.get_rm8(modrm)?;
+        let operand = self.decode_rm(modrm)?;
+        let rm_val = self.read_operand8(operand);
         let imm = self.fetch_byte()?;
-        println!("Group1: rm_val = {:#04x}, imm = {:#04x}", rm_val, imm);
+
         let op_type = (modrm >> 3) & 0x07;
-        let op_name = match op_type {
-            0 => "ADD",
-            1 => "OR",
-            2 => "ADC",
-            3 => "SBB",
-            4 => "AND",
-            5 => "SUB",
-            6 => "XOR",
-            7 => "CMP",
-            _ => "INVALID",
-        };
-        println!("Group1: Operation = {}", op_name);
-        let result = match op_type {
-            0 => rm_val.wrapping_add(imm), // ADD
-            1 => rm_val | imm,             // OR
-            2 => rm_val
-                .wrapping_add(if self.regs.flags.get_carry() { 1 } else { 0 })
-                .wrapping_add(imm), // ADC
-            3 => rm_val
-                .wrapping_sub(if self.regs.flags.get_carry() { 1 } else { 0 })
-                .wrapping_sub(imm), // SBB
-            4 => rm_val & imm,             // AND
-            5 => rm_val.wrapping_sub(imm), // SUB
-            6 => rm_val ^ imm,             // XOR
-            7 => {
-                // CMP
-                let _ = rm_val.wrapping_sub(imm);
-                rm_val
+        if op_type == 7 {
+            self.cmp8(rm_val, imm);
+            return Ok(());
+        }
+        let carry_in = self.regs.flags.get_carry() as u16;
+        let (result, carry, overflow) = match op_type {
+            0 | 2 => {
+                // ADD, ADC
+                let carry_in = if op_type == 2 { carry_in } else { 0 };
+                let wide = rm_val as u16 + imm as u16 + carry_in;
+                let result = wide as u8;
+                let overflow = (rm_val ^ result) & (imm ^ result) & 0x80 != 0;
+                (result, wide > 0xFF, overflow)
             }
-            _ => return Err("Invalid group1 operation".to_string()),
+            3 | 5 => {
+                // SBB, SUB
+                let borrow_in = if op_type == 3 { carry_in } else { 0 };
+                let result = rm_val.wrapping_sub(imm).wrapping_sub(borrow_in as u8);
+                let borrow = (rm_val as u16) < imm as u16 + borrow_in;
+                let overflow = (rm_val ^ imm) & (rm_val ^ result) & 0x80 != 0;
+                (result, borrow, overflow)
+            }
+            // OR, AND, XOR clear CF and OF
+            1 => (rm_val | imm, false, false),
+            4 => (rm_val & imm, false, false),
+            _ => (rm_val ^ imm, false, false),
         };
-        println!("Group1: Result = {:#04x}", result);
-        if op_type != 7 {
-            // Don't write result for CMP
-            self.write_rm8(modrm, result)?;
-        }
-        self.update_flags_arithmetic(rm_val, imm, result, op_type >= 5);
+        println!(
+            "Group1: op={} rm_val={:#04x} imm={:#04x} result={:#04x}",
+            op_type, rm_val, imm, result
+        );
+
+        self.write_operand8(operand, result)?;
+
+        let auxiliary = match op_type {
+            1 | 4 | 6 => false,
+            _ => (rm_val ^ imm ^ result) & 0x10 != 0,
+        };
+        self.regs.flags.set_carry(carry);
+        self.regs.flags.set_overflow(overflow);
+        self.regs.flags.set_auxiliary(auxiliary);
+        self.regs.flags.set_zero(result == 0);
+        self.regs.flags.set_sign((result as i8) < 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
     pub(crate) fn handle_81_group(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
-        let rm_val = self.get_rm16(modrm)?;
+        let operand = self.decode_rm(modrm)?;
         let imm = self.fetch_word()?;
-        let result = match (modrm >> 3) & 0x07 {
-            0 => rm_val.wrapping_add(imm), // ADD
-            1 => rm_val | imm,             // OR
-            2 => rm_val
-                .wrapping_add(if self.regs.flags.get_carry() { 1 } else { 0 })
-                .wrapping_add(imm), // ADC
-            3 => rm_val
-                .wrapping_sub(if self.regs.flags.get_carry() { 1 } else { 0 })
-                .wrapping_sub(imm), // SBB
-            4 => rm_val & imm,             // AND
-            5 => rm_val.wrapping_sub(imm), // SUB
-            6 => rm_val ^ imm,             // XOR
-            7 => {
-                // CMP
-                let _ = rm_val.wrapping_sub(imm);
-                rm_val
-            }
-            _ => return Err("Invalid group1 operation".to_string()),
-        };
-        if (modrm >> 3) & 0x07 != 7 {
-            // Don't write result for CMP
-            self.write_rm16(modrm, result)?;
-        }
-        self.update_flags_arithmetic_16(rm_val, imm, result, (modrm >> 3) & 0x07 >= 5);
-        Ok(())
+        self.group1_rm16(modrm, operand, imm)
     }
 
+    /// 0x82 is an undocumented alias of 0x80 on the 8086: the same r/m8,
+    /// imm8 operands, not a sign-extended form like 0x83
     pub(crate) fn handle_82_group(&mut self) -> Result<(), String> {
-        self.execute_group1_rm8_imm8(2)
+        self.execute_group1_rm8_imm8()
     }
 
     pub(crate) fn handle_83_group(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
-        let rm_val = self.get_rm16(modrm)?;
+        let operand = self.decode_rm(modrm)?;
         let imm = (self.fetch_byte()? as i8) as i16 as u16;
-        let result = match (modrm >> 3) & 0x07 {
-            0 => rm_val.wrapping_add(imm), // ADD
-            1 => rm_val | imm,             // OR
-            2 => rm_val
-                .wrapping_add(if self.regs.flags.get_carry() { 1 } else { 0 })
-                .wrapping_add(imm), // ADC
-            3 => rm_val
-                .wrapping_sub(if self.regs.flags.get_carry() { 1 } else { 0 })
-                .wrapping_sub(imm), // SBB
-            4 => rm_val & imm,             // AND
-            5 => rm_val.wrapping_sub(imm), // SUB
-            6 => rm_val ^ imm,             // XOR
-            7 => {
-                // CMP
-                let _ = rm_val.wrapping_sub(imm);
-                rm_val
+        self.group1_rm16(modrm, operand, imm)
+    }
+
+    // Shared by 0x81 and 0x83 once the immediate is a full word. Flags are
+    // computed on the 16-bit operands, so a sign-extended 0xFF acts as 0xFFFF:
+    // ADD AX,-1 carries unless AX is 0, SUB AX,-1 borrows unless AX is 0xFFFF.
+    // The operand was decoded before the immediate was fetched, so writing
+    // the result back doesn't read the displacement a second time.
+    fn group1_rm16(&mut self, modrm: u8, operand: RmOperand, imm: u16) -> Result<(), String> {
+        let rm_val = self.read_operand16(operand);
+        let op_type = (modrm >> 3) & 0x07;
+        if op_type == 7 {
+            self.cmp16(rm_val, imm);
+            return Ok(());
+        }
+        let carry_in = self.regs.flags.get_carry() as u32;
+        let (result, carry, is_sub) = match op_type {
+            0 => {
+                // ADD
+                let wide = rm_val as u32 + imm as u32;
+                (wide as u16, wide > 0xFFFF, false)
+            }
+            2 => {
+                // ADC
+                let wide = rm_val as u32 + imm as u32 + carry_in;
+                (wide as u16, wide > 0xFFFF, false)
+            }
+            3 => {
+                // SBB
+                let borrow = (rm_val as u32) < imm as u32 + carry_in;
+                (
+                    rm_val.wrapping_sub(imm).wrapping_sub(carry_in as u16),
+                    borrow,
+                    true,
+                )
+            }
+            5 => (rm_val.wrapping_sub(imm), rm_val < imm, true), // SUB
+            1 | 4 | 6 => {
+                // OR, AND, XOR clear CF and OF
+                let result = match op_type {
+                    1 => rm_val | imm,
+                    4 => rm_val & imm,
+                    _ => rm_val ^ imm,
+                };
+                self.write_operand16(operand, result)?;
+                self.regs.flags.set_carry(false);
+                self.regs.flags.set_overflow(false);
+                self.regs.flags.set_auxiliary(false);
+                self.regs.flags.set_zero(result == 0);
+                self.regs.flags.set_sign((result as i16) < 0);
+                self.regs
+                    .flags
+                    .set_parity((result as u8).count_ones().is_multiple_of(2));
+                return Ok(());
             }
             _ => return Err("Invalid group1 operation".to_string()),
         };
-        if (modrm >> 3) & 0x07 != 7 {
-            // Don't write result for CMP
-            self.write_rm16(modrm, result)?;
+
+        self.write_operand16(operand, result)?;
+
+        let overflow = if is_sub {
+            (rm_val ^ imm) & (rm_val ^ result) & 0x8000 != 0
+        } else {
+            (rm_val ^ result) & (imm ^ result) & 0x8000 != 0
+        };
+        self.regs.flags.set_carry(carry);
+        self.regs.flags.set_overflow(overflow);
+        self.regs
+            .flags
+            .set_auxiliary((rm_val ^ imm ^ result) & 0x10 != 0);
+        self.regs.flags.set_zero(result == 0);
+        self.regs.flags.set_sign((result as i16) < 0);
+        self.regs
+            .flags
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
+        Ok(())
+    }
+
+    pub(crate) fn handle_d0_group(&mut self) -> Result<(), String> {
+        self.execute_group2(false, ShiftCount::One)
+    }
+
+    pub(crate) fn handle_d1_group(&mut self) -> Result<(), String> {
+        self.execute_group2(true, ShiftCount::One)
+    }
+
+    pub(crate) fn handle_d2_group(&mut self) -> Result<(), String> {
+        self.execute_group2(false, ShiftCount::Cl)
+    }
+
+    pub(crate) fn handle_d3_group(&mut self) -> Result<(), String> {
+        self.execute_group2(true, ShiftCount::Cl)
+    }
+
+    pub(crate) fn handle_c0_group(&mut self) -> Result<(), String> {
+        self.require_model(CpuModel::I80186, "shift/rotate r/m8, imm8")?;
+        self.execute_group2(false, ShiftCount::Imm8)
+    }
+
+    pub(crate) fn handle_c1_group(&mut self) -> Result<(), String> {
+        self.require_model(CpuModel::I80186, "shift/rotate r/m16, imm8")?;
+        self.execute_group2(true, ShiftCount::Imm8)
+    }
+
+    // Decodes the r/m operand once so the displacement, and the C0/C1
+    // immediate that follows it, are only fetched a single time.
+    fn execute_group2(&mut self, wide: bool, count: ShiftCount) -> Result<(), String> {
+        let modrm = self.fetch_byte()?;
+        let operand = self.decode_rm(modrm)?;
+        let value = if wide {
+            self.read_operand16(operand)
+        } else {
+            self.read_operand8(operand) as u16
+        };
+
+        let mut count = match count {
+            ShiftCount::One => 1,
+            ShiftCount::Cl => self.regs.get_cl(),
+            ShiftCount::Imm8 => self.fetch_byte()?,
+        };
+        // The 80186 and later only use the low five bits of the count
+        if self.model() >= CpuModel::I80186 {
+            count &= 0x1F;
+        }
+
+        let op = (modrm >> 3) & 0x07;
+        if op == 6 && !self.allow_undocumented {
+            // /6 is an undocumented alias of SHL
+            return self.raise_invalid_opcode();
+        }
+        let bits = if wide { 16 } else { 8 };
+        let result = self.shift_rotate(op, value, count, bits);
+        println!(
+            "Group2: op={} value={:#06x} count={} result={:#06x}",
+            op, value, count, result
+        );
+
+        if wide {
+            self.write_operand16(operand, result)?;
+        } else {
+            self.write_operand8(operand, result as u8)?;
         }
-        self.update_flags_arithmetic_16(rm_val, imm, result, (modrm >> 3) & 0x07 >= 5);
         Ok(())
     }
 
+    // ROL/ROR/RCL/RCR only touch CF and OF. SHL/SHR/SAR also set ZF, SF and
+    // PF from the result. A zero count leaves every flag alone. OF is only
+    // architecturally defined for single-bit counts; it is computed the same
+    // way for larger ones, as the 8086 does.
+    fn shift_rotate(&mut self, op: u8, value: u16, count: u8, bits: u32) -> u16 {
+        if count == 0 {
+            return value;
+        }
+
+        let mask: u16 = if bits == 16 { 0xFFFF } else { 0x00FF };
+        let msb = |v: u16| (v >> (bits - 1)) & 1 != 0;
+        let count = count as u32;
+        let flags = &mut self.regs.flags;
+
+        match op {
+            0 => {
+                // ROL
+                let n = count % bits;
+                let result = ((value << n) | (value >> ((bits - n) % bits))) & mask;
+                let carry = result & 1 != 0;
+                flags.set_carry(carry);
+                flags.set_overflow(msb(result) != carry);
+                result
+            }
+            1 => {
+                // ROR
+                let n = count % bits;
+                let result = ((value >> n) | (value << ((bits - n) % bits))) & mask;
+                flags.set_carry(msb(result));
+                flags.set_overflow(msb(result) != msb(result << 1));
+                result
+            }
+            2 => {
+                // RCL: rotate through the carry flag as a (bits + 1)-bit value
+                let mut result = value;
+                let mut carry = flags.get_carry();
+                for _ in 0..count {
+                    let out = msb(result);
+                    result = ((result << 1) | carry as u16) & mask;
+                    carry = out;
+                }
+                flags.set_carry(carry);
+                flags.set_overflow(msb(result) != carry);
+                result
+            }
+            3 => {
+                // RCR
+                let mut result = value;
+                let mut carry = flags.get_carry();
+                for _ in 0..count {
+                    let out = result & 1 != 0;
+                    result = (result >> 1) | ((carry as u16) << (bits - 1));
+                    carry = out;
+                }
+                flags.set_carry(carry);
+                flags.set_overflow(msb(result) != msb(result << 1));
+                result
+            }
+            _ => {
+                let (result, carry, overflow) = match op {
+                    4 | 6 => {
+                        // SHL/SAL
+                        let carry = count <= bits && (value >> (bits - count)) & 1 != 0;
+                        let result = if count >= bits {
+                            0
+                        } else {
+                            (value << count) & mask
+                        };
+                        (result, carry, msb(result) != carry)
+                    }
+                    5 => {
+                        // SHR; OF is the original sign bit
+                        let carry = count <= bits && (value >> (count - 1)) & 1 != 0;
+                        let result = if count >= bits { 0 } else { value >> count };
+                        (result, carry, msb(value))
+                    }
+                    _ => {
+                        // SAR fills with the sign bit and never overflows
+                        let signed = ((value as i32) << (32 - bits)) >> (32 - bits);
+                        let n = count.min(bits);
+                        let carry = (signed >> (n - 1)) & 1 != 0;
+                        ((signed >> n) as u16 & mask, carry, false)
+                    }
+                };
+                flags.set_carry(carry);
+                flags.set_overflow(overflow);
+                flags.set_zero(result == 0);
+                flags.set_sign(msb(result));
+                flags.set_parity((result as u8).count_ones().is_multiple_of(2));
+                result
+            }
+        }
+    }
+
     pub(crate) fn handle_f6_group(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
+        // TEST's immediate comes after the displacement
+        let operand = self.decode_rm(modrm)?;
+        let rm_val = self.read_operand8(operand);
         match (modrm >> 3) & 0x07 {
             0 | 1 => {
                 // TEST
-                let rm_val = self.get_rm8(modrm)?;
                 let imm = self.fetch_byte()?;
                 let result = rm_val & imm;
                 self.regs.flags.set_zero(result == 0);
@@ -129,20 +344,18 @@ impl Cpu {
             }
             2 => {
                 // NOT
-                let rm_val = self.get_rm8(modrm)?;
-                self.write_rm8(modrm, !rm_val)?;
+                self.write_operand8(operand, !rm_val)?;
             }
             3 => {
                 // NEG
-                let rm_val = self.get_rm8(modrm)?;
                 let result = (!rm_val).wrapping_add(1);
-                self.write_rm8(modrm, result)?;
-                self.update_flags_arithmetic(rm_val, 0, result, true);
+                self.write_operand8(operand, result)?;
+                // Flags are those of 0 - operand: CF unless it was zero
+                self.update_flags_sub(0, rm_val, result, rm_val != 0);
             }
             4 => {
                 // MUL
-                let rm_val = self.get_rm8(modrm)?;
-                let al = self.regs.get_reg8(0);
+                let al = self.regs.get_al();
                 let result = (al as u16) * (rm_val as u16);
                 self.regs.ax = result;
                 self.regs.flags.set_carry(result > 0xFF);
@@ -150,8 +363,8 @@ impl Cpu {
             }
             5 => {
                 // IMUL
-                let rm_val = self.get_rm8(modrm)? as i8;
-                let al = self.regs.get_reg8(0) as i8;
+                let rm_val = rm_val as i8;
+                let al = self.regs.get_al() as i8;
                 let result = (al as i16) * (rm_val as i16);
                 self.regs.ax = result as u16;
                 self.regs.flags.set_carry(!(-0x80..=0x7F).contains(&result));
@@ -161,33 +374,35 @@ impl Cpu {
             }
             6 => {
                 // DIV
-                let rm_val = self.get_rm8(modrm)?;
                 if rm_val == 0 {
-                    return Err("Division by zero".to_string());
+                    return Err(self.divide_error("Division by zero"));
                 }
                 let ax = self.regs.ax;
                 let quotient = ax / (rm_val as u16);
                 let remainder = ax % (rm_val as u16);
                 if quotient > 0xFF {
-                    return Err("Division overflow".to_string());
+                    return Err(self.divide_error("Division overflow"));
                 }
-                self.regs.set_reg8(0, quotient as u8)?; // AL
-                self.regs.set_reg8(1, remainder as u8)?; // AH
+                self.regs.set_al(quotient as u8);
+                self.regs.set_ah(remainder as u8);
             }
             7 => {
                 // IDIV
-                let rm_val = self.get_rm8(modrm)? as i8;
+                let rm_val = rm_val as i8;
                 if rm_val == 0 {
-                    return Err("Division by zero".to_string());
+                    return Err(self.divide_error("Division by zero"));
                 }
-                let ax = self.regs.ax as i16;
-                let quotient = ax / (rm_val as i16);
-                let remainder = ax % (rm_val as i16);
+                // Widened so -8000h / -1 overflows the range check rather
+                // than the division itself. Both round toward zero, leaving
+                // the remainder with the dividend's sign.
+                let ax = self.regs.ax as i16 as i32;
+                let quotient = ax / (rm_val as i32);
+                let remainder = ax % (rm_val as i32);
                 if !(-0x80..=0x7F).contains(&quotient) {
-                    return Err("Division overflow".to_string());
+                    return Err(self.divide_error("Division overflow"));
                 }
-                self.regs.set_reg8(0, quotient as u8)?; // AL
-                self.regs.set_reg8(1, remainder as u8)?; // AH
+                self.regs.set_al(quotient as u8);
+                self.regs.set_ah(remainder as u8);
             }
             _ => return Err("Invalid group2 operation".to_string()),
         }
@@ -196,10 +411,12 @@ impl Cpu {
 
     pub(crate) fn handle_f7_group(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
+        // TEST's immediate comes after the displacement
+        let operand = self.decode_rm(modrm)?;
+        let rm_val = self.read_operand16(operand);
         match (modrm >> 3) & 0x07 {
             0 | 1 => {
                 // TEST
-                let rm_val = self.get_rm16(modrm)?;
                 let imm = self.fetch_word()?;
                 let result = rm_val & imm;
                 self.regs.flags.set_zero(result == 0);
@@ -209,19 +426,16 @@ impl Cpu {
             }
             2 => {
                 // NOT
-                let rm_val = self.get_rm16(modrm)?;
-                self.write_rm16(modrm, !rm_val)?;
+                self.write_operand16(operand, !rm_val)?;
             }
             3 => {
                 // NEG
-                let rm_val = self.get_rm16(modrm)?;
                 let result = (!rm_val).wrapping_add(1);
-                self.write_rm16(modrm, result)?;
-                self.update_flags_arithmetic_16(rm_val, 0, result, true);
+                self.write_operand16(operand, result)?;
+                self.update_flags_sub16(0, rm_val, result, rm_val != 0);
             }
             4 => {
                 // MUL
-                let rm_val = self.get_rm16(modrm)?;
                 let ax = self.regs.ax;
                 let result = (ax as u32) * (rm_val as u32);
                 self.regs.ax = result as u16;
@@ -231,7 +445,7 @@ impl Cpu {
             }
             5 => {
                 // IMUL
-                let rm_val = self.get_rm16(modrm)? as i16;
+                let rm_val = rm_val as i16;
                 let ax = self.regs.ax as i16;
                 let result = (ax as i32) * (rm_val as i32);
                 self.regs.ax = result as u16;
@@ -245,30 +459,31 @@ impl Cpu {
             }
             6 => {
                 // DIV
-                let rm_val = self.get_rm16(modrm)?;
                 if rm_val == 0 {
-                    return Err("Division by zero".to_string());
+                    return Err(self.divide_error("Division by zero"));
                 }
                 let dividend = ((self.regs.dx as u32) << 16) | (self.regs.ax as u32);
                 let quotient = dividend / (rm_val as u32);
                 let remainder = dividend % (rm_val as u32);
                 if quotient > 0xFFFF {
-                    return Err("Division overflow".to_string());
+                    return Err(self.divide_error("Division overflow"));
                 }
                 self.regs.ax = quotient as u16;
                 self.regs.dx = remainder as u16;
             }
             7 => {
                 // IDIV
-                let rm_val = self.get_rm16(modrm)? as i16;
+                let rm_val = rm_val as i16;
                 if rm_val == 0 {
-                    return Err("Division by zero".to_string());
+                    return Err(self.divide_error("Division by zero"));
                 }
-                let dividend = ((self.regs.dx as i32) << 16) | (self.regs.ax as i32);
-                let quotient = dividend / (rm_val as i32);
-                let remainder = dividend % (rm_val as i32);
+                // As for the byte form, widened for -80000000h / -1
+                let dividend =
+                    ((((self.regs.dx as u32) << 16) | self.regs.ax as u32) as i32) as i64;
+                let quotient = dividend / (rm_val as i64);
+                let remainder = dividend % (rm_val as i64);
                 if !(-0x8000..=0x7FFF).contains(&quotient) {
-                    return Err("Division overflow".to_string());
+                    return Err(self.divide_error("Division overflow"));
                 }
                 self.regs.ax = quotient as u16;
                 self.regs.dx = remainder as u16;
@@ -281,7 +496,8 @@ impl Cpu {
     pub(crate) fn handle_fe_group(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         println!("FE Group: ModR/M byte = {:#04x}", modrm);
-        let rm_val = self.get_rm8(modrm)?;
+        let operand = self.decode_rm(modrm)?;
+        let rm_val = self.read_operand8(operand);
         let op_type = (modrm >> 3) & 0x07;
         let op_name = match op_type {
             0 => "INC",
@@ -296,13 +512,11 @@ impl Cpu {
             _ => return Err("Invalid group3 operation".to_string()),
         };
         println!("FE Group: Result = {:#04x}", result);
-        self.write_rm8(modrm, result)?;
-        self.update_flags_arithmetic(
-            rm_val,
-            if op_type == 0 { 1 } else { !0 },
-            result,
-            op_type == 1,
-        );
+        self.write_operand8(operand, result)?;
+        // INC and DEC leave CF alone
+        let carry = self.regs.flags.get_carry();
+        self.update_flags_arithmetic(rm_val, 1, result, op_type == 1);
+        self.regs.flags.set_carry(carry);
         Ok(())
     }
 
@@ -311,19 +525,17 @@ impl Cpu {
         match (modrm >> 3) & 0x07 {
             0 | 1 => {
                 // INC/DEC
-                let rm_val = self.get_rm16(modrm)?;
+                let operand = self.decode_rm(modrm)?;
+                let rm_val = self.read_operand16(operand);
                 let result = if (modrm >> 3) & 0x07 == 0 {
                     rm_val.wrapping_add(1)
                 } else {
                     rm_val.wrapping_sub(1)
                 };
-                self.write_rm16(modrm, result)?;
-                self.update_flags_arithmetic_16(
-                    rm_val,
-                    if (modrm >> 3) & 0x07 == 0 { 1 } else { !0 },
-                    result,
-                    (modrm >> 3) & 0x07 == 1,
-                );
+                self.write_operand16(operand, result)?;
+                let carry = self.regs.flags.get_carry();
+                self.update_flags_arithmetic_16(rm_val, 1, result, (modrm >> 3) & 0x07 == 1);
+                self.regs.flags.set_carry(carry);
             }
             2 => {
                 // CALL near
@@ -334,9 +546,10 @@ impl Cpu {
             }
             3 => {
                 // CALL far
-                let rm_addr = self.get_rm_addr(modrm)?;
-                let offset = self.read_word(rm_addr)?;
-                let segment = self.read_word(rm_addr.wrapping_add(2))?;
+                let rm_addr = self.get_rm_addr(modrm)? as u16;
+                let rm_segment = self.rm_segment(modrm);
+                let offset = self.read_word_at(rm_segment, rm_addr);
+                let segment = self.read_word_at(rm_segment, rm_addr.wrapping_add(2));
                 let next_ip = self.regs.ip;
                 let next_cs = self.regs.cs;
                 self.push(next_cs)?;
@@ -351,9 +564,10 @@ impl Cpu {
             }
             5 => {
                 // JMP far
-                let rm_addr = self.get_rm_addr(modrm)?;
-                let offset = self.read_word(rm_addr)?;
-                let segment = self.read_word(rm_addr.wrapping_add(2))?;
+                let rm_addr = self.get_rm_addr(modrm)? as u16;
+                let rm_segment = self.rm_segment(modrm);
+                let offset = self.read_word_at(rm_segment, rm_addr);
+                let segment = self.read_word_at(rm_segment, rm_addr.wrapping_add(2));
                 self.regs.ip = offset;
                 self.regs.cs = segment;
             }
@@ -371,6 +585,9 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
+    use crate::cpu::StopReason;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -391,7 +608,7 @@ mod tests {
         cpu.regs.ax = 0x0505; // AL = 5
         cpu.memory.write_byte(0x100, 0xC0); // ModR/M byte for register-to-register, reg=0 (ADD)
         cpu.memory.write_byte(0x101, 0x03); // Immediate value 3
-        assert!(cpu.execute_group1_rm8_imm8(0).is_ok());
+        assert!(cpu.execute_group1_rm8_imm8().is_ok());
         assert_eq!(cpu.regs.get_al(), 0x08); // 5 + 3 = 8
         assert!(!cpu.regs.flags.get_carry());
         assert!(!cpu.regs.flags.get_zero());
@@ -434,4 +651,391 @@ mod tests {
         assert!(!cpu.regs.flags.get_zero());
         assert_eq!(cpu.regs.ip, 0x101); // IP should be advanced by 1 byte (ModR/M)
     }
+
+    #[test]
+    fn test_neg_flags_are_those_of_zero_minus_operand() {
+        // NEG AL: (AL before, AL after, CF, ZF, SF, OF)
+        for (al, result, cf, zf, sf, of) in [
+            (0x01, 0xFF, true, false, true, false),
+            (0x00, 0x00, false, true, false, false),
+            (0x80, 0x80, true, false, true, true),
+            (0xFF, 0x01, true, false, false, false),
+        ] {
+            let mut cpu = setup_executable_cpu(&[0xF6, 0xD8]);
+            cpu.regs.set_al(al);
+            cpu.execute_instruction().unwrap();
+            let flags = &cpu.regs.flags;
+            assert_eq!(cpu.regs.get_al(), result, "NEG {:02X}", al);
+            assert_eq!(
+                (flags.get_carry(), flags.get_zero(), flags.get_sign()),
+                (cf, zf, sf),
+                "NEG {:02X}",
+                al
+            );
+            assert_eq!(flags.get_overflow(), of, "NEG {:02X}", al);
+        }
+
+        // NEG AX on the most negative word overflows
+        let mut cpu = setup_executable_cpu(&[0xF7, 0xD8]);
+        cpu.regs.ax = 0x8000;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0x8000);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_overflow());
+    }
+
+    // Runs one 0x83 instruction with AX as the operand: /op, imm8
+    fn run_83_on_ax(cpu: &mut Cpu, op: u8, imm: u8) {
+        cpu.regs.cs = 0;
+        cpu.regs.ip = 0;
+        cpu.memory.write_byte(0, 0xC0 | (op << 3));
+        cpu.memory.write_byte(1, imm);
+        assert!(cpu.handle_83_group().is_ok());
+    }
+
+    #[test]
+    fn test_83_add_negative_immediate() {
+        let mut cpu = setup_cpu();
+
+        // ADD AX,-1 with AX=0: no unsigned carry out of 0x0000 + 0xFFFF
+        cpu.regs.ax = 0;
+        run_83_on_ax(&mut cpu, 0, 0xFF);
+        assert_eq!(cpu.regs.ax, 0xFFFF);
+        assert!(!cpu.regs.flags.get_carry());
+        assert!(!cpu.regs.flags.get_overflow());
+        assert!(cpu.regs.flags.get_sign());
+
+        // ADD AX,-1 with AX=1 wraps to 0 and carries
+        cpu.regs.ax = 1;
+        run_83_on_ax(&mut cpu, 0, 0xFF);
+        assert_eq!(cpu.regs.ax, 0);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_zero());
+
+        // ADD AX,-1 with AX=0x8000 overflows to 0x7FFF
+        cpu.regs.ax = 0x8000;
+        run_83_on_ax(&mut cpu, 0, 0xFF);
+        assert_eq!(cpu.regs.ax, 0x7FFF);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_83_sub_cmp_negative_immediate() {
+        let mut cpu = setup_cpu();
+
+        // SUB AX,-1 with AX=0 borrows: 0x0000 - 0xFFFF
+        cpu.regs.ax = 0;
+        run_83_on_ax(&mut cpu, 5, 0xFF);
+        assert_eq!(cpu.regs.ax, 1);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(!cpu.regs.flags.get_overflow());
+
+        // SUB AX,-1 with AX=0x7FFF overflows
+        cpu.regs.ax = 0x7FFF;
+        run_83_on_ax(&mut cpu, 5, 0xFF);
+        assert_eq!(cpu.regs.ax, 0x8000);
+        assert!(cpu.regs.flags.get_overflow());
+
+        // CMP AX,-1 with AX=0xFFFF is equal and leaves AX alone
+        cpu.regs.ax = 0xFFFF;
+        run_83_on_ax(&mut cpu, 7, 0xFF);
+        assert_eq!(cpu.regs.ax, 0xFFFF);
+        assert!(cpu.regs.flags.get_zero());
+        assert!(!cpu.regs.flags.get_carry());
+    }
+
+    #[test]
+    fn test_83_adc_sbb_include_carry() {
+        let mut cpu = setup_cpu();
+
+        // ADC AX,-1 with CF=1 and AX=0: 0 + 0xFFFF + 1 carries out
+        cpu.regs.ax = 0;
+        cpu.regs.flags.set_carry(true);
+        run_83_on_ax(&mut cpu, 2, 0xFF);
+        assert_eq!(cpu.regs.ax, 0);
+        assert!(cpu.regs.flags.get_carry());
+
+        // SBB AX,0 with CF=1 and AX=0 borrows
+        cpu.regs.ax = 0;
+        cpu.regs.flags.set_carry(true);
+        run_83_on_ax(&mut cpu, 3, 0x00);
+        assert_eq!(cpu.regs.ax, 0xFFFF);
+        assert!(cpu.regs.flags.get_carry());
+
+        // XOR clears CF and OF
+        cpu.regs.ax = 0x00F0;
+        cpu.regs.flags.set_carry(true);
+        cpu.regs.flags.set_overflow(true);
+        run_83_on_ax(&mut cpu, 6, 0xFF);
+        assert_eq!(cpu.regs.ax, 0xFF0F);
+        assert!(!cpu.regs.flags.get_carry());
+        assert!(!cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_rotate_leaves_zf_sf_pf_alone() {
+        // ROL AL,1 with AL=0 gives zero but must not set ZF
+        let mut cpu = setup_executable_cpu(&[0xD0, 0xC0]);
+        cpu.regs.ax = 0x0000;
+        cpu.regs.flags.set_zero(false);
+        cpu.regs.flags.set_sign(true);
+        cpu.regs.flags.set_parity(false);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al(), 0x00);
+        assert!(!cpu.regs.flags.get_zero());
+        assert!(cpu.regs.flags.get_sign());
+        assert!(!cpu.regs.flags.get_parity());
+        assert!(!cpu.regs.flags.get_carry());
+
+        // RCR AL,1 with AL=1, CF=0 also gives zero; only CF and OF change
+        let mut cpu = setup_executable_cpu(&[0xD0, 0xD8]);
+        cpu.regs.ax = 0x0001;
+        cpu.regs.flags.set_zero(false);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al(), 0x00);
+        assert!(!cpu.regs.flags.get_zero());
+        assert!(cpu.regs.flags.get_carry());
+    }
+
+    #[test]
+    fn test_shift_updates_zf_sf_pf() {
+        // SHL AL,1 with AL=80h gives zero and sets ZF, with the bit in CF
+        let mut cpu = setup_executable_cpu(&[0xD0, 0xE0]);
+        cpu.regs.ax = 0x0080;
+        cpu.regs.flags.set_zero(false);
+        cpu.regs.flags.set_sign(true);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al(), 0x00);
+        assert!(cpu.regs.flags.get_zero());
+        assert!(!cpu.regs.flags.get_sign());
+        assert!(cpu.regs.flags.get_parity());
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_overflow());
+
+        // SAR AX,CL keeps the sign; bit 3 is the last one shifted out
+        let mut cpu = setup_executable_cpu(&[0xD3, 0xF8]);
+        cpu.regs.ax = 0x8018;
+        cpu.regs.cx = 4;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0xF801);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_sign());
+        assert!(!cpu.regs.flags.get_zero());
+        assert!(!cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_rotate_word_in_memory() {
+        // ROL WORD [BX+2],1 and RCL with the carry it produced
+        let mut cpu = setup_executable_cpu(&[0xD1, 0x47, 0x02, 0xD1, 0x57, 0x02]);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x0200;
+        cpu.memory.write_word(0x202, 0x8001);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x202), 0x0003);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ip, 0x103);
+
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x202), 0x0007);
+        assert!(!cpu.regs.flags.get_carry());
+    }
+
+    #[test]
+    fn test_shift_imm8_needs_80186() {
+        // SHR AX,3
+        let mut cpu = setup_executable_cpu(&[0xC1, 0xE8, 0x03]);
+        cpu.regs.ax = 0x0045;
+        assert!(cpu.execute_instruction().is_err());
+
+        let mut cpu = setup_executable_cpu(&[0xC1, 0xE8, 0x03]);
+        cpu.set_model(CpuModel::I80186);
+        cpu.regs.ax = 0x0045;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0x0008);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ip, 0x103);
+    }
+
+    #[test]
+    fn test_div8_remainder_goes_to_ah() {
+        // DIV BL; IDIV BH
+        let mut cpu = setup_executable_cpu(&[0xF6, 0xF3, 0xF6, 0xFF]);
+        cpu.regs.ax = 100;
+        cpu.regs.bx = 0xFD07; // BH = -3, BL = 7
+        cpu.regs.cx = 0x5555;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al(), 14);
+        assert_eq!(cpu.regs.get_ah(), 2);
+        assert_eq!(cpu.regs.cx, 0x5555); // CL used to take the remainder
+
+        cpu.regs.ax = (-20i16) as u16;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al() as i8, 6);
+        assert_eq!(cpu.regs.get_ah() as i8, -2);
+    }
+
+    #[test]
+    fn test_idiv_truncates_toward_zero() {
+        // IDIV BL; IDIV BX
+        let mut cpu = setup_executable_cpu(&[0xF6, 0xFB, 0xF7, 0xFB]);
+        cpu.regs.ax = (-7i16) as u16;
+        cpu.regs.bx = 0x0002;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al() as i8, -3);
+        assert_eq!(cpu.regs.get_ah() as i8, -1);
+
+        cpu.regs.dx = 0xFFFF;
+        cpu.regs.ax = (-7i16) as u16;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax as i16, -3);
+        assert_eq!(cpu.regs.dx as i16, -1);
+    }
+
+    #[test]
+    fn test_idiv_most_negative_by_minus_one_is_a_divide_error() {
+        // IDIV BL with AX = -8000h, BL = -1
+        let mut cpu = setup_executable_cpu(&[0xF6, 0xFB]);
+        cpu.regs.ax = 0x8000;
+        cpu.regs.bx = 0x00FF;
+        assert_eq!(cpu.run_for(1), StopReason::DivideError);
+        assert_eq!(cpu.regs.ax, 0x8000);
+
+        // IDIV BX with DX:AX = -80000000h, BX = -1
+        let mut cpu = setup_executable_cpu(&[0xF7, 0xFB]);
+        cpu.regs.dx = 0x8000;
+        cpu.regs.ax = 0x0000;
+        cpu.regs.bx = 0xFFFF;
+        assert_eq!(cpu.run_for(1), StopReason::DivideError);
+        assert_eq!((cpu.regs.dx, cpu.regs.ax), (0x8000, 0x0000));
+    }
+
+    // Runs `opcode modrm imm` (plus a disp8 for memory forms) and returns
+    // AL, the byte at DS:0x210, the flags and the final IP
+    fn run_group1_rm8(
+        opcode: u8,
+        op: u8,
+        memory: bool,
+        al: u8,
+        imm: u8,
+        carry: bool,
+    ) -> (u8, u8, u16, u16) {
+        let code = if memory {
+            // op [BX+0x10], imm8 with BX=0x200
+            vec![opcode, 0x47 | (op << 3), 0x10, imm]
+        } else {
+            vec![opcode, 0xC0 | (op << 3), imm]
+        };
+        let mut cpu = setup_executable_cpu(&code);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x200;
+        cpu.regs.set_al(al);
+        cpu.memory.write_byte(0x210, al);
+        cpu.regs.flags.set_carry(carry);
+        cpu.execute_instruction().unwrap();
+        (
+            cpu.regs.get_al(),
+            cpu.memory.read_byte(0x210),
+            cpu.regs.flags.as_u16(),
+            cpu.regs.ip,
+        )
+    }
+
+    #[test]
+    fn test_group1_82_matches_80() {
+        let operands = [
+            (0x05, 0x03),
+            (0x7F, 0x01),
+            (0x80, 0x01),
+            (0xFF, 0xFF),
+            (0x10, 0x20),
+            (0x00, 0x00),
+        ];
+        for op in 0..8 {
+            for &(al, imm) in &operands {
+                for carry in [false, true] {
+                    for memory in [false, true] {
+                        assert_eq!(
+                            run_group1_rm8(0x82, op, memory, al, imm, carry),
+                            run_group1_rm8(0x80, op, memory, al, imm, carry),
+                            "op /{} AL={:02X} imm={:02X} CF={} memory={}",
+                            op,
+                            al,
+                            imm,
+                            carry,
+                            memory
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_group1_rm8_results() {
+        // (op, AL, imm, CF in) -> (result, CF, ZF, OF)
+        let cases = [
+            (0, 0xFF, 0x01, false, 0x00, true, true, false), // ADD wraps
+            (0, 0x7F, 0x01, false, 0x80, false, false, true), // ADD overflows
+            (1, 0x0F, 0xF0, true, 0xFF, false, false, false), // OR clears CF
+            (2, 0xFE, 0x01, true, 0x00, true, true, false),  // ADC adds CF
+            (3, 0x00, 0x00, true, 0xFF, true, false, false), // SBB borrows CF
+            (4, 0xF0, 0x0F, false, 0x00, false, true, false), // AND
+            (5, 0x80, 0x01, false, 0x7F, false, false, true), // SUB overflows
+            (6, 0xAA, 0xAA, false, 0x00, false, true, false), // XOR
+            (7, 0x05, 0x05, false, 0x05, false, true, false), // CMP equal
+            (7, 0x01, 0x02, false, 0x01, true, false, false), // CMP below
+        ];
+        for (op, al, imm, carry, result, cf, zf, of) in cases {
+            let (new_al, in_memory, flags, ip) = run_group1_rm8(0x82, op, true, al, imm, carry);
+            assert_eq!(in_memory, result, "op /{}", op);
+            assert_eq!(new_al, al, "memory form must not touch AL");
+            assert_eq!(ip, 0x104);
+            assert_eq!(flags & 0x0001 != 0, cf, "CF for op /{}", op);
+            assert_eq!(flags & 0x0040 != 0, zf, "ZF for op /{}", op);
+            assert_eq!(flags & 0x0800 != 0, of, "OF for op /{}", op);
+        }
+    }
+
+    #[test]
+    fn test_81_add_memory_with_disp16() {
+        // ADD WORD PTR [BX+1234h],5678h: the displacement comes before the
+        // immediate and the write-back must not fetch it again
+        let mut cpu = setup_executable_cpu(&[0x81, 0x87, 0x34, 0x12, 0x78, 0x56]);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x0100;
+        cpu.memory.write_word(0x1334, 0x1111);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x1334), 0x6789);
+        assert_eq!(cpu.regs.ip, 0x106);
+        assert!(!cpu.regs.flags.get_carry());
+    }
+
+    #[test]
+    fn test_read_modify_write_groups_with_displacement() {
+        // AND WORD PTR [BX+10h],-10h
+        let mut cpu = setup_executable_cpu(&[0x83, 0x67, 0x10, 0xF0]);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x0200;
+        cpu.memory.write_word(0x210, 0x1234);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x210), 0x1230);
+        assert_eq!(cpu.regs.ip, 0x104);
+
+        // NEG WORD PTR [BX+10h]; INC BYTE PTR [BX+10h]; DEC WORD PTR [BX+10h]
+        let mut cpu = setup_executable_cpu(&[0xF7, 0x5F, 0x10, 0xFE, 0x47, 0x10, 0xFF, 0x4F, 0x10]);
+        cpu.regs.ds = 0;
+        cpu.regs.bx = 0x0200;
+        cpu.memory.write_word(0x210, 0x0001);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x210), 0xFFFF);
+        assert_eq!(cpu.regs.ip, 0x103);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x210), 0xFF00);
+        assert_eq!(cpu.regs.ip, 0x106);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x210), 0xFEFF);
+        assert_eq!(cpu.regs.ip, 0x109);
+    }
 }
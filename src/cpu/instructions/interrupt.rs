@@ -1,7 +1,9 @@
-use crate::bios::handle_bios_interrupt;
-use crate::cpu::Cpu;
+use crate::cpu::CpuGeneric;
+use crate::memory::Memory;
+use crate::pic::Pic;
+use crate::prelude::*;
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub(crate) fn int(&mut self, interrupt_number: u8) -> Result<(), String> {
         // Save flags and CS:IP
         let flags = self.regs.flags.as_word();
@@ -13,18 +15,20 @@ impl Cpu {
         self.push_word(cs)?;
         self.push_word(ip)?;
 
-        // Clear IF and TF
+        // Clear IF and TF. DF is left as the interrupted code had it, as on
+        // the real CPU: a handler that uses string instructions has to CLD
+        // itself, and IRET puts the caller's DF back either way.
         self.regs.flags.set_interrupt(false);
         self.regs.flags.set_trap(false);
 
-        // Get interrupt vector
-        let vector_addr = (interrupt_number as u32) * 4;
-        let new_ip = self.memory.read_word(vector_addr);
-        let new_cs = self.memory.read_word(vector_addr + 2);
+        let (new_cs, new_ip) = self.get_ivt(interrupt_number);
 
-        // Handle BIOS interrupts
+        // Handle BIOS interrupts; without std only the ROM's own code runs
+        #[cfg(feature = "std")]
         if new_cs == 0xF000 {
-            handle_bios_interrupt(self, interrupt_number)?;
+            if let Some(native) = self.native_interrupts {
+                native(self, interrupt_number)?;
+            }
         }
 
         // Jump to interrupt handler
@@ -34,6 +38,102 @@ impl Cpu {
         Ok(())
     }
 
+    /// The `(segment, offset)` in the interrupt vector table for `vector`
+    pub fn get_ivt(&self, vector: u8) -> (u16, u16) {
+        let addr = (vector as u32) * 4;
+        self.memory_accesses.set(self.memory_accesses.get() + 2);
+        (self.memory.read_word(addr + 2), self.memory.read_word(addr))
+    }
+
+    /// Points `vector` at `segment:offset`. Each entry is four bytes at
+    /// 0000:vector*4, offset word first.
+    pub fn set_ivt(&mut self, vector: u8, segment: u16, offset: u16) {
+        let addr = (vector as u32) * 4;
+        self.memory.write_word(addr, offset);
+        self.memory.write_word(addr + 2, segment);
+    }
+
+    // CC: the one-byte breakpoint form of INT 3
+    pub(crate) fn int3(&mut self) -> Result<(), String> {
+        self.int(3)
+    }
+
+    // CE: INT 4 if OF is set, otherwise nothing
+    pub(crate) fn int_overflow(&mut self) -> Result<(), String> {
+        if self.regs.flags.get_overflow() {
+            self.int(4)?;
+        }
+        Ok(())
+    }
+
+    /// Takes software interrupt `n` as if the guest had executed `INT n`
+    pub fn int_n(&mut self, n: u8) -> Result<(), String> {
+        self.int(n)
+    }
+
+    /// Services an external (hardware) interrupt between instructions
+    pub fn service_interrupt(&mut self, interrupt_number: u8) -> Result<(), String> {
+        // IP already points back at the start of an unfinished REP string
+        // instruction; the 8086 quirk instead returns to its last prefix
+        if let Some(last_prefix_ip) = self.rep_last_prefix_ip.take() {
+            if self.quirk_rep_prefix_bug {
+                self.regs.ip = last_prefix_ip;
+            }
+        }
+        self.int(interrupt_number)
+    }
+
+    /// UD2 (0x0F 0x0B): an opcode that is defined to be invalid, used as a
+    /// trap marker. It takes INT 6 like any invalid opcode but is not counted
+    /// as a gap in opcode coverage.
+    pub(crate) fn ud2(&mut self) -> Result<(), String> {
+        println!(
+            "UD2 at {:04X}:{:04X}",
+            self.regs.cs, self.instruction_start_ip
+        );
+        self.raise_invalid_opcode()
+    }
+
+    /// Signals a hardware interrupt on PIC line `irq` (0-7). It is delivered
+    /// before the next instruction once IF is set and the line is unmasked;
+    /// e.g. IRQ 0 for a timer tick or IRQ 1 for a keypress.
+    pub fn request_irq(&mut self, irq: u8) {
+        match self.io.device_mut::<Pic>() {
+            Some(pic) => pic.request(irq),
+            None => println!("IRQ {} requested with no PIC on the bus", irq),
+        }
+    }
+
+    /// True if an interrupt would be taken before the next instruction
+    pub fn irq_pending(&mut self) -> bool {
+        if !self.regs.flags.get_interrupt() || self.interrupt_shadow {
+            return false;
+        }
+        self.io
+            .device_mut::<Pic>()
+            .is_some_and(|pic| pic.pending().is_some())
+    }
+
+    // Takes a pending hardware interrupt, if any, between instructions. A
+    // HLT waiting for an interrupt wakes up here.
+    pub(crate) fn deliver_pending_irq(&mut self) -> Result<bool, String> {
+        if !self.irq_pending() {
+            return Ok(false);
+        }
+        let vector = match self
+            .io
+            .device_mut::<Pic>()
+            .and_then(|pic| pic.acknowledge())
+        {
+            Some(vector) => vector,
+            None => return Ok(false),
+        };
+        println!("IRQ: Delivering INT {:02X}h", vector);
+        self.halted = false;
+        self.service_interrupt(vector)?;
+        Ok(true)
+    }
+
     pub(crate) fn iret(&mut self) -> Result<(), String> {
         // Pop IP, CS, and FLAGS
         let new_ip = self.pop_word()?;
@@ -68,6 +168,8 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -115,6 +217,56 @@ mod tests {
         assert_eq!(cpu.regs.ip, 0x3000);
     }
 
+    #[test]
+    fn test_ivt_accessors() {
+        let mut cpu = setup_cpu();
+        cpu.set_ivt(0x21, 0x1234, 0x5678);
+        assert_eq!(cpu.get_ivt(0x21), (0x1234, 0x5678));
+        // Offset low word first, both little-endian
+        let raw: Vec<u8> = (0x84..0x88)
+            .map(|addr| cpu.memory.read_byte(addr))
+            .collect();
+        assert_eq!(raw, [0x78, 0x56, 0x34, 0x12]);
+        // The neighbours are untouched
+        cpu.set_ivt(0x20, 0, 0);
+        cpu.set_ivt(0x22, 0, 0);
+        assert_eq!(cpu.get_ivt(0x21), (0x1234, 0x5678));
+    }
+
+    #[test]
+    fn test_software_interrupts_return_past_the_whole_instruction() {
+        // INT3; MOV AX,1111h; INT 60h; MOV BX,2222h; INTO; MOV CX,3333h; HLT
+        let code = [
+            0xCC, 0xB8, 0x11, 0x11, 0xCD, 0x60, 0xBB, 0x22, 0x22, 0xCE, 0xB9, 0x33, 0x33, 0xF4,
+        ];
+        let mut cpu = setup_executable_cpu(&code);
+        // Every handler is a bare IRET at 0000:0200
+        cpu.memory.write_byte(0x200, 0xCF);
+        for vector in [0x03, 0x04, 0x60] {
+            cpu.set_ivt(vector, 0x0000, 0x0200);
+        }
+        cpu.regs.flags.set_overflow(true);
+
+        let mut return_ips = Vec::new();
+        while !cpu.is_halted() {
+            cpu.step().unwrap();
+            if cpu.regs.ip == 0x0200 {
+                return_ips.push(cpu.peek_word(cpu.regs.ss, cpu.regs.sp));
+            }
+        }
+        assert_eq!(return_ips, [0x0101, 0x0106, 0x010A]);
+        assert_eq!(
+            (cpu.regs.ax, cpu.regs.bx, cpu.regs.cx),
+            (0x1111, 0x2222, 0x3333)
+        );
+
+        // With OF clear INTO falls straight through
+        let mut cpu = setup_executable_cpu(&[0xCE, 0xF4]);
+        cpu.regs.flags.set_overflow(false);
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0101));
+    }
+
     #[test]
     fn test_iret() {
         let mut cpu = setup_cpu();
@@ -155,4 +307,155 @@ mod tests {
         // Check that flags were cleared
         assert!(!cpu.regs.flags.get_interrupt());
     }
+
+    fn setup_rep_interrupt(quirk: bool) -> Cpu {
+        // CS: REP MOVSB, encoded with the override first (2E F3 A4)
+        let mut cpu = setup_executable_cpu(&[0x2E, 0xF3, 0xA4]);
+        cpu.set_quirk_rep_prefix_bug(quirk);
+        cpu.regs.ds = 0x1000;
+        cpu.regs.es = 0x2000;
+        cpu.regs.si = 0x0200;
+        cpu.regs.di = 0;
+        cpu.regs.cx = 3;
+        for (i, byte) in [0xAA, 0xBB, 0xCC].iter().enumerate() {
+            cpu.memory.write_byte(0x0200 + i as u32, *byte); // CS:SI
+            cpu.memory
+                .write_byte(0x10200 + i as u32, 0x11 * (i as u8 + 1)); // DS:SI
+        }
+
+        // INT 50h handler is a bare IRET at 0000:0500
+        cpu.memory.write_word(0x50 * 4, 0x0500);
+        cpu.memory.write_word(0x50 * 4 + 2, 0x0000);
+        cpu.memory.write_byte(0x0500, 0xCF);
+
+        cpu.execute_instruction().unwrap(); // First iteration
+        cpu.service_interrupt(0x50).unwrap();
+        cpu.execute_instruction().unwrap(); // IRET
+        cpu
+    }
+
+    #[test]
+    fn test_interrupt_mid_rep_resumes_with_all_prefixes() {
+        let mut cpu = setup_rep_interrupt(false);
+        assert_eq!(cpu.regs.ip, 0x100);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_byte(0x20001), 0xBB);
+    }
+
+    #[test]
+    fn test_quirk_rep_prefix_bug_drops_segment_override() {
+        let mut cpu = setup_rep_interrupt(true);
+        assert_eq!(cpu.regs.ip, 0x101);
+        while cpu.regs.ip != 0x103 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert_eq!(cpu.memory.read_byte(0x20000), 0xAA);
+        // The remaining iterations read DS:SI, not CS:SI
+        assert_eq!(cpu.memory.read_byte(0x20001), 0x22);
+        assert_eq!(cpu.memory.read_byte(0x20002), 0x33);
+        assert_eq!(cpu.regs.cx, 0);
+    }
+
+    #[test]
+    fn test_request_irq_enters_handler_on_next_step() {
+        // NOP; NOP
+        let mut cpu = setup_executable_cpu(&[0x90, 0x90]);
+        // INT 9 (IRQ 1) handler at 0000:0600
+        cpu.memory.write_word(0x09 * 4, 0x0600);
+        cpu.memory.write_word(0x09 * 4 + 2, 0x0000);
+        cpu.memory.write_byte(0x0600, 0x90);
+
+        // Not taken while IF is clear
+        cpu.regs.flags.set_interrupt(false);
+        cpu.request_irq(1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ip, 0x101);
+
+        cpu.regs.flags.set_interrupt(true);
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0600));
+        assert!(!cpu.regs.flags.get_interrupt());
+        // Returns to the NOP that hadn't run yet
+        assert_eq!(cpu.memory.read_word(cpu.regs.sp as u32), 0x101);
+        assert_eq!(cpu.io.device_mut::<Pic>().unwrap().isr, 0b0000_0010);
+    }
+
+    #[test]
+    fn test_request_irq_wakes_hlt() {
+        // HLT; NOP
+        let mut cpu = setup_executable_cpu(&[0xF4, 0x90]);
+        cpu.memory.write_word(0x08 * 4, 0x0600);
+        cpu.memory.write_word(0x08 * 4 + 2, 0x0000);
+        cpu.regs.flags.set_interrupt(true);
+        assert_eq!(cpu.run_for(10), crate::cpu::StopReason::Halted);
+
+        cpu.request_irq(0);
+        cpu.step().unwrap();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.regs.ip, 0x0600);
+    }
+
+    #[test]
+    fn test_irq_waits_for_the_instruction_after_pop_ss() {
+        // POP SS; MOV SP,2000h; NOP
+        let mut cpu = setup_executable_cpu(&[0x17, 0xBC, 0x00, 0x20, 0x90]);
+        cpu.memory.write_word(0x09 * 4, 0x0600);
+        cpu.memory.write_word(0x09 * 4 + 2, 0x0000);
+        cpu.regs.sp = 0x0FFE;
+        cpu.memory.write_word(0x0FFE, 0x0100);
+        cpu.regs.flags.set_interrupt(true);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ss, 0x0100);
+        assert_eq!(cpu.regs.sp, 0x1000);
+        assert!(cpu.in_interrupt_shadow());
+
+        // The IRQ arrives between POP SS and MOV SP but has to wait
+        cpu.request_irq(1);
+        assert!(!cpu.irq_pending());
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ip, 0x104);
+        assert_eq!(cpu.regs.sp, 0x2000);
+
+        // Now it's taken, on the new stack
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0600));
+        assert_eq!(cpu.regs.ss, 0x0100);
+        assert_eq!(cpu.regs.sp, 0x1FFA);
+        assert_eq!(cpu.peek_word(0x0100, 0x1FFA), 0x104);
+    }
+
+    #[test]
+    fn test_direction_flag_is_saved_and_restored_around_a_handler() {
+        // STD; INT 60h; NOP
+        let mut cpu = setup_executable_cpu(&[0xFD, 0xCD, 0x60, 0x90]);
+        // The handler at 0000:0200: PUSHF; POP BX; STOSB; CLD; IRET
+        for (i, &byte) in [0x9C, 0x5B, 0xAA, 0xFC, 0xCF].iter().enumerate() {
+            cpu.memory.write_byte(0x200 + i as u32, byte);
+        }
+        cpu.memory.write_word(0x60 * 4, 0x0200);
+        cpu.memory.write_word(0x60 * 4 + 2, 0x0000);
+        cpu.regs.es = 0;
+        cpu.regs.di = 0x0300;
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0200));
+        // The whole flags word was saved, DF included
+        assert_ne!(cpu.peek_word(0, cpu.regs.sp.wrapping_add(4)) & 0x0400, 0);
+
+        // The handler runs with the caller's DF until it clears it
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_ne!(cpu.regs.bx & 0x0400, 0);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.di, 0x02FF);
+        cpu.step().unwrap();
+        assert!(!cpu.regs.flags.get_direction());
+
+        // IRET brings the caller's DF back
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ip, 0x0103);
+        assert!(cpu.regs.flags.get_direction());
+    }
 }
@@ -1,6 +1,10 @@
-use crate::cpu::Cpu;
+use crate::cpu::CpuGeneric;
+use crate::debug_exit::DebugExit;
+use crate::memory::Memory;
+use crate::prelude::*;
+use crate::serial::{SerialPort, COM1_BASE, LSR};
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub(crate) fn in_al_imm8(&mut self) -> Result<(), String> {
         let port = self.fetch_byte()?;
         let value = self.io_read_byte(port as u16)?;
@@ -52,28 +56,91 @@ impl Cpu {
     }
 
     // Helper functions
-    pub(crate) fn io_read_byte(&mut self, _port: u16) -> Result<u8, String> {
-        Ok(0)
+    pub(crate) fn io_read_byte(&mut self, port: u16) -> Result<u8, String> {
+        self.count_io_access();
+        self.feed_uart(port);
+        Ok(self.io.read_byte(port))
     }
 
-    pub(crate) fn io_read_word(&mut self, _port: u16) -> Result<u16, String> {
-        Ok(0)
+    pub(crate) fn io_read_word(&mut self, port: u16) -> Result<u16, String> {
+        self.count_io_access();
+        self.feed_uart(port);
+        Ok(self.io.read_word(port))
     }
 
-    pub(crate) fn io_write_byte(&mut self, _port: u16, _value: u8) -> Result<(), String> {
+    pub(crate) fn io_write_byte(&mut self, port: u16, value: u8) -> Result<(), String> {
+        self.count_io_access();
+        self.io.write_byte(port, value);
+        self.drain_uart();
+        self.check_guest_exit();
         Ok(())
     }
 
-    pub(crate) fn io_write_word(&mut self, _port: u16, _value: u16) -> Result<(), String> {
+    pub(crate) fn io_write_word(&mut self, port: u16, value: u16) -> Result<(), String> {
+        self.count_io_access();
+        self.io.write_word(port, value);
+        self.drain_uart();
+        self.check_guest_exit();
         Ok(())
     }
+
+    // Input queued on `serial` arrives in the UART's receive FIFO, as far
+    // as it has room. A guest polling LSR with nothing to read is waiting
+    // for the user, so the console is flushed first.
+    fn feed_uart(&mut self, port: u16) {
+        if !self.serial.has_data() {
+            if port == COM1_BASE + LSR as u16
+                && self
+                    .io
+                    .device_mut::<SerialPort>()
+                    .is_some_and(|uart| !uart.has_data())
+            {
+                self.serial.flush_console();
+            }
+            return;
+        }
+        if let Some(uart) = self.io.device_mut::<SerialPort>() {
+            // Stopping once it's empty, rather than at take_input's None,
+            // keeps this from counting as a wait for input
+            while !uart.rx_full() && self.serial.has_data() {
+                if let Some(byte) = self.serial.take_input() {
+                    uart.receive_byte(byte);
+                }
+            }
+        }
+    }
+
+    // Bytes the guest wrote to the UART's THR go out through `serial`
+    fn drain_uart(&mut self) {
+        if let Some(uart) = self.io.device_mut::<SerialPort>() {
+            while let Some(byte) = uart.tx_fifo.pop_front() {
+                self.serial.transmit(byte);
+            }
+        }
+    }
+
+    // A write to the debug-exit port stops the CPU with the code written
+    fn check_guest_exit(&mut self) {
+        let code = self
+            .io
+            .device_mut::<DebugExit>()
+            .and_then(|port| port.take_exit_code());
+        if let Some(code) = code {
+            self.guest_exit = Some(code);
+            self.halted = true;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
+    use crate::pic::Pic;
+    use crate::pit::Pit;
     use crate::serial::Serial;
     use std::path::Path;
 
@@ -87,6 +154,8 @@ mod tests {
     #[test]
     fn test_in_al_imm8() {
         let mut cpu = setup_cpu();
+        cpu.regs.cs = 0;
+        cpu.regs.ip = 0;
         cpu.memory.write_byte(0, 0x42); // Port number
         assert!(cpu.in_al_imm8().is_ok());
         assert_eq!(cpu.regs.get_al(), 0); // Default implementation returns 0
@@ -95,6 +164,8 @@ mod tests {
     #[test]
     fn test_in_ax_imm8() {
         let mut cpu = setup_cpu();
+        cpu.regs.cs = 0;
+        cpu.regs.ip = 0;
         cpu.memory.write_byte(0, 0x42); // Port number
         assert!(cpu.in_ax_imm8().is_ok());
         assert_eq!(cpu.regs.ax, 0); // Default implementation returns 0
@@ -171,4 +242,98 @@ mod tests {
         let mut cpu = setup_cpu();
         assert!(cpu.io_write_word(0x42, 0x1234).is_ok()); // Default implementation just returns Ok
     }
+
+    #[test]
+    fn test_pit_reload_and_latched_read() {
+        let mut cpu = setup_executable_cpu(&[
+            0xB0, 0x36, // mov al, 0x36 (channel 0, lo/hi, mode 3)
+            0xE6, 0x43, // out 0x43, al
+            0xB0, 0x9C, // mov al, 0x9C
+            0xE6, 0x40, // out 0x40, al
+            0xB0, 0x2E, // mov al, 0x2E
+            0xE6, 0x40, // out 0x40, al
+            0xB0, 0x00, // mov al, 0x00 (latch channel 0)
+            0xE6, 0x43, // out 0x43, al
+            0xE4, 0x40, // in al, 0x40
+            0x88, 0xC3, // mov bl, al
+            0xE4, 0x40, // in al, 0x40
+            0x88, 0xC7, // mov bh, al
+        ]);
+        for _ in 0..12 {
+            cpu.execute_instruction().unwrap();
+        }
+
+        let pit = cpu.io.device_mut::<Pit>().unwrap();
+        assert_eq!(pit.channels[0].reload, 0x2E9C);
+        assert_eq!(pit.channels[0].count, 0x2E9C);
+        assert_eq!(cpu.regs.bx, 0x2E9C);
+    }
+
+    #[test]
+    fn test_pic_mask_via_out_dx() {
+        let mut cpu = setup_cpu();
+        cpu.regs.dx = 0x21;
+        cpu.regs.ax = 0x00FD; // Unmask only the keyboard IRQ
+        assert!(cpu.out_dx_al().is_ok());
+        assert_eq!(cpu.io.device_mut::<Pic>().unwrap().imr, 0xFD);
+        cpu.regs.ax = 0;
+        assert!(cpu.in_al_dx().is_ok());
+        assert_eq!(cpu.regs.get_al(), 0xFD);
+    }
+
+    #[test]
+    fn test_uart_on_the_bus() {
+        // MOV AX,0x3F8; MOV DX,AX; MOV AL,'H'; OUT DX,AL; ADD DX,5 (LSR);
+        // IN AL,DX
+        let mut cpu = setup_executable_cpu(&[
+            0xB8, 0xF8, 0x03, 0x8B, 0xD0, 0xB0, b'H', 0xEE, 0x83, 0xC2, 0x05, 0xEC,
+        ]);
+        cpu.serial.add_input(b'x');
+        while cpu.regs.ip < 0x10C {
+            cpu.execute_instruction().unwrap();
+        }
+        assert_eq!(cpu.regs.dx, 0x3FD);
+        assert_eq!(
+            cpu.serial.output().iter().copied().collect::<Vec<_>>(),
+            b"H"
+        );
+        // THRE and TEMT, plus DR for the queued input
+        assert_eq!(cpu.regs.get_al(), 0x61);
+        assert_eq!(cpu.io_read_byte(0x3F8).unwrap(), b'x');
+        assert_eq!(cpu.io_read_byte(0x3FD).unwrap(), 0x60);
+    }
+
+    #[test]
+    fn test_polling_lsr_flushes_the_console() {
+        use crate::serial::SerialSink;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        struct Flushes(Rc<Cell<usize>>);
+        impl SerialSink for Flushes {
+            fn write_byte(&mut self, _byte: u8) {}
+            fn flush(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let mut cpu = setup_executable_cpu(&[]);
+        let flushes = Rc::new(Cell::new(0));
+        cpu.serial
+            .set_console(Some(Box::new(Flushes(flushes.clone()))));
+        // A prompt with no line end stays buffered under the default policy
+        b"C:\\>".iter().for_each(|&byte| cpu.serial.echo(byte));
+        assert_eq!(flushes.get(), 0);
+
+        // Other ports leave it alone; an LSR poll with nothing to read doesn't
+        cpu.io_read_byte(0x3FB).unwrap();
+        assert_eq!(flushes.get(), 0);
+        cpu.io_read_byte(0x3FD).unwrap();
+        assert_eq!(flushes.get(), 1);
+
+        // With a key on its way there is no wait, so no flush
+        cpu.serial.add_input(b'd');
+        assert_eq!(cpu.io_read_byte(0x3FD).unwrap(), 0x61);
+        assert_eq!(flushes.get(), 1);
+    }
 }
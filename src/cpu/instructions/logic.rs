@@ -1,6 +1,8 @@
-use crate::cpu::Cpu;
+use crate::cpu::CpuGeneric;
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub(crate) fn and_rm8_r8(&mut self) -> Result<(), String> {
         let modrm = self.fetch_byte()?;
         let rm_val = self.get_rm8(modrm)?;
@@ -13,7 +15,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -29,7 +33,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -56,7 +62,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
 
         println!(
             "XOR_RM8_R8: Flags - ZF={}, SF={}, PF={}",
@@ -81,7 +89,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -97,7 +107,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -112,7 +124,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -128,7 +142,9 @@ impl Cpu {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x80) != 0);
         self.regs.flags.set_overflow(false);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -146,7 +162,7 @@ impl Cpu {
         self.regs.flags.set_overflow(false);
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -165,7 +181,7 @@ impl Cpu {
         self.regs.flags.set_overflow(false);
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
         Ok(())
     }
 
@@ -175,6 +191,7 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
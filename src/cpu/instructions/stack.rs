@@ -1,6 +1,8 @@
-use crate::cpu::Cpu;
+use crate::cpu::CpuGeneric;
+use crate::memory::Memory;
+use crate::prelude::*;
 
-impl Cpu {
+impl<M: Memory> CpuGeneric<M> {
     pub fn push_ax(&mut self) -> Result<(), String> {
         self.push_word(self.regs.ax)?;
         Ok(())
@@ -30,11 +32,8 @@ impl Cpu {
         // Decrement SP by 2 (word size)
         self.regs.sp = self.regs.sp.wrapping_sub(2);
 
-        // Calculate physical address for the stack
-        let address = ((self.regs.ss as u32) << 4) | (self.regs.sp as u32);
-
-        // Write the word to memory
-        self.memory.write_word(address, value);
+        // Write the word to the stack segment
+        self.write_word_at(self.regs.ss, self.regs.sp, value);
 
         Ok(())
     }
@@ -64,6 +63,14 @@ impl Cpu {
         Ok(())
     }
 
+    // Like MOV SS, this holds off interrupts until after the next
+    // instruction, so a following MOV SP completes the stack switch first
+    pub fn pop_ss(&mut self) -> Result<(), String> {
+        self.regs.ss = self.pop_word()?;
+        self.interrupt_shadow = true;
+        Ok(())
+    }
+
     pub fn leave(&mut self) -> Result<(), String> {
         self.regs.sp = self.regs.bp;
         self.regs.bp = self.pop_word()?;
@@ -87,7 +94,7 @@ impl Cpu {
         if nesting_level > 0 {
             for level in 1..=nesting_level {
                 self.regs.bp = self.regs.bp.wrapping_sub(2);
-                let temp = self.memory.read_word(self.regs.bp as u32);
+                let temp = self.read_word_at(self.regs.ss, self.regs.bp);
                 println!("ENTER: Level {}: temp = {:#06x}", level, temp);
                 self.push_word(temp)?;
             }
@@ -115,7 +122,7 @@ impl Cpu {
         // Set CS:IP first, then adjust SP
         self.regs.ip = ip;
         self.regs.cs = cs;
-        self.regs.sp += imm16;
+        self.regs.sp = self.regs.sp.wrapping_add(imm16);
         println!(
             "RET FAR: Final SP = {:#06x} (after adding imm16)",
             self.regs.sp
@@ -127,6 +134,7 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::Cpu;
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
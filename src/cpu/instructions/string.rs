@@ -1,11 +1,65 @@
-use crate::cpu::Cpu;
+use crate::cpu::{CpuGeneric, RepPrefix, SegmentRegister};
+use crate::memory::Memory;
+use crate::prelude::*;
+
+impl<M: Memory> CpuGeneric<M> {
+    /// Runs one iteration of a string instruction. Under a REP prefix, IP is
+    /// rewound to `start_ip` while iterations remain, so the next step repeats
+    /// the instruction and interrupts can be taken between iterations.
+    pub(crate) fn execute_string(&mut self, opcode: u8, start_ip: u16) -> Result<(), String> {
+        let rep = match self.rep_prefix {
+            Some(rep) => rep,
+            None => return self.execute_string_once(opcode),
+        };
+
+        if self.regs.cx == 0 {
+            return Ok(());
+        }
+
+        // String opcodes have no operand bytes, so the last prefix sits
+        // immediately before the opcode just fetched
+        let last_prefix_ip = self.regs.ip.wrapping_sub(2);
+
+        self.execute_string_once(opcode)?;
+        self.regs.cx = self.regs.cx.wrapping_sub(1);
+
+        // CMPS and SCAS also stop on the REPE/REPNE condition
+        let compares = matches!(opcode, 0xA6 | 0xA7 | 0xAE | 0xAF);
+        let stop = compares
+            && match rep {
+                RepPrefix::Rep => !self.regs.flags.get_zero(),
+                RepPrefix::Repne => self.regs.flags.get_zero(),
+            };
+
+        if self.regs.cx != 0 && !stop {
+            self.regs.ip = start_ip;
+            self.rep_last_prefix_ip = Some(last_prefix_ip);
+        }
+        Ok(())
+    }
+
+    fn execute_string_once(&mut self, opcode: u8) -> Result<(), String> {
+        match opcode {
+            0xA4 => self.movsb(),
+            0xA5 => self.movsw(),
+            0xA6 => self.cmpsb(),
+            0xA7 => self.cmpsw(),
+            0xAA => self.stosb(),
+            0xAB => self.stosw(),
+            0xAC => self.lodsb(),
+            0xAD => self.lodsw(),
+            0xAE => self.scasb(),
+            0xAF => self.scasw(),
+            _ => Err(format!("Not a string opcode {:#04X}", opcode)),
+        }
+    }
 
-impl Cpu {
     pub(crate) fn movsb(&mut self) -> Result<(), String> {
-        let src_addr = self.get_physical_address(self.regs.ds, self.regs.si);
+        let src_seg = self.effective_segment(SegmentRegister::DS);
+        let src_addr = self.get_physical_address(src_seg, self.regs.si);
         let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
-        let value = self.memory.read_byte(src_addr);
-        self.memory.write_byte(dst_addr, value);
+        let value = self.bus_read_byte(src_addr);
+        self.bus_write_byte(dst_addr, value);
 
         if !self.regs.flags.get_direction() {
             self.regs.si = self.regs.si.wrapping_add(1);
@@ -18,10 +72,9 @@ impl Cpu {
     }
 
     pub(crate) fn movsw(&mut self) -> Result<(), String> {
-        let src_addr = self.get_physical_address(self.regs.ds, self.regs.si);
-        let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
-        let value = self.memory.read_word(src_addr);
-        self.memory.write_word(dst_addr, value);
+        let src_seg = self.effective_segment(SegmentRegister::DS);
+        let value = self.read_word_at(src_seg, self.regs.si);
+        self.write_word_at(self.regs.es, self.regs.di, value);
 
         if !self.regs.flags.get_direction() {
             self.regs.si = self.regs.si.wrapping_add(2);
@@ -34,8 +87,9 @@ impl Cpu {
     }
 
     pub(crate) fn lodsb(&mut self) -> Result<(), String> {
-        let src_addr = self.get_physical_address(self.regs.ds, self.regs.si);
-        let value = self.memory.read_byte(src_addr);
+        let src_seg = self.effective_segment(SegmentRegister::DS);
+        let src_addr = self.get_physical_address(src_seg, self.regs.si);
+        let value = self.bus_read_byte(src_addr);
         self.regs.ax = (self.regs.ax & 0xFF00) | (value as u16);
 
         if !self.regs.flags.get_direction() {
@@ -47,9 +101,8 @@ impl Cpu {
     }
 
     pub(crate) fn lodsw(&mut self) -> Result<(), String> {
-        let src_addr = self.get_physical_address(self.regs.ds, self.regs.si);
-        let value = self.memory.read_word(src_addr);
-        self.regs.ax = value;
+        let src_seg = self.effective_segment(SegmentRegister::DS);
+        self.regs.ax = self.read_word_at(src_seg, self.regs.si);
 
         if !self.regs.flags.get_direction() {
             self.regs.si = self.regs.si.wrapping_add(2);
@@ -62,7 +115,7 @@ impl Cpu {
     pub(crate) fn stosb(&mut self) -> Result<(), String> {
         let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
         let value = self.regs.ax as u8;
-        self.memory.write_byte(dst_addr, value);
+        self.bus_write_byte(dst_addr, value);
 
         if !self.regs.flags.get_direction() {
             self.regs.di = self.regs.di.wrapping_add(1);
@@ -73,8 +126,7 @@ impl Cpu {
     }
 
     pub(crate) fn stosw(&mut self) -> Result<(), String> {
-        let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
-        self.memory.write_word(dst_addr, self.regs.ax);
+        self.write_word_at(self.regs.es, self.regs.di, self.regs.ax);
 
         if !self.regs.flags.get_direction() {
             self.regs.di = self.regs.di.wrapping_add(2);
@@ -84,15 +136,16 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn cmpsb(&mut self) -> Result<(), String> {
-        let src_addr = self.get_physical_address(self.regs.ds, self.regs.si);
+        let src_seg = self.effective_segment(SegmentRegister::DS);
+        let src_addr = self.get_physical_address(src_seg, self.regs.si);
         let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
-        let src_val = self.memory.read_byte(src_addr);
-        let dst_val = self.memory.read_byte(dst_addr);
+        let src_val = self.bus_read_byte(src_addr);
+        let dst_val = self.bus_read_byte(dst_addr);
 
-        let (result, carry) = dst_val.overflowing_sub(src_val);
-        self.update_flags_sub(dst_val, src_val, result, carry);
+        // CMPS subtracts the destination from the source, the reverse of
+        // the operand order MOVS suggests
+        self.cmp8(src_val, dst_val);
 
         if !self.regs.flags.get_direction() {
             self.regs.si = self.regs.si.wrapping_add(1);
@@ -104,17 +157,17 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn cmpsw(&mut self) -> Result<(), String> {
-        let src_addr = self.get_physical_address(self.regs.ds, self.regs.si);
+        let src_seg = self.effective_segment(SegmentRegister::DS);
+        let src_addr = self.get_physical_address(src_seg, self.regs.si);
         let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
         println!(
             "CMPSW: Source address = {:#010x}, Destination address = {:#010x}",
             src_addr, dst_addr
         );
 
-        let src_val = self.memory.read_word(src_addr);
-        let dst_val = self.memory.read_word(dst_addr);
+        let src_val = self.read_word_at(src_seg, self.regs.si);
+        let dst_val = self.read_word_at(self.regs.es, self.regs.di);
         println!(
             "CMPSW: Source value = {:#06x}, Destination value = {:#06x}",
             src_val, dst_val
@@ -124,12 +177,7 @@ impl Cpu {
             self.regs.si, self.regs.di
         );
 
-        let (result, carry) = dst_val.overflowing_sub(src_val);
-        println!(
-            "CMPSW: Comparison result = {:#06x}, Carry = {}",
-            result, carry
-        );
-        self.update_flags_sub16(dst_val, src_val, result, carry);
+        self.cmp16(src_val, dst_val);
 
         if !self.regs.flags.get_direction() {
             self.regs.si = self.regs.si.wrapping_add(2);
@@ -150,14 +198,12 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn scasb(&mut self) -> Result<(), String> {
         let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
-        let dst_val = self.memory.read_byte(dst_addr);
+        let dst_val = self.bus_read_byte(dst_addr);
         let al = self.regs.ax as u8;
 
-        let (result, carry) = al.overflowing_sub(dst_val);
-        self.update_flags_sub(al, dst_val, result, carry);
+        self.cmp8(al, dst_val);
 
         if !self.regs.flags.get_direction() {
             self.regs.di = self.regs.di.wrapping_add(1);
@@ -167,13 +213,10 @@ impl Cpu {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub(crate) fn scasw(&mut self) -> Result<(), String> {
-        let dst_addr = self.get_physical_address(self.regs.es, self.regs.di);
-        let dst_val = self.memory.read_word(dst_addr);
+        let dst_val = self.read_word_at(self.regs.es, self.regs.di);
 
-        let (result, carry) = self.regs.ax.overflowing_sub(dst_val);
-        self.update_flags_sub16(self.regs.ax, dst_val, result, carry);
+        self.cmp16(self.regs.ax, dst_val);
 
         if !self.regs.flags.get_direction() {
             self.regs.di = self.regs.di.wrapping_add(2);
@@ -187,6 +230,8 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::{Cpu, CpuModel};
     use crate::disk::disk_image::DiskImage;
     use crate::memory::ram::RamMemory;
     use crate::serial::Serial;
@@ -383,8 +428,8 @@ mod tests {
 
         assert!(cpu.cmpsw().is_ok());
 
-        // Check flags for inequality (dst > src)
-        assert!(!cpu.regs.flags.get_carry()); // No borrow needed
+        // Check flags for inequality: 1234 - 5678 borrows
+        assert!(cpu.regs.flags.get_carry());
         assert!(!cpu.regs.flags.get_zero()); // Not equal
 
         // Check that SI and DI were incremented by 2
@@ -434,4 +479,157 @@ mod tests {
         // Check that DI was incremented by 2
         assert_eq!(cpu.regs.di, 0x0202);
     }
+
+    #[test]
+    fn test_rep_segment_override_movsb() {
+        // REP ES: MOVSB
+        let mut cpu = setup_executable_cpu(&[0xF3, 0x26, 0xA4, 0xF4]);
+        cpu.regs.ds = 0x1000;
+        cpu.regs.es = 0x2000;
+        cpu.regs.si = 0x0010;
+        cpu.regs.di = 0x0100;
+        cpu.regs.cx = 3;
+        for (i, byte) in [0x11, 0x22, 0x33].iter().enumerate() {
+            cpu.memory.write_byte(0x20010 + i as u32, *byte);
+            cpu.memory.write_byte(0x10010 + i as u32, 0xEE); // DS:SI must not be used
+        }
+
+        for _ in 0..3 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert_eq!(cpu.regs.cx, 0);
+        assert_eq!(cpu.regs.ip, 0x103);
+        assert_eq!(cpu.segment_override, None);
+        assert_eq!(cpu.rep_prefix, None);
+        assert_eq!(cpu.memory.read_byte(0x20100), 0x11);
+        assert_eq!(cpu.memory.read_byte(0x20101), 0x22);
+        assert_eq!(cpu.memory.read_byte(0x20102), 0x33);
+
+        cpu.execute_instruction().unwrap();
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    fn test_repne_scasb_stops_on_match() {
+        // REPNE SCASB
+        let mut cpu = setup_executable_cpu(&[0xF2, 0xAE]);
+        cpu.regs.es = 0x2000;
+        cpu.regs.di = 0;
+        cpu.regs.cx = 10;
+        cpu.regs.set_al(0x42);
+        cpu.memory.write_byte(0x20002, 0x42);
+
+        while cpu.regs.ip != 0x102 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert!(cpu.regs.flags.get_zero());
+        assert_eq!(cpu.regs.di, 3);
+        assert_eq!(cpu.regs.cx, 7);
+    }
+
+    #[test]
+    fn test_rep_with_zero_count_is_skipped() {
+        let mut cpu = setup_executable_cpu(&[0xF3, 0xAA]);
+        cpu.regs.es = 0x2000;
+        cpu.regs.cx = 0;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ip, 0x102);
+        assert_eq!(cpu.regs.di, 0);
+    }
+
+    #[test]
+    fn test_lock_prefix_is_accepted() {
+        // LOCK INC byte ptr [BX]
+        let mut cpu = setup_executable_cpu(&[0xF0, 0xFE, 0x07]);
+        cpu.regs.ds = 0x1000;
+        cpu.regs.bx = 0x20;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_byte(0x10020), 1);
+        assert_eq!(cpu.regs.ip, 0x103);
+        assert!(!cpu.lock_prefix);
+    }
+
+    #[test]
+    fn test_lock_prefix_only_before_memory_read_modify_write() {
+        // LOCK INC word ptr [BX], on the model that checks
+        let mut cpu = setup_executable_cpu(&[0xF0, 0xFF, 0x07]);
+        cpu.set_model(CpuModel::I80386);
+        cpu.regs.ds = 0x1000;
+        cpu.regs.bx = 0x20;
+        cpu.memory.write_word(0x10020, 0x00FF);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_word(0x10020), 0x0100);
+        assert_eq!(cpu.regs.ip, 0x103);
+
+        // The 8086 runs LOCK MOV AX,BX like a plain MOV
+        let mut cpu = setup_executable_cpu(&[0xF0, 0x8B, 0xC3]);
+        cpu.regs.bx = 0x1234;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0x1234);
+        assert_eq!(cpu.regs.ip, 0x103);
+
+        // The 80386 faults on LOCK MOV AX,BX, LOCK INC AX (a register) and
+        // LOCK CMP word ptr [BX],1
+        for code in [
+            &[0xF0, 0x8B, 0xC3][..],
+            &[0xF0, 0xFF, 0xC0],
+            &[0xF0, 0x83, 0x3F, 0x01],
+        ] {
+            let mut cpu = setup_executable_cpu(code);
+            cpu.set_model(CpuModel::I80386);
+            cpu.memory.write_word(6 * 4, 0x0500);
+            cpu.memory.write_word(6 * 4 + 2, 0x0000);
+            cpu.regs.bx = 0x1234;
+            cpu.execute_instruction().unwrap();
+            assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0500));
+            // The return address is the LOCK prefix, and nothing ran
+            assert_eq!(cpu.memory.read_word(cpu.regs.sp as u32), 0x0100);
+            assert_eq!(cpu.regs.ax, 0);
+            assert!(!cpu.lock_prefix);
+        }
+    }
+
+    #[test]
+    fn test_segment_override_lodsb_reads_cs() {
+        // CS: LODSB
+        let mut cpu = setup_executable_cpu(&[0x2E, 0xAC]);
+        cpu.regs.ds = 0x2000;
+        cpu.regs.si = 0x0300;
+        cpu.memory.write_byte(0x0300, 0x77); // CS:SI
+        cpu.memory.write_byte(0x20300, 0x11); // DS:SI
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_al(), 0x77);
+        assert_eq!(cpu.regs.si, 0x0301);
+    }
+
+    #[test]
+    fn test_segment_override_cmpsb_source_only() {
+        // SS: CMPSB compares SS:SI against ES:DI; the destination stays on ES
+        let mut cpu = setup_executable_cpu(&[0x36, 0xA6]);
+        cpu.regs.ss = 0x3000;
+        cpu.regs.ds = 0x2000;
+        cpu.regs.es = 0x4000;
+        cpu.regs.si = 0x10;
+        cpu.regs.di = 0x20;
+        cpu.memory.write_byte(0x30010, 0x42); // SS:SI
+        cpu.memory.write_byte(0x20010, 0x99); // DS:SI
+        cpu.memory.write_byte(0x40020, 0x42); // ES:DI
+        cpu.memory.write_byte(0x30020, 0x00); // SS:DI, must not be used
+        cpu.execute_instruction().unwrap();
+        assert!(cpu.regs.flags.get_zero());
+    }
+
+    #[test]
+    fn test_segment_override_stosb_keeps_es() {
+        // CS: STOSB still writes ES:DI
+        let mut cpu = setup_executable_cpu(&[0x2E, 0xAA]);
+        cpu.regs.es = 0x5000;
+        cpu.regs.di = 0x40;
+        cpu.regs.set_al(0xAB);
+        // CS:DI is in the vector table, which the BIOS has filled in
+        let cs_di = cpu.memory.read_byte(0x0040);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.memory.read_byte(0x50040), 0xAB);
+        assert_eq!(cpu.memory.read_byte(0x0040), cs_di);
+    }
 }
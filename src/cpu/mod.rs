@@ -1,28 +1,166 @@
+pub mod coverage;
+pub mod decode;
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod dump;
 pub mod execute;
 pub mod flags;
 pub mod instructions;
+pub mod model;
+pub mod prefetch;
 pub mod registers;
+pub mod runaway;
+pub mod stop;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod timing;
 
+#[cfg(feature = "std")]
+use crate::bios::{handle_bios_interrupt, InterruptTable};
+#[cfg(feature = "std")]
+use crate::clock::{Clock, HostClock, ManualClock};
+#[cfg(feature = "std")]
 use crate::disk::{DiskImage, PARTITION_TABLE_OFFSET};
+#[cfg(feature = "std")]
+use crate::dos::DosState;
+#[cfg(feature = "std")]
+use crate::ems::Ems;
+use crate::io::IoBus;
+use crate::memory::profiled::ProfiledMemory;
+use crate::memory::ram::RamMemory;
 use crate::memory::Memory;
-use crate::memory::SystemMemory;
+use crate::memory::{MemoryProfiler, SystemMemory};
+use crate::pit::PIT_INPUT_HZ;
+use crate::prelude::*;
 use crate::serial::Serial;
+use alloc::rc::Rc;
+#[cfg(feature = "std")]
+use chrono::NaiveDateTime;
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use coverage::OpcodeCoverage;
+use decode::FetchedBytes;
+pub use model::CpuModel;
+use prefetch::PrefetchQueue;
 pub use registers::Registers;
-use std::fmt;
+use runaway::RunawayDetector;
+pub use stop::StopReason;
 
-pub struct Cpu {
+/// `cycles` counts instructions rather than clocks, and an 8088 at 4.77 MHz
+/// averages very roughly one instruction per microsecond. Clock costs go to
+/// `clocks` instead; see `timing`.
+pub const DEFAULT_CPU_FREQUENCY: u64 = 1_000_000;
+
+/// Where execution starts after a reset: F000:FFF0, 16 bytes below 1MB
+pub const RESET_SEGMENT: u16 = 0xF000;
+pub const RESET_OFFSET: u16 = 0xFFF0;
+
+// One BIOS tick is 65536 PIT input clocks
+fn cycles_per_tick(cpu_frequency: u64) -> u64 {
+    (cpu_frequency.saturating_mul(65536) / PIT_INPUT_HZ).max(1)
+}
+
+/// The CPU and everything wired to it, over memory of type `M`. Most code
+/// wants `Cpu`, which boxes the memory so it can be swapped and wrapped at
+/// run time; a concrete `M` dispatches memory accesses statically, for
+/// embedding the core where that matters. Only `Cpu` gets the native BIOS
+/// and DOS services: a `CpuGeneric` runs the guest's own handlers.
+pub struct CpuGeneric<M: Memory> {
     pub regs: Registers,
-    pub memory: Box<dyn Memory>,
+    pub memory: M,
     pub serial: Serial,
+    #[cfg(feature = "std")]
     pub disk: DiskImage,
+    pub io: IoBus,
     pub halted: bool,
+    // One per instruction stepped; what the timer and `cpu_frequency` run on
     pub cycles: u64,
+    // 8086 clocks for what the timing model covers: the instructions with a
+    // cost in `timing`, plus wait states. Nothing else adds to it.
+    pub clocks: u64,
+    // The clock cost of the instruction being executed, when `timing` has
+    // one; `step` adds it to `clocks`
+    pub(crate) instruction_clocks: Option<u64>,
+    // Instructions executed since the last reset, however they were run
+    instruction_count: u64,
+    // Extra clocks per memory and per I/O bus cycle; see
+    // `set_memory_wait_states`
+    memory_wait_states: u64,
+    io_wait_states: u64,
+    // Bus cycles the instruction being stepped has run so far
+    memory_accesses: Cell<u64>,
+    io_accesses: Cell<u64>,
+    // Emulated speed in cycles per second and the cycles in one BIOS timer
+    // tick it works out to; see `set_cpu_frequency`
+    pub(crate) cpu_frequency: u64,
+    pub(crate) cycles_per_tick: u64,
     pub segment_override: Option<SegmentRegister>,
+    pub rep_prefix: Option<RepPrefix>,
+    pub lock_prefix: bool,
+    pub quirk_rep_prefix_bug: bool,
+    // Whether undocumented opcodes run or take INT 6; see
+    // `set_allow_undocumented`
+    pub(crate) allow_undocumented: bool,
+    // The 8086 prefetch queue, when `set_prefetch_emulation` has enabled it
+    pub(crate) prefetch: Option<PrefetchQueue>,
+    // Offset of the prefix byte just before the opcode of a REP string
+    // instruction that still has iterations left
+    pub(crate) rep_last_prefix_ip: Option<u16>,
+    // Offset of the first byte (including prefixes) of the current instruction
+    pub(crate) instruction_start_ip: u16,
+    // Set by loading SS; holds off interrupts until the next instruction is done
+    pub(crate) interrupt_shadow: bool,
+    // The instruction being executed as it was fetched; see `fetch_byte`
+    pub(crate) fetched: FetchedBytes,
+    pub(crate) coverage: OpcodeCoverage,
+    // Set when the last instruction hit an unimplemented or illegal opcode;
+    // two-byte opcodes are recorded as 0x0Fxx
+    pub(crate) last_invalid_opcode: Option<u16>,
+    // Set when the last instruction was a DIV/IDIV that faulted
+    pub(crate) last_divide_error: bool,
+    // CS:IP of the last instruction if it panicked inside the emulator
+    pub(crate) last_internal_fault: Option<(u16, u16)>,
+    // Set by INT 18h when there was nothing to boot
+    pub(crate) no_boot_device: bool,
+    // The floppy change line: set when the medium in drive 00h is swapped,
+    // cleared once INT 13h AH=16h has reported it
+    #[cfg(feature = "std")]
+    pub(crate) disk_changed: bool,
+    // Set when the guest wrote an exit code to the debug-exit port
+    pub(crate) guest_exit: Option<u8>,
+    pub(crate) runaway: RunawayDetector,
+    // The profiler behind `memory` once `set_memory_profiler` has wrapped it
+    pub(crate) memory_profiler: Option<Rc<RefCell<MemoryProfiler>>>,
     pub has_valid_mbr: bool,
     pub has_valid_boot_sector: bool,
+    // Set by `inject_bytes_at_reset`: the code to run came from the caller,
+    // so there's no need for a bootable disk behind it
+    pub(crate) injected_reset_code: bool,
+    pub model: CpuModel,
+    // When false, addresses wrap at 1MB like on an 8086
+    pub a20_enabled: bool,
+    #[cfg(feature = "std")]
+    pub ems: Option<Ems>,
+    #[cfg(feature = "std")]
+    pub dos: DosState,
+    // Date/time source for the BIOS and DOS time services
+    #[cfg(feature = "std")]
+    pub(crate) clock: Box<dyn Clock>,
+    // Native services for vectors that point into the BIOS segment
+    #[cfg(feature = "std")]
+    pub(crate) interrupt_handlers: InterruptTable,
+    // What `int` calls for vectors in the BIOS segment; only `Cpu` has one
+    #[cfg(feature = "std")]
+    pub(crate) native_interrupts: Option<NativeInterrupts<M>>,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The boxed-memory CPU everything outside the core uses
+pub type Cpu = CpuGeneric<Box<dyn Memory>>;
+
+#[cfg(feature = "std")]
+pub(crate) type NativeInterrupts<M> = fn(&mut CpuGeneric<M>, u8) -> Result<(), String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum SegmentRegister {
     CS,
@@ -31,35 +169,331 @@ pub enum SegmentRegister {
     SS,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepPrefix {
+    Rep,   // F3: REP / REPE / REPZ
+    Repne, // F2: REPNE / REPNZ
+}
+
+/// A decoded ModR/M operand: a register number, or a memory location with
+/// its displacement already consumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RmOperand {
+    Register(u8),
+    Memory { segment: u16, offset: u16 },
+}
+
 impl Cpu {
+    #[cfg(feature = "std")]
     pub fn new(memory: Box<dyn Memory>, serial: Serial, disk: DiskImage) -> Self {
+        let mut cpu = Cpu::without_disk(memory, serial);
+        cpu.disk = disk;
+        cpu.check_boot_code();
+        cpu
+    }
+
+    /// A CPU with nothing in the drive. This is the only constructor without
+    /// the `std` feature, where there are no disk or DOS services at all.
+    pub fn without_disk(memory: Box<dyn Memory>, serial: Serial) -> Self {
+        #[allow(unused_mut)]
+        let mut cpu = CpuGeneric::with_memory(memory, serial);
+        #[cfg(feature = "std")]
+        {
+            cpu.native_interrupts = Some(handle_bios_interrupt);
+        }
+        cpu
+    }
+
+    /// Reports every guest memory access to `profiler` as `(address,
+    /// is_write)`, e.g. to build an access heatmap or find hot code. The
+    /// first call wraps `memory`; until then accesses go straight to it.
+    /// Calling again replaces the profiler.
+    pub fn set_memory_profiler(&mut self, profiler: MemoryProfiler) {
+        if let Some(current) = &self.memory_profiler {
+            *current.borrow_mut() = profiler;
+            return;
+        }
+        let profiler = Rc::new(RefCell::new(profiler));
+        let memory = core::mem::replace(&mut self.memory, Box::new(RamMemory::new(0)));
+        self.memory = Box::new(ProfiledMemory::new(memory, Rc::clone(&profiler)));
+        self.memory_profiler = Some(profiler);
+    }
+}
+
+impl<M: Memory> CpuGeneric<M> {
+    // Sets has_valid_mbr and has_valid_boot_sector from what is in the drive
+    #[cfg(feature = "std")]
+    pub(crate) fn check_boot_code(&mut self) {
         // Check if disk has valid MBR boot code
-        let mbr = match disk.read_sector(0) {
+        let mbr = match self.disk.read_sector(0) {
             Some(data) => data,
             None => vec![0; 512],
         };
-        let has_valid_mbr = !mbr[..PARTITION_TABLE_OFFSET].iter().all(|&byte| byte == 0);
+        self.has_valid_mbr = !mbr[..PARTITION_TABLE_OFFSET].iter().all(|&byte| byte == 0);
 
-        // Check if disk has valid boot sector at LBA 63
-        let boot = match disk.read_sector(63) {
-            Some(data) => data,
-            None => vec![0; 512],
-        };
-        let boot_valid = boot.len() == 512 && boot[510] == 0x55 && boot[511] == 0xAA; // Must have valid boot signature
+        // Check the boot sector has a valid signature: LBA 0 on a floppy,
+        // the first partition's first sector on a hard disk
+        let boot = &self.disk.boot_sector;
+        self.has_valid_boot_sector = boot[510] == 0x55 && boot[511] == 0xAA; // Must have valid boot signature
+    }
 
-        Cpu {
+    /// A CPU over `memory` with nothing in the drive and no native BIOS
+    /// services, so only the guest's own interrupt handlers run
+    pub fn with_memory(memory: M, serial: Serial) -> Self {
+        CpuGeneric {
             memory,
             regs: Registers::new(),
             serial,
-            disk,
+            #[cfg(feature = "std")]
+            disk: DiskImage::empty(),
+            io: IoBus::with_standard_devices(),
             halted: false,
             cycles: 0,
+            clocks: 0,
+            instruction_clocks: None,
+            instruction_count: 0,
+            memory_wait_states: 0,
+            io_wait_states: 0,
+            memory_accesses: Cell::new(0),
+            io_accesses: Cell::new(0),
+            cpu_frequency: DEFAULT_CPU_FREQUENCY,
+            cycles_per_tick: cycles_per_tick(DEFAULT_CPU_FREQUENCY),
             segment_override: None,
-            has_valid_mbr,
-            has_valid_boot_sector: boot_valid,
+            rep_prefix: None,
+            lock_prefix: false,
+            quirk_rep_prefix_bug: false,
+            allow_undocumented: true,
+            prefetch: None,
+            rep_last_prefix_ip: None,
+            instruction_start_ip: 0,
+            interrupt_shadow: false,
+            fetched: FetchedBytes::default(),
+            coverage: OpcodeCoverage::new(),
+            last_invalid_opcode: None,
+            last_divide_error: false,
+            last_internal_fault: None,
+            no_boot_device: false,
+            #[cfg(feature = "std")]
+            disk_changed: false,
+            guest_exit: None,
+            runaway: RunawayDetector::new(),
+            memory_profiler: None,
+            has_valid_mbr: false,
+            has_valid_boot_sector: false,
+            injected_reset_code: false,
+            model: CpuModel::default(),
+            a20_enabled: false,
+            #[cfg(feature = "std")]
+            ems: None,
+            #[cfg(feature = "std")]
+            dos: DosState::new(),
+            #[cfg(feature = "std")]
+            clock: Box::new(HostClock),
+            #[cfg(feature = "std")]
+            interrupt_handlers: InterruptTable::new(),
+            #[cfg(feature = "std")]
+            native_interrupts: None,
         }
     }
 
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: CpuModel) {
+        self.model = model;
+    }
+
+    /// Sets how many cycles the emulated CPU runs per second. BIOS time of
+    /// day ticks (18.2 Hz) are derived from the cycle count at this rate, so
+    /// they don't depend on how fast the host happens to run.
+    pub fn set_cpu_frequency(&mut self, hz: u64) {
+        self.cpu_frequency = hz.max(1);
+        self.cycles_per_tick = cycles_per_tick(self.cpu_frequency);
+    }
+
+    pub fn cpu_frequency(&self) -> u64 {
+        self.cpu_frequency
+    }
+
+    /// Instructions executed since the last reset, through `step`,
+    /// `run_for` or `execute_instruction` alike. Unlike `cycles`, which only
+    /// `step` advances, each REP iteration and each instruction that faults
+    /// counts once here, and taking an IRQ counts nothing.
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// Wait states: extra clocks `step` adds to `clocks` for every memory
+    /// bus cycle the instruction runs, code fetches included, as a slow board
+    /// would insert them. A word access is one bus cycle. `cycles`, and so
+    /// the timer, doesn't see them.
+    pub fn set_memory_wait_states(&mut self, n: u64) {
+        self.memory_wait_states = n;
+    }
+
+    /// The same for every IN or OUT
+    pub fn set_io_wait_states(&mut self, n: u64) {
+        self.io_wait_states = n;
+    }
+
+    // Memory accesses made on the guest's behalf, counted for wait states.
+    // The debugger's peek/poke don't come through here.
+    pub(crate) fn bus_read_byte(&self, addr: u32) -> u8 {
+        self.memory_accesses.set(self.memory_accesses.get() + 1);
+        self.memory.read_byte(addr)
+    }
+
+    pub(crate) fn bus_write_byte(&mut self, addr: u32, value: u8) {
+        self.memory_accesses.set(self.memory_accesses.get() + 1);
+        self.memory.write_byte(addr, value);
+    }
+
+    pub(crate) fn count_io_access(&self) {
+        self.io_accesses.set(self.io_accesses.get() + 1);
+    }
+
+    /// BIOS timer ticks since the cycle counter started
+    pub fn timer_ticks(&self) -> u32 {
+        (self.cycles / self.cycles_per_tick) as u32
+    }
+
+    /// Replaces where the BIOS and DOS date/time services read the time
+    /// from; the host's local time by default
+    #[cfg(feature = "std")]
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Stops the guest's clock at `instant`: the BIOS and DOS date/time
+    /// services report it until it is set again. To keep the clock moving,
+    /// hand `set_clock` a `ManualClock` and advance that instead.
+    #[cfg(feature = "std")]
+    pub fn set_guest_datetime(&mut self, instant: NaiveDateTime) {
+        self.clock = Box::new(ManualClock::new(instant));
+    }
+
+    /// The date and time the guest currently sees
+    #[cfg(feature = "std")]
+    pub fn guest_datetime(&self) -> NaiveDateTime {
+        self.clock.now()
+    }
+
+    /// Emulates the 8086 bug where an interrupt taken part way through a REP
+    /// string instruction returns to the last prefix byte only, so any earlier
+    /// prefixes (typically a segment override) are lost on resume.
+    pub fn set_quirk_rep_prefix_bug(&mut self, enabled: bool) {
+        self.quirk_rep_prefix_bug = enabled;
+    }
+
+    /// Whether undocumented opcodes and encodings run as the 8086 runs them:
+    /// SALC (0xD6), the 0x82 alias of group 0x80 and shift/rotate /6 (an
+    /// alias of SHL). Allowed by default; when disallowed they take INT 6
+    /// the way a processor without them would.
+    pub fn set_allow_undocumented(&mut self, allowed: bool) {
+        self.allow_undocumented = allowed;
+    }
+
+    pub fn allow_undocumented(&self) -> bool {
+        self.allow_undocumented
+    }
+
+    /// Runs an undocumented instruction, or takes INT 6 if they are
+    /// disallowed
+    pub(crate) fn undocumented(
+        &mut self,
+        execute: fn(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if !self.allow_undocumented {
+            println!(
+                "Undocumented opcode at {:04X}:{:04X} disallowed",
+                self.regs.cs, self.instruction_start_ip
+            );
+            return self.raise_invalid_opcode();
+        }
+        execute(self)
+    }
+
+    /// Models the 8086's 6-byte prefetch queue: code fetched into it runs as
+    /// it was read even if the program then overwrites it, until a jump,
+    /// call, return or interrupt empties the queue. Off by default, in which
+    /// case every fetch sees current memory.
+    pub fn set_prefetch_emulation(&mut self, enabled: bool) {
+        self.prefetch = enabled.then(PrefetchQueue::new);
+    }
+
+    /// Arms the runaway detector used by `run_for`: stop after `invalid_opcodes`
+    /// consecutive invalid opcodes, or after the same CS:IP is re-executed
+    /// `revisits` times in a row without any register changing. `None`
+    /// disables that check.
+    pub fn set_runaway_limits(&mut self, invalid_opcodes: Option<u32>, revisits: Option<u32>) {
+        self.runaway.set_limits(invalid_opcodes, revisits);
+    }
+
+    /// Number of times each primary opcode has been executed
+    pub fn opcode_coverage(&self) -> [u64; 256] {
+        self.coverage.primary
+    }
+
+    /// Number of times each 0x0F two-byte opcode has been executed, indexed
+    /// by the second opcode byte
+    pub fn opcode_0f_coverage(&self) -> [u64; 256] {
+        self.coverage.extended
+    }
+
+    /// Unimplemented opcodes the guest tried to execute; two-byte opcodes
+    /// are reported as 0x0Fxx
+    pub fn unimplemented_opcodes(&self) -> Vec<u16> {
+        self.coverage.unimplemented()
+    }
+
+    pub fn print_unimplemented_opcodes(&self) {
+        let opcodes = self.unimplemented_opcodes();
+        if opcodes.is_empty() {
+            println!("No unimplemented opcodes were attempted");
+            return;
+        }
+        println!("Unimplemented opcodes attempted:");
+        for opcode in opcodes {
+            if opcode > 0xFF {
+                let count = self.coverage.extended[(opcode & 0xFF) as usize];
+                println!("  0F {:02X}: {} time(s)", opcode & 0xFF, count);
+            } else {
+                let count = self.coverage.primary[opcode as usize];
+                println!("  {:02X}: {} time(s)", opcode, count);
+            }
+        }
+    }
+
+    pub fn reset_opcode_coverage(&mut self) {
+        self.coverage.clear();
+    }
+
+    /// Rejects an instruction that the configured model does not implement.
+    pub(crate) fn require_model(&self, minimum: CpuModel, mnemonic: &str) -> Result<(), String> {
+        if self.model < minimum {
+            return Err(format!(
+                "{} requires an {} or later (configured model: {})",
+                mnemonic, minimum, self.model
+            ));
+        }
+        Ok(())
+    }
+
+    /// The gate for instructions from processors newer than any `CpuModel`,
+    /// which no configured model can run. Fails the same way
+    /// `require_model` does, naming the processor that introduced them.
+    pub(crate) fn require_unemulated_model(
+        &self,
+        introduced_on: &str,
+        mnemonic: &str,
+    ) -> Result<(), String> {
+        Err(format!(
+            "{} requires a {} or later (configured model: {})",
+            mnemonic, introduced_on, self.model
+        ))
+    }
+
     pub fn has_valid_rom(&self) -> bool {
         // Check if memory is SystemMemory and has valid ROM code
         if let Some(sys_mem) = self.memory.as_any().downcast_ref::<SystemMemory>() {
@@ -79,11 +513,45 @@ impl Cpu {
         self.has_valid_boot_sector
     }
 
-    #[allow(dead_code)]
+    /// Pulls the reset line: registers and every device go back to their
+    /// power-on state. What's on the disks survives.
     pub fn reset(&mut self) {
         self.regs.reset();
         self.halted = false;
+        self.no_boot_device = false;
+        self.guest_exit = None;
         self.cycles = 0;
+        self.clocks = 0;
+        self.instruction_count = 0;
+        self.runaway.clear();
+        if let Some(queue) = self.prefetch.as_mut() {
+            queue.flush();
+        }
+        self.io.reset();
+        self.serial.reset();
+        #[cfg(feature = "std")]
+        self.disk.clear_caches();
+    }
+
+    /// True while interrupts are held off after an instruction that loaded
+    /// SS, so a following SP load completes before any interrupt pushes
+    pub fn in_interrupt_shadow(&self) -> bool {
+        self.interrupt_shadow
+    }
+
+    /// Takes the invalid-opcode exception (INT 6) with the return address
+    /// pointing back at the faulting instruction, as the 80186 and later do
+    pub(crate) fn raise_invalid_opcode(&mut self) -> Result<(), String> {
+        self.regs.ip = self.instruction_start_ip;
+        self.int(6)
+    }
+
+    /// Records a divide error so `run_for` can report it, and returns the
+    /// message for the instruction to fail with. There is no INT 0 dispatch
+    /// yet, so the program stops here.
+    pub(crate) fn divide_error(&mut self, message: &str) -> String {
+        self.last_divide_error = true;
+        message.to_string()
     }
 
     pub fn is_halted(&self) -> bool {
@@ -99,24 +567,144 @@ impl Cpu {
         self.execute_instruction()
     }
 
+    /// The next code byte at CS:IP. Operands of the instruction being
+    /// executed come from the copy fetched to decode it.
     pub fn fetch_byte(&mut self) -> Result<u8, String> {
-        let addr = self.get_physical_address(self.regs.cs, self.regs.ip);
-        let byte = self.memory.read_byte(addr);
-        self.regs.ip = self.regs.ip.wrapping_add(1);
+        let (cs, ip) = (self.regs.cs, self.regs.ip);
+        if let Some(byte) = self.fetched.get(cs, ip) {
+            self.regs.ip = ip.wrapping_add(1);
+            return Ok(byte);
+        }
+        let byte = match self.prefetch.as_mut() {
+            Some(queue) => {
+                let (memory, accesses) = (&self.memory, &self.memory_accesses);
+                queue.fetch(cs, ip, |offset| {
+                    accesses.set(accesses.get() + 1);
+                    memory.read_byte(((cs as u32) << 4) + offset as u32)
+                })
+            }
+            None => self.bus_read_byte(self.get_physical_address(cs, ip)),
+        };
+        self.regs.ip = ip.wrapping_add(1);
         Ok(byte)
     }
 
     pub fn fetch_word(&mut self) -> Result<u16, String> {
-        let addr = self.get_physical_address(self.regs.cs, self.regs.ip);
-        let word = self.memory.read_word(addr);
+        if self.prefetch.is_some() || self.fetched.get(self.regs.cs, self.regs.ip).is_some() {
+            let low = self.fetch_byte()? as u16;
+            let high = self.fetch_byte()? as u16;
+            return Ok((high << 8) | low);
+        }
+        let word = self.read_word_at(self.regs.cs, self.regs.ip);
         self.regs.ip = self.regs.ip.wrapping_add(2);
         Ok(word)
     }
 
+    /// The code byte at segment:offset as the next fetch would see it: the
+    /// prefetch queue's copy if it holds one, otherwise memory
+    pub fn peek_code_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.prefetch
+            .as_ref()
+            .and_then(|queue| queue.peek(segment, offset))
+            .unwrap_or_else(|| self.peek_byte(segment, offset))
+    }
+
     pub fn get_physical_address(&self, segment: u16, offset: u16) -> u32 {
         ((segment as u32) << 4) + (offset as u32)
     }
 
+    /// Reads the word at segment:offset. The high byte comes from offset + 1
+    /// in the same segment, so a word at offset 0xFFFF wraps around to
+    /// offset 0x0000 instead of spilling into the next 64K.
+    pub(crate) fn read_word_at(&self, segment: u16, offset: u16) -> u16 {
+        self.memory_accesses.set(self.memory_accesses.get() + 1);
+        let low = self
+            .memory
+            .read_byte(self.get_physical_address(segment, offset)) as u16;
+        let high = self
+            .memory
+            .read_byte(self.get_physical_address(segment, offset.wrapping_add(1)))
+            as u16;
+        (high << 8) | low
+    }
+
+    /// Writes a word at segment:offset, wrapping within the segment like
+    /// `read_word_at`
+    pub(crate) fn write_word_at(&mut self, segment: u16, offset: u16, value: u16) {
+        let low_addr = self.get_physical_address(segment, offset);
+        let high_addr = self.get_physical_address(segment, offset.wrapping_add(1));
+        self.memory_accesses.set(self.memory_accesses.get() + 1);
+        self.memory.write_byte(low_addr, value as u8);
+        self.memory.write_byte(high_addr, (value >> 8) as u8);
+    }
+
+    pub fn set_a20(&mut self, enabled: bool) {
+        self.a20_enabled = enabled;
+    }
+
+    // With A20 off, segment:offset pairs past FFFF:000F wrap to low memory
+    fn masked_address(&self, segment: u16, offset: u16) -> u32 {
+        let addr = self.get_physical_address(segment, offset);
+        if self.a20_enabled {
+            addr
+        } else {
+            addr & 0xFFFFF
+        }
+    }
+
+    pub fn peek_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.memory.read_byte(self.masked_address(segment, offset))
+    }
+
+    /// Reads a little-endian word; the high byte comes from offset + 1,
+    /// wrapping within the segment
+    pub fn peek_word(&self, segment: u16, offset: u16) -> u16 {
+        let low = self.peek_byte(segment, offset) as u16;
+        let high = self.peek_byte(segment, offset.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    pub fn poke_byte(&mut self, segment: u16, offset: u16, value: u8) {
+        let addr = self.masked_address(segment, offset);
+        self.memory.write_byte(addr, value);
+    }
+
+    pub fn poke_word(&mut self, segment: u16, offset: u16, value: u16) {
+        self.poke_byte(segment, offset, value as u8);
+        self.poke_byte(segment, offset.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    /// Copies `bytes` to `segment:offset`, wrapping within the segment.
+    /// This goes through the memory bus like any other write, so ROM
+    /// regions ignore it; a CPU over plain `RamMemory` takes it anywhere.
+    pub fn load_at(&mut self, segment: u16, offset: u16, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.poke_byte(segment, offset.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// Resets the CPU and places `code` at the reset vector, so boot-time
+    /// code can be run without a disk or a BIOS ROM image. Only the 16
+    /// bytes below 1MB are there; anything longer has to jump elsewhere.
+    /// With `SystemMemory` the code is patched into the BIOS ROM, since it
+    /// ignores bus writes there. The CPU will then run without a valid MBR
+    /// or boot sector.
+    pub fn inject_bytes_at_reset(&mut self, code: &[u8]) -> Result<(), String> {
+        if code.len() > 0x10 {
+            return Err(format!(
+                "{} bytes don't fit between the reset vector and 1MB",
+                code.len()
+            ));
+        }
+        self.reset();
+        match self.memory.as_any_mut().downcast_mut::<SystemMemory>() {
+            Some(system) => system.patch_bios_rom(RESET_OFFSET as usize, code)?,
+            None => self.load_at(RESET_SEGMENT, RESET_OFFSET, code),
+        }
+        self.injected_reset_code = true;
+        Ok(())
+    }
+
     // Helper functions used by instructions
     pub(crate) fn get_rm8(&mut self, modrm: u8) -> Result<u8, String> {
         let rm = modrm & 0x07;
@@ -129,17 +717,13 @@ impl Cpu {
         } else {
             // Memory operand
             let addr = self.get_rm_addr(modrm)?;
-            let segment = match rm {
-                2 | 3 | 6 if mod_bits == 0 && rm == 6 => self.regs.ds, // Special case for direct address
-                2 | 3 | 6 => self.regs.ss, // BP-based addressing uses SS
-                _ => self.regs.ds,         // Other cases use DS
-            };
+            let segment = self.rm_segment(modrm);
             let physical_addr = self.get_physical_address(segment, addr as u16);
             println!(
                 "get_rm8: Memory mode, addr={:#x}, physical_addr={:#x}",
                 addr, physical_addr
             );
-            Ok(self.memory.read_byte(physical_addr))
+            Ok(self.bus_read_byte(physical_addr))
         }
     }
 
@@ -154,17 +738,13 @@ impl Cpu {
         } else {
             // Memory operand
             let addr = self.get_rm_addr(modrm)?;
-            let segment = match rm {
-                2 | 3 | 6 if mod_bits == 0 && rm == 6 => self.regs.ds, // Special case for direct address
-                2 | 3 | 6 => self.regs.ss, // BP-based addressing uses SS
-                _ => self.regs.ds,         // Other cases use DS
-            };
+            let segment = self.rm_segment(modrm);
             let physical_addr = self.get_physical_address(segment, addr as u16);
             println!(
                 "write_rm8: Memory mode, addr={:#x}, physical_addr={:#x}, value={:#x}",
                 addr, physical_addr, value
             );
-            self.memory.write_byte(physical_addr, value);
+            self.bus_write_byte(physical_addr, value);
         }
         Ok(())
     }
@@ -179,14 +759,8 @@ impl Cpu {
         } else {
             // Memory operand
             let addr = self.get_rm_addr(modrm)?;
-            let segment = match rm {
-                2 | 3 | 6 if mod_bits == 0 && rm == 6 => self.regs.ds, // Special case for direct address
-                2 | 3 | 6 => self.regs.ss, // BP-based addressing uses SS
-                _ => self.regs.ds,         // Other cases use DS
-            };
-            Ok(self
-                .memory
-                .read_word(self.get_physical_address(segment, addr as u16)))
+            let segment = self.rm_segment(modrm);
+            Ok(self.read_word_at(segment, addr as u16))
         }
     }
 
@@ -200,13 +774,57 @@ impl Cpu {
         } else {
             // Memory operand
             let addr = self.get_rm_addr(modrm)?;
-            let segment = match rm {
-                2 | 3 | 6 if mod_bits == 0 && rm == 6 => self.regs.ds, // Special case for direct address
-                2 | 3 | 6 => self.regs.ss, // BP-based addressing uses SS
-                _ => self.regs.ds,         // Other cases use DS
-            };
-            self.memory
-                .write_word(self.get_physical_address(segment, addr as u16), value);
+            let segment = self.rm_segment(modrm);
+            self.write_word_at(segment, addr as u16, value);
+        }
+        Ok(())
+    }
+
+    /// Decodes a ModR/M operand once, fetching any displacement, so a
+    /// read-modify-write instruction can write back to the same place
+    /// after fetching its immediate
+    pub(crate) fn decode_rm(&mut self, modrm: u8) -> Result<RmOperand, String> {
+        if (modrm >> 6) == 3 {
+            return Ok(RmOperand::Register(modrm & 0x07));
+        }
+        let offset = self.get_rm_addr(modrm)? as u16;
+        Ok(RmOperand::Memory {
+            segment: self.rm_segment(modrm),
+            offset,
+        })
+    }
+
+    pub(crate) fn read_operand8(&self, operand: RmOperand) -> u8 {
+        match operand {
+            RmOperand::Register(reg) => self.regs.get_reg8(reg),
+            RmOperand::Memory { segment, offset } => {
+                self.bus_read_byte(self.get_physical_address(segment, offset))
+            }
+        }
+    }
+
+    pub(crate) fn write_operand8(&mut self, operand: RmOperand, value: u8) -> Result<(), String> {
+        match operand {
+            RmOperand::Register(reg) => self.regs.set_reg8(reg, value)?,
+            RmOperand::Memory { segment, offset } => {
+                let addr = self.get_physical_address(segment, offset);
+                self.bus_write_byte(addr, value);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_operand16(&self, operand: RmOperand) -> u16 {
+        match operand {
+            RmOperand::Register(reg) => self.regs.get_reg16(reg),
+            RmOperand::Memory { segment, offset } => self.read_word_at(segment, offset),
+        }
+    }
+
+    pub(crate) fn write_operand16(&mut self, operand: RmOperand, value: u16) -> Result<(), String> {
+        match operand {
+            RmOperand::Register(reg) => self.regs.set_reg16(reg, value)?,
+            RmOperand::Memory { segment, offset } => self.write_word_at(segment, offset, value),
         }
         Ok(())
     }
@@ -218,37 +836,51 @@ impl Cpu {
         self.regs
             .flags
             .set_overflow(((a ^ b) & (a ^ result) & 0x80) != 0);
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
-    }
-
-    pub(crate) fn update_flags_sub16(&mut self, a: u16, b: u16, result: u16, carry: bool) {
-        self.regs.flags.set_carry(carry);
-        self.regs.flags.set_zero(result == 0);
-        self.regs.flags.set_sign((result & 0x8000) != 0);
         self.regs
             .flags
-            .set_overflow(((a ^ b) & (a ^ result) & 0x8000) != 0);
+            .set_auxiliary(((a ^ b ^ result) & 0x10) != 0);
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_parity(result.count_ones().is_multiple_of(2));
+    }
+
+    pub(crate) fn update_flags_sub16(&mut self, a: u16, b: u16, result: u16, carry: bool) {
+        self.update_flags_arithmetic_16(a, b, result, true);
+        self.regs.flags.set_carry(carry);
+    }
+
+    /// Flags for CMP a,b: those of a - b with the result thrown away, CF
+    /// being the unsigned borrow (a < b). Every CMP, CMPS and SCAS goes
+    /// through these so they can't disagree.
+    pub(crate) fn cmp8(&mut self, a: u8, b: u8) {
+        let (result, borrow) = a.overflowing_sub(b);
+        self.update_flags_sub(a, b, result, borrow);
+    }
+
+    pub(crate) fn cmp16(&mut self, a: u16, b: u16) {
+        let (result, borrow) = a.overflowing_sub(b);
+        self.update_flags_sub16(a, b, result, borrow);
     }
 
     pub(crate) fn update_flags_inc16(&mut self, result: u16) {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x8000) != 0);
         self.regs.flags.set_overflow(result == 0x8000);
+        self.regs.flags.set_auxiliary((result & 0x0F) == 0);
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
     }
 
     pub(crate) fn update_flags_dec16(&mut self, result: u16) {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x8000) != 0);
         self.regs.flags.set_overflow(result == 0x7FFF);
+        // Borrowed into the low nibble when it was 0 and wrapped to F
+        self.regs.flags.set_auxiliary((result & 0x0F) == 0x0F);
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
     }
 
     pub(crate) fn get_rm_addr(&mut self, modrm: u8) -> Result<u32, String> {
@@ -301,9 +933,18 @@ impl Cpu {
                 .flags
                 .set_overflow(((op1 ^ result) & (op2 ^ result) & 0x80) != 0);
         }
-        self.regs.flags.set_parity(result.count_ones() % 2 == 0);
+        // Carry out of (or borrow into) bit 3 shows up as bit 4 of the
+        // operands and result disagreeing
+        self.regs
+            .flags
+            .set_auxiliary(((op1 ^ op2 ^ result) & 0x10) != 0);
+        self.regs
+            .flags
+            .set_parity(result.count_ones().is_multiple_of(2));
     }
 
+    // Every 16-bit ADD/SUB/CMP flag update ends up here. PF only ever looks
+    // at the low byte of the result, even for word operations.
     pub(crate) fn update_flags_arithmetic_16(
         &mut self,
         op1: u16,
@@ -326,20 +967,21 @@ impl Cpu {
         }
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_auxiliary(((op1 ^ op2 ^ result) & 0x10) != 0);
+        self.regs
+            .flags
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
     }
 
     pub(crate) fn push(&mut self, value: u16) -> Result<(), String> {
         self.regs.sp = self.regs.sp.wrapping_sub(2);
-        let addr = self.get_physical_address(self.regs.ss, self.regs.sp);
-        self.memory.write_word(addr, value);
+        self.write_word_at(self.regs.ss, self.regs.sp, value);
         Ok(())
     }
 
     #[allow(dead_code)]
     pub(crate) fn pop(&mut self) -> Result<u16, String> {
-        let addr = self.get_physical_address(self.regs.ss, self.regs.sp);
-        let value = self.memory.read_word(addr);
+        let value = self.read_word_at(self.regs.ss, self.regs.sp);
         self.regs.sp = self.regs.sp.wrapping_add(2);
         Ok(value)
     }
@@ -357,23 +999,42 @@ impl Cpu {
 
     #[allow(dead_code)]
     pub fn step(&mut self) -> Result<(), String> {
-        if self.halted {
+        if self.halted && !self.irq_pending() {
             return Ok(());
         }
 
+        self.instruction_clocks = None;
+        self.memory_accesses.set(0);
+        self.io_accesses.set(0);
         self.execute_instruction()?;
         self.cycles += 1;
+        self.clocks += self.instruction_clocks.take().unwrap_or(0)
+            + self.memory_accesses.get() * self.memory_wait_states
+            + self.io_accesses.get() * self.io_wait_states;
         Ok(())
     }
 
+    /// Writes `bytes` at CS:IP and steps once, so a test exercises the real
+    /// prefix decoding and opcode dispatch rather than calling a handler
+    /// directly. Like `step`, does nothing while halted with no IRQ pending.
+    pub fn step_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.load_at(self.regs.cs, self.regs.ip, bytes);
+        self.step()
+    }
+
+    /// Charges a conditional branch at its taken or not-taken cost
+    pub(crate) fn charge_branch(&mut self, cost: timing::BranchCost, taken: bool) {
+        self.instruction_clocks = Some(cost.clocks(taken));
+    }
+
     pub(crate) fn update_flags_inc(&mut self, operand: u16, result: u16) {
         self.regs.flags.set_zero(result == 0);
         self.regs.flags.set_sign((result & 0x8000) != 0);
         self.regs
             .flags
-            .set_parity((result as u8).count_ones() % 2 == 0);
+            .set_parity((result as u8).count_ones().is_multiple_of(2));
         self.regs.flags.set_overflow(operand == 0x7FFF);
-        self.regs.flags.set_adjust((operand & 0xF) == 0xF);
+        self.regs.flags.set_auxiliary((operand & 0xF) == 0xF);
     }
 
     pub fn set_segment_override(&mut self, segment: SegmentRegister) {
@@ -383,9 +1044,43 @@ impl Cpu {
     pub fn clear_segment_override(&mut self) {
         self.segment_override = None;
     }
+
+    /// Drops every prefix collected for the instruction that just finished
+    pub fn clear_prefixes(&mut self) {
+        self.segment_override = None;
+        self.rep_prefix = None;
+        self.lock_prefix = false;
+    }
+
+    pub fn get_segment(&self, segment: SegmentRegister) -> u16 {
+        match segment {
+            SegmentRegister::CS => self.regs.cs,
+            SegmentRegister::DS => self.regs.ds,
+            SegmentRegister::ES => self.regs.es,
+            SegmentRegister::SS => self.regs.ss,
+        }
+    }
+
+    /// The segment a memory operand uses: the override prefix if present,
+    /// otherwise the instruction's default segment
+    pub(crate) fn effective_segment(&self, default: SegmentRegister) -> u16 {
+        self.get_segment(self.segment_override.unwrap_or(default))
+    }
+
+    // Default segment for a ModR/M memory operand, honoring any override
+    fn rm_segment(&self, modrm: u8) -> u16 {
+        let rm = modrm & 0x07;
+        let mod_bits = (modrm >> 6) & 0x03;
+        let default = match rm {
+            6 if mod_bits == 0 => SegmentRegister::DS, // Special case for direct address
+            2 | 3 | 6 => SegmentRegister::SS,          // BP-based addressing uses SS
+            _ => SegmentRegister::DS,                  // Other cases use DS
+        };
+        self.effective_segment(default)
+    }
 }
 
-impl fmt::Debug for Cpu {
+impl<M: Memory> fmt::Debug for CpuGeneric<M> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
@@ -413,4 +1108,369 @@ pub(crate) mod test_utils {
         init_bios_data_area(&mut cpu);
         cpu
     }
+
+    /// A CPU backed by SystemMemory, so the BIOS ROM is valid and programs
+    /// can be run through `execute_instruction`. Code is loaded at 0000:0100.
+    pub fn setup_executable_cpu(code: &[u8]) -> Cpu {
+        super::test_support::executable_cpu(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::{setup_executable_cpu, setup_test_cpu};
+    use super::{Registers, StopReason};
+
+    #[test]
+    fn test_peek_poke_segmented() {
+        let mut cpu = setup_test_cpu();
+        cpu.regs.ds = 0x1234;
+        cpu.poke_word(cpu.regs.ds, 0x100, 0xBEEF);
+        assert_eq!(cpu.peek_word(0x1234, 0x100), 0xBEEF);
+        assert_eq!(cpu.peek_byte(0x1234, 0x101), 0xBE);
+        assert_eq!(cpu.memory.read_word(0x12440), 0xBEEF);
+
+        // Same physical byte through a different segment:offset pair
+        assert_eq!(cpu.peek_byte(0x1244, 0x0000), 0xEF);
+    }
+
+    #[test]
+    fn test_peek_poke_a20_wrap() {
+        // SystemMemory, so addresses past 1MB read as unmapped rather than panic
+        let mut cpu = setup_executable_cpu(&[]);
+        cpu.poke_byte(0xFFFF, 0x0010, 0x5A);
+        assert_eq!(cpu.memory.read_byte(0x00000), 0x5A);
+        assert_eq!(cpu.peek_byte(0x0000, 0x0000), 0x5A);
+
+        cpu.set_a20(true);
+        assert_eq!(cpu.peek_byte(0xFFFF, 0x0010), 0x00);
+    }
+
+    #[test]
+    fn test_modrm_reg8_matches_registers() {
+        let mut cpu = setup_test_cpu();
+        // ModR/M register mode decodes rm the same way Registers does
+        for reg in 0..8u8 {
+            cpu.regs.ax = 0;
+            cpu.regs.bx = 0;
+            cpu.regs.cx = 0;
+            cpu.regs.dx = 0;
+            cpu.write_rm8(0xC0 | reg, 0x80 | reg).unwrap();
+            assert_eq!(cpu.regs.get_reg8(reg), 0x80 | reg);
+            assert_eq!(cpu.get_rm8(0xC0 | reg).unwrap(), 0x80 | reg);
+        }
+        assert_eq!(cpu.regs.bx, 0x8700);
+        assert_eq!(cpu.regs.get_bh(), 0x87);
+
+        // MOV AH,BH through the reg field: 8A E7
+        let mut cpu = setup_executable_cpu(&[0x8A, 0xE7]);
+        cpu.regs.bx = 0x4200;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.get_reg8(Registers::AH), 0x42);
+        assert_eq!(cpu.regs.ax, 0x4200);
+    }
+
+    #[test]
+    fn test_reg16_index_mapping_is_standard_everywhere() {
+        let mut cpu = setup_test_cpu();
+        let values = [
+            0x1000, 0x1001, 0x1002, 0x1003, 0x1004, 0x1005, 0x1006, 0x1007,
+        ];
+        for (reg, &value) in values.iter().enumerate() {
+            cpu.regs.set_reg16(reg as u8, value).unwrap();
+        }
+        assert_eq!(Registers::CX, 1);
+        assert_eq!(Registers::BX, 3);
+        assert_eq!(
+            [
+                cpu.regs.ax,
+                cpu.regs.cx,
+                cpu.regs.dx,
+                cpu.regs.bx,
+                cpu.regs.sp,
+                cpu.regs.bp,
+                cpu.regs.si,
+                cpu.regs.di
+            ],
+            values
+        );
+
+        // ModR/M register operands, read both ways
+        for reg in 0..8u8 {
+            assert_eq!(cpu.get_rm16(0xC0 | reg).unwrap(), values[reg as usize]);
+            let operand = cpu.decode_rm(0xC0 | reg).unwrap();
+            assert_eq!(cpu.read_operand16(operand), values[reg as usize]);
+        }
+        cpu.write_rm16(0xC0 | Registers::BX, 0xB0B0).unwrap();
+        assert_eq!(cpu.regs.bx, 0xB0B0);
+
+        // MOV BX,CX (89 CB: reg=1 is CX, rm=3 is BX) then XCHG AX,BX
+        let mut cpu = setup_executable_cpu(&[0x89, 0xCB, 0x93]);
+        cpu.regs.ax = 0xAAAA;
+        cpu.regs.cx = 0xCCCC;
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.bx, 0xCCCC);
+        cpu.execute_instruction().unwrap();
+        assert_eq!(cpu.regs.ax, 0xCCCC);
+        assert_eq!(cpu.regs.bx, 0xAAAA);
+        assert_eq!(cpu.regs.cx, 0xCCCC);
+    }
+
+    #[test]
+    fn test_rm_addr_modes() {
+        // mod=00 [BX+SI], mod=01 [BP+DI+disp8], mod=10 [BX+SI+disp16] and
+        // the mod=00 rm=110 direct address, each displacement read at CS:IP
+        let mut cpu = setup_executable_cpu(&[0x20, 0x00, 0x10, 0x34, 0x12]);
+        cpu.regs.bx = 0x2000;
+        cpu.regs.si = 0x0100;
+        cpu.regs.bp = 0x3000;
+        cpu.regs.di = 0x0200;
+        assert_eq!(cpu.get_rm_addr(0x00).unwrap(), 0x2100);
+        assert_eq!(cpu.get_rm_addr(0x43).unwrap(), 0x3220);
+        assert_eq!(cpu.get_rm_addr(0x80).unwrap(), 0x3100);
+        assert_eq!(cpu.get_rm_addr(0x06).unwrap(), 0x1234);
+        assert_eq!(cpu.regs.ip, 0x105);
+    }
+
+    #[test]
+    fn test_run_code_injected_at_reset_without_a_disk() {
+        use crate::memory::{ram::RamMemory, SystemMemory};
+        use crate::serial::Serial;
+        use crate::StopReason;
+
+        // MOV AL,41h; INC AX; HLT
+        let memory = Box::new(SystemMemory::new(1024 * 1024));
+        let mut cpu = super::Cpu::without_disk(memory, Serial::new());
+        cpu.regs.cs = 0;
+        cpu.inject_bytes_at_reset(&[0xB0, 0x41, 0x40, 0xF4])
+            .unwrap();
+        assert_eq!(
+            (cpu.regs.cs, cpu.regs.ip),
+            (super::RESET_SEGMENT, super::RESET_OFFSET)
+        );
+        assert_eq!(cpu.memory.read_byte(0xFFFF0), 0xB0);
+        assert_eq!(cpu.run_for(10), StopReason::Halted);
+        assert_eq!(cpu.regs.ax, 0x0042);
+        assert_eq!(cpu.regs.ip, 0xFFF4);
+
+        assert!(cpu.inject_bytes_at_reset(&[0x90; 17]).is_err());
+
+        // Over plain RAM the bytes are just written; load_at wraps within
+        // the segment
+        let mut cpu =
+            super::Cpu::without_disk(Box::new(RamMemory::new(1024 * 1024)), Serial::new());
+        cpu.inject_bytes_at_reset(&[0xF4]).unwrap();
+        assert_eq!(cpu.memory.read_byte(0xFFFF0), 0xF4);
+        cpu.load_at(0x1000, 0xFFFF, &[0x11, 0x22]);
+        assert_eq!(cpu.memory.read_byte(0x1FFFF), 0x11);
+        assert_eq!(cpu.memory.read_byte(0x10000), 0x22);
+    }
+
+    #[test]
+    fn test_reset_resets_devices_but_keeps_disk_contents() {
+        use crate::disk::DiskImage;
+        use crate::memory::ram::RamMemory;
+        use crate::serial::Serial;
+
+        let mut cpu =
+            super::Cpu::without_disk(Box::new(RamMemory::new(1024 * 1024)), Serial::new());
+        cpu.disk = DiskImage::ram_disk(64).unwrap();
+        cpu.disk.write_sector(3, &[0xA5; 512]).unwrap();
+
+        cpu.serial.add_input(b'x');
+        cpu.serial.transmit(b'y');
+        cpu.serial.get_port(0).unwrap().add_input(b'z');
+        cpu.io.write_byte(0x21, 0xFF); // Mask every IRQ
+        cpu.io.write_byte(0x43, 0x34); // Channel 0, lobyte/hibyte, mode 2
+        cpu.io.write_byte(0x40, 0x00);
+        cpu.io.write_byte(0x40, 0x10);
+        assert!(cpu.serial.has_data());
+
+        cpu.reset();
+        assert!(!cpu.serial.has_data());
+        assert!(cpu.serial.output().is_empty());
+        assert!(!cpu.serial.get_port(0).unwrap().has_data());
+        assert_eq!(cpu.io.read_byte(0x21), 0x00);
+        let pit = cpu.io.device_mut::<crate::pit::Pit>().unwrap();
+        assert_eq!((pit.channels[0].reload, pit.channels[0].mode), (0, 0));
+        assert_eq!(cpu.disk.read_sector(3).unwrap(), vec![0xA5; 512]);
+    }
+
+    #[test]
+    fn test_boxed_and_static_memory_run_the_same() {
+        use super::{CpuGeneric, Memory};
+        use crate::memory::SystemMemory;
+        use crate::serial::Serial;
+
+        // JMP FAR 0000:0100 from the reset vector, then
+        // MOV CX,5; MOV BX,0200h; MOV [BX],CL; ADD AX,CX; ADD BX,1; LOOP -9; HLT
+        let reset = [0xEA, 0x00, 0x01, 0x00, 0x00];
+        let code = [
+            0xB9, 0x05, 0x00, 0xBB, 0x00, 0x02, 0x88, 0x0F, 0x01, 0xC8, 0x83, 0xC3, 0x01, 0xE2,
+            0xF7, 0xF4,
+        ];
+        fn run<M: Memory>(cpu: &mut CpuGeneric<M>, reset: &[u8], code: &[u8]) {
+            cpu.inject_bytes_at_reset(reset).unwrap();
+            cpu.load_at(0x0000, 0x0100, code);
+            assert_eq!(cpu.run_for(100), StopReason::Halted);
+        }
+
+        let mut boxed =
+            super::Cpu::without_disk(Box::new(SystemMemory::new(1024 * 1024)), Serial::new());
+        let mut fixed = CpuGeneric::with_memory(SystemMemory::new(1024 * 1024), Serial::new());
+        run(&mut boxed, &reset, &code);
+        run(&mut fixed, &reset, &code);
+
+        assert_eq!(fixed.regs.ax, 15);
+        assert_eq!(boxed.regs, fixed.regs);
+        assert_eq!(boxed.cycles, fixed.cycles);
+        assert_eq!(boxed.instruction_count(), fixed.instruction_count());
+        for addr in 0x200..0x205 {
+            assert_eq!(boxed.memory.read_byte(addr), fixed.memory.read_byte(addr));
+        }
+        assert_eq!(fixed.memory.read_byte(0x204), 1);
+    }
+
+    #[test]
+    fn test_wait_states_are_charged_per_bus_cycle() {
+        // MOV AL,BL; MOV AL,[BX]; ADD [BX],AL; IN AL,DX
+        let code = [0x8A, 0xC3, 0x8A, 0x07, 0x00, 0x07, 0xEC];
+        let step_clocks = |memory: u64, io: u64| {
+            let mut cpu = setup_executable_cpu(&code);
+            cpu.regs.ds = 0;
+            cpu.regs.bx = 0x0300;
+            cpu.set_memory_wait_states(memory);
+            cpu.set_io_wait_states(io);
+            let clocks = (0..4)
+                .map(|_| {
+                    let before = cpu.clocks;
+                    cpu.step().unwrap();
+                    cpu.clocks - before
+                })
+                .collect::<Vec<u64>>();
+            assert_eq!(cpu.cycles, 4);
+            clocks
+        };
+
+        // None of these has a cost in `timing`
+        assert_eq!(step_clocks(0, 0), [0, 0, 0, 0]);
+        // Two code fetches each, plus one data read, plus a read and a write
+        let with_wait = step_clocks(2, 0);
+        assert_eq!(with_wait, [2 * 2, 3 * 2, 4 * 2, 2]);
+        assert_eq!(with_wait[1] - with_wait[0], 2);
+        // IN is one fetch and one I/O cycle
+        assert_eq!(step_clocks(0, 3), [0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_timer_ticks_follow_cpu_frequency() {
+        let mut cpu = setup_test_cpu();
+        assert_eq!(cpu.cpu_frequency(), super::DEFAULT_CPU_FREQUENCY);
+
+        // A 4.77 MHz PC runs its CPU at exactly four PIT clocks, so a tick
+        // is 4 * 65536 cycles
+        cpu.set_cpu_frequency(4 * super::PIT_INPUT_HZ);
+        cpu.cycles = 262_144 * 100 + 5;
+        assert_eq!(cpu.timer_ticks(), 100);
+
+        // INT 1Ah AH=00 reports the same count in CX:DX
+        cpu.regs.ax = 0x0000;
+        cpu.int1a_time_services().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.dx), (0, 100));
+
+        // Twice the speed, half the ticks for the same cycles
+        cpu.set_cpu_frequency(8 * super::PIT_INPUT_HZ);
+        assert_eq!(cpu.timer_ticks(), 50);
+
+        // Far past anything real, the tick is just very long
+        cpu.set_cpu_frequency(u64::MAX);
+        assert_eq!(cpu.timer_ticks(), 0);
+    }
+
+    #[test]
+    fn test_update_flags_add16() {
+        let mut cpu = setup_test_cpu();
+
+        // Addition with overflow
+        cpu.update_flags_add16(0x7FFF, 0x0001, 0x8000, false);
+        assert!(!cpu.regs.flags.get_carry());
+        assert!(!cpu.regs.flags.get_zero());
+        assert!(cpu.regs.flags.get_sign());
+        assert!(cpu.regs.flags.get_overflow());
+
+        // Addition with carry
+        cpu.update_flags_add16(0xFFFF, 0x0001, 0x0000, true);
+        assert!(cpu.regs.flags.get_carry());
+        assert!(cpu.regs.flags.get_zero());
+        assert!(!cpu.regs.flags.get_sign());
+        assert!(!cpu.regs.flags.get_overflow());
+    }
+
+    #[test]
+    fn test_word_parity_uses_low_byte() {
+        // 0x0103 has an odd number of bits set but an even low byte, 0x0701
+        // the other way round
+        let programs: [(&str, &[u8], u16, bool); 6] = [
+            ("ADD AX,imm16", &[0x05, 0x01, 0x00], 0x0102, true),
+            ("ADD AX,imm16", &[0x05, 0x01, 0x00], 0x0700, false),
+            ("SUB AX,imm8", &[0x83, 0xE8, 0x01], 0x0104, true),
+            ("SUB AX,imm8", &[0x83, 0xE8, 0x01], 0x0702, false),
+            ("CMP AX,BX", &[0x3B, 0xC3], 0x0104, true),
+            ("INC AX", &[0x40], 0x0700, false),
+        ];
+        for (name, code, ax, parity) in programs {
+            let mut cpu = setup_executable_cpu(code);
+            cpu.regs.ax = ax;
+            cpu.regs.bx = 1;
+            cpu.execute_instruction().unwrap();
+            assert_eq!(
+                cpu.regs.flags.get_parity(),
+                parity,
+                "{} with AX={:04X}",
+                name,
+                ax
+            );
+        }
+    }
+
+    #[test]
+    fn test_memory_profiler_sees_rep_stosb_writes() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // MOV CX,5 ; REP STOSB
+        let mut cpu = setup_executable_cpu(&[0xB9, 0x05, 0x00, 0xF3, 0xAA]);
+        cpu.regs.es = 0;
+        cpu.regs.di = 0x500;
+        cpu.regs.set_al(0x77);
+
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&writes);
+        cpu.set_memory_profiler(Box::new(move |addr, is_write| {
+            if is_write {
+                seen.borrow_mut().push(addr);
+            }
+        }));
+        // Downcasts still find the memory underneath
+        assert!(cpu.has_valid_rom());
+
+        while cpu.regs.ip < 0x105 {
+            cpu.execute_instruction().unwrap();
+        }
+        assert_eq!(*writes.borrow(), vec![0x500, 0x501, 0x502, 0x503, 0x504]);
+        assert_eq!(cpu.memory.read_byte(0x504), 0x77);
+
+        // Replacing the profiler doesn't wrap the memory a second time
+        let reads = Rc::new(RefCell::new(0));
+        let count = Rc::clone(&reads);
+        cpu.set_memory_profiler(Box::new(move |_, is_write| {
+            if !is_write {
+                *count.borrow_mut() += 1;
+            }
+        }));
+        writes.borrow_mut().clear();
+        cpu.memory.read_byte(0x500);
+        assert_eq!(*reads.borrow(), 1);
+        assert!(writes.borrow().is_empty());
+    }
 }
@@ -0,0 +1,38 @@
+use core::fmt;
+
+/// The processor generation being emulated. Instructions introduced after
+/// the 8086 are only decoded when the configured model supports them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum CpuModel {
+    #[default]
+    I8086,
+    I80186,
+    I80286,
+    I80386,
+}
+
+impl fmt::Display for CpuModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CpuModel::I8086 => "8086",
+            CpuModel::I80186 => "80186",
+            CpuModel::I80286 => "80286",
+            CpuModel::I80386 => "80386",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_ordering() {
+        assert!(CpuModel::I8086 < CpuModel::I80186);
+        assert!(CpuModel::I80186 < CpuModel::I80286);
+        assert!(CpuModel::I80286 < CpuModel::I80386);
+        assert_eq!(CpuModel::default(), CpuModel::I8086);
+        assert_eq!(CpuModel::I80186.to_string(), "80186");
+    }
+}
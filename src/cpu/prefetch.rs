@@ -0,0 +1,144 @@
+//! An optional model of the 8086's 6-byte instruction prefetch queue. Bytes
+//! already in the queue are executed as they were read, even if the program
+//! has written to them since; only a control transfer throws them away.
+//! Some copy protection and debugger detection relies on exactly that.
+
+use super::decode::Instruction;
+
+pub const QUEUE_SIZE: usize = 6;
+
+#[derive(Debug, Clone, Default)]
+pub struct PrefetchQueue {
+    cs: u16,
+    // The offset bytes[0] was read from
+    ip: u16,
+    bytes: [u8; QUEUE_SIZE],
+    len: usize,
+}
+
+impl PrefetchQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flush(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The queued copy of the byte at `cs:ip`, if the queue holds it
+    pub fn peek(&self, cs: u16, ip: u16) -> Option<u8> {
+        let index = ip.wrapping_sub(self.ip) as usize;
+        (cs == self.cs && index < self.len).then(|| self.bytes[index])
+    }
+
+    /// Takes the byte at `cs:ip` from the queue and tops the queue back up
+    /// with `read`. Fetching from anywhere but the front of the queue means
+    /// the program has jumped, so the queue starts over from there.
+    pub fn fetch(&mut self, cs: u16, ip: u16, mut read: impl FnMut(u16) -> u8) -> u8 {
+        if self.is_empty() || cs != self.cs || ip != self.ip {
+            self.cs = cs;
+            self.ip = ip;
+            self.len = 0;
+        }
+        self.fill(&mut read);
+        let byte = self.bytes[0];
+        self.bytes.copy_within(1..self.len, 0);
+        self.len -= 1;
+        self.ip = self.ip.wrapping_add(1);
+        self.fill(&mut read);
+        byte
+    }
+
+    // The real bus unit fills the queue while instructions execute; keeping
+    // it full at every fetch is close enough for code that writes ahead of
+    // itself
+    fn fill(&mut self, read: &mut impl FnMut(u16) -> u8) {
+        while self.len < QUEUE_SIZE {
+            self.bytes[self.len] = read(self.ip.wrapping_add(self.len as u16));
+            self.len += 1;
+        }
+    }
+}
+
+/// Whether an instruction always transfers control and so empties the
+/// queue, even when it lands on the very next byte (JMP $+2 is the usual
+/// way to flush it). Conditional branches that are taken show up as a fetch
+/// from somewhere other than the front of the queue instead.
+pub fn always_transfers(insn: &Instruction) -> bool {
+    match insn.opcode {
+        0x9A | 0xC2 | 0xC3 | 0xCA..=0xCD | 0xCF | 0xE8..=0xEB => true,
+        0xFF => insn.modrm.is_some_and(|modrm| (2..=5).contains(&modrm.reg)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::test_utils::setup_executable_cpu;
+
+    // MOV [0104],AL with AL=90 overwrites the INC AX that follows with NOP
+    const PATCH_NEXT: [u8; 5] = [0x88, 0x06, 0x04, 0x01, 0x40];
+
+    #[test]
+    fn test_write_to_prefetched_bytes_does_not_take_effect() {
+        let mut cpu = setup_executable_cpu(&PATCH_NEXT);
+        cpu.regs.ds = 0;
+        cpu.regs.ax = 0x0090;
+        cpu.set_prefetch_emulation(true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.peek_byte(0, 0x104), 0x90);
+        // The INC AX was already in the queue
+        assert_eq!(cpu.disassemble(0, 0x104).0, "INC AX");
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ax, 0x0091);
+
+        let mut cpu = setup_executable_cpu(&PATCH_NEXT);
+        cpu.regs.ds = 0;
+        cpu.regs.ax = 0x0090;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.ax, 0x0090);
+    }
+
+    #[test]
+    fn test_jump_flushes_the_queue() {
+        // MOV [0106],AL / JMP 0106 / INC AX
+        let code = [0x88, 0x06, 0x06, 0x01, 0xEB, 0x00, 0x40];
+        let mut cpu = setup_executable_cpu(&code);
+        cpu.regs.ds = 0;
+        cpu.regs.ax = 0x0090;
+        cpu.set_prefetch_emulation(true);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.regs.ip, 0x107);
+        assert_eq!(cpu.regs.ax, 0x0090);
+    }
+
+    #[test]
+    fn test_flush_follows_the_queued_instruction_not_memory() {
+        // MOV [0108],AL / MOV [010A],AL / JMP 010A / INC AX. The JMP and the
+        // INC AX are both queued when they are overwritten with NOPs, so the
+        // old JMP runs and its flush brings in the NOP.
+        let code = [
+            0x88, 0x06, 0x08, 0x01, 0x88, 0x06, 0x0A, 0x01, 0xEB, 0x00, 0x40,
+        ];
+        let mut cpu = setup_executable_cpu(&code);
+        cpu.regs.ds = 0;
+        cpu.regs.ax = 0x0090;
+        cpu.set_prefetch_emulation(true);
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.regs.ip, 0x10B);
+        assert_eq!(cpu.regs.ax, 0x0090);
+    }
+}
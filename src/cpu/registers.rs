@@ -1,6 +1,8 @@
 use super::flags::Flags;
+use crate::prelude::*;
+use core::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Registers {
     pub ax: u16,
     pub bx: u16,
@@ -25,6 +27,27 @@ impl Default for Registers {
 }
 
 impl Registers {
+    // 8-bit register indices as ModR/M encodes them
+    pub const AL: u8 = 0;
+    pub const CL: u8 = 1;
+    pub const DL: u8 = 2;
+    pub const BL: u8 = 3;
+    pub const AH: u8 = 4;
+    pub const CH: u8 = 5;
+    pub const DH: u8 = 6;
+    pub const BH: u8 = 7;
+
+    // 16-bit register indices, in the same AX, CX, DX, BX, SP, BP, SI, DI
+    // order ModR/M and the register-in-opcode forms use
+    pub const AX: u8 = 0;
+    pub const CX: u8 = 1;
+    pub const DX: u8 = 2;
+    pub const BX: u8 = 3;
+    pub const SP: u8 = 4;
+    pub const BP: u8 = 5;
+    pub const SI: u8 = 6;
+    pub const DI: u8 = 7;
+
     pub fn new() -> Self {
         Registers {
             ax: 0,
@@ -103,12 +126,10 @@ impl Registers {
         self.ax = (self.ax & 0xFF00) | (value as u16);
     }
 
-    #[allow(dead_code)]
     pub fn set_bh(&mut self, value: u8) {
         self.bx = (self.bx & 0x00FF) | ((value as u16) << 8);
     }
 
-    #[allow(dead_code)]
     pub fn set_bl(&mut self, value: u8) {
         self.bx = (self.bx & 0xFF00) | (value as u16);
     }
@@ -183,33 +204,44 @@ impl Registers {
         self.di = value;
     }
 
+    /// Reads an 8-bit register by its ModR/M encoding. Indices 0-3 are the
+    /// low bytes of AX, CX, DX and BX, and 4-7 the high bytes of the same
+    /// registers; only the low three bits of `reg` are used.
+    ///
+    /// ```
+    /// use dos_emu::cpu::Registers;
+    ///
+    /// let mut regs = Registers::new();
+    /// regs.bx = 0xBEEF;
+    /// assert_eq!(regs.get_reg8(Registers::BL), 0xEF);
+    /// assert_eq!(regs.get_reg8(Registers::BH), 0xBE);
+    /// ```
     pub fn get_reg8(&self, reg: u8) -> u8 {
-        match reg & 0x07 {
-            0 => self.ax as u8,        // AL
-            1 => self.cx as u8,        // CL
-            2 => self.dx as u8,        // DL
-            3 => self.bx as u8,        // BL
-            4 => (self.ax >> 8) as u8, // AH
-            5 => (self.cx >> 8) as u8, // CH
-            6 => (self.dx >> 8) as u8, // DH
-            7 => (self.bx >> 8) as u8, // BH
-            _ => unreachable!(),
-        }
+        let (word, shift) = Self::reg8_location(reg);
+        (self.get_reg16(word) >> shift) as u8
+    }
+
+    /// Writes an 8-bit register by its ModR/M encoding, leaving the other
+    /// half of the 16-bit register alone.
+    ///
+    /// ```
+    /// use dos_emu::cpu::Registers;
+    ///
+    /// let mut regs = Registers::new();
+    /// regs.ax = 0x1234;
+    /// regs.set_reg8(Registers::AH, 0xAB).unwrap();
+    /// assert_eq!(regs.ax, 0xAB34);
+    /// ```
+    pub fn set_reg8(&mut self, reg: u8, value: u8) -> Result<(), String> {
+        let (word, shift) = Self::reg8_location(reg);
+        let merged = (self.get_reg16(word) & !(0xFF << shift)) | ((value as u16) << shift);
+        self.set_reg16(word, merged)
     }
 
-    pub fn set_reg8(&mut self, reg: u8, value: u8) -> Result<(), String> {
-        match reg & 0x07 {
-            0 => self.ax = (self.ax & 0xFF00) | (value as u16), // AL
-            1 => self.cx = (self.cx & 0xFF00) | (value as u16), // CL
-            2 => self.dx = (self.dx & 0xFF00) | (value as u16), // DL
-            3 => self.bx = (self.bx & 0xFF00) | (value as u16), // BL
-            4 => self.ax = (self.ax & 0x00FF) | ((value as u16) << 8), // AH
-            5 => self.cx = (self.cx & 0x00FF) | ((value as u16) << 8), // CH
-            6 => self.dx = (self.dx & 0x00FF) | ((value as u16) << 8), // DH
-            7 => self.bx = (self.bx & 0x00FF) | ((value as u16) << 8), // BH
-            _ => return Err("Invalid register index".to_string()),
-        }
-        Ok(())
+    // The one place the 8-bit encoding is decoded: which of AX/CX/DX/BX (as
+    // a 16-bit register index) and which byte of it
+    fn reg8_location(reg: u8) -> (u8, u32) {
+        (reg & 0x03, if reg & 0x04 != 0 { 8 } else { 0 })
     }
 
     #[allow(dead_code)]
@@ -232,6 +264,9 @@ impl Registers {
         self.set_reg8((reg & 0x3) | 0x4, value) // Bottom 2 bits select AH, CH, DH, BH
     }
 
+    /// Reads a 16-bit register by its ModR/M encoding. This is the only
+    /// decoding of the index; instruction handlers all go through it and
+    /// `set_reg16` rather than keeping their own tables.
     pub fn get_reg16(&self, reg: u8) -> u16 {
         match reg & 0x07 {
             0 => self.ax,
@@ -291,6 +326,43 @@ impl Registers {
     }
 }
 
+/// Formats the registers the way DEBUG.COM's `R` command does, e.g.
+/// `AX=0000 BX=0000 ... CS=F000 IP=FFF0  NV UP DI PL NZ NA PO NC`
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = &self.flags;
+        let flag = |set: bool, on: &'static str, off: &'static str| if set { on } else { off };
+
+        write!(
+            f,
+            "AX={:04X} BX={:04X} CX={:04X} DX={:04X} SP={:04X} BP={:04X} SI={:04X} DI={:04X} \
+             DS={:04X} ES={:04X} SS={:04X} CS={:04X} IP={:04X}  \
+             {} {} {} {} {} {} {} {}",
+            self.ax,
+            self.bx,
+            self.cx,
+            self.dx,
+            self.sp,
+            self.bp,
+            self.si,
+            self.di,
+            self.ds,
+            self.es,
+            self.ss,
+            self.cs,
+            self.ip,
+            flag(flags.get_overflow(), "OV", "NV"),
+            flag(flags.get_direction(), "DN", "UP"),
+            flag(flags.get_interrupt(), "EI", "DI"),
+            flag(flags.get_sign(), "NG", "PL"),
+            flag(flags.get_zero(), "ZR", "NZ"),
+            flag(flags.get_auxiliary(), "AC", "NA"),
+            flag(flags.get_parity(), "PE", "PO"),
+            flag(flags.get_carry(), "CY", "NC"),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -346,6 +418,33 @@ mod tests {
         assert_eq!(regs.dx, 0x78F0);
     }
 
+    #[test]
+    fn test_reg8_index_mapping() {
+        let mut regs = Registers::new();
+        regs.ax = 0x1122;
+        regs.cx = 0x3344;
+        regs.dx = 0x5566;
+        regs.bx = 0x7788;
+
+        let expected = [0x22, 0x44, 0x66, 0x88, 0x11, 0x33, 0x55, 0x77];
+        for (reg, &value) in expected.iter().enumerate() {
+            assert_eq!(regs.get_reg8(reg as u8), value, "index {}", reg);
+        }
+        assert_eq!(regs.get_reg8(Registers::AH), regs.get_ah());
+        assert_eq!(regs.get_reg8(Registers::BH), regs.get_bh());
+        assert_eq!(regs.get_reg8_high(0), regs.get_reg8(4));
+        assert_eq!(regs.get_reg8_high(3), regs.get_reg8(7));
+        // Only the low three bits select the register
+        assert_eq!(regs.get_reg8(0x0C), regs.get_ah());
+
+        regs.set_reg8(4, 0xAA).unwrap();
+        regs.set_reg8(7, 0xBB).unwrap();
+        assert_eq!(regs.ax, 0xAA22);
+        assert_eq!(regs.bx, 0xBB88);
+        regs.set_reg8(Registers::CL, 0xCC).unwrap();
+        assert_eq!(regs.cx, 0x33CC);
+    }
+
     #[test]
     fn test_16bit_register_operations() {
         let mut regs = Registers::new();
@@ -412,4 +511,33 @@ mod tests {
         assert_eq!(regs.get_reg16(2), 0x9ABC); // DX
         assert_eq!(regs.get_reg16(3), 0xDEF0); // BX
     }
+
+    #[test]
+    fn test_display_matches_debug_r() {
+        let regs = Registers::new();
+        assert_eq!(
+            regs.to_string(),
+            "AX=0000 BX=0000 CX=0000 DX=0000 SP=0000 BP=0000 SI=0000 DI=0000 \
+             DS=0000 ES=0000 SS=0000 CS=F000 IP=FFF0  NV UP DI PL NZ NA PO NC"
+        );
+
+        let mut regs = Registers::new();
+        regs.ax = 0x1234;
+        regs.sp = 0xFFEE;
+        regs.cs = 0x0100;
+        regs.ip = 0x0100;
+        regs.flags.set_overflow(true);
+        regs.flags.set_direction(true);
+        regs.flags.set_interrupt(true);
+        regs.flags.set_sign(true);
+        regs.flags.set_zero(true);
+        regs.flags.set_auxiliary(true);
+        regs.flags.set_parity(true);
+        regs.flags.set_carry(true);
+        assert_eq!(
+            regs.to_string(),
+            "AX=1234 BX=0000 CX=0000 DX=0000 SP=FFEE BP=0000 SI=0000 DI=0000 \
+             DS=0000 ES=0000 SS=0000 CS=0100 IP=0100  OV DN EI NG ZR AC PE CY"
+        );
+    }
 }
@@ -0,0 +1,99 @@
+/// Detects a guest that has lost control, either by executing a run of
+/// invalid opcodes (typically jumping into uninitialized memory) or by
+/// spinning on one CS:IP without changing any register. Both checks are off
+/// until enabled with `Cpu::set_runaway_limits`.
+#[derive(Debug, Default)]
+pub struct RunawayDetector {
+    invalid_opcode_limit: Option<u32>,
+    revisit_limit: Option<u32>,
+    invalid_streak: u32,
+    last_cs_ip: Option<(u16, u16)>,
+    revisits: u32,
+}
+
+impl RunawayDetector {
+    pub fn new() -> Self {
+        RunawayDetector::default()
+    }
+
+    pub fn set_limits(&mut self, invalid_opcodes: Option<u32>, revisits: Option<u32>) {
+        self.invalid_opcode_limit = invalid_opcodes;
+        self.revisit_limit = revisits;
+        self.clear();
+    }
+
+    pub fn invalid_opcode_limit(&self) -> Option<u32> {
+        self.invalid_opcode_limit
+    }
+
+    /// Forgets any streak in progress, keeping the limits
+    pub fn clear(&mut self) {
+        self.invalid_streak = 0;
+        self.last_cs_ip = None;
+        self.revisits = 0;
+    }
+
+    /// Records an invalid opcode; true once the consecutive limit is reached
+    pub fn record_invalid_opcode(&mut self) -> bool {
+        self.last_cs_ip = None;
+        self.revisits = 0;
+        self.invalid_streak += 1;
+        matches!(self.invalid_opcode_limit, Some(limit) if self.invalid_streak >= limit)
+    }
+
+    /// Records a successfully executed instruction that started at `cs_ip`.
+    /// `unchanged` says whether it left every register as it found it.
+    /// True once the same CS:IP has been revisited the limit number of times
+    /// without progress.
+    pub fn record_step(&mut self, cs_ip: (u16, u16), unchanged: bool) -> bool {
+        self.invalid_streak = 0;
+        if unchanged && self.last_cs_ip == Some(cs_ip) {
+            self.revisits += 1;
+        } else {
+            self.revisits = 0;
+        }
+        self.last_cs_ip = Some(cs_ip);
+        matches!(self.revisit_limit, Some(limit) if self.revisits >= limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::stop::StopReason;
+    use crate::cpu::test_utils::setup_executable_cpu;
+
+    #[test]
+    fn test_jmp_self_trips_revisit_detector() {
+        // JMP $
+        let mut cpu = setup_executable_cpu(&[0xEB, 0xFE]);
+        cpu.set_runaway_limits(None, Some(50));
+        // The first visit doesn't count as a revisit
+        assert_eq!(cpu.run_for(50), StopReason::InstructionLimit);
+        assert_eq!(cpu.run_for(1000), StopReason::Runaway);
+
+        let mut cpu = setup_executable_cpu(&[0xEB, 0xFE]);
+        cpu.set_runaway_limits(None, Some(50));
+        assert_eq!(cpu.run_for(51), StopReason::Runaway);
+    }
+
+    #[test]
+    fn test_loop_with_progress_is_not_runaway() {
+        // INC AX; JMP back to INC
+        let mut cpu = setup_executable_cpu(&[0x40, 0xEB, 0xFD]);
+        cpu.set_runaway_limits(None, Some(5));
+        assert_eq!(cpu.run_for(100), StopReason::InstructionLimit);
+        assert_eq!(cpu.regs.ax, 50);
+    }
+
+    #[test]
+    fn test_invalid_opcode_streak_trips_detector() {
+        let mut cpu = setup_executable_cpu(&[0xF1; 8]);
+        cpu.set_runaway_limits(Some(4), None);
+        assert_eq!(cpu.run_for(100), StopReason::Runaway);
+        assert_eq!(cpu.regs.ip, 0x104);
+
+        // Without the detector the first invalid opcode stops the run
+        let mut cpu = setup_executable_cpu(&[0xF1; 8]);
+        assert_eq!(cpu.run_for(100), StopReason::InvalidOpcode(0xF1));
+    }
+}
@@ -0,0 +1,248 @@
+use super::CpuGeneric;
+use crate::memory::Memory;
+use crate::prelude::*;
+
+/// Why `Cpu::run_for` returned control to the caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The CPU executed HLT (or was otherwise halted)
+    Halted,
+    /// The runaway detector tripped; see `Cpu::set_runaway_limits`
+    Runaway,
+    /// The instruction budget ran out before anything else happened
+    InstructionLimit,
+    /// An unimplemented or illegal opcode; two-byte opcodes are 0x0Fxx
+    InvalidOpcode(u16),
+    /// DIV/IDIV by zero or with a quotient that doesn't fit
+    DivideError,
+    /// INT 18h ran with no ROM BASIC behind it: there was nothing to boot
+    NoBootDevice,
+    /// The guest wrote this exit code to the debug-exit port (0x501)
+    GuestExit(u8),
+    /// `run_until_output` saw the pattern it was waiting for
+    OutputMatched,
+    /// An instruction failed
+    Error(String),
+    /// The emulator itself panicked while running the instruction at
+    /// `cs:ip`; the CPU state may be inconsistent
+    InternalFault { cs: u16, ip: u16, message: String },
+}
+
+impl<M: Memory> CpuGeneric<M> {
+    /// Executes up to `max_instructions` instructions, stopping early on
+    /// halt, error or a tripped runaway detector.
+    pub fn run_for(&mut self, max_instructions: u64) -> StopReason {
+        for _ in 0..max_instructions {
+            // Unlike HLT, a guest exit can't be woken up by an interrupt
+            if self.guest_exit.is_some() || (self.halted && !self.irq_pending()) {
+                return self.halt_reason();
+            }
+
+            let cs_ip = (self.regs.cs, self.regs.ip);
+            let before = self.regs.clone();
+            let result = self.execute_instruction();
+
+            if let Some(opcode) = self.last_invalid_opcode {
+                if self.runaway.invalid_opcode_limit().is_none() {
+                    return StopReason::InvalidOpcode(opcode);
+                }
+                // Keep going through the garbage so the streak can be counted
+                self.halted = false;
+                if self.runaway.record_invalid_opcode() {
+                    return StopReason::Runaway;
+                }
+                continue;
+            }
+            if let Err(e) = result {
+                if self.last_divide_error {
+                    return StopReason::DivideError;
+                }
+                if let Some((cs, ip)) = self.last_internal_fault {
+                    return StopReason::InternalFault { cs, ip, message: e };
+                }
+                return StopReason::Error(e);
+            }
+            if self.runaway.record_step(cs_ip, before == self.regs) {
+                return StopReason::Runaway;
+            }
+        }
+
+        if self.halted {
+            self.halt_reason()
+        } else {
+            StopReason::InstructionLimit
+        }
+    }
+
+    fn halt_reason(&self) -> StopReason {
+        if let Some(code) = self.guest_exit {
+            StopReason::GuestExit(code)
+        } else if self.no_boot_device {
+            StopReason::NoBootDevice
+        } else {
+            StopReason::Halted
+        }
+    }
+
+    /// Runs until `needle` shows up in the serial output transmitted since
+    /// the call started, for at most `max_cycles` instructions. Any other
+    /// reason to stop is passed through unchanged.
+    pub fn run_until_output(&mut self, needle: &[u8], max_cycles: u64) -> StopReason {
+        let start = self.serial.output().len();
+        let mut scanned = start;
+        for _ in 0..max_cycles {
+            let reason = self.run_for(1);
+            if reason != StopReason::InstructionLimit {
+                return reason;
+            }
+
+            let output = self.serial.output();
+            if output.len() > scanned {
+                // Back up far enough to catch a match straddling the old end
+                let from = scanned.saturating_sub(needle.len()).max(start);
+                let window: Vec<u8> = output.range(from..).copied().collect();
+                if window.windows(needle.len().max(1)).any(|w| w == needle) {
+                    return StopReason::OutputMatched;
+                }
+                scanned = output.len();
+            }
+        }
+        StopReason::InstructionLimit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::test_utils::setup_executable_cpu;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_run_for_stops_on_halt_and_limit() {
+        // NOP; NOP; HLT
+        let mut cpu = setup_executable_cpu(&[0x90, 0x90, 0xF4]);
+        assert_eq!(cpu.run_for(1), StopReason::InstructionLimit);
+        assert_eq!(cpu.run_for(10), StopReason::Halted);
+        assert_eq!(cpu.regs.ip, 0x103);
+    }
+
+    #[test]
+    fn test_instruction_count() {
+        // MOV CX,3; INC AX; LOOP -3; HLT: 1 + 3 * 2 + 1 instructions
+        let code = [0xB9, 0x03, 0x00, 0x40, 0xE2, 0xFD, 0xF4];
+        let mut cpu = setup_executable_cpu(&code);
+        assert_eq!(cpu.run_for(100), StopReason::Halted);
+        assert_eq!(cpu.regs.ax, 3);
+        assert_eq!(cpu.instruction_count(), 8);
+        // A halted CPU executes nothing more
+        cpu.run_for(10);
+        assert_eq!(cpu.instruction_count(), 8);
+
+        cpu.reset();
+        assert_eq!(cpu.instruction_count(), 0);
+    }
+
+    #[test]
+    fn test_run_for_reports_divide_error() {
+        // XOR BX,BX; MOV AX,1234h; DIV BX
+        let mut cpu = setup_executable_cpu(&[0x31, 0xDB, 0xB8, 0x34, 0x12, 0xF7, 0xF3]);
+        assert_eq!(cpu.run_for(10), StopReason::DivideError);
+        assert_eq!(cpu.regs.ax, 0x1234);
+    }
+
+    #[test]
+    fn test_run_for_reports_invalid_opcode_byte() {
+        // NOP; then 0xF1, which no model implements
+        let mut cpu = setup_executable_cpu(&[0x90, 0xF1]);
+        assert_eq!(cpu.run_for(10), StopReason::InvalidOpcode(0xF1));
+    }
+
+    #[test]
+    fn test_panic_in_a_handler_is_an_internal_fault() {
+        // NOP; INT 60h, whose native handler indexes past the end of a table
+        let mut cpu = setup_executable_cpu(&[0x90, 0xCD, 0x60]);
+        cpu.set_interrupt_handler(
+            0x60,
+            Box::new(|cpu: &mut Cpu| {
+                let table = [0u8; 4];
+                cpu.regs.ax = table[cpu.regs.bx as usize] as u16;
+                Ok(())
+            }),
+        );
+        cpu.regs.bx = 0x10;
+        match cpu.run_for(10) {
+            StopReason::InternalFault { cs, ip, message } => {
+                assert_eq!((cs, ip), (0x0000, 0x0101));
+                assert!(message.contains("index out of bounds"), "{}", message);
+            }
+            other => panic!("expected an internal fault, got {:?}", other),
+        }
+
+        // The host can carry on with the CPU afterwards, handler and all
+        cpu.regs.cs = 0x0000;
+        cpu.regs.ip = 0x0100;
+        assert!(cpu.execute_instruction().is_ok());
+        assert_eq!(cpu.last_internal_fault, None);
+        cpu.regs.ax = 0xFFFF;
+        cpu.regs.bx = 0;
+        assert!(cpu.execute_instruction().is_ok());
+        assert_eq!(cpu.regs.ax, 0);
+    }
+
+    // MOV AH,01h; MOV AL,<ch>; INT 14h for each character, then HLT
+    fn serial_print_program(text: &[u8]) -> Vec<u8> {
+        let mut code = Vec::new();
+        for &ch in text {
+            code.extend_from_slice(&[0xB4, 0x01, 0xB0, ch, 0xCD, 0x14]);
+        }
+        code.push(0xF4);
+        code
+    }
+
+    #[test]
+    fn test_run_until_output_stops_at_pattern() {
+        let mut cpu = setup_executable_cpu(&serial_print_program(b"BOOT READY!"));
+        assert_eq!(
+            cpu.run_until_output(b"READY", 10_000),
+            StopReason::OutputMatched
+        );
+        // Stopped right after the Y went out, before the trailing '!'
+        let output: Vec<u8> = cpu.serial.output().iter().copied().collect();
+        assert_eq!(output, b"BOOT READY");
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_run_until_output_passes_through_halt_and_budget() {
+        let mut cpu = setup_executable_cpu(&serial_print_program(b"NOPE"));
+        assert_eq!(cpu.run_until_output(b"READY", 10_000), StopReason::Halted);
+
+        let mut cpu = setup_executable_cpu(&serial_print_program(b"READY"));
+        assert_eq!(
+            cpu.run_until_output(b"READY", 3),
+            StopReason::InstructionLimit
+        );
+    }
+
+    #[test]
+    fn test_debug_exit_port_stops_run() {
+        // MOV AX,0x501; MOV DX,AX; MOV AL,0; OUT DX,AL; then a NOP that
+        // must not run
+        let exit_with = |code: u8| vec![0xB8, 0x01, 0x05, 0x8B, 0xD0, 0xB0, code, 0xEE];
+        let mut program = exit_with(0);
+        program.push(0x90);
+        let mut cpu = setup_executable_cpu(&program);
+        assert_eq!(cpu.run_for(100), StopReason::GuestExit(0));
+        assert_eq!(cpu.regs.ip, 0x108);
+
+        // A failing test reports its code, even with interrupts enabled
+        let mut program = vec![0xFB]; // STI
+        program.extend(exit_with(0x2A));
+        program.extend([0xEB, 0xFE]);
+        let mut cpu = setup_executable_cpu(&program);
+        assert_eq!(cpu.run_for(100), StopReason::GuestExit(0x2A));
+
+        cpu.reset();
+        assert!(cpu.guest_exit.is_none());
+    }
+}
@@ -0,0 +1,70 @@
+// Helpers for instruction tests, here and in downstream crates (with the
+// `test-support` feature): a CPU that can actually execute, with the code
+// under test at a fixed CS:IP, so a test only has to say what's different.
+
+use super::Cpu;
+use crate::bios::{init_bios_data_area, init_bios_interrupts};
+use crate::disk::DiskImage;
+use crate::memory::SystemMemory;
+use crate::serial::Serial;
+use std::path::Path;
+
+/// Where `run_one` and `executable_cpu` put the code: 0000:0100, as in a .COM
+pub const CODE_SEGMENT: u16 = 0x0000;
+pub const CODE_OFFSET: u16 = 0x0100;
+
+/// A CPU backed by SystemMemory with the BIOS set up, so `code` can be run
+/// through `execute_instruction`. Code is loaded at 0000:0100 and the stack
+/// starts at 0000:FFFE.
+pub fn executable_cpu(code: &[u8]) -> Cpu {
+    let memory = Box::new(SystemMemory::new(1024 * 1024));
+    let disk = DiskImage::new(Path::new("drive_c")).expect("Failed to create disk image");
+    let mut cpu = Cpu::new(memory, Serial::new(), disk);
+    init_bios_interrupts(&mut cpu);
+    init_bios_data_area(&mut cpu);
+    cpu.regs.cs = CODE_SEGMENT;
+    cpu.regs.ip = CODE_OFFSET;
+    cpu.regs.ss = 0;
+    cpu.regs.sp = 0xFFFE;
+    for (i, &byte) in code.iter().enumerate() {
+        cpu.poke_byte(CODE_SEGMENT, CODE_OFFSET.wrapping_add(i as u16), byte);
+    }
+    cpu
+}
+
+/// Writes `code` at 0000:0100, lets `setup` adjust registers or memory,
+/// executes exactly one instruction and hands the CPU back for assertions.
+/// Panics if the instruction fails, with the error and where it happened.
+///
+/// ```
+/// use dos_emu::cpu::test_support::run_one;
+///
+/// // ADD AX,BX
+/// let cpu = run_one(
+///     |cpu| {
+///         cpu.regs.ax = 0x7FFF;
+///         cpu.regs.bx = 1;
+///     },
+///     &[0x01, 0xD8],
+/// );
+/// assert_eq!(cpu.regs.ax, 0x8000);
+/// assert!(cpu.regs.flags.get_overflow());
+/// assert_eq!(cpu.regs.ip, 0x0102);
+/// ```
+pub fn run_one(setup: impl FnOnce(&mut Cpu), code: &[u8]) -> Cpu {
+    let mut cpu = executable_cpu(code);
+    setup(&mut cpu);
+    let (cs, ip) = (cpu.regs.cs, cpu.regs.ip);
+    if let Err(e) = cpu.execute_instruction() {
+        panic!("Instruction at {:04X}:{:04X} failed: {}", cs, ip, e);
+    }
+    cpu
+}
+
+/// Writes `code` at the CPU's current CS:IP and executes exactly one
+/// instruction. Unlike `run_one` the CPU is the caller's and errors come
+/// back rather than panicking, for runners that collect their own failures.
+pub fn execute_one_raw(cpu: &mut Cpu, code: &[u8]) -> Result<(), String> {
+    cpu.load_at(cpu.regs.cs, cpu.regs.ip, code);
+    cpu.execute_instruction()
+}
@@ -0,0 +1,52 @@
+//! Clock costs for the instructions the timing model knows about, which
+//! `step` adds to `clocks`. Anything without an entry here adds none; every
+//! instruction still counts once in `cycles`, the instruction-count model
+//! `DEFAULT_CPU_FREQUENCY` is based on.
+
+/// 8086 clocks for a conditional branch, which refills the prefetch queue
+/// only when it is taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchCost {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCost {
+    pub fn clocks(self, taken: bool) -> u64 {
+        if taken {
+            self.taken
+        } else {
+            self.not_taken
+        }
+    }
+}
+
+/// Jcc rel8 (0x70-0x7F)
+pub const JCC: BranchCost = BranchCost {
+    taken: 16,
+    not_taken: 4,
+};
+
+/// LOOP rel8 (0xE2)
+pub const LOOP: BranchCost = BranchCost {
+    taken: 17,
+    not_taken: 5,
+};
+
+/// LOOPNZ rel8 (0xE0)
+pub const LOOPNZ: BranchCost = BranchCost {
+    taken: 19,
+    not_taken: 5,
+};
+
+/// LOOPZ rel8 (0xE1)
+pub const LOOPZ: BranchCost = BranchCost {
+    taken: 18,
+    not_taken: 6,
+};
+
+/// JCXZ rel8 (0xE3)
+pub const JCXZ: BranchCost = BranchCost {
+    taken: 18,
+    not_taken: 6,
+};
@@ -0,0 +1,59 @@
+// Debug-exit port for test programs. A guest writes its exit code to port
+// 0x501 and the run stops with `StopReason::GuestExit(code)`, which lets a
+// .COM test report pass/fail to whatever is driving the emulator. Nothing on
+// a real PC lives at 0x501.
+
+use crate::io::IoDevice;
+use core::any::Any;
+
+pub const DEBUG_EXIT_PORT: u16 = 0x501;
+
+#[derive(Default)]
+pub struct DebugExit {
+    exit_code: Option<u8>,
+}
+
+impl DebugExit {
+    pub fn new() -> Self {
+        DebugExit { exit_code: None }
+    }
+
+    /// The code the guest wrote, if it has written one since the last call
+    pub fn take_exit_code(&mut self) -> Option<u8> {
+        self.exit_code.take()
+    }
+}
+
+impl IoDevice for DebugExit {
+    fn read_port(&mut self, _port: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_port(&mut self, _port: u16, value: u8) {
+        println!("Debug exit: guest exited with code {}", value);
+        self.exit_code = Some(value);
+    }
+
+    fn reset(&mut self) {
+        self.exit_code = None;
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_latches_once() {
+        let mut port = DebugExit::new();
+        assert_eq!(port.take_exit_code(), None);
+        port.write_port(DEBUG_EXIT_PORT, 3);
+        assert_eq!(port.read_port(DEBUG_EXIT_PORT), 0xFF);
+        assert_eq!(port.take_exit_code(), Some(3));
+        assert_eq!(port.take_exit_code(), None);
+    }
+}
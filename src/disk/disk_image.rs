@@ -1,10 +1,12 @@
 use super::{
-    Mbr, BYTES_PER_SECTOR, FAT16_MEDIA_DESCRIPTOR, FAT16_NUMBER_OF_FATS, FAT16_RESERVED_SECTORS,
-    FAT16_ROOT_ENTRIES, FAT16_SECTORS_PER_CLUSTER, FAT16_SECTORS_PER_FAT, FAT16_TOTAL_SECTORS,
-    HEADS_PER_CYLINDER, SECTORS_PER_TRACK, SECTOR_SIZE,
+    Mbr, PartitionEntry, BYTES_PER_SECTOR, FAT12_SYSTEM_ID, FAT16_MEDIA_DESCRIPTOR,
+    FAT16_NUMBER_OF_FATS, FAT16_RESERVED_SECTORS, FAT16_ROOT_ENTRIES, FAT16_SECTORS_PER_CLUSTER,
+    FAT16_SECTORS_PER_FAT, FAT16_SYSTEM_ID, FAT16_TOTAL_SECTORS, HEADS_PER_CYLINDER, MBR_SIGNATURE,
+    SECTORS_PER_TRACK, SECTOR_SIZE,
 };
+use crate::dos::dos_date_time;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 // Define sector start constants
 const BOOT_SECTOR: u32 = 63; // Boot sector is at sector 63
@@ -14,6 +16,22 @@ const ROOT_DIR_START: u32 = FAT2_START + FAT16_SECTORS_PER_FAT as u32;
 const DATA_START: u32 =
     ROOT_DIR_START + (FAT16_ROOT_ENTRIES * 32).div_ceil(SECTOR_SIZE as u16) as u32;
 
+// The BPB sits after the 3-byte jump and 8-byte OEM name and runs to the
+// end of the large sector count
+const BPB_OFFSET: usize = 0x0B;
+const BPB_SIZE: usize = 25;
+
+// fatfs switches to FAT32 from 512MB, which DOS can't read
+const RAM_DISK_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_ARCHIVE: u8 = 0x20;
+// 1980-01-01, the earliest date a directory entry can hold
+const DOS_EPOCH_DATE: u16 = (1 << 5) | 1;
+// FAT16 cluster numbers from here up are reserved or mark the end of a chain
+const FAT16_FIRST_RESERVED_CLUSTER: usize = 0xFFF0;
+const FAT16_END_OF_CHAIN: u16 = 0xFFFF;
+
 #[derive(Debug, Clone)]
 pub struct BootSector {
     data: [u8; SECTOR_SIZE],
@@ -35,9 +53,154 @@ impl BootSector {
         BootSector { data }
     }
 
+    /// Wraps an existing 512-byte sector, e.g. one read from an image
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let data: [u8; SECTOR_SIZE] = data
+            .try_into()
+            .map_err(|_| format!("Invalid boot sector size: {} bytes", data.len()))?;
+        Ok(BootSector { data })
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
+
+    /// Decodes the BPB stored at offset 0x0B
+    pub fn parse_bpb(&self) -> Result<BiosParameterBlock, String> {
+        BiosParameterBlock::from_bytes(&self.data[BPB_OFFSET..BPB_OFFSET + BPB_SIZE])
+    }
+
+    /// Writes `bpb` over the BPB area, leaving the jump and boot code alone
+    pub fn set_bpb(&mut self, bpb: &BiosParameterBlock) {
+        let bytes = bpb.to_bytes();
+        self.data[BPB_OFFSET..BPB_OFFSET + BPB_SIZE].copy_from_slice(&bytes[..BPB_SIZE]);
+    }
+
+    pub fn bytes_per_sector(&self) -> u16 {
+        self.read_u16(0x0B)
+    }
+
+    pub fn set_bytes_per_sector(&mut self, value: u16) {
+        self.write_u16(0x0B, value);
+    }
+
+    pub fn sectors_per_cluster(&self) -> u8 {
+        self.data[0x0D]
+    }
+
+    pub fn set_sectors_per_cluster(&mut self, value: u8) {
+        self.data[0x0D] = value;
+    }
+
+    pub fn reserved_sectors(&self) -> u16 {
+        self.read_u16(0x0E)
+    }
+
+    pub fn set_reserved_sectors(&mut self, value: u16) {
+        self.write_u16(0x0E, value);
+    }
+
+    pub fn num_fats(&self) -> u8 {
+        self.data[0x10]
+    }
+
+    pub fn set_num_fats(&mut self, value: u8) {
+        self.data[0x10] = value;
+    }
+
+    pub fn root_entries(&self) -> u16 {
+        self.read_u16(0x11)
+    }
+
+    pub fn set_root_entries(&mut self, value: u16) {
+        self.write_u16(0x11, value);
+    }
+
+    /// The 16-bit sector count; 0 means the count is in `large_sectors`
+    pub fn total_sectors(&self) -> u16 {
+        self.read_u16(0x13)
+    }
+
+    pub fn set_total_sectors(&mut self, value: u16) {
+        self.write_u16(0x13, value);
+    }
+
+    pub fn media_descriptor(&self) -> u8 {
+        self.data[0x15]
+    }
+
+    pub fn set_media_descriptor(&mut self, value: u8) {
+        self.data[0x15] = value;
+    }
+
+    pub fn sectors_per_fat(&self) -> u16 {
+        self.read_u16(0x16)
+    }
+
+    pub fn set_sectors_per_fat(&mut self, value: u16) {
+        self.write_u16(0x16, value);
+    }
+
+    pub fn sectors_per_track(&self) -> u16 {
+        self.read_u16(0x18)
+    }
+
+    pub fn set_sectors_per_track(&mut self, value: u16) {
+        self.write_u16(0x18, value);
+    }
+
+    pub fn num_heads(&self) -> u16 {
+        self.read_u16(0x1A)
+    }
+
+    pub fn set_num_heads(&mut self, value: u16) {
+        self.write_u16(0x1A, value);
+    }
+
+    pub fn hidden_sectors(&self) -> u32 {
+        self.read_u32(0x1C)
+    }
+
+    pub fn set_hidden_sectors(&mut self, value: u32) {
+        self.write_u32(0x1C, value);
+    }
+
+    pub fn large_sectors(&self) -> u32 {
+        self.read_u32(0x20)
+    }
+
+    pub fn set_large_sectors(&mut self, value: u32) {
+        self.write_u32(0x20, value);
+    }
+
+    /// The two bytes at offset 510; 55 AA on a bootable sector
+    pub fn signature(&self) -> [u8; 2] {
+        [self.data[SECTOR_SIZE - 2], self.data[SECTOR_SIZE - 1]]
+    }
+
+    pub fn set_signature(&mut self, signature: [u8; 2]) {
+        self.data[SECTOR_SIZE - 2..].copy_from_slice(&signature);
+    }
+
+    pub fn has_boot_signature(&self) -> bool {
+        self.signature() == MBR_SIGNATURE
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    fn write_u16(&mut self, offset: usize, value: u16) {
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
 }
 
 #[derive(Debug)]
@@ -49,6 +212,9 @@ pub struct DiskGeometry {
     #[allow(dead_code)]
     pub sectors: u8,
     pub _bytes_per_sector: u16,
+    // Unrecognized image size; the CHS values are only a best-effort
+    // translation and the disk should be addressed by LBA
+    pub lba_only: bool,
 }
 
 impl Default for DiskGeometry {
@@ -58,11 +224,82 @@ impl Default for DiskGeometry {
             heads: 2,
             sectors: 18,
             _bytes_per_sector: 512,
+            lba_only: false,
         }
     }
 }
 
-#[derive(Debug)]
+// Standard PC floppy formats: (image size, cylinders, heads, sectors per track)
+const FLOPPY_FORMATS: [(u64, u16, u8, u8); 8] = [
+    (163_840, 40, 1, 8),    // 160K 5.25"
+    (184_320, 40, 1, 9),    // 180K 5.25"
+    (327_680, 40, 2, 8),    // 320K 5.25"
+    (368_640, 40, 2, 9),    // 360K 5.25"
+    (737_280, 80, 2, 9),    // 720K 3.5"
+    (1_228_800, 80, 2, 15), // 1.2M 5.25"
+    (1_474_560, 80, 2, 18), // 1.44M 3.5"
+    (2_949_120, 80, 2, 36), // 2.88M 3.5"
+];
+
+const FLOPPY_1440K_SIZE: u64 = 1_474_560;
+
+impl DiskGeometry {
+    /// Infers the geometry of a raw image from its size. Known floppy sizes
+    /// get their real C/H/S; anything else is treated as an LBA hard disk
+    /// with the usual 16-head, 63-sector translation.
+    pub fn from_image_size(size: u64) -> Self {
+        if let Some(&(_, cylinders, heads, sectors)) =
+            FLOPPY_FORMATS.iter().find(|format| format.0 == size)
+        {
+            return DiskGeometry {
+                cylinders,
+                heads,
+                sectors,
+                _bytes_per_sector: BYTES_PER_SECTOR,
+                lba_only: false,
+            };
+        }
+
+        DiskGeometry {
+            lba_only: true,
+            ..DiskGeometry::hard_disk(size / SECTOR_SIZE as u64)
+        }
+    }
+
+    /// The 16-head, 63-sector translation for a hard disk of `total_sectors`.
+    /// Cylinders are capped at the 1024 INT 13h can address, so a larger disk
+    /// is only partly reachable through CHS.
+    pub fn hard_disk(total_sectors: u64) -> Self {
+        let per_cylinder = (HEADS_PER_CYLINDER * SECTORS_PER_TRACK) as u64;
+        let cylinders = (total_sectors / per_cylinder).clamp(1, 1024);
+        DiskGeometry {
+            cylinders: cylinders as u16,
+            heads: HEADS_PER_CYLINDER as u8,
+            sectors: SECTORS_PER_TRACK as u8,
+            _bytes_per_sector: BYTES_PER_SECTOR,
+            lba_only: false,
+        }
+    }
+
+    /// Converts a 1-based CHS address to an LBA, or `None` if it lies
+    /// outside this geometry
+    pub fn chs_to_lba(&self, cylinder: u16, head: u8, sector: u8) -> Option<u32> {
+        if sector == 0 || sector > self.sectors || head >= self.heads || cylinder >= self.cylinders
+        {
+            return None;
+        }
+        Some(
+            (cylinder as u32 * self.heads as u32 + head as u32) * self.sectors as u32
+                + (sector - 1) as u32,
+        )
+    }
+
+    pub fn is_floppy(&self) -> bool {
+        !self.lba_only && self.heads <= 2
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct BiosParameterBlock {
     pub _bytes_per_sector: u16,
     pub _sectors_per_cluster: u8,
@@ -80,6 +317,8 @@ pub struct BiosParameterBlock {
 
 #[derive(Debug, PartialEq)]
 pub enum DiskRegion {
+    // Between the MBR and the partition; nothing of the filesystem is here
+    Gap,
     BootSector,
     FAT1,
     FAT2,
@@ -107,11 +346,34 @@ pub struct DiskImage {
     pub root_dir_cache: Vec<u8>,
     #[allow(dead_code)]
     pub data_sector_cache: Vec<u8>,
+    // Host directory whose files are exposed to DOS file services
+    pub fs_root: Option<PathBuf>,
+    // Flat sector contents for RAM disks and loaded host images; None for
+    // the generated C: image
+    pub raw: Option<Vec<u8>>,
+    // Host file a raw image was loaded from
+    pub image_path: Option<PathBuf>,
 }
 
 impl DiskImage {
-    pub fn new(_drive_c_path: &Path) -> io::Result<Self> {
-        let geometry = DiskGeometry::default();
+    /// The generated C: drive. The disk itself lives in memory, so a
+    /// `drive_c_path` that doesn't exist still gives a usable, empty drive;
+    /// only the DOS file services need `<drive_c_path>/fs` on the host, and
+    /// without it they report path not found.
+    pub fn new(drive_c_path: &Path) -> io::Result<Self> {
+        Self::with_boot_sector(drive_c_path, BootSector::new().as_bytes())
+    }
+
+    /// Like `new`, but with a custom partition boot sector, which must be
+    /// exactly one sector long
+    pub fn with_boot_sector(drive_c_path: &Path, boot_sector: &[u8]) -> io::Result<Self> {
+        let boot_sector: [u8; SECTOR_SIZE] = boot_sector.try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid boot sector size: {} bytes", boot_sector.len()),
+            )
+        })?;
+        let geometry = DiskGeometry::hard_disk((BOOT_SECTOR + FAT16_TOTAL_SECTORS) as u64);
 
         // Create MBR with bootable FAT16 partition starting at sector 63
         let boot_code = vec![
@@ -128,11 +390,8 @@ impl DiskImage {
             0xEA, 0x00, 0x06, 0x00, 0x00, // jmp 0:0x0600
         ];
 
-        let mbr = Mbr::create_bootable_fat16_mbr(boot_code)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mbr = Mbr::create_bootable_fat16_mbr(boot_code).map_err(io::Error::other)?;
 
-        // Create boot sector for the FAT16 partition
-        let boot_sector = BootSector::new();
         let bpb = BiosParameterBlock::new(
             FAT16_SECTORS_PER_CLUSTER,
             FAT16_RESERVED_SECTORS,
@@ -163,7 +422,7 @@ impl DiskImage {
         Ok(DiskImage {
             geometry,
             mbr,
-            boot_sector: boot_sector.as_bytes().try_into().unwrap(),
+            boot_sector,
             fat_table,
             root_directory,
             data_sectors,
@@ -173,12 +432,272 @@ impl DiskImage {
             fat_cache: Vec::new(),
             root_dir_cache: Vec::new(),
             data_sector_cache: Vec::new(),
+            fs_root: Some(drive_c_path.join("fs")).filter(|root| root.is_dir()),
+            raw: None,
+            image_path: None,
         })
     }
 
+    /// Creates a scratch disk held entirely in memory: an MBR with a single
+    /// partition at LBA 63, freshly formatted FAT12 or FAT16 depending on
+    /// size. Nothing touches the host and the contents are lost on drop.
+    pub fn ram_disk(size_kb: u32) -> io::Result<Self> {
+        let total_sectors = size_kb as usize * 1024 / SECTOR_SIZE;
+        if total_sectors as u64 * SECTOR_SIZE as u64 >= RAM_DISK_MAX_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("RAM disk too large for FAT16: {} KB", size_kb),
+            ));
+        }
+        if total_sectors <= BOOT_SECTOR as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("RAM disk too small: {} KB", size_kb),
+            ));
+        }
+
+        let mut data = vec![0; total_sectors * SECTOR_SIZE];
+        let partition_start = BOOT_SECTOR as usize * SECTOR_SIZE;
+        let fat_type = {
+            let volume = &mut data[partition_start..];
+            fatfs::format_volume(
+                io::Cursor::new(&mut *volume),
+                fatfs::FormatVolumeOptions::new(),
+            )?;
+            let fs = fatfs::FileSystem::new(io::Cursor::new(volume), fatfs::FsOptions::new())?;
+            fs.fat_type()
+        };
+
+        let partition_sectors = (total_sectors - BOOT_SECTOR as usize) as u32;
+        let mut mbr = Mbr::new();
+        mbr.partitions[0] = PartitionEntry {
+            bootable: true,
+            start_head: 1,
+            start_sector: 1,
+            start_cylinder: 0,
+            system_id: match fat_type {
+                fatfs::FatType::Fat12 => FAT12_SYSTEM_ID,
+                _ => FAT16_SYSTEM_ID,
+            },
+            end_head: (HEADS_PER_CYLINDER - 1) as u8,
+            end_sector: SECTORS_PER_TRACK as u8,
+            end_cylinder: (total_sectors as u32 / (SECTORS_PER_TRACK * HEADS_PER_CYLINDER) as u32)
+                .min(1023) as u16,
+            start_lba: BOOT_SECTOR,
+            total_sectors: partition_sectors,
+        };
+        data[..SECTOR_SIZE].copy_from_slice(&mbr.to_bytes());
+
+        let geometry = DiskGeometry::hard_disk(total_sectors as u64);
+        Ok(Self::from_raw(data, geometry, mbr, BOOT_SECTOR))
+    }
+
+    /// Opens a raw host image, inferring its geometry from the file size.
+    /// Floppy images boot from sector 0; hard disk images from the first
+    /// partition. The image is read into memory, so writes are not saved
+    /// back to the file.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let data = std::fs::read(path)?;
+        if data.is_empty() || data.len() % SECTOR_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: size {} is not a whole number of sectors",
+                    path.display(),
+                    data.len()
+                ),
+            ));
+        }
+
+        let geometry = DiskGeometry::from_image_size(data.len() as u64);
+        let (mbr, boot_lba) = if geometry.is_floppy() {
+            (Mbr::new(), 0)
+        } else {
+            let mbr = Mbr::from_bytes(&data[..SECTOR_SIZE]).map_err(io::Error::other)?;
+            let start = mbr.partitions[0].start_lba;
+            (mbr, start)
+        };
+        println!(
+            "Opened {}: {} sectors, C/H/S {}/{}/{}{}",
+            path.display(),
+            data.len() / SECTOR_SIZE,
+            geometry.cylinders,
+            geometry.heads,
+            geometry.sectors,
+            if geometry.lba_only { " (LBA only)" } else { "" }
+        );
+
+        let mut disk = Self::from_raw(data, geometry, mbr, boot_lba);
+        disk.image_path = Some(path.to_path_buf());
+        Ok(disk)
+    }
+
+    /// Opens a raw 1.44M FAT12 floppy image. There is no MBR: the boot
+    /// sector is LBA 0. Any other size is refused rather than guessed at.
+    pub fn from_floppy_image(path: &Path) -> io::Result<Self> {
+        let size = std::fs::metadata(path)?.len();
+        if size != FLOPPY_1440K_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: size {} is not a 1.44M floppy ({} bytes)",
+                    path.display(),
+                    size,
+                    FLOPPY_1440K_SIZE
+                ),
+            ));
+        }
+        Self::from_path(path)
+    }
+
+    /// A drive with no medium: every sector reads back as zeros
+    pub fn empty() -> Self {
+        Self::from_raw(Vec::new(), DiskGeometry::default(), Mbr::new(), 0)
+    }
+
+    /// False for `empty`, the one kind of drive there is nothing in
+    pub fn has_medium(&self) -> bool {
+        self.raw.as_ref().is_none_or(|raw| !raw.is_empty())
+    }
+
+    fn from_raw(data: Vec<u8>, geometry: DiskGeometry, mbr: Mbr, boot_lba: u32) -> Self {
+        let mut boot_sector = [0u8; SECTOR_SIZE];
+        let boot_start = boot_lba as usize * SECTOR_SIZE;
+        if let Some(sector) = data.get(boot_start..boot_start + SECTOR_SIZE) {
+            boot_sector.copy_from_slice(sector);
+        }
+        let total_sectors = ((data.len() / SECTOR_SIZE) as u32).saturating_sub(boot_lba);
+
+        DiskImage {
+            geometry,
+            mbr,
+            boot_sector,
+            fat_table: Vec::new(),
+            root_directory: Vec::new(),
+            data_sectors: Vec::new(),
+            write_protected: false,
+            _bpb: BiosParameterBlock::new(
+                0,
+                FAT16_RESERVED_SECTORS,
+                FAT16_NUMBER_OF_FATS,
+                0,
+                total_sectors.min(u16::MAX as u32) as u16,
+                FAT16_MEDIA_DESCRIPTOR,
+                0,
+            ),
+            mbr_cache: Vec::new(),
+            fat_cache: Vec::new(),
+            root_dir_cache: Vec::new(),
+            data_sector_cache: Vec::new(),
+            fs_root: None,
+            raw: Some(data),
+            image_path: None,
+        }
+    }
+
+    /// How many sectors the drive holds, as INT 13h AH=15h reports it
+    pub fn sector_count(&self) -> u32 {
+        match &self.raw {
+            Some(raw) => (raw.len() / SECTOR_SIZE) as u32,
+            None => FAT16_TOTAL_SECTORS,
+        }
+    }
+
+    /// The host directory backing the drive's files (`<drive>/fs`), if any
+    pub fn fs_root(&self) -> Option<&Path> {
+        self.fs_root.as_deref()
+    }
+
+    pub fn is_ram_disk(&self) -> bool {
+        self.raw.is_some() && self.image_path.is_none()
+    }
+
+    /// Writes one sector. Raw images (RAM disks and loaded host images)
+    /// take it as is; the generated C: image routes it to the MBR, boot
+    /// sector, FAT, root directory or data area the same way `read_sector`
+    /// does. Both FAT copies share one table, so a write to either shows up
+    /// in the other.
+    pub fn write_sector(&mut self, lba: u32, data: &[u8]) -> io::Result<()> {
+        if data.len() != SECTOR_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid sector size: {} bytes", data.len()),
+            ));
+        }
+        if self.write_protected {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Disk is write protected",
+            ));
+        }
+        match self.raw.as_mut() {
+            Some(raw) => {
+                let offset = lba as usize * SECTOR_SIZE;
+                let sector = raw.get_mut(offset..offset + SECTOR_SIZE).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Sector {} beyond end of disk", lba),
+                    )
+                })?;
+                println!("Writing raw sector {}", lba);
+                sector.copy_from_slice(data);
+                Ok(())
+            }
+            None => self.write_generated_sector(lba, data),
+        }
+    }
+
+    fn write_generated_sector(&mut self, lba: u32, data: &[u8]) -> io::Result<()> {
+        let beyond_end = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Sector {} beyond end of disk", lba),
+            )
+        };
+        if lba >= FAT16_TOTAL_SECTORS {
+            return Err(beyond_end());
+        }
+
+        if lba == 0 {
+            println!("Writing MBR");
+            self.mbr =
+                Mbr::from_bytes(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return Ok(());
+        }
+
+        let region = self.sector_to_region(lba);
+        println!("Writing sector {} in region {:?}", lba, region);
+        let (table, offset) = match region {
+            DiskRegion::Gap => return Err(below_partition(lba)),
+            DiskRegion::BootSector => {
+                self.boot_sector.copy_from_slice(data);
+                return Ok(());
+            }
+            DiskRegion::FAT1 => (&mut self.fat_table, lba - FAT1_START),
+            DiskRegion::FAT2 => (&mut self.fat_table, lba - FAT2_START),
+            DiskRegion::RootDirectory => (&mut self.root_directory, lba - ROOT_DIR_START),
+            DiskRegion::Data => (&mut self.data_sectors, lba - DATA_START),
+        };
+        let offset = offset as usize * SECTOR_SIZE;
+        table
+            .get_mut(offset..offset + SECTOR_SIZE)
+            .ok_or_else(beyond_end)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
     pub fn read_sector(&self, lba: u32) -> Option<Vec<u8>> {
         let mut sector = vec![0; SECTOR_SIZE];
         println!("Reading sector {}", lba);
+
+        if let Some(raw) = &self.raw {
+            // Sectors past the end read back as zeros, like the generated image
+            let offset = lba as usize * SECTOR_SIZE;
+            if let Some(data) = raw.get(offset..offset + SECTOR_SIZE) {
+                sector.copy_from_slice(data);
+            }
+            return Some(sector);
+        }
         println!("FAT table first bytes: {:?}", &self.fat_table[..4]);
 
         // For sectors beyond disk size, return a zeroed sector
@@ -195,6 +714,8 @@ impl DiskImage {
                 let region = self.sector_to_region(lba);
                 println!("Region for sector {}: {:?}", lba, region);
                 match region {
+                    // Reads as zeros, as on a freshly partitioned disk
+                    DiskRegion::Gap => {}
                     DiskRegion::BootSector => {
                         println!("Reading boot sector region");
                         sector.copy_from_slice(&self.boot_sector);
@@ -241,6 +762,8 @@ impl DiskImage {
     fn sector_to_region(&self, sector: u32) -> DiskRegion {
         if sector == 0 || sector == BOOT_SECTOR {
             DiskRegion::BootSector
+        } else if sector < BOOT_SECTOR {
+            DiskRegion::Gap
         } else if (FAT1_START..FAT2_START).contains(&sector) {
             DiskRegion::FAT1
         } else if (FAT2_START..ROOT_DIR_START).contains(&sector) {
@@ -253,17 +776,8 @@ impl DiskImage {
     }
 
     #[allow(dead_code)]
-    fn fat_offset(&self, sector: u32) -> usize {
-        let partition = &self.mbr.partitions[0];
-        let rel_sector = sector
-            .checked_sub(partition.start_lba)
-            .ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Sector outside partition bounds",
-                )
-            })
-            .unwrap_or(0);
+    fn fat_offset(&self, sector: u32) -> io::Result<usize> {
+        let rel_sector = self.partition_relative(sector)?;
 
         let fat_start = if self.sector_to_region(sector) == DiskRegion::FAT1 {
             self._bpb._reserved_sectors as u32
@@ -271,35 +785,160 @@ impl DiskImage {
             self._bpb._reserved_sectors as u32 + self._bpb._sectors_per_fat as u32
         };
 
-        // Check if rel_sector is less than fat_start to avoid underflow
-        if rel_sector < fat_start {
-            0
-        } else {
-            ((rel_sector - fat_start) * self._bpb._bytes_per_sector as u32) as usize
-        }
+        let fat_sector = rel_sector.checked_sub(fat_start).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Sector {} is before the FAT", sector),
+            )
+        })?;
+        Ok((fat_sector * self._bpb._bytes_per_sector as u32) as usize)
     }
 
     #[allow(dead_code)]
-    fn root_dir_offset(&self, sector: u32) -> usize {
-        let partition = &self.mbr.partitions[0];
-        let rel_sector = sector
-            .checked_sub(partition.start_lba)
-            .ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Sector outside partition bounds",
-                )
-            })
-            .unwrap_or(0);
+    fn root_dir_offset(&self, sector: u32) -> io::Result<usize> {
+        let rel_sector = self.partition_relative(sector)?;
 
         let root_dir_start = self._bpb._reserved_sectors as u32
             + (self._bpb._num_fats as u32 * self._bpb._sectors_per_fat as u32);
 
-        if rel_sector < root_dir_start {
+        let dir_sector = rel_sector.checked_sub(root_dir_start).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Sector {} is before the root directory", sector),
+            )
+        })?;
+        Ok((dir_sector * self._bpb._bytes_per_sector as u32) as usize)
+    }
+
+    // `sector` counted from the start of the first partition
+    fn partition_relative(&self, sector: u32) -> io::Result<u32> {
+        sector
+            .checked_sub(self.mbr.partitions[0].start_lba)
+            .ok_or_else(|| below_partition(sector))
+    }
+
+    /// Copies a host file into the root directory as `dos_name`, which
+    /// must be a plain 8.3 name. The guest sees it like any file written
+    /// by DOS: clusters are allocated in the FAT and a directory entry
+    /// points at them. Raw images must hold a FAT filesystem (on the first
+    /// partition for hard disks).
+    pub fn import_file(&mut self, host_path: &Path, dos_name: &str) -> io::Result<()> {
+        let short_name = dos_short_name(dos_name)?;
+        let contents = std::fs::read(host_path)?;
+        // Stamped with the host file's modification time; DOS shows a zero
+        // date as invalid, so the fallback is 1980-01-01 00:00
+        let stamp = std::fs::metadata(host_path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| dos_date_time(modified.into()))
+            .unwrap_or((DOS_EPOCH_DATE, 0));
+        println!(
+            "Importing {} as {} ({} bytes)",
+            host_path.display(),
+            dos_name,
+            contents.len()
+        );
+        if self.write_protected {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Disk is write protected",
+            ));
+        }
+        if self.raw.is_some() {
+            self.import_into_raw(&dos_name.to_ascii_uppercase(), &contents)
+        } else {
+            self.import_into_generated(short_name, &contents, stamp)
+        }
+    }
+
+    // Raw images are real FAT volumes, so fatfs does the work
+    fn import_into_raw(&mut self, dos_name: &str, contents: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+
+        let partition_lba = if self.geometry.is_floppy() {
             0
         } else {
-            ((rel_sector - root_dir_start) * self._bpb._bytes_per_sector as u32) as usize
+            self.mbr.partitions[0].start_lba
+        };
+        let volume = self
+            .raw
+            .as_mut()
+            .and_then(|raw| raw.get_mut(partition_lba as usize * SECTOR_SIZE..))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No FAT volume"))?;
+        let fs = fatfs::FileSystem::new(io::Cursor::new(volume), fatfs::FsOptions::new())?;
+        {
+            let root = fs.root_dir();
+            if root.open_file(dos_name).is_ok() {
+                return Err(already_exists(dos_name));
+            }
+            root.create_file(dos_name)?.write_all(contents)?;
         }
+        fs.unmount()
+    }
+
+    // The generated image keeps its FAT, root directory and data area as
+    // separate tables, so the filesystem is written by hand
+    fn import_into_generated(
+        &mut self,
+        name: [u8; 11],
+        contents: &[u8],
+        (date, time): (u16, u16),
+    ) -> io::Result<()> {
+        let mut entries = self.root_directory.chunks(DIR_ENTRY_SIZE);
+        if entries.clone().any(|entry| entry[..11] == name) {
+            return Err(already_exists(&String::from_utf8_lossy(&name)));
+        }
+        let slot = entries
+            .position(|entry| entry[0] == 0x00 || entry[0] == 0xE5)
+            .ok_or_else(|| io::Error::other("Root directory is full"))?;
+
+        let cluster_size = self._bpb._sectors_per_cluster as usize * SECTOR_SIZE;
+        let cluster_count = (self.fat_table.len() / 2)
+            .min(self.data_sectors.len() / cluster_size + 2)
+            .min(FAT16_FIRST_RESERVED_CLUSTER);
+        let needed = contents.len().div_ceil(cluster_size);
+        let clusters: Vec<usize> = (2..cluster_count)
+            .filter(|&cluster| self.fat_entry(cluster) == 0)
+            .take(needed)
+            .collect();
+        if clusters.len() < needed {
+            return Err(io::Error::other("Disk full"));
+        }
+
+        for (i, (&cluster, chunk)) in clusters
+            .iter()
+            .zip(contents.chunks(cluster_size))
+            .enumerate()
+        {
+            let offset = (cluster - 2) * cluster_size;
+            self.data_sectors[offset..offset + chunk.len()].copy_from_slice(chunk);
+            let next = clusters
+                .get(i + 1)
+                .map_or(FAT16_END_OF_CHAIN, |&n| n as u16);
+            self.fat_table[cluster * 2..cluster * 2 + 2].copy_from_slice(&next.to_le_bytes());
+        }
+
+        let entry = &mut self.root_directory[slot * DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE];
+        entry.fill(0);
+        entry[..11].copy_from_slice(&name);
+        entry[11] = ATTR_ARCHIVE;
+        entry[22..24].copy_from_slice(&time.to_le_bytes());
+        entry[24..26].copy_from_slice(&date.to_le_bytes());
+        let start = clusters.first().map_or(0, |&cluster| cluster as u16);
+        entry[26..28].copy_from_slice(&start.to_le_bytes());
+        entry[28..32].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+        Ok(())
+    }
+
+    fn fat_entry(&self, cluster: usize) -> u16 {
+        u16::from_le_bytes([self.fat_table[cluster * 2], self.fat_table[cluster * 2 + 1]])
+    }
+
+    /// Empties the sector caches. The image contents are untouched.
+    pub fn clear_caches(&mut self) {
+        self.mbr_cache.clear();
+        self.fat_cache.clear();
+        self.root_dir_cache.clear();
+        self.data_sector_cache.clear();
     }
 
     #[allow(dead_code)]
@@ -316,6 +955,43 @@ impl DiskImage {
     }
 }
 
+// The padded 11-byte directory form of an 8.3 name, uppercased
+fn dos_short_name(name: &str) -> io::Result<[u8; 11]> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not an 8.3 file name", name),
+        )
+    };
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    let valid_char = |c: u8| c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&c);
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base.bytes().chain(ext.bytes()).all(valid_char)
+    {
+        return Err(invalid());
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    Ok(short)
+}
+
+fn below_partition(sector: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Sector {} is below the partition start", sector),
+    )
+}
+
+fn already_exists(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} is already on the disk", name.trim()),
+    )
+}
+
 impl BiosParameterBlock {
     pub fn new(
         sectors_per_cluster: u8,
@@ -344,6 +1020,10 @@ impl BiosParameterBlock {
 
     #[allow(dead_code)]
     pub fn into_bytes(self) -> [u8; 27] {
+        self.to_bytes()
+    }
+
+    pub fn to_bytes(&self) -> [u8; 27] {
         let mut bytes = [0u8; 27];
         bytes[0..2].copy_from_slice(&self._bytes_per_sector.to_le_bytes());
         bytes[2] = self._sectors_per_cluster;
@@ -359,12 +1039,42 @@ impl BiosParameterBlock {
         bytes[21..25].copy_from_slice(&self._large_sectors.to_le_bytes());
         bytes
     }
+
+    /// The inverse of `to_bytes`; `bytes` must hold at least the 25 BPB bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < BPB_SIZE {
+            return Err(format!("Invalid BPB data size: {} bytes", bytes.len()));
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        Ok(BiosParameterBlock {
+            _bytes_per_sector: u16_at(0),
+            _sectors_per_cluster: bytes[2],
+            _reserved_sectors: u16_at(3),
+            _num_fats: bytes[5],
+            _root_entries: u16_at(6),
+            _total_sectors: u16_at(8),
+            _media_descriptor: bytes[10],
+            _sectors_per_fat: u16_at(11),
+            _sectors_per_track: u16_at(13),
+            _num_heads: u16_at(15),
+            _hidden_sectors: u32_at(17),
+            _large_sectors: u32_at(21),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::disk::{FAT16_SYSTEM_ID, MBR_SIGNATURE};
+    use crate::disk::MBR_SIGNATURE;
     use std::path::PathBuf;
 
     #[test]
@@ -381,6 +1091,59 @@ mod tests {
         assert_eq!(bytes[SECTOR_SIZE - 3], 0x4);
     }
 
+    #[test]
+    fn test_wrong_sized_boot_sector_is_an_error() {
+        let path = PathBuf::from("drive_c");
+        let err = DiskImage::with_boot_sector(&path, &[0u8; 100]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut custom = [0u8; SECTOR_SIZE];
+        custom[0] = 0xEB;
+        let disk = DiskImage::with_boot_sector(&path, &custom).unwrap();
+        assert_eq!(disk.boot_sector, custom);
+    }
+
+    #[test]
+    fn test_boot_sector_bpb_round_trip() {
+        // A 1.44M floppy BPB
+        let mut bpb = BiosParameterBlock::new(1, 1, 2, 224, 2880, 0xF0, 9);
+        bpb._sectors_per_track = 18;
+        bpb._num_heads = 2;
+
+        let mut boot_sector = BootSector::new();
+        boot_sector.set_bpb(&bpb);
+        boot_sector.set_hidden_sectors(0x12345678);
+        bpb._hidden_sectors = 0x12345678;
+
+        // Fields land at their standard offsets
+        let bytes = boot_sector.as_bytes().to_vec();
+        assert_eq!(&bytes[0x0B..0x0D], &[0x00, 0x02]);
+        assert_eq!(bytes[0x0D], 1);
+        assert_eq!(&bytes[0x13..0x15], &2880u16.to_le_bytes());
+        assert_eq!(bytes[0x15], 0xF0);
+        assert_eq!(&bytes[0x1C..0x20], &[0x78, 0x56, 0x34, 0x12]);
+
+        let parsed = BootSector::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.parse_bpb().unwrap(), bpb);
+        assert_eq!(parsed.bytes_per_sector(), 512);
+        assert_eq!(parsed.root_entries(), 224);
+        assert_eq!(parsed.sectors_per_fat(), 9);
+        assert_eq!(parsed.sectors_per_track(), 18);
+        assert_eq!(parsed.num_heads(), 2);
+        assert!(parsed.has_boot_signature());
+        assert_eq!(parsed.as_bytes(), &bytes[..]);
+
+        let mut unsigned = parsed.clone();
+        unsigned.set_signature([0, 0]);
+        unsigned.set_total_sectors(0);
+        unsigned.set_large_sectors(70_000);
+        assert!(!unsigned.has_boot_signature());
+        assert_eq!(unsigned.parse_bpb().unwrap()._large_sectors, 70_000);
+
+        assert!(BootSector::from_bytes(&bytes[..511]).is_err());
+        assert!(BiosParameterBlock::from_bytes(&bytes[BPB_OFFSET..BPB_OFFSET + 24]).is_err());
+    }
+
     #[test]
     fn test_disk_geometry_default() {
         let geometry = DiskGeometry::default();
@@ -453,6 +1216,29 @@ mod tests {
         assert_eq!(disk_image.sector_to_region(DATA_START), DiskRegion::Data);
     }
 
+    #[test]
+    fn test_sectors_below_the_partition_are_not_the_filesystem() {
+        let mut disk = DiskImage::new(Path::new("drive_c")).unwrap();
+        let fat_before = disk.fat_table.clone();
+        let data_before = disk.read_sector(DATA_START).unwrap();
+
+        for lba in [1, 10, BOOT_SECTOR - 1] {
+            assert_eq!(disk.sector_to_region(lba), DiskRegion::Gap);
+            let err = disk.write_sector(lba, &[0xAA; SECTOR_SIZE]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(disk.read_sector(lba).unwrap(), vec![0; SECTOR_SIZE]);
+        }
+        assert_eq!(disk.fat_table, fat_before);
+        assert_eq!(disk.read_sector(DATA_START).unwrap(), data_before);
+
+        // The offset helpers refuse them too rather than answering 0
+        assert!(disk.fat_offset(10).is_err());
+        assert!(disk.root_dir_offset(10).is_err());
+        assert_eq!(disk.fat_offset(FAT1_START + 1).unwrap(), SECTOR_SIZE);
+        assert_eq!(disk.root_dir_offset(ROOT_DIR_START).unwrap(), 0);
+        assert!(disk.root_dir_offset(FAT2_START).is_err());
+    }
+
     #[test]
     fn test_bios_parameter_block() {
         let bpb = BiosParameterBlock::new(
@@ -502,4 +1288,208 @@ mod tests {
         assert_eq!(u16::from_le_bytes([bytes[6], bytes[7]]), FAT16_ROOT_ENTRIES);
         assert_eq!(bytes[10], FAT16_MEDIA_DESCRIPTOR);
     }
+
+    #[test]
+    fn test_new_with_missing_directory_gives_empty_disk() {
+        let path = std::env::temp_dir().join(format!("dos_emu_missing_{}", std::process::id()));
+        assert!(!path.exists());
+        let mut disk = DiskImage::new(&path).unwrap();
+        assert!(disk.has_medium());
+        assert!(disk.fs_root().is_none());
+        // Nothing was created on the host
+        assert!(!path.exists());
+
+        let mbr = disk.read_sector(0).unwrap();
+        assert_eq!(&mbr[SECTOR_SIZE - 2..], &MBR_SIGNATURE);
+        let root = disk.read_sector(ROOT_DIR_START).unwrap();
+        assert!(root.iter().all(|&b| b == 0));
+        let entry = vec![0x41; SECTOR_SIZE];
+        disk.write_sector(DATA_START, &entry).unwrap();
+        assert_eq!(disk.read_sector(DATA_START).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_generated_disk_write_sector_mirrors_fat() {
+        let path = PathBuf::from("drive_c");
+        let mut disk = DiskImage::new(&path).unwrap();
+
+        let mut sector = disk.read_sector(FAT1_START).unwrap();
+        sector[4] = 0xFF;
+        sector[5] = 0xFF;
+        disk.write_sector(FAT1_START, &sector).unwrap();
+        assert_eq!(disk.read_sector(FAT1_START).unwrap(), sector);
+        assert_eq!(disk.read_sector(FAT2_START).unwrap(), sector);
+
+        let entry = vec![0x41; SECTOR_SIZE];
+        disk.write_sector(ROOT_DIR_START, &entry).unwrap();
+        assert_eq!(disk.read_sector(ROOT_DIR_START).unwrap(), entry);
+        disk.write_sector(DATA_START + 1, &entry).unwrap();
+        assert_eq!(disk.read_sector(DATA_START + 1).unwrap(), entry);
+        assert!(disk.write_sector(FAT16_TOTAL_SECTORS, &entry).is_err());
+
+        disk.write_protected = true;
+        let err = disk.write_sector(FAT1_START, &entry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(disk.read_sector(FAT1_START).unwrap(), sector);
+    }
+
+    #[test]
+    fn test_ram_disk_read_write_sector() {
+        let mut disk = DiskImage::ram_disk(1024).unwrap();
+        assert!(disk.is_ram_disk());
+        assert!(disk.fs_root().is_none());
+
+        // MBR points at a formatted partition
+        let mbr = disk.read_sector(0).unwrap();
+        assert_eq!(&mbr[SECTOR_SIZE - 2..], &MBR_SIGNATURE);
+        assert_eq!(disk.mbr.partitions[0].start_lba, 63);
+        assert_eq!(disk.mbr.partitions[0].system_id, FAT12_SYSTEM_ID);
+        let boot = disk.read_sector(63).unwrap();
+        assert_eq!(&boot[SECTOR_SIZE - 2..], &[0x55, 0xAA]);
+        assert_eq!(&boot[0x36..0x3B], b"FAT12");
+
+        let sector: Vec<u8> = (0..SECTOR_SIZE).map(|i| i as u8).collect();
+        disk.write_sector(100, &sector).unwrap();
+        assert_eq!(disk.read_sector(100).unwrap(), sector);
+
+        assert!(disk.write_sector(2048, &sector).is_err());
+        assert_eq!(disk.read_sector(2048).unwrap(), vec![0; SECTOR_SIZE]);
+
+        let fat16 = DiskImage::ram_disk(8 * 1024).unwrap();
+        assert_eq!(fat16.mbr.partitions[0].system_id, FAT16_SYSTEM_ID);
+        assert!(DiskImage::ram_disk(16).is_err());
+    }
+
+    fn write_image(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("dos_emu_{}_{}.img", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_import_file_into_generated_image() {
+        // Two 32K clusters' worth, so the chain has a link in it
+        let contents: Vec<u8> = (0..40_000u32).map(|i| i as u8).collect();
+        let host = write_image("import", &contents);
+        let mut disk = DiskImage::new(Path::new("drive_c")).unwrap();
+        disk.import_file(&host, "io.sys").unwrap();
+
+        let root = disk.read_sector(ROOT_DIR_START).unwrap();
+        assert_eq!(&root[..11], b"IO      SYS");
+        assert_eq!(root[11], ATTR_ARCHIVE);
+        // Dated like the host file
+        let modified = std::fs::metadata(&host).unwrap().modified().unwrap();
+        let (date, time) = dos_date_time(modified.into());
+        assert_eq!(u16::from_le_bytes([root[22], root[23]]), time);
+        assert_eq!(u16::from_le_bytes([root[24], root[25]]), date);
+        assert!(date >= DOS_EPOCH_DATE);
+        assert_eq!(u16::from_le_bytes([root[26], root[27]]), 2);
+        assert_eq!(u32::from_le_bytes(root[28..32].try_into().unwrap()), 40_000);
+        assert_eq!(disk.fat_entry(2), 3);
+        assert_eq!(disk.fat_entry(3), FAT16_END_OF_CHAIN);
+        // FAT2 is the same table
+        let fat2 = disk.read_sector(FAT2_START).unwrap();
+        assert_eq!(&fat2[4..8], &[0x03, 0x00, 0xFF, 0xFF]);
+
+        // Cluster 2 is the first data sector, cluster 3 the 64th after it
+        assert_eq!(disk.read_data_sector(0).unwrap(), &contents[..SECTOR_SIZE]);
+        let cluster3 = disk.read_sector(DATA_START + 64).unwrap();
+        assert_eq!(&cluster3[..], &contents[32_768..32_768 + SECTOR_SIZE]);
+
+        // A second file goes in the next slot and the next free cluster
+        disk.import_file(&host, "COMMAND.COM").unwrap();
+        let root = disk.read_sector(ROOT_DIR_START).unwrap();
+        assert_eq!(&root[32..43], b"COMMAND COM");
+        assert_eq!(u16::from_le_bytes([root[58], root[59]]), 4);
+
+        let err = disk.import_file(&host, "IO.SYS").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        let err = disk.import_file(&host, "TOOLONGNAME").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(&host).unwrap();
+    }
+
+    #[test]
+    fn test_import_file_into_ram_disk() {
+        use std::io::Read;
+
+        let host = write_image("import_raw", b"ECHO HELLO\r\n");
+        let mut disk = DiskImage::ram_disk(1024).unwrap();
+        disk.import_file(&host, "autoexec.bat").unwrap();
+        std::fs::remove_file(&host).unwrap();
+
+        let raw = disk.raw.as_mut().unwrap();
+        let volume = &mut raw[BOOT_SECTOR as usize * SECTOR_SIZE..];
+        let fs = fatfs::FileSystem::new(io::Cursor::new(volume), fatfs::FsOptions::new()).unwrap();
+        let mut text = String::new();
+        fs.root_dir()
+            .open_file("AUTOEXEC.BAT")
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "ECHO HELLO\r\n");
+    }
+
+    #[test]
+    fn test_from_path_detects_floppy_geometry() {
+        for (size, chs) in [
+            (368_640, (40, 2, 9)),
+            (737_280, (80, 2, 9)),
+            (1_228_800, (80, 2, 15)),
+            (1_474_560, (80, 2, 18)),
+        ] {
+            let mut data = vec![0u8; size];
+            data[SECTOR_SIZE - 2] = 0x55;
+            data[SECTOR_SIZE - 1] = 0xAA;
+            let path = write_image(&format!("floppy_{}", size), &data);
+            let disk = DiskImage::from_path(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let geometry = &disk.geometry;
+            assert_eq!((geometry.cylinders, geometry.heads, geometry.sectors), chs);
+            assert!(!geometry.lba_only);
+            assert!(!disk.is_ram_disk());
+            // Floppies boot straight from sector 0
+            assert_eq!(&disk.boot_sector[SECTOR_SIZE - 2..], &[0x55, 0xAA]);
+        }
+    }
+
+    #[test]
+    fn test_from_floppy_image_only_takes_1440k() {
+        let path = write_image("floppy_720k", &vec![0u8; 737_280]);
+        assert!(DiskImage::from_floppy_image(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let path = write_image("floppy_1440k", &vec![0u8; 1_474_560]);
+        let disk = DiskImage::from_floppy_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(disk.geometry.is_floppy());
+        assert_eq!(disk.geometry.sectors, 18);
+    }
+
+    #[test]
+    fn test_from_path_unknown_size_is_lba_hard_disk() {
+        let mut data = vec![0u8; 10 * 1024 * 1024];
+        let mut mbr = Mbr::new();
+        mbr.partitions[0].start_lba = 63;
+        mbr.partitions[0].system_id = FAT16_SYSTEM_ID;
+        data[..SECTOR_SIZE].copy_from_slice(&mbr.to_bytes());
+        data[63 * SECTOR_SIZE] = 0xEB;
+        let path = write_image("hard_disk", &data);
+        let disk = DiskImage::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(disk.geometry.lba_only);
+        assert_eq!(disk.geometry.heads, 16);
+        assert_eq!(disk.geometry.sectors, 63);
+        assert_eq!(disk.geometry.cylinders, 20);
+        assert_eq!(disk.mbr.partitions[0].start_lba, 63);
+        assert_eq!(disk.boot_sector[0], 0xEB);
+        assert_eq!(disk.read_sector(63).unwrap()[0], 0xEB);
+
+        let path = write_image("ragged", &[0u8; 1000]);
+        assert!(DiskImage::from_path(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -12,6 +12,9 @@ pub const PARTITION_ENTRY_SIZE: usize = 16;
 pub const NUM_PARTITIONS: usize = 4;
 pub const PARTITION_TABLE_OFFSET: usize = 446;
 
+// FAT12 constants
+pub const FAT12_SYSTEM_ID: u8 = 0x01; // FAT12 partition type
+
 // FAT16 constants
 pub const FAT16_MEDIA_DESCRIPTOR: u8 = 0xF8; // Fixed disk
 pub const FAT16_SYSTEM_ID: u8 = 0x06; // FAT16 partition type
@@ -0,0 +1,1351 @@
+// DOS services (INT 21h) implemented natively for running programs without
+// a DOS kernel on the disk. Files are served from the drive's host `fs/`
+// directory. Only installed on request via `install_dos_services`, so a
+// real DOS booted from disk keeps its own INT 21h.
+
+use crate::bios::{bios_seg, iret_offset, set_interrupt_vector, tty_output};
+use crate::cpu::Cpu;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+// DOS error codes returned in AX with CF set
+const DOS_ERR_INVALID_FUNCTION: u16 = 0x01;
+const DOS_ERR_FILE_NOT_FOUND: u16 = 0x02;
+const DOS_ERR_PATH_NOT_FOUND: u16 = 0x03;
+const DOS_ERR_TOO_MANY_OPEN_FILES: u16 = 0x04;
+const DOS_ERR_ACCESS_DENIED: u16 = 0x05;
+const DOS_ERR_INVALID_HANDLE: u16 = 0x06;
+const DOS_ERR_ARENA_TRASHED: u16 = 0x07;
+const DOS_ERR_INSUFFICIENT_MEMORY: u16 = 0x08;
+const DOS_ERR_INVALID_BLOCK: u16 = 0x09;
+const DOS_ERR_INVALID_ACCESS: u16 = 0x0C;
+const DOS_ERR_NO_MORE_FILES: u16 = 0x12;
+
+// File attribute bits
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+
+// Layout of the find block FindFirst/FindNext write to the DTA
+const FIND_ATTRIBUTE: u16 = 0x15;
+const FIND_TIME: u16 = 0x16;
+const FIND_DATE: u16 = 0x18;
+const FIND_SIZE: u16 = 0x1A;
+const FIND_NAME: u16 = 0x1E;
+
+// Handles 0-4 are the standard devices (stdin, stdout, stderr, aux, prn)
+const FIRST_FILE_HANDLE: u16 = 5;
+const MAX_FILE_HANDLES: u16 = 20;
+
+// Program segment prefix layout
+const PSP_SIZE: u16 = 0x100;
+const PSP_COMMAND_TAIL: u16 = 0x80;
+// Tail bytes fit between the length byte and the end of the PSP, leaving
+// room for the terminating carriage return
+const PSP_MAX_TAIL: usize = 126;
+// First segment past conventional memory, stored at PSP:0002
+const PSP_MEMORY_TOP: u16 = 0xA000;
+const COM_MAX_SIZE: usize = 0xFF00 - 2; // Leave room for the initial stack word
+
+// Memory control block: a one-paragraph header in front of every block,
+// 'M' for all but the last block in the chain, which is 'Z'
+const MCB_MEMBER: u8 = b'M';
+const MCB_LAST: u8 = b'Z';
+const MCB_OWNER: u16 = 0x01;
+const MCB_SIZE: u16 = 0x03;
+// Owner values: free, or owned by DOS itself when no program is loaded
+const MCB_OWNER_FREE: u16 = 0x0000;
+const MCB_OWNER_DOS: u16 = 0x0008;
+
+struct FoundEntry {
+    name: String, // 8.3 name as DOS sees it, e.g. "README.TXT"
+    attributes: u8,
+    size: u32,
+    time: u16,
+    date: u16,
+}
+
+// Matches left for the active FindFirst/FindNext search
+struct FindState {
+    entries: Vec<FoundEntry>,
+    next: usize,
+}
+
+#[derive(Default)]
+pub struct DosState {
+    files: BTreeMap<u16, File>,
+    // Disk transfer area (segment, offset)
+    pub dta: (u16, u16),
+    find: Option<FindState>,
+    // What an AH=0Ah call has collected while it waits for the rest of
+    // the line
+    line: Option<Vec<u8>>,
+    // Segment of the first memory control block, once there is an arena
+    first_mcb: Option<u16>,
+    // PSP of the running program, which owns the blocks it allocates
+    psp: u16,
+}
+
+impl DosState {
+    pub fn new() -> Self {
+        DosState {
+            files: BTreeMap::new(),
+            dta: (0x0000, 0x0080),
+            find: None,
+            line: None,
+            first_mcb: None,
+            psp: MCB_OWNER_DOS,
+        }
+    }
+
+    pub fn open_file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    fn allocate_handle(&mut self, file: File) -> Option<u16> {
+        let handle = (FIRST_FILE_HANDLE..MAX_FILE_HANDLES).find(|h| !self.files.contains_key(h))?;
+        self.files.insert(handle, file);
+        Some(handle)
+    }
+}
+
+/// Points INT 21h at the native DOS services
+pub fn install_dos_services(cpu: &mut Cpu) {
+    set_interrupt_vector(cpu, 0x21, bios_seg(), iret_offset());
+}
+
+/// Loads a .COM image at `segment:0100` behind a freshly built PSP and
+/// points every segment register at it, ready to run from CS:IP.
+pub fn load_com(
+    cpu: &mut Cpu,
+    image: &[u8],
+    segment: u16,
+    command_line: &str,
+) -> Result<(), String> {
+    if image.len() > COM_MAX_SIZE {
+        return Err(format!(
+            "COM image too large: {} bytes (max {})",
+            image.len(),
+            COM_MAX_SIZE
+        ));
+    }
+    build_psp(cpu, segment, command_line)?;
+    for (i, &byte) in image.iter().enumerate() {
+        let addr = cpu.get_physical_address(segment, PSP_SIZE + i as u16);
+        cpu.memory.write_byte(addr, byte);
+    }
+
+    cpu.regs.cs = segment;
+    cpu.regs.ds = segment;
+    cpu.regs.es = segment;
+    cpu.regs.ss = segment;
+    cpu.regs.ip = PSP_SIZE;
+    cpu.regs.sp = 0xFFFE;
+    // A near RET from the program lands on the INT 20h at PSP:0000
+    let addr = cpu.get_physical_address(segment, cpu.regs.sp);
+    cpu.memory.write_word(addr, 0x0000);
+
+    cpu.dos.dta = (segment, PSP_COMMAND_TAIL);
+    // Like DOS, give the program every paragraph up to the top of memory;
+    // it has to shrink its block with AH=4Ah before allocating more
+    let mcb = segment.wrapping_sub(1);
+    init_memory_arena(cpu, mcb, PSP_MEMORY_TOP);
+    cpu.dos.psp = segment;
+    write_mcb(
+        cpu,
+        &Mcb {
+            segment: mcb,
+            last: true,
+            owner: segment,
+            size: PSP_MEMORY_TOP - segment,
+        },
+    );
+    Ok(())
+}
+
+fn build_psp(cpu: &mut Cpu, segment: u16, command_line: &str) -> Result<(), String> {
+    let tail = command_line.as_bytes();
+    if tail.len() > PSP_MAX_TAIL {
+        return Err(format!(
+            "Command line too long: {} bytes (max {})",
+            tail.len(),
+            PSP_MAX_TAIL
+        ));
+    }
+
+    let mut psp = [0u8; PSP_SIZE as usize];
+    psp[0x00] = 0xCD; // INT 20h
+    psp[0x01] = 0x20;
+    psp[0x02..0x04].copy_from_slice(&PSP_MEMORY_TOP.to_le_bytes());
+    let tail_start = PSP_COMMAND_TAIL as usize;
+    psp[tail_start] = tail.len() as u8;
+    psp[tail_start + 1..tail_start + 1 + tail.len()].copy_from_slice(tail);
+    psp[tail_start + 1 + tail.len()] = 0x0D;
+
+    for (i, &byte) in psp.iter().enumerate() {
+        let addr = cpu.get_physical_address(segment, i as u16);
+        cpu.memory.write_byte(addr, byte);
+    }
+    Ok(())
+}
+
+pub fn handle_dos_interrupt(cpu: &mut Cpu) -> Result<(), String> {
+    let result = match cpu.regs.get_ah() {
+        // Character input without echo; 07h skips the Ctrl-C check, which
+        // makes no difference here
+        0x07 | 0x08 => dos_read_char(cpu),
+        0x0A => dos_buffered_input(cpu),
+        0x1A => {
+            // Set DTA to DS:DX
+            cpu.dos.dta = (cpu.regs.ds, cpu.regs.dx);
+            Ok(())
+        }
+        0x2A => dos_get_date(cpu),
+        0x2C => dos_get_time(cpu),
+        0x2F => {
+            // Get DTA in ES:BX
+            cpu.regs.es = cpu.dos.dta.0;
+            cpu.regs.bx = cpu.dos.dta.1;
+            Ok(())
+        }
+        0x3D => dos_open(cpu),
+        0x3E => dos_close(cpu),
+        0x3F => dos_read(cpu),
+        0x40 => dos_write(cpu),
+        0x42 => dos_seek(cpu),
+        0x48 => dos_allocate(cpu),
+        0x49 => dos_free(cpu),
+        0x4A => dos_resize(cpu),
+        0x4E => dos_find_first(cpu),
+        0x4F => dos_find_next(cpu),
+        _ => {
+            return Err(format!(
+                "Unhandled DOS function: {:#04X}",
+                cpu.regs.get_ah()
+            ))
+        }
+    };
+
+    match result {
+        Ok(()) => cpu.regs.flags.set_carry(false),
+        Err(code) => {
+            cpu.regs.ax = code;
+            cpu.regs.flags.set_carry(true);
+        }
+    }
+    Ok(())
+}
+
+/// Starts a memory arena of one free block with its MCB at `first_mcb`,
+/// running up to (not including) `end_segment`, for AH=48h/49h/4Ah to
+/// allocate from
+pub fn init_memory_arena(cpu: &mut Cpu, first_mcb: u16, end_segment: u16) {
+    write_mcb(
+        cpu,
+        &Mcb {
+            segment: first_mcb,
+            last: true,
+            owner: MCB_OWNER_FREE,
+            size: end_segment.saturating_sub(first_mcb).saturating_sub(1),
+        },
+    );
+    cpu.dos.first_mcb = Some(first_mcb);
+}
+
+#[derive(Clone, Copy)]
+struct Mcb {
+    segment: u16,
+    last: bool,
+    owner: u16,
+    // In paragraphs, not counting the MCB itself
+    size: u16,
+}
+
+impl Mcb {
+    fn is_free(&self) -> bool {
+        self.owner == MCB_OWNER_FREE
+    }
+
+    // The segment of the block's first paragraph, as returned to programs
+    fn block(&self) -> u16 {
+        self.segment.wrapping_add(1)
+    }
+}
+
+fn read_mcb(cpu: &Cpu, segment: u16) -> Result<Mcb, u16> {
+    let last = match cpu.peek_byte(segment, 0) {
+        MCB_MEMBER => false,
+        MCB_LAST => true,
+        _ => return Err(DOS_ERR_ARENA_TRASHED),
+    };
+    Ok(Mcb {
+        segment,
+        last,
+        owner: cpu.peek_word(segment, MCB_OWNER),
+        size: cpu.peek_word(segment, MCB_SIZE),
+    })
+}
+
+fn write_mcb(cpu: &mut Cpu, mcb: &Mcb) {
+    let signature = if mcb.last { MCB_LAST } else { MCB_MEMBER };
+    cpu.poke_byte(mcb.segment, 0, signature);
+    cpu.poke_word(mcb.segment, MCB_OWNER, mcb.owner);
+    cpu.poke_word(mcb.segment, MCB_SIZE, mcb.size);
+}
+
+fn mcb_chain(cpu: &Cpu) -> Result<Vec<Mcb>, u16> {
+    let mut segment = cpu.dos.first_mcb.ok_or(DOS_ERR_INSUFFICIENT_MEMORY)?;
+    let mut chain = Vec::new();
+    loop {
+        let mcb = read_mcb(cpu, segment)?;
+        chain.push(mcb);
+        if mcb.last {
+            return Ok(chain);
+        }
+        // A chain that runs off the end of memory has been overwritten
+        segment = segment
+            .checked_add(mcb.size)
+            .and_then(|s| s.checked_add(1))
+            .ok_or(DOS_ERR_ARENA_TRASHED)?;
+    }
+}
+
+// Joins runs of adjacent free blocks, as DOS does before searching
+fn merge_free_blocks(cpu: &mut Cpu) -> Result<Vec<Mcb>, u16> {
+    let mut merged: Vec<Mcb> = Vec::new();
+    for mcb in mcb_chain(cpu)? {
+        match merged.last_mut() {
+            Some(previous) if previous.is_free() && mcb.is_free() => {
+                // The last block's size isn't bounded by the chain walk
+                previous.size = previous
+                    .size
+                    .checked_add(mcb.size)
+                    .and_then(|size| size.checked_add(1))
+                    .ok_or(DOS_ERR_ARENA_TRASHED)?;
+                previous.last = mcb.last;
+                write_mcb(cpu, previous);
+            }
+            _ => merged.push(mcb),
+        }
+    }
+    Ok(merged)
+}
+
+// Cuts `mcb` down to `size` paragraphs, leaving the rest as a free block
+// behind it
+fn split_block(cpu: &mut Cpu, mcb: &mut Mcb, size: u16) {
+    if mcb.size > size {
+        let rest = Mcb {
+            segment: mcb.block().wrapping_add(size),
+            last: mcb.last,
+            owner: MCB_OWNER_FREE,
+            size: mcb.size - size - 1,
+        };
+        write_mcb(cpu, &rest);
+        mcb.last = false;
+        mcb.size = size;
+    }
+    write_mcb(cpu, mcb);
+}
+
+// The chain index of the block a program refers to by its segment in ES
+fn find_block(chain: &[Mcb], segment: u16) -> Result<usize, u16> {
+    chain
+        .iter()
+        .position(|mcb| mcb.block() == segment)
+        .ok_or(DOS_ERR_INVALID_BLOCK)
+}
+
+// BX paragraphs, first fit; the segment comes back in AX, or the largest
+// free block in BX when nothing is big enough
+fn dos_allocate(cpu: &mut Cpu) -> Result<(), u16> {
+    let wanted = cpu.regs.bx;
+    let chain = merge_free_blocks(cpu)?;
+    match chain.iter().find(|mcb| mcb.is_free() && mcb.size >= wanted) {
+        Some(&mcb) => {
+            let mut mcb = mcb;
+            mcb.owner = cpu.dos.psp;
+            split_block(cpu, &mut mcb, wanted);
+            cpu.regs.ax = mcb.block();
+            Ok(())
+        }
+        None => {
+            let largest = chain.iter().filter(|mcb| mcb.is_free()).map(|mcb| mcb.size);
+            cpu.regs.bx = largest.max().unwrap_or(0);
+            Err(DOS_ERR_INSUFFICIENT_MEMORY)
+        }
+    }
+}
+
+// Frees the block at ES
+fn dos_free(cpu: &mut Cpu) -> Result<(), u16> {
+    let chain = mcb_chain(cpu)?;
+    let mut mcb = chain[find_block(&chain, cpu.regs.es)?];
+    mcb.owner = MCB_OWNER_FREE;
+    write_mcb(cpu, &mcb);
+    Ok(())
+}
+
+// Resizes the block at ES to BX paragraphs, growing into a free block
+// after it if need be; on failure BX holds the most it could grow to
+fn dos_resize(cpu: &mut Cpu) -> Result<(), u16> {
+    let wanted = cpu.regs.bx;
+    let chain = merge_free_blocks(cpu)?;
+    let index = find_block(&chain, cpu.regs.es)?;
+    let mut mcb = chain[index];
+    let next = chain.get(index + 1).filter(|next| next.is_free());
+    let available = match next {
+        Some(next) => mcb
+            .size
+            .checked_add(next.size)
+            .and_then(|size| size.checked_add(1))
+            .ok_or(DOS_ERR_ARENA_TRASHED)?,
+        None => mcb.size,
+    };
+    if wanted > available {
+        cpu.regs.bx = available;
+        return Err(DOS_ERR_INSUFFICIENT_MEMORY);
+    }
+    if wanted > mcb.size {
+        // Only possible with a free block behind this one to absorb
+        if let Some(next) = next {
+            mcb.size = available;
+            mcb.last = next.last;
+        }
+    }
+    split_block(cpu, &mut mcb, wanted);
+    Ok(())
+}
+
+// Year in CX, month in DH, day in DL and day of the week (0 = Sunday) in AL
+fn dos_get_date(cpu: &mut Cpu) -> Result<(), u16> {
+    let now = cpu.clock.now();
+    cpu.regs.set_cx(now.year() as u16);
+    cpu.regs.set_dh(now.month() as u8);
+    cpu.regs.set_dl(now.day() as u8);
+    cpu.regs.set_al(now.weekday().num_days_from_sunday() as u8);
+    Ok(())
+}
+
+// Hours in CH, minutes in CL, seconds in DH and hundredths in DL
+fn dos_get_time(cpu: &mut Cpu) -> Result<(), u16> {
+    let now = cpu.clock.now();
+    cpu.regs.set_ch(now.hour() as u8);
+    cpu.regs.set_cl(now.minute() as u8);
+    cpu.regs.set_dh(now.second() as u8);
+    // A leap second shows up as nanoseconds past 1e9
+    cpu.regs
+        .set_dl((now.nanosecond() / 10_000_000).min(99) as u8);
+    Ok(())
+}
+
+// Console input is the serial line, the same source INT 14h reads
+fn console_input(cpu: &mut Cpu) -> Option<u8> {
+    cpu.serial.take_input()
+}
+
+// With nothing typed yet a call is retried rather than blocking the host:
+// the return address INT 21h pushed is moved back over the 2-byte
+// instruction, so the guest issues it again after the IRET and pending
+// IRQs get their turn in between
+fn retry_when_input_arrives(cpu: &mut Cpu) {
+    let addr = cpu.get_physical_address(cpu.regs.ss, cpu.regs.sp);
+    let ip = cpu.memory.read_word(addr);
+    cpu.memory.write_word(addr, ip.wrapping_sub(2));
+}
+
+// AH=07h/08h: next input character in AL
+fn dos_read_char(cpu: &mut Cpu) -> Result<(), u16> {
+    match console_input(cpu) {
+        Some(byte) => cpu.regs.set_al(byte),
+        None => retry_when_input_arrives(cpu),
+    }
+    Ok(())
+}
+
+// AH=0Ah: reads an echoed line into the buffer at DS:DX. Byte 0 holds the
+// buffer size, including the carriage return that ends the line; the
+// length without it comes back in byte 1 and the text from byte 2 on.
+// Backspace takes back the last character, and once the buffer is full
+// anything but Enter and backspace is refused with a beep.
+fn dos_buffered_input(cpu: &mut Cpu) -> Result<(), u16> {
+    let (segment, offset) = (cpu.regs.ds, cpu.regs.dx);
+    let max = cpu
+        .memory
+        .read_byte(cpu.get_physical_address(segment, offset)) as usize;
+    if max == 0 {
+        return Ok(());
+    }
+
+    let mut line = cpu.dos.line.take().unwrap_or_default();
+    loop {
+        let Some(byte) = console_input(cpu) else {
+            cpu.dos.line = Some(line);
+            retry_when_input_arrives(cpu);
+            return Ok(());
+        };
+        match byte {
+            b'\r' => break,
+            0x08 => {
+                if line.pop().is_some() {
+                    for echo in [0x08, b' ', 0x08] {
+                        tty_output(echo, cpu);
+                    }
+                }
+            }
+            _ if line.len() + 1 >= max => tty_output(0x07, cpu),
+            _ => {
+                line.push(byte);
+                tty_output(byte, cpu);
+            }
+        }
+    }
+    tty_output(b'\r', cpu);
+
+    let count_addr = cpu.get_physical_address(segment, offset.wrapping_add(1));
+    cpu.memory.write_byte(count_addr, line.len() as u8);
+    for (i, &byte) in line.iter().chain(std::iter::once(&b'\r')).enumerate() {
+        let addr = cpu.get_physical_address(segment, offset.wrapping_add(2 + i as u16));
+        cpu.memory.write_byte(addr, byte);
+    }
+    Ok(())
+}
+
+/// Reads a NUL-terminated string from guest memory
+pub(crate) fn read_asciiz(cpu: &Cpu, segment: u16, offset: u16) -> String {
+    let mut name = String::new();
+    for i in 0..128u16 {
+        let addr = cpu.get_physical_address(segment, offset.wrapping_add(i));
+        match cpu.memory.read_byte(addr) {
+            0 => break,
+            byte => name.push(byte as char),
+        }
+    }
+    name
+}
+
+// RAM disks have no host directory to serve files from
+fn drive_root(cpu: &Cpu) -> Result<&Path, u16> {
+    cpu.disk.fs_root().ok_or(DOS_ERR_PATH_NOT_FOUND)
+}
+
+/// Maps a DOS path such as `C:\DIR\FILE.TXT` onto the host `fs/` directory.
+/// Each component is matched case-insensitively against existing entries;
+/// components that don't exist yet are used as given, upper-cased.
+pub(crate) fn resolve_dos_path(root: &Path, dos_path: &str) -> Result<PathBuf, u16> {
+    let path = match dos_path.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => &dos_path[2..],
+        _ => dos_path,
+    };
+
+    let mut host = root.to_path_buf();
+    for part in path.split(['\\', '/']).filter(|p| !p.is_empty()) {
+        if part == "." {
+            continue;
+        }
+        // Never let a guest path climb out of the drive
+        let mut components = Path::new(part).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(DOS_ERR_PATH_NOT_FOUND);
+        }
+
+        let existing = fs::read_dir(&host).ok().and_then(|entries| {
+            entries.filter_map(|entry| entry.ok()).find(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(part)
+            })
+        });
+        host = match existing {
+            Some(entry) => entry.path(),
+            None => host.join(part.to_ascii_uppercase()),
+        };
+    }
+    Ok(host)
+}
+
+// AH=3Dh: open existing file DS:DX with access mode AL, handle in AX
+fn dos_open(cpu: &mut Cpu) -> Result<(), u16> {
+    let name = read_asciiz(cpu, cpu.regs.ds, cpu.regs.dx);
+    let path = resolve_dos_path(drive_root(cpu)?, &name)?;
+
+    let mut options = OpenOptions::new();
+    match cpu.regs.get_al() & 0x07 {
+        0 => options.read(true),
+        1 => options.write(true),
+        2 => options.read(true).write(true),
+        _ => return Err(DOS_ERR_INVALID_ACCESS),
+    };
+
+    if path.is_dir() {
+        return Err(DOS_ERR_ACCESS_DENIED);
+    }
+    let file = options.open(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            if path.parent().is_some_and(|p| p.is_dir()) {
+                DOS_ERR_FILE_NOT_FOUND
+            } else {
+                DOS_ERR_PATH_NOT_FOUND
+            }
+        }
+        _ => DOS_ERR_ACCESS_DENIED,
+    })?;
+
+    let handle = cpu
+        .dos
+        .allocate_handle(file)
+        .ok_or(DOS_ERR_TOO_MANY_OPEN_FILES)?;
+    cpu.regs.ax = handle;
+    Ok(())
+}
+
+// AH=3Eh: close handle BX
+fn dos_close(cpu: &mut Cpu) -> Result<(), u16> {
+    let handle = cpu.regs.bx;
+    if handle < FIRST_FILE_HANDLE {
+        return Ok(()); // Standard devices stay open
+    }
+    cpu.dos
+        .files
+        .remove(&handle)
+        .map(|_| ())
+        .ok_or(DOS_ERR_INVALID_HANDLE)
+}
+
+// AH=3Fh: read CX bytes from handle BX into DS:DX, count read in AX
+fn dos_read(cpu: &mut Cpu) -> Result<(), u16> {
+    let handle = cpu.regs.bx;
+    let mut buffer = vec![0; cpu.regs.cx as usize];
+    let file = cpu
+        .dos
+        .files
+        .get_mut(&handle)
+        .ok_or(DOS_ERR_INVALID_HANDLE)?;
+
+    let mut count = 0;
+    while count < buffer.len() {
+        match file.read(&mut buffer[count..]) {
+            Ok(0) => break,
+            Ok(n) => count += n,
+            Err(_) => return Err(DOS_ERR_ACCESS_DENIED),
+        }
+    }
+
+    let (segment, offset) = (cpu.regs.ds, cpu.regs.dx);
+    for (i, &byte) in buffer[..count].iter().enumerate() {
+        let addr = cpu.get_physical_address(segment, offset.wrapping_add(i as u16));
+        cpu.memory.write_byte(addr, byte);
+    }
+    cpu.regs.ax = count as u16;
+    Ok(())
+}
+
+// AH=40h: write CX bytes from DS:DX to handle BX, count written in AX.
+// A zero-length write truncates the file at the current position.
+fn dos_write(cpu: &mut Cpu) -> Result<(), u16> {
+    let handle = cpu.regs.bx;
+    let (segment, offset) = (cpu.regs.ds, cpu.regs.dx);
+    let data: Vec<u8> = (0..cpu.regs.cx)
+        .map(|i| {
+            cpu.memory
+                .read_byte(cpu.get_physical_address(segment, offset.wrapping_add(i)))
+        })
+        .collect();
+
+    if handle == 1 || handle == 2 {
+        // stdout/stderr go to the console
+        data.iter().for_each(|&byte| cpu.serial.echo(byte));
+        cpu.regs.ax = data.len() as u16;
+        return Ok(());
+    }
+
+    let file = cpu
+        .dos
+        .files
+        .get_mut(&handle)
+        .ok_or(DOS_ERR_INVALID_HANDLE)?;
+
+    if data.is_empty() {
+        let position = file.stream_position().map_err(|_| DOS_ERR_ACCESS_DENIED)?;
+        file.set_len(position).map_err(|_| DOS_ERR_ACCESS_DENIED)?;
+    } else {
+        file.write_all(&data).map_err(|_| DOS_ERR_ACCESS_DENIED)?;
+    }
+    cpu.regs.ax = data.len() as u16;
+    Ok(())
+}
+
+// AH=42h: move handle BX's file pointer by CX:DX from origin AL,
+// new position returned in DX:AX
+fn dos_seek(cpu: &mut Cpu) -> Result<(), u16> {
+    let handle = cpu.regs.bx;
+    let distance = ((cpu.regs.cx as u32) << 16) | cpu.regs.dx as u32;
+    let target = match cpu.regs.get_al() {
+        0 => SeekFrom::Start(distance as u64),
+        1 => SeekFrom::Current(distance as i32 as i64),
+        2 => SeekFrom::End(distance as i32 as i64),
+        _ => return Err(DOS_ERR_INVALID_FUNCTION),
+    };
+
+    let file = cpu
+        .dos
+        .files
+        .get_mut(&handle)
+        .ok_or(DOS_ERR_INVALID_HANDLE)?;
+    let position = file.seek(target).map_err(|_| DOS_ERR_INVALID_FUNCTION)? as u32;
+    cpu.regs.dx = (position >> 16) as u16;
+    cpu.regs.ax = position as u16;
+    Ok(())
+}
+
+// Expands a DOS wildcard pattern or file name into the 11-character
+// blank-padded FCB form, with '*' filling the rest of its field with '?'
+fn to_fcb_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    if base.len() > 8 || ext.len() > 3 || base.contains('.') {
+        return None;
+    }
+
+    let mut fcb = [b' '; 11];
+    for (field, start, len) in [(base, 0, 8), (ext, 8, 3)] {
+        for (i, c) in field.bytes().enumerate() {
+            if c == b'*' {
+                fcb[start + i..start + len].fill(b'?');
+                break;
+            }
+            fcb[start + i] = c.to_ascii_uppercase();
+        }
+    }
+    Some(fcb)
+}
+
+fn fcb_matches(pattern: &[u8; 11], name: &[u8; 11]) -> bool {
+    pattern
+        .iter()
+        .zip(name.iter())
+        .all(|(&p, &n)| p == b'?' || p == n)
+}
+
+// Packs a timestamp into the (date, time) words of a directory entry
+pub(crate) fn dos_date_time(modified: DateTime<Local>) -> (u16, u16) {
+    let date = (((modified.year() - 1980).max(0) as u16) << 9)
+        | ((modified.month() as u16) << 5)
+        | modified.day() as u16;
+    let time = ((modified.hour() as u16) << 11)
+        | ((modified.minute() as u16) << 5)
+        | (modified.second() as u16 / 2);
+    (date, time)
+}
+
+// Lists the host directory entries that match `pattern` and `search_attributes`
+fn find_matches(dir: &Path, pattern: &[u8; 11], search_attributes: u8) -> Vec<FoundEntry> {
+    let mut found = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return found,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let name = entry.file_name().to_string_lossy().to_ascii_uppercase();
+
+        // Host names that don't fit 8.3 are invisible to DOS
+        let fcb = match to_fcb_name(&name) {
+            Some(fcb) if !fcb.contains(&b'?') && !name.starts_with('.') => fcb,
+            _ => continue,
+        };
+        if !fcb_matches(pattern, &fcb) {
+            continue;
+        }
+
+        let mut attributes = if metadata.is_dir() {
+            ATTR_DIRECTORY
+        } else {
+            ATTR_ARCHIVE
+        };
+        if metadata.permissions().readonly() {
+            attributes |= ATTR_READ_ONLY;
+        }
+        // Directories are only returned when asked for
+        if attributes & ATTR_DIRECTORY != 0 && search_attributes & ATTR_DIRECTORY == 0 {
+            continue;
+        }
+
+        let (date, time) = metadata
+            .modified()
+            .map(|t| dos_date_time(t.into()))
+            .unwrap_or((0, 0));
+        found.push(FoundEntry {
+            name,
+            attributes,
+            size: if metadata.is_dir() {
+                0
+            } else {
+                metadata.len().min(u32::MAX as u64) as u32
+            },
+            time,
+            date,
+        });
+    }
+
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    found
+}
+
+// Writes the next pending match into the DTA find block
+fn write_next_match(cpu: &mut Cpu) -> Result<(), u16> {
+    let find = cpu.dos.find.as_mut().ok_or(DOS_ERR_NO_MORE_FILES)?;
+    let entry = match find.entries.get(find.next) {
+        Some(entry) => entry,
+        None => {
+            cpu.dos.find = None;
+            return Err(DOS_ERR_NO_MORE_FILES);
+        }
+    };
+    find.next += 1;
+
+    let (segment, offset) = cpu.dos.dta;
+    let mut block = [0u8; 43];
+    block[FIND_ATTRIBUTE as usize] = entry.attributes;
+    block[FIND_TIME as usize..FIND_TIME as usize + 2].copy_from_slice(&entry.time.to_le_bytes());
+    block[FIND_DATE as usize..FIND_DATE as usize + 2].copy_from_slice(&entry.date.to_le_bytes());
+    block[FIND_SIZE as usize..FIND_SIZE as usize + 4].copy_from_slice(&entry.size.to_le_bytes());
+    let name = entry.name.as_bytes();
+    block[FIND_NAME as usize..FIND_NAME as usize + name.len()].copy_from_slice(name);
+
+    for (i, &byte) in block.iter().enumerate() {
+        let addr = cpu.get_physical_address(segment, offset.wrapping_add(i as u16));
+        cpu.memory.write_byte(addr, byte);
+    }
+    Ok(())
+}
+
+// AH=4Eh: find the first file matching the pattern at DS:DX with attributes CX
+fn dos_find_first(cpu: &mut Cpu) -> Result<(), u16> {
+    let spec = read_asciiz(cpu, cpu.regs.ds, cpu.regs.dx);
+    let (dir, pattern) = match spec.rfind(['\\', '/', ':']) {
+        Some(split) => (&spec[..=split], &spec[split + 1..]),
+        None => ("", spec.as_str()),
+    };
+
+    let dir = resolve_dos_path(drive_root(cpu)?, dir)?;
+    if !dir.is_dir() {
+        return Err(DOS_ERR_PATH_NOT_FOUND);
+    }
+    let pattern = to_fcb_name(pattern).ok_or(DOS_ERR_FILE_NOT_FOUND)?;
+    let search_attributes = cpu.regs.cx as u8 & !(ATTR_HIDDEN | ATTR_SYSTEM);
+
+    cpu.dos.find = Some(FindState {
+        entries: find_matches(&dir, &pattern, search_attributes),
+        next: 0,
+    });
+    write_next_match(cpu)
+}
+
+// AH=4Fh: continue the search started by FindFirst
+fn dos_find_next(cpu: &mut Cpu) -> Result<(), u16> {
+    write_next_match(cpu)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::disk::DiskImage;
+    use crate::memory::ram::RamMemory;
+    use crate::serial::Serial;
+
+    /// Creates a fresh drive directory with an empty `fs/` under the
+    /// system temp directory
+    pub(crate) fn temp_drive(name: &str) -> PathBuf {
+        let drive = std::env::temp_dir().join(format!("dos_emu_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&drive);
+        fs::create_dir_all(drive.join("fs")).unwrap();
+        drive
+    }
+
+    pub(crate) fn setup_dos_cpu(drive: &Path) -> Cpu {
+        let memory = Box::new(RamMemory::new(1024 * 1024));
+        let disk = DiskImage::new(drive).expect("Failed to create disk image");
+        let mut cpu = Cpu::new(memory, Serial::new(), disk);
+        cpu.regs.ds = 0x1000;
+        cpu
+    }
+
+    pub(crate) fn write_guest_string(cpu: &mut Cpu, segment: u16, offset: u16, text: &str) {
+        for (i, byte) in text.bytes().chain(std::iter::once(0)).enumerate() {
+            let addr = cpu.get_physical_address(segment, offset + i as u16);
+            cpu.memory.write_byte(addr, byte);
+        }
+    }
+
+    fn dos_call(cpu: &mut Cpu, ah: u8) {
+        cpu.regs.set_ah(ah);
+        handle_dos_interrupt(cpu).unwrap();
+    }
+
+    fn feed(cpu: &mut Cpu, input: &[u8]) {
+        input.iter().for_each(|&b| cpu.serial.add_input(b));
+    }
+
+    #[test]
+    fn test_buffered_input() {
+        let drive = temp_drive("buffered_input");
+        let mut cpu = setup_dos_cpu(&drive);
+        cpu.memory.write_byte(0x10000, 10);
+        cpu.regs.dx = 0;
+
+        feed(&mut cpu, b"HI\r");
+        dos_call(&mut cpu, 0x0A);
+        let buffer: Vec<u8> = (0..5).map(|i| cpu.memory.read_byte(0x10000 + i)).collect();
+        assert_eq!(buffer, [10, 2, b'H', b'I', b'\r']);
+        assert!(cpu.serial.output().iter().eq(b"HI\r"));
+
+        // Backspace edits, and a line longer than the buffer is cut short
+        cpu.memory.write_byte(0x10000, 4);
+        feed(&mut cpu, b"AX\x08BCDE\r");
+        dos_call(&mut cpu, 0x0A);
+        let buffer: Vec<u8> = (0..6).map(|i| cpu.memory.read_byte(0x10000 + i)).collect();
+        assert_eq!(buffer, [4, 3, b'A', b'B', b'C', b'\r']);
+
+        // Run out of input mid-line: the INT 21h is set up to be retried
+        // and picks up where it left off
+        cpu.regs.ss = 0x2000;
+        cpu.regs.sp = 0x0100;
+        cpu.memory.write_word(0x20100, 0x0112);
+        cpu.memory.write_byte(0x10000, 10);
+        feed(&mut cpu, b"O");
+        dos_call(&mut cpu, 0x0A);
+        assert_eq!(cpu.memory.read_word(0x20100), 0x0110);
+        feed(&mut cpu, b"K\r");
+        dos_call(&mut cpu, 0x0A);
+        assert_eq!(cpu.memory.read_byte(0x10001), 2);
+        assert_eq!(cpu.memory.read_byte(0x10003), b'K');
+
+        fs::remove_dir_all(&drive).unwrap();
+    }
+
+    #[test]
+    fn test_read_char_without_echo() {
+        let drive = temp_drive("read_char");
+        let mut cpu = setup_dos_cpu(&drive);
+        feed(&mut cpu, b"yq");
+        dos_call(&mut cpu, 0x08);
+        assert_eq!(cpu.regs.get_al(), b'y');
+        dos_call(&mut cpu, 0x07);
+        assert_eq!(cpu.regs.get_al(), b'q');
+        assert!(cpu.serial.output().is_empty());
+        fs::remove_dir_all(&drive).unwrap();
+    }
+
+    #[test]
+    fn test_open_read_close() {
+        let drive = temp_drive("open_read");
+        fs::write(drive.join("fs").join("hello.txt"), b"Hello, DOS!").unwrap();
+        let mut cpu = setup_dos_cpu(&drive);
+
+        write_guest_string(&mut cpu, 0x1000, 0x0000, "C:\\HELLO.TXT");
+        cpu.regs.dx = 0;
+        cpu.regs.set_al(0);
+        dos_call(&mut cpu, 0x3D);
+        assert!(!cpu.regs.flags.get_carry());
+        let handle = cpu.regs.ax;
+        assert!(handle >= FIRST_FILE_HANDLE);
+
+        cpu.regs.bx = handle;
+        cpu.regs.cx = 64;
+        cpu.regs.dx = 0x0100;
+        dos_call(&mut cpu, 0x3F);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, 11);
+        let read: Vec<u8> = (0..11).map(|i| cpu.memory.read_byte(0x10100 + i)).collect();
+        assert_eq!(read, b"Hello, DOS!");
+
+        // Seek back to offset 7 and read the rest
+        cpu.regs.bx = handle;
+        cpu.regs.set_al(0);
+        cpu.regs.cx = 0;
+        cpu.regs.dx = 7;
+        dos_call(&mut cpu, 0x42);
+        assert_eq!((cpu.regs.dx, cpu.regs.ax), (0, 7));
+        cpu.regs.bx = handle;
+        cpu.regs.cx = 64;
+        cpu.regs.dx = 0x0200;
+        dos_call(&mut cpu, 0x3F);
+        assert_eq!(cpu.regs.ax, 4);
+        assert_eq!(cpu.memory.read_byte(0x10200), b'D');
+
+        cpu.regs.bx = handle;
+        dos_call(&mut cpu, 0x3E);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.dos.open_file_count(), 0);
+
+        // The handle is gone now
+        cpu.regs.bx = handle;
+        dos_call(&mut cpu, 0x3E);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_INVALID_HANDLE);
+
+        fs::remove_dir_all(&drive).unwrap();
+    }
+
+    #[test]
+    fn test_open_missing_and_write() {
+        let drive = temp_drive("open_write");
+        fs::write(drive.join("fs").join("OUT.TXT"), b"old contents").unwrap();
+        let mut cpu = setup_dos_cpu(&drive);
+
+        write_guest_string(&mut cpu, 0x1000, 0, "MISSING.TXT");
+        cpu.regs.dx = 0;
+        cpu.regs.set_al(0);
+        dos_call(&mut cpu, 0x3D);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_FILE_NOT_FOUND);
+
+        write_guest_string(&mut cpu, 0x1000, 0, "..\\ESCAPE.TXT");
+        cpu.regs.dx = 0;
+        dos_call(&mut cpu, 0x3D);
+        assert_eq!(cpu.regs.ax, DOS_ERR_PATH_NOT_FOUND);
+
+        write_guest_string(&mut cpu, 0x1000, 0, "out.txt");
+        cpu.regs.dx = 0;
+        cpu.regs.set_al(1);
+        dos_call(&mut cpu, 0x3D);
+        assert!(!cpu.regs.flags.get_carry());
+        let handle = cpu.regs.ax;
+
+        write_guest_string(&mut cpu, 0x1000, 0x100, "new");
+        cpu.regs.bx = handle;
+        cpu.regs.cx = 3;
+        cpu.regs.dx = 0x100;
+        dos_call(&mut cpu, 0x40);
+        assert_eq!(cpu.regs.ax, 3);
+
+        // A zero-length write truncates at the file pointer
+        cpu.regs.bx = handle;
+        cpu.regs.cx = 0;
+        dos_call(&mut cpu, 0x40);
+        cpu.regs.bx = handle;
+        dos_call(&mut cpu, 0x3E);
+
+        assert_eq!(fs::read(drive.join("fs").join("OUT.TXT")).unwrap(), b"new");
+        fs::remove_dir_all(&drive).unwrap();
+    }
+
+    fn read_dta_name(cpu: &Cpu) -> String {
+        let (segment, offset) = cpu.dos.dta;
+        read_asciiz(cpu, segment, offset + FIND_NAME)
+    }
+
+    fn read_dta_size(cpu: &Cpu) -> u32 {
+        let (segment, offset) = cpu.dos.dta;
+        let addr = cpu.get_physical_address(segment, offset + FIND_SIZE);
+        cpu.memory.read_word(addr) as u32 | (cpu.memory.read_word(addr + 2) as u32) << 16
+    }
+
+    #[test]
+    fn test_find_first_find_next() {
+        let drive = temp_drive("find");
+        let root = drive.join("fs");
+        fs::write(root.join("alpha.txt"), vec![b'a'; 100]).unwrap();
+        fs::write(root.join("BETA.TXT"), vec![b'b'; 70_000]).unwrap();
+        fs::write(root.join("gamma.dat"), b"g").unwrap();
+        fs::write(root.join("a_very_long_name.txt"), b"x").unwrap();
+        fs::create_dir(root.join("subdir.txt")).unwrap();
+        let mut cpu = setup_dos_cpu(&drive);
+
+        // Point the DTA at 1000:0200
+        cpu.regs.dx = 0x0200;
+        dos_call(&mut cpu, 0x1A);
+        dos_call(&mut cpu, 0x2F);
+        assert_eq!((cpu.regs.es, cpu.regs.bx), (0x1000, 0x0200));
+
+        write_guest_string(&mut cpu, 0x1000, 0, "C:\\*.TXT");
+        cpu.regs.dx = 0;
+        cpu.regs.cx = 0;
+        dos_call(&mut cpu, 0x4E);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(read_dta_name(&cpu), "ALPHA.TXT");
+        assert_eq!(read_dta_size(&cpu), 100);
+        assert_eq!(
+            cpu.memory.read_byte(0x10200 + FIND_ATTRIBUTE as u32),
+            ATTR_ARCHIVE
+        );
+
+        dos_call(&mut cpu, 0x4F);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(read_dta_name(&cpu), "BETA.TXT");
+        assert_eq!(read_dta_size(&cpu), 70_000);
+
+        // The directory and the long name aren't returned
+        dos_call(&mut cpu, 0x4F);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_NO_MORE_FILES);
+
+        // Asking for directories includes them
+        write_guest_string(&mut cpu, 0x1000, 0, "SUB*.*");
+        cpu.regs.dx = 0;
+        cpu.regs.cx = ATTR_DIRECTORY as u16;
+        dos_call(&mut cpu, 0x4E);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(read_dta_name(&cpu), "SUBDIR.TXT");
+
+        write_guest_string(&mut cpu, 0x1000, 0, "G?MMA.DAT");
+        cpu.regs.dx = 0;
+        cpu.regs.cx = 0;
+        dos_call(&mut cpu, 0x4E);
+        assert_eq!(read_dta_name(&cpu), "GAMMA.DAT");
+
+        write_guest_string(&mut cpu, 0x1000, 0, "*.COM");
+        cpu.regs.dx = 0;
+        dos_call(&mut cpu, 0x4E);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_NO_MORE_FILES);
+
+        fs::remove_dir_all(&drive).unwrap();
+    }
+
+    #[test]
+    fn test_fcb_name_expansion() {
+        assert_eq!(&to_fcb_name("*.*").unwrap(), b"???????????");
+        assert_eq!(&to_fcb_name("FOO*.C").unwrap(), b"FOO?????C  ");
+        assert_eq!(&to_fcb_name("readme").unwrap(), b"README     ");
+        assert!(to_fcb_name("toolongname.txt").is_none());
+    }
+
+    #[test]
+    fn test_load_com_builds_psp() {
+        let drive = temp_drive("load_com");
+        let mut cpu = setup_dos_cpu(&drive);
+        let segment = 0x0800;
+        load_com(&mut cpu, &[0xB4, 0x4C, 0xCD, 0x21], segment, "FOO BAR").unwrap();
+
+        let psp = (segment as u32) << 4;
+        assert_eq!(cpu.memory.read_byte(psp), 0xCD);
+        assert_eq!(cpu.memory.read_byte(psp + 1), 0x20);
+        assert_eq!(cpu.memory.read_byte(psp + 0x80), 7);
+        let tail: Vec<u8> = (0..7)
+            .map(|i| cpu.memory.read_byte(psp + 0x81 + i))
+            .collect();
+        assert_eq!(tail, b"FOO BAR");
+        assert_eq!(cpu.memory.read_byte(psp + 0x88), 0x0D);
+        assert_eq!(cpu.memory.read_byte(psp + 0x100), 0xB4);
+
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (segment, 0x100));
+        assert_eq!(
+            (cpu.regs.ds, cpu.regs.es, cpu.regs.ss),
+            (segment, segment, segment)
+        );
+        assert_eq!(cpu.regs.sp, 0xFFFE);
+        assert_eq!(cpu.dos.dta, (segment, 0x80));
+
+        let long = "X".repeat(127);
+        assert!(load_com(&mut cpu, &[], segment, &long).is_err());
+        let _ = fs::remove_dir_all(&drive);
+    }
+
+    #[test]
+    fn test_allocate_free_and_reuse_memory() {
+        let drive = temp_drive("memory_blocks");
+        let mut cpu = setup_dos_cpu(&drive);
+        let psp = 0x0800;
+        load_com(&mut cpu, &[0xC3], psp, "").unwrap();
+
+        // The program owns everything, so nothing can be allocated yet
+        cpu.regs.bx = 0x10;
+        dos_call(&mut cpu, 0x48);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_INSUFFICIENT_MEMORY);
+        assert_eq!(cpu.regs.bx, 0);
+
+        // Shrink the program to 64K
+        cpu.regs.es = psp;
+        cpu.regs.bx = 0x1000;
+        dos_call(&mut cpu, 0x4A);
+        assert!(!cpu.regs.flags.get_carry());
+
+        cpu.regs.bx = 0x10;
+        dos_call(&mut cpu, 0x48);
+        assert!(!cpu.regs.flags.get_carry());
+        let block = cpu.regs.ax;
+        assert_eq!(block, psp + 0x1000 + 1);
+        assert_eq!(cpu.peek_word(block - 1, MCB_OWNER), psp);
+        cpu.poke_word(block, 0, 0xBEEF);
+        assert_eq!(cpu.peek_word(block, 0), 0xBEEF);
+
+        cpu.regs.es = block;
+        dos_call(&mut cpu, 0x49);
+        assert!(!cpu.regs.flags.get_carry());
+        // An ES that doesn't start a block is rejected
+        cpu.regs.es = block + 1;
+        dos_call(&mut cpu, 0x49);
+        assert_eq!(cpu.regs.ax, DOS_ERR_INVALID_BLOCK);
+
+        cpu.regs.bx = 0x10;
+        dos_call(&mut cpu, 0x48);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, block);
+
+        // Too big: BX comes back with the largest free block
+        cpu.regs.bx = 0xFFFF;
+        dos_call(&mut cpu, 0x48);
+        assert!(cpu.regs.flags.get_carry());
+        let largest = PSP_MEMORY_TOP - block - 0x10 - 1;
+        assert_eq!(cpu.regs.bx, largest);
+
+        // Growing the block into the free space behind it
+        cpu.regs.es = block;
+        cpu.regs.bx = 0x10 + 1 + largest;
+        dos_call(&mut cpu, 0x4A);
+        assert!(!cpu.regs.flags.get_carry());
+        assert_eq!(cpu.peek_byte(block - 1, 0), MCB_LAST);
+        let _ = fs::remove_dir_all(&drive);
+    }
+
+    #[test]
+    fn test_corrupt_block_sizes_are_a_destroyed_arena() {
+        let drive = temp_drive("memory_overflow");
+        let mut cpu = setup_dos_cpu(&drive);
+        let psp = 0x0800;
+        load_com(&mut cpu, &[0xC3], psp, "").unwrap();
+        cpu.regs.es = psp;
+        cpu.regs.bx = 0x1000;
+        dos_call(&mut cpu, 0x4A);
+        assert!(!cpu.regs.flags.get_carry());
+
+        // The free block behind the program claims more than fits in 1MB
+        let free = psp + 0x1000;
+        let corrupt = Mcb {
+            segment: free,
+            last: true,
+            owner: MCB_OWNER_FREE,
+            size: 0xFFFF,
+        };
+        write_mcb(&mut cpu, &corrupt);
+        cpu.regs.es = psp;
+        cpu.regs.bx = 0x2000;
+        dos_call(&mut cpu, 0x4A);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_ARENA_TRASHED);
+
+        // Two free blocks whose sizes only overflow once merged
+        write_mcb(
+            &mut cpu,
+            &Mcb {
+                last: false,
+                size: 0x10,
+                ..corrupt
+            },
+        );
+        write_mcb(
+            &mut cpu,
+            &Mcb {
+                segment: free + 0x11,
+                size: 0xFFF8,
+                ..corrupt
+            },
+        );
+        cpu.regs.bx = 0x10;
+        dos_call(&mut cpu, 0x48);
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_ARENA_TRASHED);
+        let _ = fs::remove_dir_all(&drive);
+    }
+
+    #[test]
+    fn test_get_date_and_time_from_clock() {
+        use crate::clock::FixedClock;
+        use chrono::NaiveDate;
+
+        let mut cpu = crate::cpu::test_utils::setup_test_cpu();
+        // A Thursday
+        let instant = NaiveDate::from_ymd_opt(2024, 2, 29)
+            .unwrap()
+            .and_hms_milli_opt(13, 45, 7, 890)
+            .unwrap();
+        cpu.set_clock(Box::new(FixedClock(instant)));
+
+        dos_call(&mut cpu, 0x2A);
+        assert_eq!(cpu.regs.cx, 2024);
+        assert_eq!(cpu.regs.dx, 0x021D);
+        assert_eq!(cpu.regs.get_al(), 4);
+
+        dos_call(&mut cpu, 0x2C);
+        assert_eq!(cpu.regs.cx, 0x0D2D);
+        assert_eq!(cpu.regs.dx, 0x0759);
+        assert!(!cpu.regs.flags.get_carry());
+    }
+
+    #[test]
+    fn test_set_guest_datetime() {
+        use chrono::NaiveDate;
+
+        let mut cpu = crate::cpu::test_utils::setup_test_cpu();
+        let eve = NaiveDate::from_ymd_opt(1999, 12, 31)
+            .unwrap()
+            .and_hms_opt(23, 59, 0)
+            .unwrap();
+        cpu.set_guest_datetime(eve);
+        assert_eq!(cpu.guest_datetime(), eve);
+
+        // A Friday
+        dos_call(&mut cpu, 0x2A);
+        assert_eq!(cpu.regs.cx, 1999);
+        assert_eq!(cpu.regs.dx, 0x0C1F);
+        assert_eq!(cpu.regs.get_al(), 5);
+        dos_call(&mut cpu, 0x2C);
+        assert_eq!(cpu.regs.cx, 0x173B);
+        assert_eq!(cpu.regs.dx, 0x0000);
+
+        // The BIOS RTC calls see it too, in BCD
+        cpu.regs.set_ah(0x02);
+        cpu.int1a_time_services().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.get_dh()), (0x2359, 0x00));
+        cpu.regs.set_ah(0x04);
+        cpu.int1a_time_services().unwrap();
+        assert_eq!((cpu.regs.cx, cpu.regs.dx), (0x1999, 0x1231));
+    }
+
+    #[test]
+    fn test_guest_int21_returns_carry_to_the_caller() {
+        let mut cpu = crate::cpu::test_utils::setup_executable_cpu(&[
+            0xF9, // STC
+            0xB4, 0x3E, // MOV AH,3Eh
+            0xBB, 0x01, 0x00, // MOV BX,0001h
+            0xCD, 0x21, // INT 21h
+            0xBB, 0xFF, 0x00, // MOV BX,00FFh
+            0xCD, 0x21, // INT 21h
+        ]);
+        install_dos_services(&mut cpu);
+
+        // Closing stdout succeeds, clearing the CF set before the call
+        for _ in 0..5 {
+            cpu.execute_instruction().unwrap(); // STC; MOV; MOV; INT 21h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0108));
+        assert!(!cpu.regs.flags.get_carry());
+
+        // Closing a handle that isn't open fails
+        for _ in 0..3 {
+            cpu.execute_instruction().unwrap(); // MOV; INT 21h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x010D));
+        assert!(cpu.regs.flags.get_carry());
+        assert_eq!(cpu.regs.ax, DOS_ERR_INVALID_HANDLE);
+        assert_eq!(cpu.regs.sp, 0xFFFE);
+    }
+}
@@ -0,0 +1,355 @@
+// Minimal LIM EMS 4.0 expanded memory manager.
+//
+// Logical 16KB pages live in a fixed pool owned by the manager. Four of them
+// at a time are visible through the 64KB page frame, which is mapped into
+// SystemMemory as a device region. Guests talk to the manager through INT 67h.
+
+use crate::bios::{bios_seg, iret_offset, set_interrupt_vector};
+use crate::cpu::Cpu;
+use crate::memory::{MemoryDevice, MemoryRegion, SystemMemory};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+pub const EMS_PAGE_SIZE: usize = 0x4000;
+pub const EMS_PHYSICAL_PAGES: usize = 4;
+pub const EMS_PAGE_FRAME_SEGMENT: u16 = 0xE000;
+
+// EMS status codes returned in AH
+const EMS_OK: u8 = 0x00;
+const EMS_INVALID_HANDLE: u8 = 0x83;
+const EMS_UNSUPPORTED_FUNCTION: u8 = 0x84;
+const EMS_NO_FREE_HANDLES: u8 = 0x85;
+const EMS_NOT_ENOUGH_TOTAL_PAGES: u8 = 0x87;
+const EMS_NOT_ENOUGH_FREE_PAGES: u8 = 0x88;
+const EMS_ZERO_PAGES: u8 = 0x89;
+const EMS_LOGICAL_PAGE_OUT_OF_RANGE: u8 = 0x8A;
+const EMS_PHYSICAL_PAGE_OUT_OF_RANGE: u8 = 0x8B;
+
+const EMS_MAX_HANDLES: usize = 255;
+
+struct EmsState {
+    pages: Vec<Vec<u8>>,
+    allocated: Vec<bool>,
+    // Handle number -> pool indices of its logical pages, in order
+    handles: BTreeMap<u16, Vec<usize>>,
+    // Physical page -> pool index currently visible through the frame
+    mapping: [Option<usize>; EMS_PHYSICAL_PAGES],
+}
+
+impl EmsState {
+    fn free_pages(&self) -> usize {
+        self.allocated.iter().filter(|&&used| !used).count()
+    }
+}
+
+struct EmsPageFrame {
+    state: Rc<RefCell<EmsState>>,
+}
+
+impl MemoryDevice for EmsPageFrame {
+    fn read_byte(&self, offset: u32) -> u8 {
+        let offset = offset as usize;
+        let state = self.state.borrow();
+        match state.mapping[offset / EMS_PAGE_SIZE] {
+            Some(page) => state.pages[page][offset % EMS_PAGE_SIZE],
+            None => 0xFF, // Nothing mapped, open bus
+        }
+    }
+
+    fn write_byte(&mut self, offset: u32, value: u8) {
+        let offset = offset as usize;
+        let mut state = self.state.borrow_mut();
+        if let Some(page) = state.mapping[offset / EMS_PAGE_SIZE] {
+            state.pages[page][offset % EMS_PAGE_SIZE] = value;
+        }
+    }
+}
+
+pub struct Ems {
+    state: Rc<RefCell<EmsState>>,
+}
+
+impl Ems {
+    pub fn new(total_pages: usize) -> Self {
+        Ems {
+            state: Rc::new(RefCell::new(EmsState {
+                pages: vec![vec![0; EMS_PAGE_SIZE]; total_pages],
+                allocated: vec![false; total_pages],
+                handles: BTreeMap::new(),
+                mapping: [None; EMS_PHYSICAL_PAGES],
+            })),
+        }
+    }
+
+    pub fn total_pages(&self) -> usize {
+        self.state.borrow().pages.len()
+    }
+
+    pub fn free_pages(&self) -> usize {
+        self.state.borrow().free_pages()
+    }
+
+    /// Builds the page frame region to be mapped at `EMS_PAGE_FRAME_SEGMENT`
+    pub fn page_frame_region(&self) -> MemoryRegion {
+        MemoryRegion::device(
+            (EMS_PAGE_FRAME_SEGMENT as u32) << 4,
+            (EMS_PAGE_SIZE * EMS_PHYSICAL_PAGES) as u32,
+            Box::new(EmsPageFrame {
+                state: Rc::clone(&self.state),
+            }),
+        )
+    }
+
+    fn allocate(&mut self, count: usize) -> Result<u16, u8> {
+        let mut state = self.state.borrow_mut();
+        if count == 0 {
+            return Err(EMS_ZERO_PAGES);
+        }
+        if count > state.pages.len() {
+            return Err(EMS_NOT_ENOUGH_TOTAL_PAGES);
+        }
+        if count > state.free_pages() {
+            return Err(EMS_NOT_ENOUGH_FREE_PAGES);
+        }
+        if state.handles.len() >= EMS_MAX_HANDLES {
+            return Err(EMS_NO_FREE_HANDLES);
+        }
+
+        // Handle 0 is reserved for the operating system
+        let handle = (1..=EMS_MAX_HANDLES as u16)
+            .find(|h| !state.handles.contains_key(h))
+            .ok_or(EMS_NO_FREE_HANDLES)?;
+
+        let mut owned = Vec::with_capacity(count);
+        for (index, used) in state.allocated.iter_mut().enumerate() {
+            if owned.len() == count {
+                break;
+            }
+            if !*used {
+                *used = true;
+                owned.push(index);
+            }
+        }
+        state.handles.insert(handle, owned);
+        Ok(handle)
+    }
+
+    fn map_page(&mut self, handle: u16, physical: u8, logical: u16) -> Result<(), u8> {
+        let mut state = self.state.borrow_mut();
+        let physical = physical as usize;
+        if physical >= EMS_PHYSICAL_PAGES {
+            return Err(EMS_PHYSICAL_PAGE_OUT_OF_RANGE);
+        }
+        let owned = state.handles.get(&handle).ok_or(EMS_INVALID_HANDLE)?;
+
+        // Logical page 0xFFFF unmaps the physical page (LIM 4.0)
+        let page = if logical == 0xFFFF {
+            None
+        } else {
+            Some(
+                *owned
+                    .get(logical as usize)
+                    .ok_or(EMS_LOGICAL_PAGE_OUT_OF_RANGE)?,
+            )
+        };
+        state.mapping[physical] = page;
+        Ok(())
+    }
+
+    fn free(&mut self, handle: u16) -> Result<(), u8> {
+        let mut state = self.state.borrow_mut();
+        let owned = state.handles.remove(&handle).ok_or(EMS_INVALID_HANDLE)?;
+        for page in owned {
+            state.allocated[page] = false;
+            for slot in state.mapping.iter_mut() {
+                if *slot == Some(page) {
+                    *slot = None;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps the page frame into system memory, points INT 67h at the BIOS
+/// segment and hands the manager to the CPU.
+pub fn install_ems(cpu: &mut Cpu, ems: Ems) -> Result<(), String> {
+    let system = cpu
+        .memory
+        .as_any_mut()
+        .downcast_mut::<SystemMemory>()
+        .ok_or("EMS requires SystemMemory to map its page frame")?;
+    system.map_region(ems.page_frame_region());
+
+    set_interrupt_vector(cpu, 0x67, bios_seg(), iret_offset());
+    cpu.ems = Some(ems);
+    Ok(())
+}
+
+pub fn handle_ems_interrupt(cpu: &mut Cpu) -> Result<(), String> {
+    let function = cpu.regs.get_ah();
+    let ems = match cpu.ems.as_mut() {
+        Some(ems) => ems,
+        None => {
+            cpu.regs.set_ah(EMS_UNSUPPORTED_FUNCTION);
+            return Ok(());
+        }
+    };
+
+    let status = match function {
+        0x40 => EMS_OK, // Get status
+        0x41 => {
+            // Get page frame segment
+            cpu.regs.bx = EMS_PAGE_FRAME_SEGMENT;
+            EMS_OK
+        }
+        0x42 => {
+            // Get unallocated and total page counts
+            cpu.regs.bx = ems.free_pages() as u16;
+            cpu.regs.dx = ems.total_pages() as u16;
+            EMS_OK
+        }
+        0x43 => match ems.allocate(cpu.regs.bx as usize) {
+            // Allocate pages
+            Ok(handle) => {
+                cpu.regs.dx = handle;
+                EMS_OK
+            }
+            Err(status) => status,
+        },
+        0x44 => {
+            // Map logical page BX of handle DX into physical page AL
+            match ems.map_page(cpu.regs.dx, cpu.regs.get_al(), cpu.regs.bx) {
+                Ok(()) => EMS_OK,
+                Err(status) => status,
+            }
+        }
+        0x45 => match ems.free(cpu.regs.dx) {
+            // Release handle and its pages
+            Ok(()) => EMS_OK,
+            Err(status) => status,
+        },
+        _ => EMS_UNSUPPORTED_FUNCTION,
+    };
+
+    cpu.regs.set_ah(status);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::DiskImage;
+    use crate::serial::Serial;
+    use std::path::Path;
+
+    fn setup_cpu() -> Cpu {
+        let memory = Box::new(SystemMemory::new(1024 * 1024));
+        let disk = DiskImage::new(Path::new("drive_c/")).expect("Failed to create disk image");
+        let mut cpu = Cpu::new(memory, Serial::new(), disk);
+        install_ems(&mut cpu, Ems::new(8)).expect("Failed to install EMS");
+        cpu
+    }
+
+    fn ems_call(cpu: &mut Cpu, ah: u8) -> u8 {
+        cpu.regs.set_ah(ah);
+        handle_ems_interrupt(cpu).unwrap();
+        cpu.regs.get_ah()
+    }
+
+    #[test]
+    fn test_ems_map_and_remap() {
+        let mut cpu = setup_cpu();
+        assert_eq!(cpu.memory.read_word(0x67 * 4 + 2), 0xF000);
+
+        assert_eq!(ems_call(&mut cpu, 0x41), EMS_OK);
+        let frame = (cpu.regs.bx as u32) << 4;
+        assert_eq!(frame, 0xE0000);
+
+        cpu.regs.bx = 2;
+        assert_eq!(ems_call(&mut cpu, 0x43), EMS_OK);
+        let handle = cpu.regs.dx;
+
+        assert_eq!(ems_call(&mut cpu, 0x42), EMS_OK);
+        assert_eq!(cpu.regs.bx, 6);
+        assert_eq!(cpu.regs.dx, 8);
+
+        // Map logical page 0 into physical page 0 and write through the frame
+        cpu.regs.set_al(0);
+        cpu.regs.bx = 0;
+        cpu.regs.dx = handle;
+        assert_eq!(ems_call(&mut cpu, 0x44), EMS_OK);
+        cpu.memory.write_byte(frame + 0x10, 0xAB);
+
+        // Swap in logical page 1; the frame now shows different memory
+        cpu.regs.set_al(0);
+        cpu.regs.bx = 1;
+        cpu.regs.dx = handle;
+        assert_eq!(ems_call(&mut cpu, 0x44), EMS_OK);
+        assert_eq!(cpu.memory.read_byte(frame + 0x10), 0x00);
+        cpu.memory.write_byte(frame + 0x10, 0xCD);
+
+        // Map page 0 back in and read the original value
+        cpu.regs.set_al(0);
+        cpu.regs.bx = 0;
+        cpu.regs.dx = handle;
+        assert_eq!(ems_call(&mut cpu, 0x44), EMS_OK);
+        assert_eq!(cpu.memory.read_byte(frame + 0x10), 0xAB);
+
+        cpu.regs.dx = handle;
+        assert_eq!(ems_call(&mut cpu, 0x45), EMS_OK);
+        assert_eq!(cpu.memory.read_byte(frame + 0x10), 0xFF);
+        cpu.regs.dx = handle;
+        assert_eq!(ems_call(&mut cpu, 0x45), EMS_INVALID_HANDLE);
+    }
+
+    #[test]
+    fn test_ems_errors() {
+        let mut cpu = setup_cpu();
+
+        cpu.regs.bx = 9;
+        assert_eq!(ems_call(&mut cpu, 0x43), EMS_NOT_ENOUGH_TOTAL_PAGES);
+        cpu.regs.bx = 0;
+        assert_eq!(ems_call(&mut cpu, 0x43), EMS_ZERO_PAGES);
+
+        cpu.regs.bx = 1;
+        assert_eq!(ems_call(&mut cpu, 0x43), EMS_OK);
+        cpu.regs.set_al(4);
+        cpu.regs.bx = 0;
+        assert_eq!(ems_call(&mut cpu, 0x44), EMS_PHYSICAL_PAGE_OUT_OF_RANGE);
+        cpu.regs.set_al(0);
+        cpu.regs.bx = 1;
+        assert_eq!(ems_call(&mut cpu, 0x44), EMS_LOGICAL_PAGE_OUT_OF_RANGE);
+
+        assert_eq!(ems_call(&mut cpu, 0x5A), EMS_UNSUPPORTED_FUNCTION);
+    }
+
+    #[test]
+    fn test_guest_int67_returns_status_to_the_caller() {
+        let mut cpu = crate::cpu::test_utils::setup_executable_cpu(&[
+            0xB4, 0x41, // MOV AH,41h
+            0xCD, 0x67, // INT 67h
+            0xB4, 0x45, // MOV AH,45h
+            0xB2, 0x34, // MOV DL,34h
+            0xB6, 0x12, // MOV DH,12h
+            0xCD, 0x67, // INT 67h
+        ]);
+        install_ems(&mut cpu, Ems::new(8)).unwrap();
+
+        for _ in 0..3 {
+            cpu.execute_instruction().unwrap(); // MOV; INT 67h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x0104));
+        assert_eq!(cpu.regs.get_ah(), EMS_OK);
+        assert_eq!(cpu.regs.bx, EMS_PAGE_FRAME_SEGMENT);
+
+        // Freeing a handle that was never allocated
+        for _ in 0..5 {
+            cpu.execute_instruction().unwrap(); // MOV; MOV; MOV; INT 67h; IRET
+        }
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0x0000, 0x010C));
+        assert_eq!(cpu.regs.get_ah(), EMS_INVALID_HANDLE);
+        assert_eq!(cpu.regs.sp, 0xFFFE);
+    }
+}
@@ -0,0 +1,201 @@
+// The I/O port space. Devices claim ranges of ports and receive the full
+// port number on each access; ports nobody claims go to the default handler,
+// or read as 0xFF (a floating bus) and ignore writes if there is none.
+
+use crate::debug_exit::{DebugExit, DEBUG_EXIT_PORT};
+use crate::pic::Pic;
+use crate::pit::Pit;
+use crate::prelude::*;
+use crate::serial::{SerialPort, COM1_BASE};
+use core::any::Any;
+use core::ops::RangeInclusive;
+
+pub trait IoDevice: Any {
+    fn read_port(&mut self, port: u16) -> u8;
+    fn write_port(&mut self, port: u16, value: u8);
+
+    /// Returns the device to its power-on state, as the reset line does
+    fn reset(&mut self) {}
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Called as `(port, is_write, value)` for ports no device claims. Writes pass
+/// the byte written and ignore the return value; reads pass 0 and return the
+/// byte to supply (only the low 8 bits are used).
+pub type DefaultPortHandler = Box<dyn FnMut(u16, bool, u16) -> u16>;
+
+struct PortMapping {
+    ports: RangeInclusive<u16>,
+    device: Box<dyn IoDevice>,
+}
+
+#[derive(Default)]
+pub struct IoBus {
+    mappings: Vec<PortMapping>,
+    default_handler: Option<DefaultPortHandler>,
+}
+
+impl IoBus {
+    pub fn new() -> Self {
+        IoBus {
+            mappings: Vec::new(),
+            default_handler: None,
+        }
+    }
+
+    /// The bus of a stock PC: PIC at 0x20-0x21, PIT at 0x40-0x43 and the
+    /// COM1 UART at 0x3F8-0x3FF, plus the debug-exit port at 0x501
+    pub fn with_standard_devices() -> Self {
+        let mut bus = IoBus::new();
+        bus.register(0x20..=0x21, Box::new(Pic::new()));
+        bus.register(0x40..=0x43, Box::new(Pit::new()));
+        bus.register(
+            COM1_BASE..=COM1_BASE + 7,
+            Box::new(SerialPort::at(COM1_BASE)),
+        );
+        bus.register(
+            DEBUG_EXIT_PORT..=DEBUG_EXIT_PORT,
+            Box::new(DebugExit::new()),
+        );
+        bus
+    }
+
+    /// Claims a range of ports. Later registrations take priority, so a
+    /// device can be replaced by registering another over the same range.
+    pub fn register(&mut self, ports: RangeInclusive<u16>, device: Box<dyn IoDevice>) {
+        self.mappings.push(PortMapping { ports, device });
+    }
+
+    /// Resets every device on the bus; the mappings themselves stay
+    pub fn reset(&mut self) {
+        for mapping in &mut self.mappings {
+            mapping.device.reset();
+        }
+    }
+
+    /// Routes accesses to unclaimed ports through `handler`, e.g. to log
+    /// what an unknown program pokes at or to fake a device's responses
+    pub fn set_default_handler(&mut self, handler: DefaultPortHandler) {
+        self.default_handler = Some(handler);
+    }
+
+    fn device_at(&mut self, port: u16) -> Option<&mut Box<dyn IoDevice>> {
+        self.mappings
+            .iter_mut()
+            .rev()
+            .find(|mapping| mapping.ports.contains(&port))
+            .map(|mapping| &mut mapping.device)
+    }
+
+    /// Finds the most recently registered device of type `T`
+    pub fn device_mut<T: IoDevice>(&mut self) -> Option<&mut T> {
+        self.mappings
+            .iter_mut()
+            .rev()
+            .find_map(|mapping| mapping.device.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn read_byte(&mut self, port: u16) -> u8 {
+        if let Some(device) = self.device_at(port) {
+            return device.read_port(port);
+        }
+        match self.default_handler.as_mut() {
+            Some(handler) => handler(port, false, 0) as u8,
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_byte(&mut self, port: u16, value: u8) {
+        if let Some(device) = self.device_at(port) {
+            device.write_port(port, value);
+            return;
+        }
+        match self.default_handler.as_mut() {
+            Some(handler) => {
+                handler(port, true, value as u16);
+            }
+            None => println!(
+                "IO: Ignoring write of 0x{:02X} to unclaimed port 0x{:04X}",
+                value, port
+            ),
+        }
+    }
+
+    /// Word accesses go to `port` and `port + 1`, low byte first
+    pub fn read_word(&mut self, port: u16) -> u16 {
+        let low = self.read_byte(port) as u16;
+        let high = self.read_byte(port.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    pub fn write_word(&mut self, port: u16, value: u16) {
+        self.write_byte(port, value as u8);
+        self.write_byte(port.wrapping_add(1), (value >> 8) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Latch {
+        value: u8,
+    }
+
+    impl IoDevice for Latch {
+        fn read_port(&mut self, _port: u16) -> u8 {
+            self.value
+        }
+
+        fn write_port(&mut self, _port: u16, value: u8) {
+            self.value = value;
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_register_and_route() {
+        let mut bus = IoBus::new();
+        bus.register(0x300..=0x301, Box::new(Latch { value: 0 }));
+
+        bus.write_byte(0x300, 0x5A);
+        assert_eq!(bus.read_byte(0x301), 0x5A);
+        assert_eq!(bus.read_byte(0x302), 0xFF); // Nothing drives the bus
+        bus.write_byte(0x302, 0x11); // Unclaimed, ignored
+        assert_eq!(bus.device_mut::<Latch>().unwrap().value, 0x5A);
+
+        // A later registration shadows the earlier one
+        bus.register(0x301..=0x301, Box::new(Latch { value: 0xEE }));
+        assert_eq!(bus.read_byte(0x300), 0x5A);
+        assert_eq!(bus.read_byte(0x301), 0xEE);
+    }
+
+    #[test]
+    fn test_default_handler_sees_unclaimed_ports() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut bus = IoBus::with_standard_devices();
+        let seen = Rc::clone(&log);
+        bus.set_default_handler(Box::new(move |port, is_write, value| {
+            seen.borrow_mut().push((port, is_write, value));
+            0x42
+        }));
+
+        // Nothing claims LPT1's data port
+        bus.write_byte(0x378, 0x55);
+        assert_eq!(bus.read_byte(0x379), 0x42);
+        // Claimed ports still go to their device
+        bus.write_byte(0x21, 0xFE);
+
+        assert_eq!(
+            *log.borrow(),
+            vec![(0x378, true, 0x55), (0x379, false, 0x00)]
+        );
+    }
+}
@@ -10,22 +10,68 @@
  * - Memory Management (RAM/ROM)
  * - CPU Emulation
  * - BIOS Implementation
+ * - Guest Clock (date/time source)
  * - Disk System
  * - DMA Controller
+ * - I/O Port Bus, PIC and PIT
+ * - Debug-exit port for test programs
+ * - DOS Services (INT 21h)
+ * - Expanded Memory (EMS)
  * - Serial Interface
+ *
+ * The BIOS, disk system, DOS services and EMS need the `std` feature (on by
+ * default). Without it the CPU, memory and port devices build as
+ * `no_std` with `alloc`, for embedding the core elsewhere.
  */
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// Without std there is nowhere to print to, so the tracing output throughout
+// the core compiles away. The arguments are still type checked.
+#[cfg(not(feature = "std"))]
+macro_rules! println {
+    () => {{}};
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+// The parts of the std prelude the core relies on
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+}
+
+#[cfg(feature = "std")]
 pub mod bios;
+#[cfg(feature = "std")]
+pub mod clock;
 pub mod cpu;
+pub mod debug_exit;
+#[cfg(feature = "std")]
 pub mod disk;
 pub mod dma;
+#[cfg(feature = "std")]
+pub mod dos;
+#[cfg(feature = "std")]
+pub mod ems;
+pub mod io;
 pub mod memory;
+pub mod pic;
+pub mod pit;
 pub mod rom;
 pub mod serial;
 
 // Re-export commonly used types
-pub use bios::{init_bios_data_area, init_bios_interrupts};
-pub use cpu::Cpu;
+#[cfg(feature = "std")]
+pub use bios::{init_bios_data_area, init_bios_interrupts, init_bios_interrupts_selective};
+pub use cpu::{Cpu, CpuGeneric, CpuModel, StopReason};
+#[cfg(feature = "std")]
 pub use disk::DiskImage;
 pub use memory::SystemMemory;
 pub use serial::Serial;
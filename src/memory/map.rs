@@ -0,0 +1,174 @@
+/// A memory-mapped device such as a video buffer or an EMS page frame.
+/// Offsets are relative to the base of the region the device is mapped at.
+use crate::prelude::*;
+
+pub trait MemoryDevice {
+    fn read_byte(&self, offset: u32) -> u8;
+    fn write_byte(&mut self, offset: u32, value: u8);
+}
+
+pub enum RegionBacking {
+    Ram(Vec<u8>),
+    Rom(Vec<u8>),
+    Device(Box<dyn MemoryDevice>),
+}
+
+pub struct MemoryRegion {
+    pub base: u32,
+    pub len: u32,
+    pub backing: RegionBacking,
+}
+
+impl MemoryRegion {
+    pub fn ram(base: u32, len: u32) -> Self {
+        MemoryRegion {
+            base,
+            len,
+            backing: RegionBacking::Ram(vec![0; len as usize]),
+        }
+    }
+
+    pub fn rom(base: u32, data: Vec<u8>) -> Self {
+        MemoryRegion {
+            base,
+            len: data.len() as u32,
+            backing: RegionBacking::Rom(data),
+        }
+    }
+
+    pub fn device(base: u32, len: u32, device: Box<dyn MemoryDevice>) -> Self {
+        MemoryRegion {
+            base,
+            len,
+            backing: RegionBacking::Device(device),
+        }
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && addr - self.base < self.len
+    }
+
+    fn read_byte(&self, addr: u32) -> u8 {
+        let offset = addr - self.base;
+        match &self.backing {
+            RegionBacking::Ram(data) | RegionBacking::Rom(data) => data[offset as usize],
+            RegionBacking::Device(device) => device.read_byte(offset),
+        }
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        let offset = addr - self.base;
+        match &mut self.backing {
+            RegionBacking::Ram(data) => data[offset as usize] = value,
+            RegionBacking::Rom(_) => {} // Writes to ROM are ignored
+            RegionBacking::Device(device) => device.write_byte(offset, value),
+        }
+    }
+}
+
+/// An ordered set of regions. Regions mapped later take priority over
+/// earlier ones, so a device can be layered over part of a RAM region.
+#[derive(Default)]
+pub struct MemoryMap {
+    regions: Vec<MemoryRegion>,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        MemoryMap {
+            regions: Vec::new(),
+        }
+    }
+
+    pub fn map(&mut self, region: MemoryRegion) {
+        self.regions.push(region);
+    }
+
+    /// Removes every region starting at `base`, returning how many were
+    /// removed. The first region mapped is the base the others are layered
+    /// over, normally RAM from address 0, and is never removed: with nothing
+    /// else at `base` that is an error rather than a silent loss of memory.
+    pub fn unmap(&mut self, base: u32) -> Result<usize, String> {
+        let before = self.regions.len();
+        let mut first = true;
+        self.regions
+            .retain(|region| core::mem::take(&mut first) || region.base != base);
+        let removed = before - self.regions.len();
+        if removed == 0
+            && self
+                .regions
+                .first()
+                .is_some_and(|region| region.base == base)
+        {
+            return Err(format!("Cannot unmap the base region at {:#07X}", base));
+        }
+        Ok(removed)
+    }
+
+    pub fn region_at(&self, addr: u32) -> Option<&MemoryRegion> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.contains(addr))
+    }
+
+    /// Returns `None` when no region covers the address
+    pub fn read_byte(&self, addr: u32) -> Option<u8> {
+        self.region_at(addr).map(|region| region.read_byte(addr))
+    }
+
+    /// Returns false when no region covers the address
+    pub fn write_byte(&mut self, addr: u32, value: u8) -> bool {
+        match self
+            .regions
+            .iter_mut()
+            .rev()
+            .find(|region| region.contains(addr))
+        {
+            Some(region) => {
+                region.write_byte(addr, value);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_priority() {
+        let mut map = MemoryMap::new();
+        map.map(MemoryRegion::ram(0, 0x1000));
+        map.map(MemoryRegion::rom(0x800, vec![0xAA; 0x100]));
+
+        assert!(map.write_byte(0x7FF, 0x11));
+        assert!(map.write_byte(0x800, 0x22)); // Covered by ROM, ignored
+        assert_eq!(map.read_byte(0x7FF), Some(0x11));
+        assert_eq!(map.read_byte(0x800), Some(0xAA));
+        assert_eq!(map.read_byte(0x1000), None);
+        assert!(!map.write_byte(0x1000, 0x33));
+
+        assert_eq!(map.unmap(0x800), Ok(1));
+        assert_eq!(map.read_byte(0x800), Some(0x00));
+        assert_eq!(map.unmap(0x800), Ok(0));
+    }
+
+    #[test]
+    fn test_base_region_stays_mapped() {
+        let mut map = MemoryMap::new();
+        map.map(MemoryRegion::ram(0, 0x1000));
+        assert!(map.write_byte(0x10, 0x5A));
+
+        assert!(map.unmap(0).is_err());
+        assert_eq!(map.read_byte(0x10), Some(0x5A));
+
+        // Something layered at the same base still comes off, leaving RAM
+        map.map(MemoryRegion::rom(0, vec![0xAA; 0x100]));
+        assert_eq!(map.read_byte(0x10), Some(0xAA));
+        assert_eq!(map.unmap(0), Ok(1));
+        assert_eq!(map.read_byte(0x10), Some(0x5A));
+    }
+}
@@ -1,4 +1,5 @@
-use std::any::Any;
+use crate::prelude::*;
+use core::any::Any;
 
 pub trait Memory: Any {
     fn read_byte(&self, addr: u32) -> u8;
@@ -17,12 +18,49 @@ pub trait Memory: Any {
     }
 
     fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
+// What `Cpu` holds: every call goes through to the boxed memory, so
+// downcasts through `as_any` still see what's inside
+impl Memory for Box<dyn Memory> {
+    fn read_byte(&self, addr: u32) -> u8 {
+        (**self).read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        (**self).write_byte(addr, value)
+    }
+
+    fn has_valid_rom(&self) -> bool {
+        (**self).has_valid_rom()
+    }
+
+    fn read_word(&self, addr: u32) -> u16 {
+        (**self).read_word(addr)
+    }
+
+    fn write_word(&mut self, addr: u32, value: u16) {
+        (**self).write_word(addr, value)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        (**self).as_any()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        (**self).as_any_mut()
+    }
+}
+
+pub mod map;
+pub mod profiled;
 pub mod ram;
 pub mod system;
 
-pub use system::SystemMemory;
+pub use map::{MemoryDevice, MemoryMap, MemoryRegion, RegionBacking};
+pub use profiled::MemoryProfiler;
+pub use system::{SystemMemory, CONVENTIONAL_MEMORY_LIMIT};
 
 #[cfg(test)]
 mod tests {
@@ -48,6 +86,10 @@ mod tests {
         fn as_any(&self) -> &dyn Any {
             self
         }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
     }
 
     #[test]
@@ -0,0 +1,52 @@
+// Memory wrapper that reports every access to a profiler callback. The CPU
+// only puts it in front of its memory once a profiler is installed, so
+// without one nothing changes on the access path.
+
+use super::Memory;
+use crate::prelude::*;
+use alloc::rc::Rc;
+use core::any::Any;
+use core::cell::RefCell;
+
+/// Called as `(physical address, is_write)` for every byte the guest reads
+/// or writes, instruction fetches included. Word accesses show up as two
+/// byte accesses.
+pub type MemoryProfiler = Box<dyn FnMut(u32, bool)>;
+
+pub(crate) struct ProfiledMemory {
+    inner: Box<dyn Memory>,
+    // Shared with the CPU so the profiler can be swapped without rewrapping
+    profiler: Rc<RefCell<MemoryProfiler>>,
+}
+
+impl ProfiledMemory {
+    pub(crate) fn new(inner: Box<dyn Memory>, profiler: Rc<RefCell<MemoryProfiler>>) -> Self {
+        ProfiledMemory { inner, profiler }
+    }
+}
+
+impl Memory for ProfiledMemory {
+    fn read_byte(&self, addr: u32) -> u8 {
+        (self.profiler.borrow_mut())(addr, false);
+        self.inner.read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) {
+        (self.profiler.borrow_mut())(addr, true);
+        self.inner.write_byte(addr, value);
+    }
+
+    fn has_valid_rom(&self) -> bool {
+        self.inner.has_valid_rom()
+    }
+
+    // Downcasts see through the wrapper to the memory underneath, so code
+    // looking for e.g. SystemMemory keeps working while profiling
+    fn as_any(&self) -> &dyn Any {
+        self.inner.as_any()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self.inner.as_any_mut()
+    }
+}
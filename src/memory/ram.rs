@@ -1,5 +1,6 @@
 use super::Memory;
-use std::any::Any;
+use crate::prelude::*;
+use core::any::Any;
 
 pub struct RamMemory {
     memory: Vec<u8>,
@@ -42,6 +43,10 @@ impl Memory for RamMemory {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 #[cfg(test)]
@@ -1,17 +1,49 @@
+use super::map::{MemoryMap, MemoryRegion};
 use super::Memory;
+use crate::prelude::*;
 use crate::rom::BiosRom;
-use std::any::Any;
+use core::any::Any;
+
+/// Where conventional memory ends and the video buffer area begins
+pub const CONVENTIONAL_MEMORY_LIMIT: u32 = 0xA0000;
+
+/// Every option ROM starts 55h AAh, then its length in these units
+pub const OPTION_ROM_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+pub const OPTION_ROM_LENGTH_UNIT: usize = 512;
+
+// Where the BIOS ROM, loaded separately with `load_bios_rom`, begins
+const BIOS_ROM_BASE: u32 = 0xF0000;
 
 pub struct SystemMemory {
-    ram: Vec<u8>,
+    map: MemoryMap,
     bios_rom: BiosRom,
+    // What a read with nothing behind it returns
+    unmapped_value: u8,
 }
 
 impl SystemMemory {
+    /// A flat block of RAM from address 0; unmapped addresses read as 0
     pub fn new(ram_size: usize) -> Self {
+        Self::build(ram_size as u32, 0x00)
+    }
+
+    /// Lays memory out the way a real PC does: at most 640K of RAM, with
+    /// 0xA0000-0xEFFFF left for video buffers and adapter ROMs and the BIOS
+    /// at 0xF0000. Nothing in the hole is RAM, so reads float high (0xFF)
+    /// until a device is mapped there and a memory-size probe stops at the
+    /// 640K boundary.
+    pub fn with_memory_holes(ram_size: usize) -> Self {
+        Self::build((ram_size as u32).min(CONVENTIONAL_MEMORY_LIMIT), 0xFF)
+    }
+
+    fn build(ram_size: u32, unmapped_value: u8) -> Self {
+        let mut map = MemoryMap::new();
+        map.map(MemoryRegion::ram(0, ram_size));
+
         let mut system = SystemMemory {
-            ram: vec![0; ram_size],
+            map,
             bios_rom: BiosRom::new(),
+            unmapped_value,
         };
 
         // Verify ROM code after initialization
@@ -22,6 +54,101 @@ impl SystemMemory {
     pub fn has_valid_rom(&self) -> bool {
         self.bios_rom.has_valid_code()
     }
+
+    /// Replaces the built-in BIOS with a raw ROM image, mapped so that it
+    /// ends at 0x100000. The image is validated first (see
+    /// `BiosRom::from_image`); a rejected image leaves the current ROM in
+    /// place.
+    pub fn load_bios_rom(&mut self, image: &[u8]) -> Result<(), String> {
+        self.bios_rom = BiosRom::from_image(image)?;
+        println!("Loaded {} byte BIOS ROM image", image.len());
+        Ok(())
+    }
+
+    /// Writes `bytes` into the BIOS ROM at `offset` from 0xF0000, which
+    /// ordinary bus writes can't reach
+    pub fn patch_bios_rom(&mut self, offset: usize, bytes: &[u8]) -> Result<(), String> {
+        self.bios_rom.patch(offset, bytes)
+    }
+
+    /// Reads a BIOS ROM image from a file and loads it with `load_bios_rom`
+    #[cfg(feature = "std")]
+    pub fn load_bios_rom_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let image = std::fs::read(path)
+            .map_err(|e| format!("Failed to read BIOS ROM {}: {}", path.display(), e))?;
+        self.load_bios_rom(&image)
+    }
+
+    /// Maps an option ROM image at `base`, e.g. a video BIOS at 0xC0000,
+    /// once it passes the checks POST makes: the 55h AAh signature, and the
+    /// bytes over the length declared in byte 2 (in 512-byte units) adding
+    /// up to zero (mod 256). Anything past the declared length is mapped
+    /// but not checked.
+    pub fn map_rom_checked(&mut self, base: u32, image: &[u8]) -> Result<(), String> {
+        let end = base as u64 + image.len() as u64;
+        if image.is_empty() || end > BIOS_ROM_BASE as u64 {
+            return Err(format!(
+                "ROM image of {} bytes at {:05X} doesn't fit below the BIOS at {:05X}",
+                image.len(),
+                base,
+                BIOS_ROM_BASE
+            ));
+        }
+        if image.len() < 3 || image[..2] != OPTION_ROM_SIGNATURE {
+            return Err(format!("No option ROM signature at {:05X}", base));
+        }
+
+        let length = image[2] as usize * OPTION_ROM_LENGTH_UNIT;
+        if length == 0 || length > image.len() {
+            return Err(format!(
+                "ROM at {:05X} declares {} bytes but the image has {}",
+                base,
+                length,
+                image.len()
+            ));
+        }
+        let checksum = image[..length]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if checksum != 0 {
+            return Err(format!(
+                "ROM checksum over {:05X}-{:05X} is {:02X}, expected 00",
+                base,
+                base + length as u32 - 1,
+                checksum
+            ));
+        }
+
+        self.map.map(MemoryRegion::rom(base, image.to_vec()));
+        println!("Mapped {} byte ROM at {:05X}", image.len(), base);
+        Ok(())
+    }
+
+    /// Reads a ROM image from a file and maps it with `map_rom_checked`
+    #[cfg(feature = "std")]
+    pub fn load_rom_checked<P: AsRef<std::path::Path>>(
+        &mut self,
+        base: u32,
+        path: P,
+    ) -> Result<(), String> {
+        let path = path.as_ref();
+        let image = std::fs::read(path)
+            .map_err(|e| format!("Failed to read ROM {}: {}", path.display(), e))?;
+        self.map_rom_checked(base, &image)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Maps a region over the address space. The BIOS ROM at 0xF0000 always
+    /// takes priority; anywhere else the new region shadows what was there.
+    pub fn map_region(&mut self, region: MemoryRegion) {
+        self.map.map(region);
+    }
+
+    /// Removes the regions mapped at `base`; see `MemoryMap::unmap`
+    pub fn unmap_region(&mut self, base: u32) -> Result<usize, String> {
+        self.map.unmap(base)
+    }
 }
 
 impl Memory for SystemMemory {
@@ -29,21 +156,16 @@ impl Memory for SystemMemory {
         if (0xF0000..=0xFFFFF).contains(&addr) {
             // BIOS ROM area (64KB)
             self.bios_rom.read_byte((addr - 0xF0000) as usize)
-        } else if (addr as usize) < self.ram.len() {
-            // RAM area
-            self.ram[addr as usize]
         } else {
-            // Invalid memory address
-            0
+            self.map.read_byte(addr).unwrap_or(self.unmapped_value)
         }
     }
 
     fn write_byte(&mut self, addr: u32, value: u8) {
         if (0xF0000..=0xFFFFF).contains(&addr) {
             // BIOS ROM area - writes are ignored
-        } else if (addr as usize) < self.ram.len() {
-            // RAM area
-            self.ram[addr as usize] = value;
+        } else {
+            self.map.write_byte(addr, value);
         }
     }
 
@@ -54,11 +176,18 @@ impl Memory for SystemMemory {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::map::MemoryDevice;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     #[test]
     fn test_system_memory_initialization() {
@@ -112,4 +241,143 @@ mod tests {
         let system = SystemMemory::new(1024);
         assert!(system.has_valid_rom());
     }
+
+    struct RecordingDevice {
+        writes: Rc<RefCell<Vec<(u32, u8)>>>,
+    }
+
+    impl MemoryDevice for RecordingDevice {
+        fn read_byte(&self, offset: u32) -> u8 {
+            0x80 | (offset as u8 & 0x0F)
+        }
+
+        fn write_byte(&mut self, offset: u32, value: u8) {
+            self.writes.borrow_mut().push((offset, value));
+        }
+    }
+
+    #[test]
+    fn test_system_memory_device_region() {
+        let mut system = SystemMemory::new(1024 * 1024);
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        system.map_region(MemoryRegion::device(
+            0xE0000,
+            0x10000,
+            Box::new(RecordingDevice {
+                writes: Rc::clone(&writes),
+            }),
+        ));
+
+        system.write_byte(0xE0003, 0x42);
+        assert_eq!(*writes.borrow(), vec![(3, 0x42)]);
+        assert_eq!(system.read_byte(0xE0005), 0x85);
+
+        // RAM on either side of the device is untouched
+        system.write_byte(0xDFFFF, 0x11);
+        assert_eq!(system.read_byte(0xDFFFF), 0x11);
+        assert_eq!(system.read_byte(0x100), 0);
+        assert_eq!(writes.borrow().len(), 1);
+
+        // Unmapping restores the underlying RAM
+        assert_eq!(system.unmap_region(0xE0000), Ok(1));
+        assert_eq!(system.read_byte(0xE0003), 0);
+        // RAM itself can't be unmapped
+        assert!(system.unmap_region(0).is_err());
+        assert_eq!(system.read_byte(0xDFFFF), 0x11);
+    }
+
+    // The classic memory-size probe: write a pattern, read it back and stop
+    // at the first address that doesn't hold it
+    fn probe_ram_top(system: &mut SystemMemory) -> u32 {
+        let mut addr = 0;
+        while addr < 0x100000 {
+            system.write_byte(addr, 0x55);
+            let first = system.read_byte(addr);
+            system.write_byte(addr, 0xAA);
+            if first != 0x55 || system.read_byte(addr) != 0xAA {
+                break;
+            }
+            addr += 0x400;
+        }
+        addr
+    }
+
+    #[test]
+    fn test_memory_holes_stop_probe_at_640k() {
+        let mut system = SystemMemory::with_memory_holes(1024 * 1024);
+        assert_eq!(probe_ram_top(&mut system), CONVENTIONAL_MEMORY_LIMIT);
+
+        // The video hole floats high and the upper area doesn't hold writes
+        assert_eq!(system.read_byte(0xA0000), 0xFF);
+        assert_eq!(system.read_byte(0xBFFFF), 0xFF);
+        system.write_byte(0xD0000, 0x12);
+        assert_eq!(system.read_byte(0xD0000), 0xFF);
+        assert!(system.has_valid_rom());
+
+        // Less RAM than 640K moves the boundary down
+        let mut system = SystemMemory::with_memory_holes(256 * 1024);
+        assert_eq!(probe_ram_top(&mut system), 0x40000);
+
+        // The flat layout behaves as before
+        let mut system = SystemMemory::new(1024 * 1024);
+        assert_eq!(probe_ram_top(&mut system), 0xF0000);
+    }
+
+    #[test]
+    fn test_memory_hole_device_mapping() {
+        let mut system = SystemMemory::with_memory_holes(640 * 1024);
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        system.map_region(MemoryRegion::device(
+            0xB8000,
+            0x8000,
+            Box::new(RecordingDevice {
+                writes: Rc::clone(&writes),
+            }),
+        ));
+
+        assert_eq!(system.read_byte(0xB8001), 0x81);
+        system.write_byte(0xB8000, 0x41);
+        assert_eq!(*writes.borrow(), vec![(0, 0x41)]);
+        // The monochrome half of the hole is still empty
+        assert_eq!(system.read_byte(0xB0000), 0xFF);
+    }
+
+    #[test]
+    fn test_load_rom_checked_sums_the_declared_length() {
+        // 32K declared, with one checksum byte for the whole image: neither
+        // 8K block sums to zero by itself
+        let mut image = vec![0u8; 0x8000];
+        image[..3].copy_from_slice(&[0x55, 0xAA, 0x40]);
+        image[0x10] = 0x12;
+        let sum = image.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        image[0x7FFF] = sum.wrapping_neg();
+        let mut system = SystemMemory::with_memory_holes(640 * 1024);
+        system.map_rom_checked(0xC0000, &image).unwrap();
+        assert_eq!(system.read_byte(0xC0001), 0xAA);
+        // ROM doesn't take writes
+        system.write_byte(0xC0001, 0x00);
+        assert_eq!(system.read_byte(0xC0001), 0xAA);
+
+        // Padding past the declared length isn't checked
+        let mut padded = image.clone();
+        padded.extend_from_slice(&[0x01; 0x800]);
+        let mut system = SystemMemory::with_memory_holes(640 * 1024);
+        system.map_rom_checked(0xC0000, &padded).unwrap();
+
+        image[0x4010] ^= 0x01;
+        let path = std::env::temp_dir().join(format!("dos_emu_rom_{}.bin", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+        let mut system = SystemMemory::with_memory_holes(640 * 1024);
+        let err = system.load_rom_checked(0xC8000, &path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.contains("C8000-CFFFF"), "{}", err);
+        assert_eq!(system.read_byte(0xC8000), 0xFF);
+
+        // No signature, a length longer than the image, or no room
+        assert!(system.map_rom_checked(0xC0000, &[0; 0x2000]).is_err());
+        let mut short = vec![0u8; 0x2000];
+        short[..3].copy_from_slice(&[0x55, 0xAA, 0x20]);
+        assert!(system.map_rom_checked(0xC0000, &short).is_err());
+        assert!(system.map_rom_checked(0xEF000, &image).is_err());
+    }
 }
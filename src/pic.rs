@@ -0,0 +1,217 @@
+// Intel 8259A programmable interrupt controller, as a single master the way
+// the PC/XT wires it. Handles the ICW initialization sequence, the mask
+// register and end-of-interrupt commands.
+
+use crate::io::IoDevice;
+use core::any::Any;
+
+pub const PIC_COMMAND_PORT: u16 = 0x20;
+pub const PIC_DATA_PORT: u16 = 0x21;
+
+// Where the BIOS puts IRQ 0-7 (INT 08h-0Fh)
+const DEFAULT_VECTOR_BASE: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitStep {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+pub struct Pic {
+    pub imr: u8,
+    pub irr: u8,
+    pub isr: u8,
+    pub vector_base: u8,
+    init_step: InitStep,
+    single: bool,
+    needs_icw4: bool,
+    // OCW3 selects whether command port reads return the ISR or the IRR
+    read_isr: bool,
+}
+
+impl Default for Pic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pic {
+    pub fn new() -> Self {
+        Pic {
+            imr: 0x00,
+            irr: 0x00,
+            isr: 0x00,
+            vector_base: DEFAULT_VECTOR_BASE,
+            init_step: InitStep::Ready,
+            single: true,
+            needs_icw4: false,
+            read_isr: false,
+        }
+    }
+
+    /// Raises an IRQ line; the request is latched in the IRR until acknowledged
+    pub fn request(&mut self, irq: u8) {
+        self.irr |= 1 << (irq & 0x07);
+    }
+
+    /// The highest priority unmasked request, unless an IRQ of equal or
+    /// higher priority (lower number) is still in service
+    pub fn pending(&self) -> Option<u8> {
+        let requests = self.irr & !self.imr;
+        for irq in 0..8 {
+            if self.isr & (1 << irq) != 0 {
+                return None;
+            }
+            if requests & (1 << irq) != 0 {
+                return Some(irq);
+            }
+        }
+        None
+    }
+
+    /// The interrupt acknowledge cycle: moves the pending IRQ from the IRR to
+    /// the ISR and returns the vector the CPU should take
+    pub fn acknowledge(&mut self) -> Option<u8> {
+        let irq = self.pending()?;
+        self.irr &= !(1 << irq);
+        self.isr |= 1 << irq;
+        Some(self.vector_base + irq)
+    }
+
+    fn write_command(&mut self, value: u8) {
+        if value & 0x10 != 0 {
+            // ICW1 restarts initialization and clears the mask
+            self.single = value & 0x02 != 0;
+            self.needs_icw4 = value & 0x01 != 0;
+            self.imr = 0x00;
+            self.isr = 0x00;
+            self.read_isr = false;
+            self.init_step = InitStep::Icw2;
+        } else if value & 0x08 != 0 {
+            // OCW3: register read select
+            if value & 0x02 != 0 {
+                self.read_isr = value & 0x01 != 0;
+            }
+        } else {
+            // OCW2: end of interrupt
+            match value & 0xE0 {
+                0x20 => {
+                    // Non-specific EOI clears the highest priority in-service IRQ
+                    if self.isr != 0 {
+                        self.isr &= self.isr - 1;
+                    }
+                }
+                0x60 => self.isr &= !(1 << (value & 0x07)),
+                _ => println!("PIC: Ignoring OCW2 0x{:02X}", value),
+            }
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.init_step = match self.init_step {
+            InitStep::Ready => {
+                self.imr = value; // OCW1
+                InitStep::Ready
+            }
+            InitStep::Icw2 => {
+                self.vector_base = value & 0xF8;
+                if !self.single {
+                    InitStep::Icw3
+                } else if self.needs_icw4 {
+                    InitStep::Icw4
+                } else {
+                    InitStep::Ready
+                }
+            }
+            InitStep::Icw3 => {
+                if self.needs_icw4 {
+                    InitStep::Icw4
+                } else {
+                    InitStep::Ready
+                }
+            }
+            InitStep::Icw4 => InitStep::Ready,
+        };
+    }
+}
+
+impl IoDevice for Pic {
+    fn read_port(&mut self, port: u16) -> u8 {
+        match port {
+            PIC_COMMAND_PORT if self.read_isr => self.isr,
+            PIC_COMMAND_PORT => self.irr,
+            _ => self.imr,
+        }
+    }
+
+    fn write_port(&mut self, port: u16, value: u8) {
+        match port {
+            PIC_COMMAND_PORT => self.write_command(value),
+            _ => self.write_data(value),
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Pic::new();
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_sequence_and_mask() {
+        let mut pic = Pic::new();
+        // ICW1 (edge, single, ICW4), ICW2 vector base 0x08, ICW4 8086 mode
+        pic.write_port(PIC_COMMAND_PORT, 0x13);
+        pic.write_port(PIC_DATA_PORT, 0x08);
+        pic.write_port(PIC_DATA_PORT, 0x01);
+        assert_eq!(pic.vector_base, 0x08);
+
+        // OCW1 now lands in the mask register
+        pic.write_port(PIC_DATA_PORT, 0xBC);
+        assert_eq!(pic.read_port(PIC_DATA_PORT), 0xBC);
+    }
+
+    #[test]
+    fn test_eoi_and_isr_read() {
+        let mut pic = Pic::new();
+        pic.isr = 0b0000_0101;
+        pic.irr = 0b1000_0000;
+
+        assert_eq!(pic.read_port(PIC_COMMAND_PORT), 0b1000_0000);
+        pic.write_port(PIC_COMMAND_PORT, 0x0B); // OCW3: read ISR
+        assert_eq!(pic.read_port(PIC_COMMAND_PORT), 0b0000_0101);
+
+        pic.write_port(PIC_COMMAND_PORT, 0x20); // Non-specific EOI
+        assert_eq!(pic.isr, 0b0000_0100);
+        pic.write_port(PIC_COMMAND_PORT, 0x62); // Specific EOI for IRQ 2
+        assert_eq!(pic.isr, 0);
+    }
+
+    #[test]
+    fn test_acknowledge_priority_and_mask() {
+        let mut pic = Pic::new();
+        pic.request(4);
+        pic.request(1);
+        pic.imr = 0b0000_0010; // IRQ 1 masked
+
+        assert_eq!(pic.acknowledge(), Some(0x0C));
+        assert_eq!(pic.isr, 0b0001_0000);
+        assert_eq!(pic.irr, 0b0000_0010);
+
+        // Once unmasked, IRQ 1 outranks the in-service IRQ 4
+        pic.imr = 0;
+        assert_eq!(pic.pending(), Some(1));
+        pic.request(6);
+        pic.isr = 0b0000_0001; // IRQ 0 in service blocks everything
+        assert_eq!(pic.acknowledge(), None);
+    }
+}
@@ -0,0 +1,206 @@
+// Intel 8253 programmable interval timer. Channel 0 drives IRQ 0, channel 1
+// refreshes DRAM and channel 2 feeds the speaker. Only the programming
+// interface is modeled so far; counters hold their reload value until
+// something ticks them.
+
+use crate::io::IoDevice;
+use core::any::Any;
+
+/// The PIT's input clock. Channel 0 divides it by 65536 for the ~18.2 Hz
+/// BIOS timer tick.
+pub const PIT_INPUT_HZ: u64 = 1_193_182;
+
+pub const PIT_CHANNEL0_PORT: u16 = 0x40;
+pub const PIT_CONTROL_PORT: u16 = 0x43;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    LowByte,
+    HighByte,
+    LowHigh,
+}
+
+#[derive(Debug, Clone)]
+pub struct PitChannel {
+    pub reload: u16,
+    pub count: u16,
+    pub mode: u8,
+    pub bcd: bool,
+    pub access: AccessMode,
+    // Count captured by a latch command, returned by the next read(s)
+    latched: Option<u16>,
+    // For LowHigh access: whether the next write/read is the high byte
+    write_high: bool,
+    read_high: bool,
+}
+
+impl PitChannel {
+    fn new() -> Self {
+        PitChannel {
+            reload: 0,
+            count: 0,
+            mode: 0,
+            bcd: false,
+            access: AccessMode::LowHigh,
+            latched: None,
+            write_high: false,
+            read_high: false,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.access {
+            AccessMode::LowByte => self.load((self.reload & 0xFF00) | value as u16),
+            AccessMode::HighByte => self.load((self.reload & 0x00FF) | ((value as u16) << 8)),
+            AccessMode::LowHigh => {
+                if self.write_high {
+                    self.load((self.reload & 0x00FF) | ((value as u16) << 8));
+                } else {
+                    // Counting is held off until the full value is written
+                    self.reload = (self.reload & 0xFF00) | value as u16;
+                }
+                self.write_high = !self.write_high;
+            }
+        }
+    }
+
+    fn load(&mut self, reload: u16) {
+        self.reload = reload;
+        self.count = reload;
+    }
+
+    fn read(&mut self) -> u8 {
+        let value = self.latched.unwrap_or(self.count);
+        match self.access {
+            AccessMode::LowByte => {
+                self.latched = None;
+                value as u8
+            }
+            AccessMode::HighByte => {
+                self.latched = None;
+                (value >> 8) as u8
+            }
+            AccessMode::LowHigh => {
+                let byte = if self.read_high {
+                    self.latched = None;
+                    (value >> 8) as u8
+                } else {
+                    value as u8
+                };
+                self.read_high = !self.read_high;
+                byte
+            }
+        }
+    }
+}
+
+pub struct Pit {
+    pub channels: [PitChannel; 3],
+}
+
+impl Default for Pit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pit {
+    pub fn new() -> Self {
+        Pit {
+            channels: [PitChannel::new(), PitChannel::new(), PitChannel::new()],
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        let channel = (value >> 6) as usize;
+        if channel == 3 {
+            // Read-back command only exists on the 8254
+            return;
+        }
+
+        let ch = &mut self.channels[channel];
+        let access = match (value >> 4) & 0x03 {
+            0 => {
+                // Counter latch command; a second latch before the read is ignored
+                if ch.latched.is_none() {
+                    ch.latched = Some(ch.count);
+                    ch.read_high = false;
+                }
+                return;
+            }
+            1 => AccessMode::LowByte,
+            2 => AccessMode::HighByte,
+            _ => AccessMode::LowHigh,
+        };
+        ch.access = access;
+        ch.mode = (value >> 1) & 0x07;
+        ch.bcd = value & 0x01 != 0;
+        ch.latched = None;
+        ch.write_high = false;
+        ch.read_high = false;
+    }
+}
+
+impl IoDevice for Pit {
+    fn read_port(&mut self, port: u16) -> u8 {
+        match port {
+            0x40..=0x42 => self.channels[(port - PIT_CHANNEL0_PORT) as usize].read(),
+            _ => 0, // Control register is write-only
+        }
+    }
+
+    fn write_port(&mut self, port: u16, value: u8) {
+        match port {
+            0x40..=0x42 => self.channels[(port - PIT_CHANNEL0_PORT) as usize].write(value),
+            PIT_CONTROL_PORT => self.write_control(value),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Pit::new();
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_modes() {
+        let mut pit = Pit::new();
+
+        // Channel 2, low byte only, mode 3
+        pit.write_port(PIT_CONTROL_PORT, 0b1001_0110);
+        pit.write_port(0x42, 0x34);
+        assert_eq!(pit.channels[2].reload, 0x0034);
+        assert_eq!(pit.channels[2].mode, 3);
+        assert_eq!(pit.read_port(0x42), 0x34);
+        assert_eq!(pit.read_port(0x42), 0x34);
+
+        // Channel 1, high byte only
+        pit.write_port(PIT_CONTROL_PORT, 0b0110_0100);
+        pit.write_port(0x41, 0x12);
+        assert_eq!(pit.channels[1].count, 0x1200);
+        assert_eq!(pit.channels[1].mode, 2);
+    }
+
+    #[test]
+    fn test_latch_holds_value_until_read() {
+        let mut pit = Pit::new();
+        pit.write_port(PIT_CONTROL_PORT, 0x36);
+        pit.write_port(0x40, 0xCD);
+        pit.write_port(0x40, 0xAB);
+
+        pit.write_port(PIT_CONTROL_PORT, 0x00);
+        pit.channels[0].count = 0x1111; // Counter moves on after the latch
+        assert_eq!(pit.read_port(0x40), 0xCD);
+        assert_eq!(pit.read_port(0x40), 0xAB);
+        // Latch released; reads now follow the live counter
+        assert_eq!(pit.read_port(0x40), 0x11);
+    }
+}
@@ -1,3 +1,11 @@
+use crate::prelude::*;
+
+/// Size of the BIOS window at 0xF0000
+pub const BIOS_ROM_SIZE: usize = 0x10000;
+
+// Offset of the reset vector (F000:FFF0) in the BIOS window
+const RESET_VECTOR_OFFSET: usize = 0xFFF0;
+
 pub struct BiosRom {
     data: Vec<u8>,
     has_valid_code: bool,
@@ -39,6 +47,11 @@ const SERIAL_HANDLER: &[u8] = &[
     0xCF, // IRET
 ];
 
+const BOOT_FAILURE_HANDLER: [u8; 3] = [
+    0xF4, // HLT
+    0xEB, 0xFD, // JMP back to the HLT
+];
+
 impl Default for BiosRom {
     fn default() -> Self {
         Self::new()
@@ -54,6 +67,13 @@ impl BiosRom {
         let serial_offset = 0xE000;
         data[serial_offset..serial_offset + SERIAL_HANDLER.len()].copy_from_slice(SERIAL_HANDLER);
 
+        // Services handled natively return through an IRET at F000:E320
+        data[0xE320] = 0xCF; // IRET
+
+        // INT 18h at F000:E330 parks the CPU once the BIOS has reported that
+        // there is nothing to boot
+        data[0xE330..0xE333].copy_from_slice(&BOOT_FAILURE_HANDLER);
+
         // Reset vector at F000:FFF0
         let reset_vector = 0xFFF0;
         data[reset_vector] = 0xEA; // Far JMP
@@ -98,6 +118,24 @@ impl BiosRom {
         self.has_valid_code
     }
 
+    /// Overwrites part of the image in place, as a ROM programmer would.
+    /// Used to plant test code at the reset vector.
+    pub fn patch(&mut self, offset: usize, bytes: &[u8]) -> Result<(), String> {
+        let window = self
+            .data
+            .get_mut(offset..offset + bytes.len())
+            .ok_or_else(|| {
+                format!(
+                    "{} bytes at ROM offset {:#06X} run past the end of the {}K ROM",
+                    bytes.len(),
+                    offset,
+                    BIOS_ROM_SIZE / 1024
+                )
+            })?;
+        window.copy_from_slice(bytes);
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn set_valid_code(&mut self, valid: bool) {
         self.has_valid_code = valid;
@@ -113,6 +151,8 @@ impl BiosRom {
         // Add serial interrupt handler at F000:E000
         let serial_offset = 0xE000;
         data[serial_offset..serial_offset + SERIAL_HANDLER.len()].copy_from_slice(SERIAL_HANDLER);
+        data[0xE320] = 0xCF; // Native service IRET
+        data[0xE330..0xE333].copy_from_slice(&BOOT_FAILURE_HANDLER);
 
         // BIOS entry point at F000:E05B
         let entry_point = 0xE05B;
@@ -137,6 +177,57 @@ impl BiosRom {
     }
 }
 
+impl BiosRom {
+    /// Takes a raw BIOS image as-is, aligned so it ends at 0x100000; an
+    /// image smaller than 64K leaves the bottom of the window reading 0xFF
+    /// like an empty socket. Unlike `from_data` none of the built-in stubs
+    /// are patched in, so the image has to supply its own handlers.
+    ///
+    /// The image is checked the way POST would before trusting it: the
+    /// reset vector must be a far JMP to somewhere inside the image, and the
+    /// bytes must add up to zero (mod 256).
+    pub fn from_image(image: &[u8]) -> Result<Self, String> {
+        if image.is_empty() || image.len() > BIOS_ROM_SIZE {
+            return Err(format!(
+                "BIOS ROM image is {} bytes; expected 1-{} bytes",
+                image.len(),
+                BIOS_ROM_SIZE
+            ));
+        }
+
+        let mut data = vec![0xFF; BIOS_ROM_SIZE];
+        let start = BIOS_ROM_SIZE - image.len();
+        data[start..].copy_from_slice(image);
+
+        if start > RESET_VECTOR_OFFSET || data[RESET_VECTOR_OFFSET] != 0xEA {
+            return Err("BIOS ROM has no far JMP at the reset vector".to_string());
+        }
+        let ip = u16::from_le_bytes([data[RESET_VECTOR_OFFSET + 1], data[RESET_VECTOR_OFFSET + 2]]);
+        let cs = u16::from_le_bytes([data[RESET_VECTOR_OFFSET + 3], data[RESET_VECTOR_OFFSET + 4]]);
+        let target = ((cs as u32) << 4) + ip as u32;
+        let rom_base = 0x100000 - image.len() as u32;
+        if !(rom_base..0x100000).contains(&target) {
+            return Err(format!(
+                "BIOS ROM reset vector jumps to {:04X}:{:04X}, outside the ROM",
+                cs, ip
+            ));
+        }
+
+        let checksum = image.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if checksum != 0 {
+            return Err(format!(
+                "BIOS ROM checksum is {:02X}, expected 00",
+                checksum
+            ));
+        }
+
+        Ok(BiosRom {
+            data,
+            has_valid_code: true,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -209,8 +300,8 @@ mod tests {
         let mut data = vec![0; 0x10000];
 
         // Fill with test pattern
-        for i in 0..0x10000 {
-            data[i] = (i % 256) as u8;
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i % 256) as u8;
         }
 
         let rom = BiosRom::from_data(data);
@@ -239,8 +330,87 @@ mod tests {
         assert_eq!(slice.len(), 0x10000);
 
         // Verify slice contents match direct reads
-        for i in 0..0x10000 {
-            assert_eq!(slice[i], rom.read_byte(i));
+        for (i, &byte) in slice.iter().enumerate() {
+            assert_eq!(byte, rom.read_byte(i));
         }
     }
+
+    // A 64K image whose reset vector jumps to F000:E05B, fixed up so the
+    // checksum comes out to zero
+    fn minimal_rom_image() -> Vec<u8> {
+        let mut image = vec![0; BIOS_ROM_SIZE];
+        image[0xFFF0..0xFFF5].copy_from_slice(&[0xEA, 0x5B, 0xE0, 0x00, 0xF0]);
+        image[0xE05B] = 0xF4; // HLT
+        let sum = image.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        image[0xFFFF] = sum.wrapping_neg();
+        image
+    }
+
+    #[test]
+    fn test_bios_rom_from_image_validation() {
+        let rom = BiosRom::from_image(&minimal_rom_image()).unwrap();
+        assert!(rom.has_valid_code());
+        assert_eq!(rom.read_byte(0xE05B), 0xF4);
+        // None of the built-in stubs are patched in
+        assert_eq!(rom.read_byte(0xE000), 0x00);
+
+        // A short image ends at the top of the window
+        let mut short = minimal_rom_image()[0xE000..].to_vec();
+        short[0] = 0;
+        let sum = short.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        short[0] = sum.wrapping_neg();
+        let rom = BiosRom::from_image(&short).unwrap();
+        assert_eq!(rom.read_byte(0xDFFF), 0xFF);
+        assert_eq!(rom.read_byte(0xFFF0), 0xEA);
+
+        let mut bad_checksum = minimal_rom_image();
+        bad_checksum[0x1234] ^= 0x01;
+        assert!(BiosRom::from_image(&bad_checksum).is_err());
+
+        let mut no_jump = minimal_rom_image();
+        no_jump[0xFFF0] = 0x90;
+        no_jump[0xFFFF] = no_jump[0xFFFF].wrapping_add(0xEA - 0x90);
+        assert!(BiosRom::from_image(&no_jump).is_err());
+
+        // Reset vector pointing into RAM
+        let mut escapes = minimal_rom_image();
+        escapes[0xFFF4] = 0x00;
+        escapes[0xFFFF] = escapes[0xFFFF].wrapping_add(0xF0);
+        assert!(BiosRom::from_image(&escapes).is_err());
+
+        assert!(BiosRom::from_image(&[]).is_err());
+        assert!(BiosRom::from_image(&vec![0; BIOS_ROM_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_load_bios_rom_reset_jump() {
+        use crate::cpu::test_utils::setup_executable_cpu;
+        use crate::memory::{Memory, SystemMemory};
+
+        let mut system = SystemMemory::new(1024 * 1024);
+        let mut bad = minimal_rom_image();
+        bad[0] ^= 0xFF;
+        assert!(system.load_bios_rom(&bad).is_err());
+        // The built-in ROM is still there
+        assert_eq!(system.read_byte(0xFE000), 0x50);
+
+        system.load_bios_rom(&minimal_rom_image()).unwrap();
+        assert!(system.has_valid_rom());
+        assert_eq!(system.read_byte(0xFFFF0), 0xEA);
+
+        // Take the reset jump on a CPU running the loaded image
+        let mut cpu = setup_executable_cpu(&[]);
+        let memory = cpu
+            .memory
+            .as_any_mut()
+            .downcast_mut::<SystemMemory>()
+            .unwrap();
+        memory.load_bios_rom(&minimal_rom_image()).unwrap();
+        assert!(cpu.has_valid_rom());
+        cpu.regs.cs = 0xF000;
+        cpu.regs.ip = 0xFFF0;
+        cpu.execute_instruction().unwrap();
+        assert_eq!((cpu.regs.cs, cpu.regs.ip), (0xF000, 0xE05B));
+        assert_eq!(cpu.memory.read_byte(0xFE05B), 0xF4);
+    }
 }
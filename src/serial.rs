@@ -1,37 +1,22 @@
-#[allow(dead_code)]
 // 8250 UART registers
 const THR: u8 = 0; // Transmitter Holding Register (write)
-#[allow(dead_code)]
 const RBR: u8 = 0; // Receiver Buffer Register (read)
-#[allow(dead_code)]
 const IER: u8 = 1; // Interrupt Enable Register
-#[allow(dead_code)]
 const IIR: u8 = 2; // Interrupt Identification Register (read)
-#[allow(dead_code)]
 const FCR: u8 = 2; // FIFO Control Register
-#[allow(dead_code)]
 const LCR: u8 = 3; // Line Control Register
-#[allow(dead_code)]
 const MCR: u8 = 4; // Modem Control Register
-#[allow(dead_code)]
-const LSR: u8 = 5; // Line Status Register
-#[allow(dead_code)]
+pub(crate) const LSR: u8 = 5; // Line Status Register
 const MSR: u8 = 6; // Modem Status Register
-#[allow(dead_code)]
 const DLL: u8 = 0; // Divisor Latch LSB (when DLAB=1)
-#[allow(dead_code)]
 const DLM: u8 = 1; // Divisor Latch MSB (when DLAB=1)
 
 #[allow(dead_code)]
 // Line Status Register bits
 const LSR_DR: u8 = 0x01; // Data Ready
-#[allow(dead_code)]
 const LSR_OE: u8 = 0x02; // Overrun Error
-#[allow(dead_code)]
 const LSR_PE: u8 = 0x04; // Parity Error
-#[allow(dead_code)]
 const LSR_FE: u8 = 0x08; // Framing Error
-#[allow(dead_code)]
 const LSR_BI: u8 = 0x10; // Break Interrupt
 #[allow(dead_code)]
 const LSR_THRE: u8 = 0x20; // THR Empty
@@ -43,27 +28,26 @@ const LSR_ERR: u8 = 0x80; // Error in RCVR FIFO
 #[allow(dead_code)]
 // FIFO size and port addresses
 const FIFO_SIZE: usize = 16;
-#[allow(dead_code)]
-const COM1_BASE: u16 = 0x3F8;
-#[allow(dead_code)]
+pub const COM1_BASE: u16 = 0x3F8;
 const COM2_BASE: u16 = 0x2F8;
-#[allow(dead_code)]
 const COM3_BASE: u16 = 0x3E8;
-#[allow(dead_code)]
 const COM4_BASE: u16 = 0x2E8;
+/// Base ports of COM1-COM4, in the order the BIOS data area lists them
+pub const COM_PORT_BASES: [u16; 4] = [COM1_BASE, COM2_BASE, COM3_BASE, COM4_BASE];
 
-#[allow(dead_code)]
 // Add interrupt types
 const INT_NONE: u8 = 0x01;
-#[allow(dead_code)]
 const INT_TX_EMPTY: u8 = 0x02;
-#[allow(dead_code)]
 const INT_RX_DATA: u8 = 0x04;
-#[allow(dead_code)]
 const INT_LINE_STATUS: u8 = 0x06;
-#[allow(dead_code)]
 const INT_MODEM_STATUS: u8 = 0x00;
 
+// Interrupt enable bits
+const IER_RX_DATA: u8 = 0x01;
+const IER_THRE: u8 = 0x02;
+const IER_LINE_STATUS: u8 = 0x04;
+const IER_MODEM_STATUS: u8 = 0x08;
+
 // Add line control bits
 #[allow(dead_code)]
 const LCR_WORD_LENGTH: u8 = 0x03; // Bits 0-1: Word length
@@ -77,7 +61,6 @@ const LCR_PARITY_EVEN: u8 = 0x10; // Bit 4: Even parity
 const LCR_STICK_PARITY: u8 = 0x20; // Bit 5: Stick parity
 #[allow(dead_code)]
 const LCR_SET_BREAK: u8 = 0x40; // Bit 6: Set break
-#[allow(dead_code)]
 const LCR_DLAB: u8 = 0x80; // Bit 7: DLAB
 
 // Add modem control bits
@@ -85,21 +68,15 @@ const LCR_DLAB: u8 = 0x80; // Bit 7: DLAB
 const MCR_DTR: u8 = 0x01; // Data Terminal Ready
 #[allow(dead_code)]
 const MCR_RTS: u8 = 0x02; // Request To Send
-#[allow(dead_code)]
 const MCR_OUT1: u8 = 0x04; // Out1
 #[allow(dead_code)]
 const MCR_OUT2: u8 = 0x08; // Out2 (interrupt enable)
-#[allow(dead_code)]
 const MCR_LOOP: u8 = 0x10; // Loopback mode
 
 // Add flow control constants
-#[allow(dead_code)]
 const MSR_CTS: u8 = 0x10; // Clear To Send
-#[allow(dead_code)]
 const MSR_DSR: u8 = 0x20; // Data Set Ready
-#[allow(dead_code)]
 const MSR_RI: u8 = 0x40; // Ring Indicator
-#[allow(dead_code)]
 const MSR_DCD: u8 = 0x80; // Data Carrier Detect
 #[allow(dead_code)]
 const MSR_DCTS: u8 = 0x01; // Delta CTS
@@ -116,7 +93,12 @@ const XON: u8 = 0x11; // DC1
 #[allow(dead_code)]
 const XOFF: u8 = 0x13; // DC3
 
-use std::collections::VecDeque;
+use crate::io::IoDevice;
+use crate::prelude::*;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::any::Any;
+use core::cell::RefCell;
 
 #[allow(dead_code)]
 pub struct SerialController {
@@ -204,6 +186,9 @@ pub struct SerialPort {
     pub initialized: bool,
     pub input_buffer: VecDeque<u8>,
     pub output_buffer: VecDeque<u8>,
+    pub scratch: u8,
+    // A THR empty interrupt is waiting to be read from the IIR
+    thre_pending: bool,
 }
 
 impl Default for SerialPort {
@@ -233,6 +218,16 @@ impl SerialPort {
             xon_state: true,
             rx_fifo: VecDeque::new(),
             tx_fifo: VecDeque::new(),
+            scratch: 0,
+            thre_pending: false,
+        }
+    }
+
+    /// A UART decoding its eight registers from `base_port`
+    pub fn at(base_port: u16) -> Self {
+        SerialPort {
+            base_port,
+            ..Self::new()
         }
     }
 
@@ -243,12 +238,57 @@ impl SerialPort {
 
     #[allow(dead_code)]
     pub fn read_byte(&mut self) -> Option<u8> {
-        self.rx_fifo.pop_front()
+        let byte = self.rx_fifo.pop_front();
+        if self.rx_fifo.is_empty() {
+            self.lsr &= !LSR_DR;
+        }
+        byte
     }
 
+    /// A write to the transmit holding register. In loopback mode the
+    /// transmitter is wired straight back to the receiver, so the byte goes
+    /// to the receive FIFO instead of out on the line.
     #[allow(dead_code)]
     pub fn write_byte(&mut self, value: u8) {
-        self.tx_fifo.push_back(value);
+        if self.loopback() {
+            self.receive_byte(value);
+        } else {
+            self.tx_fifo.push_back(value);
+        }
+    }
+
+    /// A write to the modem control register. With MCR_LOOP set the modem
+    /// outputs feed the modem status inputs the way the 8250 wires them
+    /// internally: DTR to DSR, RTS to CTS, OUT1 to RI and OUT2 to DCD.
+    pub fn set_modem_control(&mut self, value: u8) {
+        self.mcr = value;
+        if self.loopback() {
+            let mut msr = self.msr & 0x0F;
+            for (output, input) in [
+                (MCR_DTR, MSR_DSR),
+                (MCR_RTS, MSR_CTS),
+                (MCR_OUT1, MSR_RI),
+                (MCR_OUT2, MSR_DCD),
+            ] {
+                if value & output != 0 {
+                    msr |= input;
+                }
+            }
+            self.msr = msr;
+        }
+    }
+
+    pub fn set_loopback(&mut self, enabled: bool) {
+        let mcr = if enabled {
+            self.mcr | MCR_LOOP
+        } else {
+            self.mcr & !MCR_LOOP
+        };
+        self.set_modem_control(mcr);
+    }
+
+    pub fn loopback(&self) -> bool {
+        self.mcr & MCR_LOOP != 0
     }
 
     #[allow(dead_code)]
@@ -256,6 +296,11 @@ impl SerialPort {
         !self.rx_fifo.is_empty()
     }
 
+    /// True once another received byte would overrun the FIFO
+    pub fn rx_full(&self) -> bool {
+        self.rx_fifo.len() >= FIFO_SIZE
+    }
+
     #[allow(dead_code)]
     pub fn add_input(&mut self, byte: u8) {
         self.input_buffer.push_back(byte);
@@ -301,10 +346,124 @@ impl SerialPort {
         if self.rx_fifo.len() < FIFO_SIZE {
             self.rx_fifo.push_back(value);
             self.lsr |= LSR_DR;
+        } else {
+            self.lsr |= LSR_OE;
+        }
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    // Transmission is instant, so the holding and shift registers are always
+    // empty by the time the guest looks
+    pub(crate) fn line_status(&self) -> u8 {
+        let mut lsr = (self.lsr & (LSR_OE | LSR_PE | LSR_FE | LSR_BI)) | LSR_THRE | LSR_TEMT;
+        if !self.rx_fifo.is_empty() {
+            lsr |= LSR_DR;
+        }
+        lsr
+    }
+
+    // The highest priority enabled interrupt. Reading it acknowledges a THR
+    // empty interrupt; the others clear when their cause goes away.
+    fn interrupt_id(&mut self) -> u8 {
+        if self.ier & IER_LINE_STATUS != 0 && self.lsr & (LSR_OE | LSR_PE | LSR_FE | LSR_BI) != 0 {
+            INT_LINE_STATUS
+        } else if self.ier & IER_RX_DATA != 0 && !self.rx_fifo.is_empty() {
+            INT_RX_DATA
+        } else if self.ier & IER_THRE != 0 && self.thre_pending {
+            self.thre_pending = false;
+            INT_TX_EMPTY
+        } else if self.ier & IER_MODEM_STATUS != 0 && self.msr & 0x0F != 0 {
+            INT_MODEM_STATUS
+        } else {
+            INT_NONE
+        }
+    }
+
+    fn set_divisor_byte(&mut self, high: bool, value: u8) {
+        if high {
+            self.dlm = value;
+        } else {
+            self.dll = value;
+        }
+        let divisor = u16::from_le_bytes([self.dll, self.dlm]);
+        if divisor != 0 {
+            self.baud_rate = 115200 / divisor as u32;
         }
     }
 }
 
+// The register file as the guest sees it at base_port..base_port + 7. DLAB
+// (LCR bit 7) swaps the divisor latch in at offsets 0 and 1.
+impl IoDevice for SerialPort {
+    fn read_port(&mut self, port: u16) -> u8 {
+        match (port.wrapping_sub(self.base_port) & 0x07) as u8 {
+            DLL if self.dlab() => self.dll,
+            DLM if self.dlab() => self.dlm,
+            RBR => self.read_byte().unwrap_or(0),
+            IER => self.ier,
+            IIR => self.interrupt_id(),
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                // Reading LSR clears the error bits
+                let lsr = self.line_status();
+                self.lsr &= !(LSR_OE | LSR_PE | LSR_FE | LSR_BI);
+                lsr
+            }
+            MSR => {
+                // Reading MSR clears the delta bits
+                let msr = self.msr;
+                self.msr &= 0xF0;
+                msr
+            }
+            _ => self.scratch, // SCR
+        }
+    }
+
+    fn write_port(&mut self, port: u16, value: u8) {
+        match (port.wrapping_sub(self.base_port) & 0x07) as u8 {
+            DLL if self.dlab() => self.set_divisor_byte(false, value),
+            DLM if self.dlab() => self.set_divisor_byte(true, value),
+            THR => {
+                self.write_byte(value);
+                self.thre_pending = true;
+            }
+            IER => {
+                // Enabling the THR empty interrupt while THR is empty raises it
+                if value & IER_THRE != 0 && self.ier & IER_THRE == 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = value & 0x0F;
+            }
+            FCR => {
+                if value & 0x02 != 0 {
+                    self.rx_fifo.clear();
+                    self.lsr &= !LSR_DR;
+                }
+                if value & 0x04 != 0 {
+                    self.tx_fifo.clear();
+                }
+            }
+            LCR => self.lcr = value,
+            MCR => self.set_modem_control(value),
+            LSR | MSR => {}            // Read only
+            _ => self.scratch = value, // SCR
+        }
+    }
+
+    // Keeps the base port: that's set by the card, not the chip
+    fn reset(&mut self) {
+        *self = SerialPort::at(self.base_port);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
 #[allow(dead_code)]
 pub struct PortStatus {
     pub rx_ready: bool,
@@ -353,11 +512,107 @@ pub struct FlowControl {
     pub xon_xoff_enabled: bool,
 }
 
+/// Somewhere transmitted bytes are copied to as they go out, alongside the
+/// output buffer `get_output` drains
+pub trait SerialSink {
+    fn write_byte(&mut self, byte: u8);
+    fn flush(&mut self) {}
+}
+
+/// A sink that collects everything in memory. Clones share the buffer, so
+/// one can be handed to `Serial::add_sink` and the other kept to look at.
+#[derive(Clone, Default)]
+pub struct VecSink(Rc<RefCell<Vec<u8>>>);
+
+impl VecSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl SerialSink for VecSink {
+    fn write_byte(&mut self, byte: u8) {
+        self.0.borrow_mut().push(byte);
+    }
+}
+
+// The copy `tee_to_file` keeps, flushed at every line end so the file is
+// current even if the emulator dies mid-session
+#[cfg(feature = "std")]
+struct FileSink(std::io::BufWriter<std::fs::File>);
+
+#[cfg(feature = "std")]
+impl SerialSink for FileSink {
+    fn write_byte(&mut self, byte: u8) {
+        use std::io::Write;
+        let _ = self.0.write_all(&[byte]);
+        if byte == b'\n' {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        use std::io::Write;
+        let _ = self.0.flush();
+    }
+}
+
+// The host terminal the console is echoed to
+#[cfg(feature = "std")]
+struct StdoutSink;
+
+#[cfg(feature = "std")]
+impl SerialSink for StdoutSink {
+    fn write_byte(&mut self, byte: u8) {
+        use std::io::Write;
+        let _ = std::io::stdout().write_all(&[byte]);
+    }
+
+    fn flush(&mut self) {
+        use std::io::Write;
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// When the console echo is flushed. Whatever the policy, it is also
+/// flushed when the guest asks for input and none is waiting, so a prompt
+/// is on screen before anyone has to type at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// After every character, which is slow for bulk output
+    PerChar,
+    /// At each line end
+    PerLine,
+    /// Only on `flush_console` (or the input wait above)
+    Manual,
+}
+
+/// How `add_input` hands host keystrokes to the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Held back and echoed until Enter, with backspace editing the line,
+    /// as a terminal in cooked mode does. For line-oriented programs.
+    Cooked,
+    /// Every byte goes straight through unechoed, for full-screen programs
+    /// that read keys themselves
+    Raw,
+}
+
 #[allow(dead_code)]
 pub struct Serial {
     input_buffer: VecDeque<u8>,
     output_buffer: VecDeque<u8>,
     ports: Vec<Option<SerialPort>>,
+    sinks: Vec<Box<dyn SerialSink>>,
+    console: Option<Box<dyn SerialSink>>,
+    flush_policy: FlushPolicy,
+    input_mode: InputMode,
+    // The cooked-mode line typed so far
+    pending_line: Vec<u8>,
 }
 
 impl Default for Serial {
@@ -372,6 +627,59 @@ impl Serial {
             input_buffer: VecDeque::new(),
             output_buffer: VecDeque::new(),
             ports: vec![Some(SerialPort::new()), None, None, None],
+            sinks: Vec::new(),
+            #[cfg(feature = "std")]
+            console: Some(Box::new(StdoutSink)),
+            #[cfg(not(feature = "std"))]
+            console: None,
+            flush_policy: FlushPolicy::PerLine,
+            input_mode: InputMode::Raw,
+            pending_line: Vec::new(),
+        }
+    }
+
+    /// Attaches a UART as COM1-COM4 (`index` 0-3). COM1 is always there;
+    /// the BIOS data area and equipment word count whatever else is.
+    pub fn attach_port(&mut self, index: usize) -> Result<(), String> {
+        let base = *COM_PORT_BASES
+            .get(index)
+            .ok_or_else(|| format!("No COM{} port; only COM1-COM4 exist", index + 1))?;
+        self.ports[index] = Some(SerialPort::at(base));
+        Ok(())
+    }
+
+    /// Base port of the UART attached as COM`index + 1`, if there is one
+    pub fn port_base(&self, index: usize) -> Option<u16> {
+        self.ports
+            .get(index)?
+            .as_ref()
+            .map(|_| COM_PORT_BASES[index])
+    }
+
+    pub fn port_count(&self) -> usize {
+        self.ports.iter().flatten().count()
+    }
+
+    /// The UART attached as COM`index + 1`, if there is one
+    pub fn get_port(&mut self, index: usize) -> Option<&mut SerialPort> {
+        self.ports.get_mut(index)?.as_mut()
+    }
+
+    /// The line status register of COM`index + 1` as INT 14h reports it.
+    /// COM1 is the line the host watches, so data ready means input is
+    /// queued with `add_input`. Polling it with nothing queued flushes the
+    /// console, as `take_input` does.
+    pub fn line_status(&mut self, index: usize) -> Option<u8> {
+        self.ports.get(index)?.as_ref()?;
+        if index == 0 {
+            if !self.has_data() {
+                self.flush_console();
+            }
+            let ready = if self.has_data() { LSR_DR } else { 0 };
+            Some(LSR_THRE | LSR_TEMT | ready)
+        } else {
+            let port = self.ports[index].as_ref()?;
+            Some(port.line_status())
         }
     }
 
@@ -395,15 +703,136 @@ impl Serial {
         !self.input_buffer.is_empty()
     }
 
-    #[allow(dead_code)]
+    /// Drops queued input and output and resets every attached UART.
+    /// Sinks, the console and which ports are attached are left alone.
+    pub fn reset(&mut self) {
+        self.input_buffer.clear();
+        self.output_buffer.clear();
+        self.pending_line.clear();
+        for port in self.ports.iter_mut().flatten() {
+            port.reset();
+        }
+    }
+
     pub fn add_input(&mut self, byte: u8) {
-        self.input_buffer.push_back(byte);
+        if self.input_mode == InputMode::Raw {
+            self.input_buffer.push_back(byte);
+            return;
+        }
+        match byte {
+            0x08 | 0x7F => {
+                if self.pending_line.pop().is_some() {
+                    b"\x08 \x08".iter().for_each(|&b| self.echo(b));
+                }
+            }
+            b'\r' | b'\n' => {
+                self.pending_line.push(byte);
+                self.input_buffer.extend(self.pending_line.drain(..));
+                self.echo(b'\r');
+                self.echo(b'\n');
+            }
+            _ => {
+                self.pending_line.push(byte);
+                self.echo(byte);
+            }
+        }
+        // Whatever the policy, the user has to see what they type
+        self.flush_console();
+    }
+
+    /// Switches between cooked and raw input. A half-typed cooked line is
+    /// delivered as it stands when switching to raw.
+    pub fn set_input_mode(&mut self, mode: InputMode) {
+        if mode == InputMode::Raw {
+            self.input_buffer.extend(self.pending_line.drain(..));
+        }
+        self.input_mode = mode;
+    }
+
+    pub fn input_mode(&self) -> InputMode {
+        self.input_mode
     }
 
     #[allow(dead_code)]
     pub fn get_output(&mut self) -> Option<u8> {
         self.output_buffer.pop_front()
     }
+
+    /// Takes the next byte queued with `add_input`. With nothing queued the
+    /// guest is about to wait for a key, so the console is flushed first.
+    pub fn take_input(&mut self) -> Option<u8> {
+        if self.input_buffer.is_empty() {
+            self.flush_console();
+        }
+        self.input_buffer.pop_front()
+    }
+
+    /// Echoes a console character to the host terminal (stdout by default),
+    /// flushing it as the flush policy says
+    pub fn echo(&mut self, byte: u8) {
+        let Some(console) = self.console.as_mut() else {
+            return;
+        };
+        console.write_byte(byte);
+        let flush = match self.flush_policy {
+            FlushPolicy::PerChar => true,
+            FlushPolicy::PerLine => byte == b'\n',
+            FlushPolicy::Manual => false,
+        };
+        if flush {
+            console.flush();
+        }
+    }
+
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Replaces where `echo` writes, or turns the echo off with None
+    pub fn set_console(&mut self, console: Option<Box<dyn SerialSink>>) {
+        self.console = console;
+    }
+
+    pub fn flush_console(&mut self) {
+        if let Some(console) = self.console.as_mut() {
+            console.flush();
+        }
+    }
+
+    /// Queues a byte sent by the guest for whoever is watching the line
+    pub fn transmit(&mut self, byte: u8) {
+        self.output_buffer.push_back(byte);
+        for sink in &mut self.sinks {
+            sink.write_byte(byte);
+        }
+    }
+
+    /// Copies everything transmitted from now on to `sink` as well
+    pub fn add_sink(&mut self, sink: Box<dyn SerialSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Flushes every sink, e.g. before the emulator exits
+    pub fn flush_sinks(&mut self) {
+        for sink in &mut self.sinks {
+            sink.flush();
+        }
+    }
+
+    /// Mirrors all output to `path` (truncated first), without taking it
+    /// away from the output buffer or any other sink. The file is flushed
+    /// at each line end.
+    #[cfg(feature = "std")]
+    pub fn tee_to_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.add_sink(Box::new(FileSink(std::io::BufWriter::new(file))));
+        Ok(())
+    }
+
+    /// Everything transmitted so far that hasn't been taken with `get_output`
+    pub fn output(&self) -> &VecDeque<u8> {
+        &self.output_buffer
+    }
 }
 
 #[cfg(test)]
@@ -539,4 +968,201 @@ mod tests {
         port.mcr |= MCR_OUT2;
         assert_eq!(port.mcr & MCR_OUT2, MCR_OUT2);
     }
+
+    #[test]
+    fn test_serial_port_loopback() {
+        let mut port = SerialPort::new();
+        port.set_modem_control(MCR_LOOP | MCR_DTR | MCR_OUT2);
+        assert!(port.loopback());
+
+        port.write_byte(0x5A);
+        assert!(port.tx_fifo.is_empty());
+        assert_eq!(port.get_status() & LSR_DR, LSR_DR);
+        assert_eq!(port.read_byte(), Some(0x5A));
+        assert_eq!(port.get_status() & LSR_DR, 0);
+
+        // Modem outputs show up on the status inputs
+        assert_eq!(port.msr & 0xF0, MSR_DSR | MSR_DCD);
+        port.set_modem_control(MCR_LOOP | MCR_RTS | MCR_OUT1);
+        assert_eq!(port.msr & 0xF0, MSR_CTS | MSR_RI);
+
+        // Back to normal operation the byte goes out on the line
+        port.set_loopback(false);
+        port.write_byte(0x41);
+        assert_eq!(port.tx_fifo.pop_front(), Some(0x41));
+        assert!(!port.has_data());
+    }
+
+    #[test]
+    fn test_uart_register_reads() {
+        let mut uart = SerialPort::at(COM1_BASE);
+        let lsr = COM1_BASE + LSR as u16;
+        let iir = COM1_BASE + IIR as u16;
+
+        uart.write_port(COM1_BASE, b'A'); // THR
+        assert_eq!(uart.tx_fifo.pop_front(), Some(b'A'));
+        assert_eq!(
+            uart.read_port(lsr) & (LSR_THRE | LSR_TEMT),
+            LSR_THRE | LSR_TEMT
+        );
+        assert_eq!(uart.read_port(lsr) & LSR_DR, 0);
+
+        uart.receive_byte(b'Z');
+        assert_eq!(uart.read_port(lsr) & LSR_DR, LSR_DR);
+        assert_eq!(uart.read_port(iir), INT_NONE); // Nothing enabled yet
+
+        // Received data outranks THR empty; reading IIR acknowledges THRE
+        uart.write_port(COM1_BASE + IER as u16, IER_RX_DATA | IER_THRE);
+        assert_eq!(
+            uart.read_port(COM1_BASE + IER as u16),
+            IER_RX_DATA | IER_THRE
+        );
+        assert_eq!(uart.read_port(iir), INT_RX_DATA);
+        assert_eq!(uart.read_port(COM1_BASE), b'Z'); // RBR
+        assert_eq!(uart.read_port(lsr) & LSR_DR, 0);
+        assert_eq!(uart.read_port(iir), INT_TX_EMPTY);
+        assert_eq!(uart.read_port(iir), INT_NONE);
+
+        // DLAB swaps the divisor latch in over RBR/THR and IER
+        uart.write_port(COM1_BASE + LCR as u16, LCR_DLAB | 0x03);
+        uart.write_port(COM1_BASE, 0x0C);
+        uart.write_port(COM1_BASE + 1, 0x00);
+        assert_eq!(uart.baud_rate, 9600);
+        assert_eq!(uart.read_port(COM1_BASE), 0x0C);
+        assert_eq!(uart.read_port(COM1_BASE + 1), 0x00);
+        assert!(uart.tx_fifo.is_empty());
+        uart.write_port(COM1_BASE + LCR as u16, 0x03);
+        assert_eq!(
+            uart.read_port(COM1_BASE + IER as u16),
+            IER_RX_DATA | IER_THRE
+        );
+
+        uart.write_port(COM1_BASE + 7, 0xA5);
+        assert_eq!(uart.read_port(COM1_BASE + 7), 0xA5);
+    }
+
+    #[test]
+    fn test_tee_to_file() {
+        let path = std::env::temp_dir().join(format!("dos_emu_tee_{}.log", std::process::id()));
+        let mut serial = Serial::new();
+        let sink = VecSink::new();
+        serial.add_sink(Box::new(sink.clone()));
+        serial.tee_to_file(&path).unwrap();
+
+        for &byte in b"C:\\>dir\r\n" {
+            serial.transmit(byte);
+        }
+        // The line end flushed the file without being asked
+        assert_eq!(std::fs::read(&path).unwrap(), b"C:\\>dir\r\n");
+        assert_eq!(sink.contents(), b"C:\\>dir\r\n");
+
+        // A partial line shows up once the sinks are flushed
+        serial.transmit(b'A');
+        serial.flush_sinks();
+        assert_eq!(std::fs::read(&path).unwrap(), b"C:\\>dir\r\nA");
+        std::fs::remove_file(&path).unwrap();
+
+        // The output buffer still sees everything
+        assert_eq!(serial.output().len(), 10);
+    }
+
+    // Counts flushes, sharing the count with the test
+    struct FlushCounter(Rc<RefCell<usize>>);
+
+    impl SerialSink for FlushCounter {
+        fn write_byte(&mut self, _byte: u8) {}
+
+        fn flush(&mut self) {
+            *self.0.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn test_input_modes() {
+        let typed = VecSink::new();
+        let mut serial = Serial::new();
+        serial.set_console(Some(Box::new(typed.clone())));
+        serial.set_input_mode(InputMode::Cooked);
+
+        // Nothing reaches the guest before Enter; backspace edits the line
+        b"dix\x08r".iter().for_each(|&b| serial.add_input(b));
+        assert!(!serial.has_data());
+        assert_eq!(typed.contents(), b"dix\x08 \x08r");
+        serial.add_input(b'\r');
+        let line: Vec<u8> = core::iter::from_fn(|| serial.take_input()).collect();
+        assert_eq!(line, b"dir\r");
+        assert_eq!(typed.contents(), b"dix\x08 \x08r\r\n");
+
+        // Raw delivers each byte at once and echoes nothing
+        serial.set_input_mode(InputMode::Raw);
+        serial.add_input(0x1B);
+        assert_eq!(serial.take_input(), Some(0x1B));
+        assert_eq!(typed.contents().len(), 9);
+
+        // A partial cooked line goes through when raw mode is switched on
+        serial.set_input_mode(InputMode::Cooked);
+        serial.add_input(b'q');
+        serial.set_input_mode(InputMode::Raw);
+        assert_eq!(serial.take_input(), Some(b'q'));
+    }
+
+    #[test]
+    fn test_cooked_echo_is_flushed_per_keystroke() {
+        let count = Rc::new(RefCell::new(0));
+        let mut serial = Serial::new();
+        serial.set_console(Some(Box::new(FlushCounter(count.clone()))));
+        serial.set_input_mode(InputMode::Cooked);
+        assert_eq!(serial.flush_policy, FlushPolicy::PerLine);
+
+        serial.add_input(b'd');
+        assert_eq!(*count.borrow(), 1);
+        serial.add_input(b'i');
+        serial.add_input(0x08);
+        assert_eq!(*count.borrow(), 3);
+
+        // Raw input echoes nothing, so there is nothing to flush
+        serial.set_input_mode(InputMode::Raw);
+        serial.add_input(b'x');
+        assert_eq!(*count.borrow(), 3);
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        // 1000 characters in 20 lines of 50
+        let text: Vec<u8> = (0..1000)
+            .map(|i| if i % 50 == 49 { b'\n' } else { b'x' })
+            .collect();
+        let flushes = |policy| {
+            let count = Rc::new(RefCell::new(0));
+            let mut serial = Serial::new();
+            serial.set_console(Some(Box::new(FlushCounter(count.clone()))));
+            serial.set_flush_policy(policy);
+            text.iter().for_each(|&byte| serial.echo(byte));
+            let flushed = *count.borrow();
+            flushed
+        };
+        assert_eq!(flushes(FlushPolicy::PerChar), 1000);
+        assert_eq!(flushes(FlushPolicy::PerLine), 20);
+        assert_eq!(flushes(FlushPolicy::Manual), 0);
+
+        // Waiting for input flushes a prompt that has no line end
+        let count = Rc::new(RefCell::new(0));
+        let mut serial = Serial::new();
+        serial.set_console(Some(Box::new(FlushCounter(count.clone()))));
+        b"C:\\>".iter().for_each(|&byte| serial.echo(byte));
+        assert_eq!(*count.borrow(), 0);
+        assert_eq!(serial.take_input(), None);
+        assert_eq!(*count.borrow(), 1);
+        // But not when a key is already waiting
+        serial.add_input(b'd');
+        assert_eq!(serial.take_input(), Some(b'd'));
+        assert_eq!(*count.borrow(), 1);
+
+        // Polling the line status for input counts as waiting too
+        assert_eq!(serial.line_status(0), Some(LSR_THRE | LSR_TEMT));
+        assert_eq!(*count.borrow(), 2);
+        serial.add_input(b'd');
+        assert_eq!(serial.line_status(0), Some(LSR_THRE | LSR_TEMT | LSR_DR));
+        assert_eq!(*count.borrow(), 2);
+    }
 }
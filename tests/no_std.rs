@@ -0,0 +1,23 @@
+// Confirms the emulation core still builds with default features off, i.e.
+// with no_std + alloc. Uses its own target directory so it doesn't contend
+// with the build that is running the tests.
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn core_builds_without_std() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO"))
+        .current_dir(manifest_dir)
+        .args(["check", "--lib", "--no-default-features", "--target-dir"])
+        .arg(manifest_dir.join("target").join("no_std"))
+        .output()
+        .expect("failed to run cargo");
+
+    assert!(
+        output.status.success(),
+        "no_std build failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,207 @@
+// Runs the reference vectors in tests/vectors/*.toml: each sets up a CPU
+// state, executes one instruction and checks the registers, flags and
+// memory it names. Failures are collected so one run reports every vector
+// that disagrees, not just the first.
+
+use dos_emu::cpu::test_support::{executable_cpu, execute_one_raw};
+use dos_emu::cpu::Cpu;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct VectorFile {
+    vector: Vec<Vector>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Vector {
+    name: String,
+    code: Vec<u8>,
+    #[serde(default)]
+    initial: State,
+    expected: State,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct State {
+    ax: Option<u16>,
+    bx: Option<u16>,
+    cx: Option<u16>,
+    dx: Option<u16>,
+    si: Option<u16>,
+    di: Option<u16>,
+    bp: Option<u16>,
+    sp: Option<u16>,
+    cs: Option<u16>,
+    ds: Option<u16>,
+    es: Option<u16>,
+    ss: Option<u16>,
+    ip: Option<u16>,
+    cf: Option<bool>,
+    pf: Option<bool>,
+    af: Option<bool>,
+    zf: Option<bool>,
+    sf: Option<bool>,
+    of: Option<bool>,
+    df: Option<bool>,
+    // (physical address, byte) pairs
+    #[serde(default)]
+    memory: Vec<(u32, u8)>,
+}
+
+macro_rules! registers {
+    ($mac:ident) => {
+        $mac!(ax, bx, cx, dx, si, di, bp, sp, cs, ds, es, ss, ip)
+    };
+}
+
+// (name, field in State, getter, setter) for each flag a vector can name
+type FlagAccess = (
+    &'static str,
+    fn(&State) -> Option<bool>,
+    fn(&Cpu) -> bool,
+    fn(&mut Cpu, bool),
+);
+
+const FLAGS: [FlagAccess; 7] = [
+    (
+        "cf",
+        |s| s.cf,
+        |c| c.regs.flags.get_carry(),
+        |c, v| c.regs.flags.set_carry(v),
+    ),
+    (
+        "pf",
+        |s| s.pf,
+        |c| c.regs.flags.get_parity(),
+        |c, v| c.regs.flags.set_parity(v),
+    ),
+    (
+        "af",
+        |s| s.af,
+        |c| c.regs.flags.get_auxiliary(),
+        |c, v| c.regs.flags.set_auxiliary(v),
+    ),
+    (
+        "zf",
+        |s| s.zf,
+        |c| c.regs.flags.get_zero(),
+        |c, v| c.regs.flags.set_zero(v),
+    ),
+    (
+        "sf",
+        |s| s.sf,
+        |c| c.regs.flags.get_sign(),
+        |c, v| c.regs.flags.set_sign(v),
+    ),
+    (
+        "of",
+        |s| s.of,
+        |c| c.regs.flags.get_overflow(),
+        |c, v| c.regs.flags.set_overflow(v),
+    ),
+    (
+        "df",
+        |s| s.df,
+        |c| c.regs.flags.get_direction(),
+        |c, v| c.regs.flags.set_direction(v),
+    ),
+];
+
+fn apply(cpu: &mut Cpu, state: &State) {
+    macro_rules! set {
+        ($($reg:ident),*) => {
+            $(if let Some(value) = state.$reg {
+                cpu.regs.$reg = value;
+            })*
+        };
+    }
+    registers!(set);
+    for (_, wanted, _, set_flag) in FLAGS {
+        if let Some(value) = wanted(state) {
+            set_flag(cpu, value);
+        }
+    }
+    for &(address, byte) in &state.memory {
+        cpu.memory.write_byte(address, byte);
+    }
+}
+
+// Every difference between the CPU and `expected`, as text
+fn mismatches(cpu: &Cpu, expected: &State) -> Vec<String> {
+    let mut found = Vec::new();
+    macro_rules! check {
+        ($($reg:ident),*) => {
+            $(if let Some(value) = expected.$reg {
+                if cpu.regs.$reg != value {
+                    found.push(format!(
+                        "{} = {:04X}, expected {:04X}",
+                        stringify!($reg), cpu.regs.$reg, value
+                    ));
+                }
+            })*
+        };
+    }
+    registers!(check);
+    for (name, wanted, get_flag, _) in FLAGS {
+        if let Some(value) = wanted(expected) {
+            if get_flag(cpu) != value {
+                found.push(format!("{} = {}, expected {}", name, !value, value));
+            }
+        }
+    }
+    for &(address, byte) in &expected.memory {
+        let actual = cpu.memory.read_byte(address);
+        if actual != byte {
+            found.push(format!(
+                "[{:05X}] = {:02X}, expected {:02X}",
+                address, actual, byte
+            ));
+        }
+    }
+    found
+}
+
+fn run_vector_file(path: &Path) -> Vec<String> {
+    let text = fs::read_to_string(path).expect("failed to read vector file");
+    let file: VectorFile = toml::from_str(&text)
+        .unwrap_or_else(|e| panic!("{}: invalid vector file: {}", path.display(), e));
+
+    let mut failures = Vec::new();
+    for vector in &file.vector {
+        let mut cpu = executable_cpu(&[]);
+        apply(&mut cpu, &vector.initial);
+        let problems = match execute_one_raw(&mut cpu, &vector.code) {
+            Ok(()) => mismatches(&cpu, &vector.expected),
+            Err(e) => vec![format!("failed: {}", e)],
+        };
+        if !problems.is_empty() {
+            failures.push(format!("{}: {}", vector.name, problems.join(", ")));
+        }
+    }
+    println!("{}: {} vectors", path.display(), file.vector.len());
+    failures
+}
+
+#[test]
+fn reference_vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .expect("tests/vectors is missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no vector files in {}", dir.display());
+
+    let failures: Vec<String> = paths.iter().flat_map(|p| run_vector_file(p)).collect();
+    assert!(
+        failures.is_empty(),
+        "{} vector(s) failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}